@@ -4,8 +4,9 @@
 //! Definitions and tooling for DAP protocol aborts.
 
 use crate::{
-    messages::{BatchSelector, TaskId, TransitionFailure},
-    DapError, DapMediaType, DapRequest, DapVersion,
+    constants::{expected_media_type, DapEndpoint, DapMediaType},
+    messages::{BatchSelector, Duration, TaskId, TransitionFailure},
+    DapError, DapRequest, DapVersion,
 };
 use prio::codec::CodecError;
 use serde::{Deserialize, Serialize};
@@ -25,6 +26,11 @@ pub enum DapAbort {
     #[error("batchMismatch")]
     BatchMismatch { detail: String, task_id: TaskId },
 
+    /// Batch not ready. Sent in response to a CollectReq that names a fixed-size batch that has
+    /// not yet aggregated enough reports to be collected. The Collector may retry later.
+    #[error("batchNotReady")]
+    BatchNotReady { detail: String, task_id: TaskId },
+
     /// Batch overlap. Sent in response to an CollectReq for which the Leader detects the same
     /// Collector requesting an aggregate share which it has collected in the past.
     #[error("batchOverlap")]
@@ -48,10 +54,25 @@ pub enum DapAbort {
     #[error("missingTaskID")]
     MissingTaskId,
 
+    /// Not found. Sent in response to a request for a resource that does not exist, e.g., an HPKE
+    /// config with an unrecognized ID.
+    #[error("not found")]
+    NotFound(String),
+
+    /// Payload too large. Sent in response to a request whose body exceeds the maximum size
+    /// permitted for its media type.
+    #[error("payload too large")]
+    PayloadTooLarge { limit: u64 },
+
     /// Query mismatch. Sent in response to a CollectReq or AggregateShareReq.
     #[error("queryMismatch")]
     QueryMismatch { detail: String, task_id: TaskId },
 
+    /// Rate limited. Sent in response to a request that exceeds the rate permitted for the task.
+    /// The request may be retried after the indicated number of seconds.
+    #[error("rate limited")]
+    RateLimited { retry_after: Duration },
+
     /// Report rejected. Sent in response to an upload request containing a Report that the Leader
     /// would reject during the aggregation sub-protocol.
     #[error("reportRejected")]
@@ -98,6 +119,16 @@ pub enum DapAbort {
     /// Unrecognized DAP task. Sent in response to a request indicating an unrecognized task ID.
     #[error("unrecognizedTask")]
     UnrecognizedTask,
+
+    /// Unsupported media type. Sent in response to a request whose content-type is missing or is
+    /// not recognized as a DAP media type.
+    #[error("unsupported media type")]
+    UnsupportedMediaType(String),
+
+    /// Unsupported DAP version. Sent in response to a request that indicates a version of the
+    /// protocol that is not recognized.
+    #[error("unsupported version")]
+    UnsupportedVersion(String),
 }
 
 impl DapAbort {
@@ -109,6 +140,7 @@ impl DapAbort {
             Self::BatchInvalid { detail, task_id }
             | Self::InvalidTask { detail, task_id }
             | Self::BatchMismatch { detail, task_id }
+            | Self::BatchNotReady { detail, task_id }
             | Self::BatchOverlap { detail, task_id }
             | Self::InvalidBatchSize { detail, task_id }
             | Self::QueryMismatch { detail, task_id }
@@ -118,9 +150,25 @@ impl DapAbort {
                 Some("A task ID must be specified in the query parameter of the request.".into()),
                 None,
             ),
-            Self::BadRequest(detail) | Self::ReportRejected { detail } => {
-                (None, Some(detail), None)
-            }
+            Self::PayloadTooLarge { limit } => (
+                None,
+                Some(format!(
+                    "The request body exceeds the limit of {limit} bytes."
+                )),
+                None,
+            ),
+            Self::RateLimited { retry_after } => (
+                None,
+                Some(format!(
+                    "Too many requests. Retry after {retry_after} seconds."
+                )),
+                None,
+            ),
+            Self::BadRequest(detail)
+            | Self::NotFound(detail)
+            | Self::ReportRejected { detail }
+            | Self::UnsupportedMediaType(detail)
+            | Self::UnsupportedVersion(detail) => (None, Some(detail), None),
             Self::RoundMismatch {
                 detail,
                 task_id,
@@ -150,18 +198,91 @@ impl DapAbort {
         }
     }
 
-    /// Abort due to unexpected value for HTTP content-type header.
-    pub fn content_type<S>(req: &DapRequest<S>, expected: DapMediaType) -> Self {
-        let want_str = expected
-            .as_str_for_version(req.version)
-            .expect("could not resolve content-type for expected media type");
+    /// Construct an abort from a problem details JSON object received from a peer. Returns `None`
+    /// if the document does not indicate a recognized DAP abort type, or if fields required by the
+    /// abort type are missing.
+    pub fn from_problem_details(problem_details: ProblemDetails) -> Option<Self> {
+        let dap_abort_type = problem_details
+            .typ
+            .as_deref()?
+            .strip_prefix("urn:ietf:params:ppm:dap:error:")?;
+        let task_id = problem_details
+            .task_id
+            .as_deref()
+            .and_then(TaskId::try_from_base64url);
+        let detail = problem_details.detail.unwrap_or_default();
+
+        let abort = match dap_abort_type {
+            "batchInvalid" => Self::BatchInvalid {
+                detail,
+                task_id: task_id?,
+            },
+            "batchMismatch" => Self::BatchMismatch {
+                detail,
+                task_id: task_id?,
+            },
+            "batchOverlap" => Self::BatchOverlap {
+                detail,
+                task_id: task_id?,
+            },
+            "invalidBatchSize" => Self::InvalidBatchSize {
+                detail,
+                task_id: task_id?,
+            },
+            "invalidTask" => Self::InvalidTask {
+                detail,
+                task_id: task_id?,
+            },
+            "missingTaskID" => Self::MissingTaskId,
+            "queryMismatch" => Self::QueryMismatch {
+                detail,
+                task_id: task_id?,
+            },
+            "reportRejected" => Self::ReportRejected { detail },
+            "reportTooLate" => Self::ReportTooLate,
+            "roundMismatch" => Self::RoundMismatch {
+                detail,
+                task_id: task_id?,
+                agg_job_id_base64url: problem_details.agg_job_id?,
+            },
+            "unauthorizedRequest" => Self::UnauthorizedRequest {
+                detail,
+                task_id: task_id?,
+            },
+            "unrecognizedAggregationJob" => Self::UnrecognizedAggregationJob {
+                task_id: task_id?,
+                agg_job_id_base64url: problem_details.agg_job_id?,
+            },
+            "unrecognizedMessage" => Self::UnrecognizedMessage,
+            "unrecognizedTask" => Self::UnrecognizedTask,
+            _ => return None,
+        };
+        Some(abort)
+    }
 
-        if let Some(got_str) = req.media_type.as_str_for_version(req.version) {
-            Self::BadRequest(format!(
+    /// Abort due to unexpected value for HTTP content-type header. `endpoints` lists the
+    /// endpoints the request may have been targeted at; the detail indicates the content-type
+    /// expected for each.
+    pub fn content_type<S>(req: &DapRequest<S>, endpoints: &[DapEndpoint]) -> Self {
+        let want_str = endpoints
+            .iter()
+            .map(|endpoint| {
+                expected_media_type(*endpoint, req.version)
+                    .expect("could not resolve content-type for expected media type")
+            })
+            .collect::<Vec<_>>()
+            .join(" or ");
+
+        let got_str = match req.media_type {
+            DapMediaType::Invalid(ref got_str) => Some(got_str.as_str()),
+            _ => req.media_type.as_str_for_version(req.version),
+        };
+        if let Some(got_str) = got_str {
+            Self::UnsupportedMediaType(format!(
                 "unexpected content-type: got {got_str}; want {want_str}"
             ))
         } else {
-            Self::BadRequest(format!("missing content-type: expected {want_str}"))
+            Self::UnsupportedMediaType(format!("missing content-type: expected {want_str}"))
         }
     }
 
@@ -173,12 +294,21 @@ impl DapAbort {
     }
 
     #[inline]
-    pub(crate) fn version_unknown() -> Self {
-        DapAbort::BadRequest("DAP version of request is not recognized".into())
+    pub fn version_unknown() -> Self {
+        DapAbort::UnsupportedVersion("DAP version of request is not recognized".into())
+    }
+
+    /// Abort a request for a version that is recognized, but for which the request is not yet
+    /// handled (i.e., draft09).
+    #[inline]
+    pub(crate) fn version_not_implemented(version: DapVersion) -> Self {
+        DapAbort::UnsupportedVersion(format!(
+            "DAP version {version} is not yet supported for this request"
+        ))
     }
 
     #[inline]
-    pub(crate) fn batch_overlap(task_id: &TaskId, batch_sel: &BatchSelector) -> Self {
+    pub fn batch_overlap(task_id: &TaskId, batch_sel: &BatchSelector) -> Self {
         Self::BatchOverlap {
             detail: format!(
                 "The batch indicated by the request: {}",
@@ -189,7 +319,7 @@ impl DapAbort {
     }
 
     #[inline]
-    pub(crate) fn query_mismatch(
+    pub fn query_mismatch(
         task_id: &TaskId,
         query_type_for_task: impl std::fmt::Display,
         query_type_for_request: impl std::fmt::Display,
@@ -209,6 +339,15 @@ impl DapAbort {
             TransitionFailure::ReportReplayed => {
                 "A report with the same ID was uploaded previously."
             }
+            TransitionFailure::HpkeUnknownConfigId => {
+                "No current HPKE configuration matches the indicated ID."
+            }
+            TransitionFailure::UnrecognizedMessage => {
+                "The report has a duplicate extension or one that is not permitted for the task."
+            }
+            TransitionFailure::ReportTooEarly => {
+                "The report pertains to a bucket that has not yet started."
+            }
             _ => return DapError::Fatal(format!("Attempted to construct a \"reportRejected\" abort with unexpected transition failure: {failure_reason:?}")).into(),
         };
 
@@ -260,6 +399,12 @@ impl DapAbort {
                 Some(self.to_string()),
             ),
             Self::BadRequest(..) => ("Bad request", None),
+            Self::BatchNotReady { .. } => ("Batch is not ready to be collected", None),
+            Self::NotFound(..) => ("Not found", None),
+            Self::PayloadTooLarge { .. } => ("Payload too large", None),
+            Self::RateLimited { .. } => ("Too many requests", None),
+            Self::UnsupportedMediaType(..) => ("Unsupported media type", None),
+            Self::UnsupportedVersion(..) => ("Unsupported DAP version", None),
             Self::Internal(..) => ("Internal server error", None),
         };
 
@@ -273,9 +418,10 @@ impl DapAbort {
 impl From<DapError> for DapAbort {
     fn from(e: DapError) -> Self {
         match e {
-            e @ DapError::Fatal(..) => Self::Internal(Box::new(e)),
+            e @ (DapError::Fatal(..) | DapError::Storage(..)) => Self::Internal(Box::new(e)),
             DapError::Abort(abort) => abort,
             DapError::Transition(failure_reason) => Self::report_rejected(failure_reason),
+            DapError::RateLimited { retry_after } => Self::RateLimited { retry_after },
         }
     }
 }
@@ -289,11 +435,11 @@ impl From<CodecError> for DapAbort {
 /// A problem details document compatible with RFC 7807.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ProblemDetails {
-    #[serde(rename = "taskid")]
     pub title: String,
     #[serde(rename = "type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub typ: Option<String>,
+    #[serde(rename = "taskid")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) task_id: Option<String>,
     #[serde(rename = "aggregationjobid")]
@@ -0,0 +1,119 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    aborts::{DapAbort, ProblemDetails},
+    messages::TaskId,
+    DapError,
+};
+use assert_matches::assert_matches;
+
+// Encode the abort as a problem details document, as if sent by a peer, and parse it back.
+fn round_trip(abort: DapAbort) -> Option<DapAbort> {
+    let encoded = serde_json::to_string(&abort.into_problem_details()).unwrap();
+    let problem_details: ProblemDetails = serde_json::from_str(&encoded).unwrap();
+    DapAbort::from_problem_details(problem_details)
+}
+
+#[test]
+fn from_problem_details() {
+    let task_id = TaskId([23; 32]);
+
+    assert_matches!(
+        round_trip(DapAbort::BatchInvalid {
+            detail: "bad batch".into(),
+            task_id: task_id.clone(),
+        }),
+        Some(DapAbort::BatchInvalid { detail, task_id: got }) => {
+            assert_eq!(detail, "bad batch");
+            assert_eq!(got, task_id);
+        }
+    );
+
+    assert_matches!(
+        round_trip(DapAbort::ReportTooLate),
+        Some(DapAbort::ReportTooLate)
+    );
+
+    assert_matches!(
+        round_trip(DapAbort::UnrecognizedAggregationJob {
+            task_id: task_id.clone(),
+            agg_job_id_base64url: "some-agg-job".into(),
+        }),
+        Some(DapAbort::UnrecognizedAggregationJob { task_id: got, agg_job_id_base64url }) => {
+            assert_eq!(got, task_id);
+            assert_eq!(agg_job_id_base64url, "some-agg-job");
+        }
+    );
+
+    // Aborts without a DAP type can't be parsed.
+    assert_matches!(round_trip(DapAbort::BadRequest("oops".into())), None);
+}
+
+#[test]
+fn from_problem_details_missing_task_id() {
+    let problem_details: ProblemDetails = serde_json::from_str(
+        r#"{"type":"urn:ietf:params:ppm:dap:error:batchInvalid","title":"Batch boundary check failed"}"#,
+    )
+    .unwrap();
+    assert_matches!(DapAbort::from_problem_details(problem_details), None);
+}
+
+#[test]
+fn problem_details_task_id_field_name() {
+    let task_id = TaskId([23; 32]);
+    let problem_details = DapAbort::UnrecognizedAggregationJob {
+        task_id: task_id.clone(),
+        agg_job_id_base64url: "some-agg-job".into(),
+    }
+    .into_problem_details();
+    let encoded: serde_json::Value = serde_json::to_value(problem_details).unwrap();
+    assert_eq!(encoded["taskid"], task_id.to_base64url());
+    assert_eq!(encoded["title"], "Unrecognized aggregation job");
+}
+
+#[test]
+fn rate_limited_is_retriable() {
+    let err = DapError::RateLimited { retry_after: 1 };
+    assert!(err.is_retriable());
+    assert!(!DapError::fatal("oops").is_retriable());
+
+    let problem_details = DapAbort::from(err).into_problem_details();
+    assert_eq!(problem_details.title, "Too many requests");
+    assert_eq!(problem_details.typ, None);
+}
+
+#[test]
+fn payload_too_large_problem_details() {
+    let problem_details = DapAbort::PayloadTooLarge { limit: 1024 }.into_problem_details();
+    assert_eq!(problem_details.title, "Payload too large");
+    assert_eq!(problem_details.typ, None);
+    assert_eq!(
+        problem_details.detail.as_deref(),
+        Some("The request body exceeds the limit of 1024 bytes.")
+    );
+}
+
+#[test]
+fn unsupported_media_type_problem_details() {
+    let problem_details =
+        DapAbort::UnsupportedMediaType("missing content-type".into()).into_problem_details();
+    assert_eq!(problem_details.title, "Unsupported media type");
+    assert_eq!(problem_details.typ, None);
+    assert_eq!(
+        problem_details.detail.as_deref(),
+        Some("missing content-type")
+    );
+}
+
+#[test]
+fn storage_error_is_internal() {
+    let err = DapError::Storage("kv_store: unreachable".into());
+    assert_eq!(err.to_string(), "storage error: kv_store: unreachable");
+
+    let abort = DapAbort::from(err);
+    assert_matches!(abort, DapAbort::Internal(ref e) => {
+        assert_eq!(e.to_string(), "storage error: kv_store: unreachable");
+    });
+    assert_eq!(abort.into_problem_details().title, "Internal server error");
+}
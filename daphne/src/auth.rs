@@ -61,12 +61,45 @@ pub trait BearerTokenProvider<'a> {
         task_id: &'a TaskId,
     ) -> Result<Option<Self::WrappedBearerToken>, DapError>;
 
+    /// Fetch the bearer tokens, other than the one returned by `get_leader_bearer_token_for()`,
+    /// that the Leader may use for the given task while a rotation of its token is in progress.
+    /// Tokens whose grace period has elapsed must not be returned.
+    ///
+    /// By default, bearer tokens are never rotated and this list is empty.
+    async fn get_rotated_leader_bearer_tokens_for(
+        &'a self,
+        _task_id: &'a TaskId,
+    ) -> Result<Vec<BearerToken>, DapError> {
+        Ok(Vec::new())
+    }
+
     /// Fetch the Collector's bearer token for the given task, if the task is recognized.
     async fn get_collector_bearer_token_for(
         &'a self,
         task_id: &'a TaskId,
     ) -> Result<Option<Self::WrappedBearerToken>, DapError>;
 
+    /// Fetch the bearer tokens of all Collectors authorized for the given task, if the task is
+    /// recognized. A request from a Collector is authorized if it carries any of these tokens.
+    ///
+    /// By default, only the token returned by `get_collector_bearer_token_for()` is authorized.
+    /// Providers that support more than one Collector per task should override this.
+    async fn get_collector_bearer_tokens_for(
+        &'a self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<Vec<BearerToken>>, DapError> {
+        Ok(self
+            .get_collector_bearer_token_for(task_id)
+            .await?
+            .map(|token| vec![token.as_ref().clone()]))
+    }
+
+    /// Fetch the Client's bearer token for the given task, if one is configured.
+    async fn get_client_bearer_token_for(
+        &'a self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<Self::WrappedBearerToken>, DapError>;
+
     /// Returns true if the given bearer token matches the leader token configured for the "taskprov" extension.
     fn is_taskprov_leader_bearer_token(&self, token: &BearerToken) -> bool;
 
@@ -118,7 +151,17 @@ pub trait BearerTokenProvider<'a> {
         if matches!(req.media_type.sender(), Some(DapSender::Leader)) {
             if let Some(ref got) = req.sender_auth {
                 if let Some(expected) = self.get_leader_bearer_token_for(task_id).await? {
-                    return Ok(if got.as_ref() == expected.as_ref() {
+                    if got.as_ref() == expected.as_ref() {
+                        return Ok(None);
+                    }
+
+                    // The token may have been rotated recently, in which case the Leader may
+                    // still be using the old one.
+                    let rotated = self.get_rotated_leader_bearer_tokens_for(task_id).await?;
+                    let authorized = rotated.iter().fold(false, |authorized, token| {
+                        (got.as_ref() == token) | authorized
+                    });
+                    return Ok(if authorized {
                         None
                     } else {
                         Some("The indicated beareer token is incorrect for the Leader.".into())
@@ -134,8 +177,12 @@ pub trait BearerTokenProvider<'a> {
 
         if matches!(req.media_type.sender(), Some(DapSender::Collector)) {
             if let Some(ref got) = req.sender_auth {
-                if let Some(expected) = self.get_collector_bearer_token_for(task_id).await? {
-                    return Ok(if got.as_ref() == expected.as_ref() {
+                if let Some(expected) = self.get_collector_bearer_tokens_for(task_id).await? {
+                    // Check every token so that the time taken doesn't reveal which one matched.
+                    let authorized = expected.iter().fold(false, |authorized, token| {
+                        (got.as_ref() == token) | authorized
+                    });
+                    return Ok(if authorized {
                         None
                     } else {
                         Some("The indicated bearer token is incorrect for the Collector.".into())
@@ -152,6 +199,18 @@ pub trait BearerTokenProvider<'a> {
             }
         }
 
+        if matches!(req.media_type.sender(), Some(DapSender::Client)) {
+            if let Some(ref got) = req.sender_auth {
+                return Ok(match self.get_client_bearer_token_for(task_id).await? {
+                    Some(expected) if got.as_ref() == expected.as_ref() => None,
+                    Some(..) => {
+                        Some("The indicated bearer token is incorrect for the Client.".into())
+                    }
+                    None => Some("No bearer token is configured for the Client.".into()),
+                });
+            }
+        }
+
         // Deny request with unhandled or unknown media type.
         Ok(Some(format!(
             "Cannot resolve sender due to unexpected media type ({:?}).",
@@ -5,7 +5,7 @@
 
 use crate::{DapSender, DapVersion};
 
-// Media types for HTTP requests.
+// Media types for HTTP requests. draft04 and draft09 use the same media types.
 const DRAFT02_MEDIA_TYPE_AGG_CONT_REQ: &str = "application/dap-aggregate-continue-req";
 const DRAFT02_MEDIA_TYPE_AGG_CONT_RESP: &str = "application/dap-aggregate-continue-resp";
 const DRAFT02_MEDIA_TYPE_AGG_INIT_REQ: &str = "application/dap-aggregate-initialize-req";
@@ -23,6 +23,41 @@ const MEDIA_TYPE_COLLECT_REQ: &str = "application/dap-collect-req";
 const MEDIA_TYPE_HPKE_CONFIG_LIST: &str = "application/dap-hpke-config-list";
 const MEDIA_TYPE_REPORT: &str = "application/dap-report";
 
+/// DAP endpoints that accept a request payload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DapEndpoint {
+    /// Leader: Report upload.
+    Upload,
+    /// Leader: Collection job initialization.
+    Collect,
+    /// Helper: Aggregation job initialization.
+    AggregationJobInit,
+    /// Helper: Aggregation job continuation.
+    AggregationJobContinue,
+    /// Helper: Aggregate share request.
+    AggregateShare,
+}
+
+/// Return the content-type expected for requests to the given endpoint, or `None` if the version
+/// is not recognized.
+pub fn expected_media_type(endpoint: DapEndpoint, version: DapVersion) -> Option<&'static str> {
+    if version == DapVersion::Unknown {
+        return None;
+    }
+
+    match endpoint {
+        DapEndpoint::Upload => DapMediaType::Report.as_str_for_version(version),
+        DapEndpoint::Collect => DapMediaType::CollectReq.as_str_for_version(version),
+        DapEndpoint::AggregationJobInit => {
+            DapMediaType::AggregationJobInitReq.as_str_for_version(version)
+        }
+        DapEndpoint::AggregationJobContinue => {
+            DapMediaType::AggregationJobContinueReq.as_str_for_version(version)
+        }
+        DapEndpoint::AggregateShare => DapMediaType::AggregateShareReq.as_str_for_version(version),
+    }
+}
+
 /// Media type for each DAP request. This is included in the "content-type" HTTP header.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum DapMediaType {
@@ -67,30 +102,42 @@ impl DapMediaType {
     pub fn from_str_for_version(version: DapVersion, content_type: Option<&str>) -> Self {
         match (version, content_type) {
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_CONT_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_JOB_CONT_REQ)) => {
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_AGG_JOB_CONT_REQ)) => {
                 Self::AggregationJobContinueReq
             }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_CONT_RESP)) => {
                 Self::Draft02AggregateContinueResp
             }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_INIT_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_JOB_INIT_REQ)) => {
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_AGG_JOB_INIT_REQ)) => {
                 Self::AggregationJobInitReq
             }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_INIT_RESP))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_JOB_RESP)) => Self::AggregationJobResp,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_AGG_JOB_RESP)) => {
+                Self::AggregationJobResp
+            }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_SHARE_RESP))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_SHARE)) => Self::AggregateShare,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_AGG_SHARE)) => {
+                Self::AggregateShare
+            }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_COLLECT_RESP))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_COLLECTION)) => Self::Collection,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_COLLECTION)) => {
+                Self::Collection
+            }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_HPKE_CONFIG))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_HPKE_CONFIG_LIST)) => Self::HpkeConfigList,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_HPKE_CONFIG_LIST)) => {
+                Self::HpkeConfigList
+            }
             (DapVersion::Draft02, Some(MEDIA_TYPE_AGG_SHARE_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_SHARE_REQ)) => Self::AggregateShareReq,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_AGG_SHARE_REQ)) => {
+                Self::AggregateShareReq
+            }
             (DapVersion::Draft02, Some(MEDIA_TYPE_COLLECT_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_COLLECT_REQ)) => Self::CollectReq,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_COLLECT_REQ)) => {
+                Self::CollectReq
+            }
             (DapVersion::Draft02, Some(MEDIA_TYPE_REPORT))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_REPORT)) => Self::Report,
+            | (DapVersion::Draft04 | DapVersion::Draft09, Some(MEDIA_TYPE_REPORT)) => Self::Report,
             (_, Some(content_type)) => Self::Invalid(content_type.to_string()),
             (_, None) => Self::Missing,
         }
@@ -102,15 +149,19 @@ impl DapMediaType {
             (DapVersion::Draft02, Self::AggregationJobInitReq) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_INIT_REQ)
             }
-            (DapVersion::Draft04, Self::AggregationJobInitReq) => Some(MEDIA_TYPE_AGG_JOB_INIT_REQ),
+            (DapVersion::Draft04 | DapVersion::Draft09, Self::AggregationJobInitReq) => {
+                Some(MEDIA_TYPE_AGG_JOB_INIT_REQ)
+            }
             (DapVersion::Draft02, Self::AggregationJobResp) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_INIT_RESP)
             }
-            (DapVersion::Draft04, Self::AggregationJobResp) => Some(MEDIA_TYPE_AGG_JOB_RESP),
+            (DapVersion::Draft04 | DapVersion::Draft09, Self::AggregationJobResp) => {
+                Some(MEDIA_TYPE_AGG_JOB_RESP)
+            }
             (DapVersion::Draft02, Self::AggregationJobContinueReq) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_CONT_REQ)
             }
-            (DapVersion::Draft04, Self::AggregationJobContinueReq) => {
+            (DapVersion::Draft04 | DapVersion::Draft09, Self::AggregationJobContinueReq) => {
                 Some(MEDIA_TYPE_AGG_JOB_CONT_REQ)
             }
             (DapVersion::Draft02, Self::Draft02AggregateContinueResp) => {
@@ -118,31 +169,51 @@ impl DapMediaType {
             }
             (_, Self::Draft02AggregateContinueResp) => None,
             (DapVersion::Draft02, Self::AggregateShareReq)
-            | (DapVersion::Draft04, Self::AggregateShareReq) => Some(MEDIA_TYPE_AGG_SHARE_REQ),
+            | (DapVersion::Draft04 | DapVersion::Draft09, Self::AggregateShareReq) => {
+                Some(MEDIA_TYPE_AGG_SHARE_REQ)
+            }
             (DapVersion::Draft02, Self::AggregateShare) => Some(DRAFT02_MEDIA_TYPE_AGG_SHARE_RESP),
-            (DapVersion::Draft04, Self::AggregateShare) => Some(MEDIA_TYPE_AGG_SHARE),
-            (DapVersion::Draft02, Self::CollectReq) | (DapVersion::Draft04, Self::CollectReq) => {
+            (DapVersion::Draft04 | DapVersion::Draft09, Self::AggregateShare) => {
+                Some(MEDIA_TYPE_AGG_SHARE)
+            }
+            (DapVersion::Draft02, Self::CollectReq)
+            | (DapVersion::Draft04 | DapVersion::Draft09, Self::CollectReq) => {
                 Some(MEDIA_TYPE_COLLECT_REQ)
             }
             (DapVersion::Draft02, Self::Collection) => Some(DRAFT02_MEDIA_TYPE_COLLECT_RESP),
-            (DapVersion::Draft04, Self::Collection) => Some(MEDIA_TYPE_COLLECTION),
+            (DapVersion::Draft04 | DapVersion::Draft09, Self::Collection) => {
+                Some(MEDIA_TYPE_COLLECTION)
+            }
             (DapVersion::Draft02, Self::HpkeConfigList) => Some(DRAFT02_MEDIA_TYPE_HPKE_CONFIG),
-            (DapVersion::Draft04, Self::HpkeConfigList) => Some(MEDIA_TYPE_HPKE_CONFIG_LIST),
-            (DapVersion::Draft02, Self::Report) | (DapVersion::Draft04, Self::Report) => {
-                Some(MEDIA_TYPE_REPORT)
+            (DapVersion::Draft04 | DapVersion::Draft09, Self::HpkeConfigList) => {
+                Some(MEDIA_TYPE_HPKE_CONFIG_LIST)
             }
+            (DapVersion::Draft02, Self::Report)
+            | (DapVersion::Draft04 | DapVersion::Draft09, Self::Report) => Some(MEDIA_TYPE_REPORT),
             (_, Self::Invalid(ref content_type)) => Some(content_type),
             (_, Self::Missing) => None,
             (DapVersion::Unknown, _) => unreachable!("unhandled version {version:?}"),
         }
     }
 
+    /// draft02 compatibility: Construct the media type for the response to an
+    /// AggregationJobInitReq. The latest draft uses the same media type for initialize and
+    /// continue responses, but draft02 represents it as "application/dap-aggregate-initialize-resp".
+    pub(crate) fn agg_job_init_resp_for_version(version: DapVersion) -> Self {
+        match version {
+            DapVersion::Draft02 | DapVersion::Draft04 | DapVersion::Draft09 => {
+                Self::AggregationJobResp
+            }
+            _ => unreachable!("unhandled version {version:?}"),
+        }
+    }
+
     /// draft02 compatibility: Construct the media type for the response to an
     /// AggregatecontinueResp. This various depending upon the version used.
     pub(crate) fn agg_job_cont_resp_for_version(version: DapVersion) -> Self {
         match version {
             DapVersion::Draft02 => Self::Draft02AggregateContinueResp,
-            DapVersion::Draft04 => Self::AggregationJobResp,
+            DapVersion::Draft04 | DapVersion::Draft09 => Self::AggregationJobResp,
             _ => unreachable!("unhandled version {version:?}"),
         }
     }
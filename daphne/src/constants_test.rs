@@ -1,7 +1,7 @@
 // Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::{constants::DapMediaType, DapVersion};
+use crate::{constants::DapMediaType, DapResponse, DapVersion};
 
 #[test]
 fn from_str_for_version() {
@@ -186,3 +186,173 @@ fn media_type_for_agg_cont_req() {
         DapMediaType::agg_job_cont_resp_for_version(DapVersion::Draft04)
     );
 }
+
+#[test]
+fn media_type_for_agg_job_resp() {
+    for (version, init_resp_content_type, cont_resp_content_type) in [
+        (
+            DapVersion::Draft02,
+            "application/dap-aggregate-initialize-resp",
+            "application/dap-aggregate-continue-resp",
+        ),
+        (
+            DapVersion::Draft04,
+            "application/dap-aggregation-job-resp",
+            "application/dap-aggregation-job-resp",
+        ),
+    ] {
+        assert_eq!(
+            DapMediaType::agg_job_init_resp_for_version(version).as_str_for_version(version),
+            Some(init_resp_content_type),
+            "unexpected init response media type for {version:?}"
+        );
+        assert_eq!(
+            DapMediaType::agg_job_cont_resp_for_version(version).as_str_for_version(version),
+            Some(cont_resp_content_type),
+            "unexpected continue response media type for {version:?}"
+        );
+    }
+}
+
+#[test]
+fn dap_response_headers() {
+    let resp = DapResponse {
+        version: DapVersion::Draft04,
+        media_type: DapMediaType::HpkeConfigList,
+        payload: Vec::new(),
+        cache_max_age: None,
+    };
+    assert_eq!(
+        resp.headers().unwrap(),
+        vec![(
+            "Content-Type",
+            "application/dap-hpke-config-list".to_string()
+        )]
+    );
+
+    let resp = DapResponse {
+        cache_max_age: Some(60),
+        ..resp
+    };
+    assert_eq!(
+        resp.headers().unwrap(),
+        vec![
+            (
+                "Content-Type",
+                "application/dap-hpke-config-list".to_string()
+            ),
+            ("Cache-Control", "max-age=60".to_string()),
+        ]
+    );
+
+    // The media type has no content-type representation for this version.
+    let resp = DapResponse {
+        version: DapVersion::Draft04,
+        media_type: DapMediaType::Draft02AggregateContinueResp,
+        payload: Vec::new(),
+        cache_max_age: None,
+    };
+    assert!(resp.headers().is_err());
+}
+
+#[test]
+fn dap_response_headers_for_version() {
+    let cases = [
+        (
+            DapVersion::Draft02,
+            DapMediaType::Report,
+            "application/dap-report",
+        ),
+        (
+            DapVersion::Draft02,
+            DapMediaType::Collection,
+            "application/dap-collect-resp",
+        ),
+        (
+            DapVersion::Draft02,
+            DapMediaType::HpkeConfigList,
+            "application/dap-hpke-config",
+        ),
+        (
+            DapVersion::Draft04,
+            DapMediaType::Report,
+            "application/dap-report",
+        ),
+        (
+            DapVersion::Draft04,
+            DapMediaType::Collection,
+            "application/dap-collection",
+        ),
+        (
+            DapVersion::Draft04,
+            DapMediaType::HpkeConfigList,
+            "application/dap-hpke-config-list",
+        ),
+    ];
+
+    for (version, media_type, content_type) in cases {
+        let resp = DapResponse {
+            version,
+            media_type,
+            payload: Vec::new(),
+            cache_max_age: None,
+        };
+        assert_eq!(
+            resp.headers().unwrap(),
+            vec![("Content-Type", content_type.to_string())],
+            "unexpected headers for version {version:?}"
+        );
+    }
+
+    // There is no way to indicate an unknown version.
+    assert!(DapVersion::Unknown.response_version_header().is_err());
+}
+
+#[test]
+fn draft09_media_types() {
+    for media_type in [
+        DapMediaType::AggregationJobInitReq,
+        DapMediaType::AggregationJobResp,
+        DapMediaType::AggregationJobContinueReq,
+        DapMediaType::AggregateShareReq,
+        DapMediaType::AggregateShare,
+        DapMediaType::CollectReq,
+        DapMediaType::Collection,
+        DapMediaType::HpkeConfigList,
+        DapMediaType::Report,
+    ] {
+        let content_type = media_type.as_str_for_version(DapVersion::Draft09);
+        assert_eq!(
+            content_type,
+            media_type.as_str_for_version(DapVersion::Draft04)
+        );
+        assert_eq!(
+            DapMediaType::from_str_for_version(DapVersion::Draft09, content_type),
+            media_type
+        );
+    }
+}
+
+#[test]
+fn draft09_is_opt_in() {
+    assert!(!DapVersion::Draft09.is_fully_supported());
+    assert_eq!(DapVersion::Draft09.as_ref(), "v09");
+
+    #[cfg(not(feature = "draft09"))]
+    {
+        assert_eq!(DapVersion::from("v09"), DapVersion::Unknown);
+        assert_eq!(
+            serde_json::from_str::<DapVersion>("\"v09\"").unwrap(),
+            DapVersion::Unknown
+        );
+    }
+
+    #[cfg(feature = "draft09")]
+    {
+        assert_eq!(DapVersion::from("v09"), DapVersion::Draft09);
+        assert_eq!(
+            serde_json::from_str::<DapVersion>("\"v09\"").unwrap(),
+            DapVersion::Draft09
+        );
+    }
+}
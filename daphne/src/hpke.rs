@@ -14,14 +14,15 @@ use hpke_rs_rust_crypto::HpkeRustCrypto as ImplHpkeCrypto;
 use crate::{
     messages::{
         decode_u16_bytes, encode_u16_bytes, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId,
-        HpkeKemId, TaskId, TransitionFailure,
+        HpkeKemId, TaskId, Time, TransitionFailure,
     },
     DapError, DapVersion,
 };
 use async_trait::async_trait;
 use prio::codec::{CodecError, Decode, Encode};
+use rand::{seq::IteratorRandom, Rng};
 use serde::{Deserialize, Serialize};
-use std::io::Cursor;
+use std::{collections::HashSet, io::Cursor};
 
 impl From<HpkeError> for DapError {
     fn from(_e: HpkeError) -> Self {
@@ -102,6 +103,21 @@ pub trait HpkeDecrypter<'a> {
         task_id: Option<&TaskId>,
     ) -> Result<Self::WrappedHpkeConfig, DapError>;
 
+    /// Look up the HPKE configuration with the given ID for the given task ID (if specified).
+    /// Returns `None` if there is no such config.
+    async fn get_hpke_config_by_id(
+        &'a self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+        hpke_config_id: u8,
+    ) -> Result<Option<Self::WrappedHpkeConfig>, DapError>;
+
+    /// Return the time (in seconds since the beginning of UNIX time) at which the given HPKE
+    /// config expires, if any. This bounds how long Clients may cache the config.
+    fn hpke_config_not_after(&self, _hpke_config: &Self::WrappedHpkeConfig) -> Option<Time> {
+        None
+    }
+
     /// Returns `true` if a ciphertext with the HPKE config ID can be consumed in the current task.
     async fn can_hpke_decrypt(&self, task_id: &TaskId, config_id: u8) -> Result<bool, DapError>;
 
@@ -121,9 +137,33 @@ pub struct HpkeReceiverConfig {
     pub config: HpkeConfig,
     #[serde(with = "HpkePrivateKeySerde")]
     private_key: HpkePrivateKey,
+
+    /// Time (in seconds since the beginning of UNIX time) at which the config may start being
+    /// advertised to Clients. If not set, then the config is valid from the time it is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<Time>,
+
+    /// Time (in seconds since the beginning of UNIX time) at which the config stops being
+    /// advertised to Clients. If not set, then the config never expires. Note that the config can
+    /// still be used to decrypt reports after it expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<Time>,
 }
 
 impl HpkeReceiverConfig {
+    /// Check whether the config may be advertised to Clients at time `now`.
+    pub fn is_valid_at(&self, now: Time) -> bool {
+        self.not_before.is_none_or(|not_before| not_before <= now)
+            && self.not_after.is_none_or(|not_after| now < not_after)
+    }
+
+    /// Check whether the config expired more than `grace_period` seconds before time `now`, in
+    /// which case it is no longer needed to decrypt reports.
+    pub fn is_expired_past_grace_period(&self, now: Time, grace_period: u64) -> bool {
+        self.not_after
+            .is_some_and(|not_after| not_after.saturating_add(grace_period) <= now)
+    }
+
     pub fn encrypt(
         &self,
         info: &[u8],
@@ -146,8 +186,19 @@ impl HpkeReceiverConfig {
             .decrypt(&self.private_key, info, aad, enc, ciphertext)
     }
 
-    /// Generate and return a new HPKE receiver context given a HPKE config ID and HPKE KEM.
+    /// Generate and return a new HPKE receiver context given a HPKE config ID and HPKE KEM. The
+    /// KDF is HKDF-SHA256 and the AEAD is AES-128-GCM.
     pub fn gen(id: u8, kem_id: HpkeKemId) -> Result<Self, DapError> {
+        Self::gen_with_suite(id, kem_id, HpkeKdfId::HkdfSha256, HpkeAeadId::Aes128Gcm)
+    }
+
+    /// Generate and return a new HPKE receiver context given a HPKE config ID, KEM, KDF, and AEAD.
+    pub fn gen_with_suite(
+        id: u8,
+        kem_id: HpkeKemId,
+        kdf_id: HpkeKdfId,
+        aead_id: HpkeAeadId,
+    ) -> Result<Self, DapError> {
         let kem = match kem_id {
             HpkeKemId::P256HkdfSha256 => KemAlgorithm::DhKemP256,
             HpkeKemId::X25519HkdfSha256 => KemAlgorithm::DhKem25519,
@@ -155,8 +206,18 @@ impl HpkeReceiverConfig {
                 return Err(DapError::Fatal(format!("Unsupported KEM ({x:?})")))
             }
         };
-        let kdf = KdfAlgorithm::HkdfSha256;
-        let aead = AeadAlgorithm::Aes128Gcm;
+        let kdf = match kdf_id {
+            HpkeKdfId::HkdfSha256 => KdfAlgorithm::HkdfSha256,
+            HpkeKdfId::NotImplemented(x) => {
+                return Err(DapError::Fatal(format!("Unsupported KDF ({x:?})")))
+            }
+        };
+        let aead = match aead_id {
+            HpkeAeadId::Aes128Gcm => AeadAlgorithm::Aes128Gcm,
+            HpkeAeadId::NotImplemented(x) => {
+                return Err(DapError::Fatal(format!("Unsupported AEAD ({x:?})")))
+            }
+        };
         let generator = Hpke::<ImplHpkeCrypto>::new(Mode::Base, kem, kdf, aead);
         match generator.generate_key_pair() {
             Ok(keypair) => {
@@ -165,11 +226,13 @@ impl HpkeReceiverConfig {
                     config: HpkeConfig {
                         id,
                         kem_id,
-                        kdf_id: HpkeKdfId::HkdfSha256,
-                        aead_id: HpkeAeadId::Aes128Gcm,
+                        kdf_id,
+                        aead_id,
                         public_key,
                     },
                     private_key,
+                    not_before: None,
+                    not_after: None,
                 })
             }
             Err(e) => Err(DapError::Fatal(format!(
@@ -177,6 +240,23 @@ impl HpkeReceiverConfig {
             ))),
         }
     }
+
+    /// Generate a new HPKE receiver context for the given KEM, KDF, and AEAD. The config ID is
+    /// chosen at random from the IDs not in `taken_config_ids`, so that the new config doesn't
+    /// collide with (and replace) an existing one. Returns an error if every config ID is taken.
+    pub fn gen_excluding<R: Rng>(
+        rng: &mut R,
+        kem_id: HpkeKemId,
+        kdf_id: HpkeKdfId,
+        aead_id: HpkeAeadId,
+        taken_config_ids: &HashSet<u8>,
+    ) -> Result<Self, DapError> {
+        let id = (0..=u8::MAX)
+            .filter(|id| !taken_config_ids.contains(id))
+            .choose(rng)
+            .ok_or_else(|| DapError::fatal("no HPKE config IDs left"))?;
+        Self::gen_with_suite(id, kem_id, kdf_id, aead_id)
+    }
 }
 
 impl TryFrom<(HpkeConfig, HpkePrivateKey)> for HpkeReceiverConfig {
@@ -194,6 +274,8 @@ impl TryFrom<(HpkeConfig, HpkePrivateKey)> for HpkeReceiverConfig {
             Ok(Self {
                 config,
                 private_key,
+                not_before: None,
+                not_after: None,
             })
         } else {
             Err(DapError::fatal("public key does not match private key"))
@@ -213,6 +295,15 @@ impl<'a> HpkeDecrypter<'a> for HpkeReceiverConfig {
         unreachable!("not implemented");
     }
 
+    async fn get_hpke_config_by_id(
+        &'a self,
+        _version: DapVersion,
+        _task_id: Option<&TaskId>,
+        hpke_config_id: u8,
+    ) -> Result<Option<Self::WrappedHpkeConfig>, DapError> {
+        Ok((self.config.id == hpke_config_id).then(|| self.config.clone()))
+    }
+
     async fn can_hpke_decrypt(&self, _task_id: &TaskId, config_id: u8) -> Result<bool, DapError> {
         Ok(config_id == self.config.id)
     }
@@ -243,6 +334,8 @@ impl Decode for HpkeReceiverConfig {
         Ok(Self {
             config: HpkeConfig::decode(bytes)?,
             private_key: HpkePrivateKey::from(decode_u16_bytes(bytes)?),
+            not_before: None,
+            not_after: None,
         })
     }
 }
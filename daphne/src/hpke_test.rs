@@ -1,11 +1,14 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::hpke::HpkeReceiverConfig;
-use crate::messages::{HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId};
+use crate::hpke::{HpkeDecrypter, HpkeReceiverConfig};
+use crate::messages::{HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, TaskId};
+use crate::DapVersion;
 use hpke_rs::{Hpke, HpkePrivateKey, HpkePublicKey, Mode};
 use hpke_rs_crypto::types::{AeadAlgorithm, KdfAlgorithm, KemAlgorithm};
 use hpke_rs_rust_crypto::HpkeRustCrypto as ImplHpkeCrypto;
+use rand::thread_rng;
+use std::collections::{HashMap, HashSet};
 
 #[test]
 fn encrypt_roundtrip_x25519_hkdf_sha256() {
@@ -65,3 +68,109 @@ fn hpke_receiver_config_try_from() {
     let bad_private_key = HpkePrivateKey::from(vec![0; 20]);
     assert!(HpkeReceiverConfig::try_from((config, bad_private_key)).is_err());
 }
+
+#[test]
+fn hpke_receiver_config_validity_window() {
+    let mut config = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256).unwrap();
+    assert!(config.is_valid_at(0));
+    assert!(!config.is_expired_past_grace_period(u64::MAX, 0));
+
+    // Configs stored without a validity window are still accepted.
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(!json.contains("not_before"));
+    assert_eq!(
+        serde_json::from_str::<HpkeReceiverConfig>(&json).unwrap(),
+        config
+    );
+
+    config.not_before = Some(1000);
+    config.not_after = Some(2000);
+    assert!(!config.is_valid_at(999));
+    assert!(config.is_valid_at(1000));
+    assert!(config.is_valid_at(1999));
+    assert!(!config.is_valid_at(2000));
+    assert!(!config.is_expired_past_grace_period(2999, 1000));
+    assert!(config.is_expired_past_grace_period(3000, 1000));
+
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(
+        serde_json::from_str::<HpkeReceiverConfig>(&json).unwrap(),
+        config
+    );
+}
+
+#[tokio::test]
+async fn hpke_receiver_config_gen_excluding_avoids_collision() {
+    let info = b"info string";
+    let aad = b"associated data";
+    let plaintext = b"plaintext";
+    let task_id = TaskId([1; 32]);
+
+    // Stored configs, keyed by config ID. An X25519 config is already stored under ID 7.
+    let mut stored = HashMap::new();
+    stored.insert(
+        7,
+        HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap(),
+    );
+
+    // Generate a P-256 config when all IDs but 7 and 8 are taken. ID 7 collides with the existing
+    // config, so ID 8 must be chosen.
+    let mut taken_config_ids: HashSet<u8> = (0..=u8::MAX).filter(|id| *id != 8).collect();
+    let config = HpkeReceiverConfig::gen_excluding(
+        &mut thread_rng(),
+        HpkeKemId::P256HkdfSha256,
+        HpkeKdfId::HkdfSha256,
+        HpkeAeadId::Aes128Gcm,
+        &taken_config_ids,
+    )
+    .unwrap();
+    assert_eq!(config.config.id, 8);
+    assert!(stored.insert(config.config.id, config).is_none());
+
+    // Reports encrypted under either config can be decrypted.
+    for (id, config) in stored.iter() {
+        let (enc, payload) = config.encrypt(info, aad, plaintext).unwrap();
+        let ciphertext = HpkeCiphertext {
+            config_id: *id,
+            enc,
+            payload,
+        };
+        assert_eq!(
+            stored[&ciphertext.config_id]
+                .hpke_decrypt(&task_id, info, aad, &ciphertext)
+                .await
+                .unwrap(),
+            plaintext
+        );
+    }
+
+    // Once every ID is taken, no more configs can be generated.
+    taken_config_ids.insert(8);
+    assert!(HpkeReceiverConfig::gen_excluding(
+        &mut thread_rng(),
+        HpkeKemId::X25519HkdfSha256,
+        HpkeKdfId::HkdfSha256,
+        HpkeAeadId::Aes128Gcm,
+        &taken_config_ids
+    )
+    .is_err());
+}
+
+#[tokio::test]
+async fn hpke_receiver_config_get_hpke_config_by_id() {
+    let config = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256).unwrap();
+    assert_eq!(
+        config
+            .get_hpke_config_by_id(DapVersion::Draft04, None, 23)
+            .await
+            .unwrap(),
+        Some(config.config.clone())
+    );
+    assert_eq!(
+        config
+            .get_hpke_config_by_id(DapVersion::Draft04, None, 24)
+            .await
+            .unwrap(),
+        None
+    );
+}
@@ -39,8 +39,8 @@ use crate::{
     hpke::HpkeReceiverConfig,
     messages::{
         AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId,
-        Draft02AggregationJobId, Duration, HpkeConfig, HpkeKemId, Interval, PartialBatchSelector,
-        ReportId, ReportMetadata, TaskId, Time, TransitionFailure,
+        Draft02AggregationJobId, Duration, Extension, HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId,
+        Interval, PartialBatchSelector, ReportId, ReportMetadata, TaskId, Time, TransitionFailure,
     },
     taskprov::TaskprovVersion,
     vdaf::{
@@ -71,6 +71,12 @@ pub enum DapError {
     #[error("fatal error: {0}")]
     Fatal(String),
 
+    /// Failure of the storage backend, e.g., a key-value store that could not be reached. Unlike
+    /// `Fatal`, this does not indicate a bug, but it is treated the same way if it triggers an
+    /// abort.
+    #[error("storage error: {0}")]
+    Storage(String),
+
     /// Error triggered by peer, resulting in an abort.
     #[error("abort: {0}")]
     Abort(DapAbort),
@@ -79,6 +85,10 @@ pub enum DapError {
     /// certain conditions, trigger an abort.
     #[error("transition error: {0}")]
     Transition(TransitionFailure),
+
+    /// Too many requests. The request may be retried after the indicated number of seconds.
+    #[error("rate limited: retry after {retry_after}s")]
+    RateLimited { retry_after: Duration },
 }
 
 impl DapError {
@@ -86,6 +96,11 @@ impl DapError {
     pub fn fatal(s: &'static str) -> Self {
         Self::Fatal(s.into())
     }
+
+    /// Returns true if the operation that produced this error may succeed if retried later.
+    pub fn is_retriable(&self) -> bool {
+        matches!(self, Self::RateLimited { .. })
+    }
 }
 
 impl From<prometheus::Error> for DapError {
@@ -131,6 +146,12 @@ pub enum DapVersion {
     #[serde(rename = "v04")]
     Draft04,
 
+    /// draft-ietf-ppm-dap-09. Support for this version is incomplete and only enabled with the
+    /// "draft09" feature. Otherwise the version is not recognized.
+    #[cfg_attr(feature = "draft09", serde(rename = "v09"))]
+    #[cfg_attr(not(feature = "draft09"), serde(skip))]
+    Draft09,
+
     #[serde(other)]
     #[serde(rename = "unknown_version")]
     Unknown,
@@ -141,6 +162,8 @@ impl From<&str> for DapVersion {
         match version {
             "v02" => DapVersion::Draft02,
             "v04" => DapVersion::Draft04,
+            #[cfg(feature = "draft09")]
+            "v09" => DapVersion::Draft09,
             _ => DapVersion::Unknown,
         }
     }
@@ -151,6 +174,7 @@ impl AsRef<str> for DapVersion {
         match self {
             DapVersion::Draft02 => "v02",
             DapVersion::Draft04 => "v04",
+            DapVersion::Draft09 => "v09",
             _ => panic!("tried to construct string from unknown DAP version"),
         }
     }
@@ -162,6 +186,27 @@ impl std::fmt::Display for DapVersion {
     }
 }
 
+impl DapVersion {
+    /// Get the HTTP header, if any, that a response must carry to indicate the version of the
+    /// protocol. For the versions supported so far, the version is implied by the content type and
+    /// the request path, so no header is sent. Returns an error if the version is unknown.
+    pub fn response_version_header(&self) -> Result<Option<(&'static str, String)>, DapError> {
+        match self {
+            DapVersion::Draft02 | DapVersion::Draft04 | DapVersion::Draft09 => Ok(None),
+            DapVersion::Unknown => Err(DapError::Fatal(format!(
+                "no version header for version {self:?}"
+            ))),
+        }
+    }
+
+    /// Return `true` if the aggregation, upload, and collection flows are implemented for this
+    /// version. Requests for versions that are recognized but for which these flows are not yet
+    /// implemented (i.e., draft09) are aborted with "unsupportedVersion".
+    pub fn is_fully_supported(&self) -> bool {
+        matches!(self, DapVersion::Draft02 | DapVersion::Draft04)
+    }
+}
+
 /// Global DAP parameters common across tasks.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct DapGlobalConfig {
@@ -198,39 +243,124 @@ pub struct DapGlobalConfig {
     /// receiver config.
     pub supported_hpke_kems: Vec<HpkeKemId>,
 
+    /// HPKE KDF types that are allowed. HPKE receiver configs are generated with the first element
+    /// of this list, and ciphertexts encrypted under a config with any other KDF are rejected.
+    #[serde(default = "default_supported_hpke_kdfs")]
+    pub supported_hpke_kdfs: Vec<HpkeKdfId>,
+
+    /// HPKE AEAD types that are allowed. HPKE receiver configs are generated with the first
+    /// element of this list, and ciphertexts encrypted under a config with any other AEAD are
+    /// rejected.
+    #[serde(default = "default_supported_hpke_aeads")]
+    pub supported_hpke_aeads: Vec<HpkeAeadId>,
+
     /// Is the taskprov extension allowed?
     pub allow_taskprov: bool,
 
     /// Which taskprov draft should be used?
     pub taskprov_version: TaskprovVersion,
+
+    /// If set, then the number of rejected reports, broken down by failure reason, is persisted
+    /// at most once per this many seconds. This is useful for post-mortem analysis of rejection
+    /// patterns. By default, rejections are only counted in the Aggregator's metrics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejection_counts_flush_interval: Option<Duration>,
+
+    /// If set, then the number of buckets spanned by the batch selector of a collect request may
+    /// not exceed this value. This bounds the number of aggregate shares that need to be fetched
+    /// in order to complete the collection job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_span: Option<u64>,
+
+    /// Helper: If set, then aggregation jobs may not contain more than this many reports. This
+    /// bounds the memory and compute the Helper commits to each request. The limit may be
+    /// overridden for individual tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reports_per_agg_job: Option<u64>,
+
+    /// Maximum number of seconds for which Clients may cache an HPKE config. If the config expires
+    /// sooner than this, then the remainder of its validity window is used instead.
+    #[serde(default = "default_hpke_config_max_age")]
+    pub hpke_config_max_age: Duration,
+}
+
+fn default_hpke_config_max_age() -> Duration {
+    // One hour
+    3600
+}
+
+fn default_supported_hpke_kdfs() -> Vec<HpkeKdfId> {
+    vec![HpkeKdfId::HkdfSha256]
+}
+
+fn default_supported_hpke_aeads() -> Vec<HpkeAeadId> {
+    vec![HpkeAeadId::Aes128Gcm]
 }
 
 impl DapGlobalConfig {
     /// Generate a list of HPKE receiver configurations, one for each element of supported KEM
     /// algorithm. `first_config_id` is used as the first config ID; subsequent IDs are chosen by
-    /// incrementing `first_config_id`.
+    /// incrementing `first_config_id`. The KDF and AEAD are chosen by
+    /// [`hpke_kdf_and_aead`](Self::hpke_kdf_and_aead).
     pub fn gen_hpke_receiver_config_list(
         &self,
         first_config_id: u8,
     ) -> impl Iterator<Item = Result<HpkeReceiverConfig, DapError>> {
         assert!(self.supported_hpke_kems.len() <= 256);
         let kem_ids = self.supported_hpke_kems.clone();
+        let kdf_and_aead = self.hpke_kdf_and_aead().map_err(|e| e.to_string());
         kem_ids.into_iter().enumerate().map(move |(i, kem_id)| {
+            let (kdf_id, aead_id) = kdf_and_aead.clone().map_err(DapError::Fatal)?;
             let (config_id, _overflowed) = first_config_id.overflowing_add(i as u8);
-            HpkeReceiverConfig::gen(config_id, kem_id)
+            HpkeReceiverConfig::gen_with_suite(config_id, kem_id, kdf_id, aead_id)
         })
     }
+
+    /// Return the KDF and AEAD with which to generate HPKE receiver configs, i.e., the first
+    /// element of each allow-list. Returns an error if either allow-list is empty.
+    pub fn hpke_kdf_and_aead(&self) -> Result<(HpkeKdfId, HpkeAeadId), DapError> {
+        let kdf_id = self.supported_hpke_kdfs.first().ok_or_else(|| {
+            DapError::fatal("cannot generate HPKE receiver config: no HPKE KDF is allowed")
+        })?;
+        let aead_id = self.supported_hpke_aeads.first().ok_or_else(|| {
+            DapError::fatal("cannot generate HPKE receiver config: no HPKE AEAD is allowed")
+        })?;
+        Ok((*kdf_id, *aead_id))
+    }
+
+    /// Check whether the KDF and AEAD of `hpke_config` are both allowed. Ciphertexts encrypted
+    /// under a config that is not allowed must not be decrypted.
+    pub fn allows_hpke_config(&self, hpke_config: &HpkeConfig) -> bool {
+        self.supported_hpke_kdfs.contains(&hpke_config.kdf_id)
+            && self.supported_hpke_aeads.contains(&hpke_config.aead_id)
+    }
+
+    /// Check whether a collect request whose batch selector spans `bucket_count` buckets is
+    /// permitted by `max_batch_span`.
+    pub fn permits_batch_span(&self, bucket_count: u64) -> bool {
+        self.max_batch_span
+            .is_none_or(|max_batch_span| bucket_count <= max_batch_span)
+    }
 }
 
 /// DAP Query configuration.
 //
 // TODO(cjpatton) Once we implement maximum batch lifetime, put the parameter here.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-#[serde(rename_all = "snake_case")]
+#[serde(
+    rename_all = "snake_case",
+    from = "DapQueryConfigCompat",
+    into = "DapQueryConfigCompat"
+)]
 pub enum DapQueryConfig {
     /// The "time-interval" query type. Each report in the batch must fall into the time interval
     /// specified by the query.
-    TimeInterval,
+    TimeInterval {
+        /// Duration of the buckets into which reports are aggregated. This must be a multiple of
+        /// the task's `time_precision`, which is used if not set. The boundaries of each queried
+        /// batch interval must be aligned to this duration.
+        bucket_duration: Option<Duration>,
+    },
 
     /// The "fixed-size" query type. The Leader partitions the reports into arbitary batches of
     /// roughly the same size.
@@ -251,7 +381,8 @@ impl DapQueryConfig {
         )
     }
 
-    pub(crate) fn is_valid_batch_sel(&self, batch_sel: &BatchSelector) -> bool {
+    /// Check whether the batch selector has the same query type.
+    pub fn is_valid_batch_sel(&self, batch_sel: &BatchSelector) -> bool {
         matches!(
             (&self, batch_sel),
             (
@@ -265,10 +396,71 @@ impl DapQueryConfig {
     }
 }
 
+/// Serialization helper for [`DapQueryConfig`]. Task configs stored before the time-interval
+/// query type had any parameters encode it as a unit variant. This encoding is kept for
+/// time-interval tasks that use the default bucket duration, so that their configs can still be
+/// read by older versions.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum DapQueryConfigCompat {
+    Unit(DapQueryConfigUnit),
+    Current(DapQueryConfigCurrent),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DapQueryConfigUnit {
+    TimeInterval,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DapQueryConfigCurrent {
+    TimeInterval {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bucket_duration: Option<Duration>,
+    },
+    FixedSize {
+        max_batch_size: u64,
+    },
+}
+
+impl From<DapQueryConfigCompat> for DapQueryConfig {
+    fn from(compat: DapQueryConfigCompat) -> Self {
+        match compat {
+            DapQueryConfigCompat::Unit(DapQueryConfigUnit::TimeInterval) => Self::TimeInterval {
+                bucket_duration: None,
+            },
+            DapQueryConfigCompat::Current(DapQueryConfigCurrent::TimeInterval {
+                bucket_duration,
+            }) => Self::TimeInterval { bucket_duration },
+            DapQueryConfigCompat::Current(DapQueryConfigCurrent::FixedSize { max_batch_size }) => {
+                Self::FixedSize { max_batch_size }
+            }
+        }
+    }
+}
+
+impl From<DapQueryConfig> for DapQueryConfigCompat {
+    fn from(query_config: DapQueryConfig) -> Self {
+        match query_config {
+            DapQueryConfig::TimeInterval {
+                bucket_duration: None,
+            } => Self::Unit(DapQueryConfigUnit::TimeInterval),
+            DapQueryConfig::TimeInterval { bucket_duration } => {
+                Self::Current(DapQueryConfigCurrent::TimeInterval { bucket_duration })
+            }
+            DapQueryConfig::FixedSize { max_batch_size } => {
+                Self::Current(DapQueryConfigCurrent::FixedSize { max_batch_size })
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for DapQueryConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::TimeInterval => write!(f, "time_interval"),
+            Self::TimeInterval { .. } => write!(f, "time_interval"),
             Self::FixedSize { .. } => write!(f, "fixed_size"),
         }
     }
@@ -278,14 +470,45 @@ impl std::fmt::Display for DapQueryConfig {
 ///
 /// A bucket is the smallest, disjoint set of reports that can be queried: For time-interval
 /// queries, the bucket to which a report is assigned is determined by truncating its timestamp by
-/// the task's `time_precision` parameter; for fixed-size queries, the span consists of a single
+/// the task's bucket duration (see [`DapTaskConfig::bucket_duration`]); for fixed-size queries, the span consists of a single
 /// bucket, which is the batch determined by the batch ID (i.e., the partial batch selector).
-#[derive(Clone, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum DapBatchBucket<'a> {
     FixedSize { batch_id: &'a BatchId },
     TimeInterval { batch_window: Time },
 }
 
+/// The batch span determined by a batch selector, i.e., the list of buckets to which a report
+/// that matches the batch selector could be assigned. The span is computed once per request and
+/// shared by each operation on the batch.
+#[derive(Clone)]
+pub struct DapBatchSpan<'a> {
+    batch_sel: &'a BatchSelector,
+    buckets: Vec<DapBatchBucket<'a>>,
+}
+
+impl<'a> DapBatchSpan<'a> {
+    /// The batch selector that determines the span.
+    pub fn batch_sel(&self) -> &'a BatchSelector {
+        self.batch_sel
+    }
+
+    /// The buckets in the span.
+    pub fn buckets(&self) -> &[DapBatchBucket<'a>] {
+        &self.buckets
+    }
+
+    /// The number of buckets in the span.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Whether the span contains no buckets.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
 /// Per-task DAP parameters.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct DapTaskConfig {
@@ -319,6 +542,65 @@ pub struct DapTaskConfig {
 
     /// The Collector's HPKE configuration for this task.
     pub collector_hpke_config: HpkeConfig,
+
+    /// Leader: If set, then the rate at which reports may be uploaded for this task is limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_rate_limit: Option<DapRateLimit>,
+
+    /// Helper: If set, then aggregation jobs for this task may not contain more than this many
+    /// reports. This overrides the limit set in the global configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reports_per_agg_job: Option<u64>,
+
+    /// Leader: If set, then uploads for this task must carry the Client's bearer token. By
+    /// default, Clients are not required to authorize their requests.
+    #[serde(default)]
+    pub client_auth: bool,
+
+    /// If set, then the Aggregator uses HPKE receiver configs scoped to this task rather than the
+    /// configs it uses for all tasks. By default, the global configs are used.
+    #[serde(default)]
+    pub task_scoped_hpke_config: bool,
+
+    /// Leader: If set, then the task is being decommissioned. New uploads are rejected, but
+    /// reports that were already uploaded are still aggregated and may be collected.
+    #[serde(default)]
+    pub quiescing: bool,
+
+    /// Leader: If set, then reports that have been pending aggregation for longer than this many
+    /// seconds are dropped rather than aggregated. By default, the report storage epoch duration
+    /// from the global configuration is used, i.e., the window outside of which reports would be
+    /// rejected anyway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_report_ttl: Option<Duration>,
+
+    /// Leader: If set, then Collectors polling a collection job for this task that is still
+    /// pending are asked to wait this many seconds before polling again. This overrides the value
+    /// set by the Aggregator's configuration, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collect_poll_retry_after: Option<Duration>,
+
+    /// Type codes of the report extensions, other than taskprov, that Clients may use for this
+    /// task. Reports carrying any other extension are rejected. By default, no such extensions are
+    /// permitted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permitted_extensions: Vec<u16>,
+
+    /// Leader: If set, then reports for time-interval tasks are rejected if the bucket they fall
+    /// into has not yet started, regardless of `report_storage_max_future_time_skew`.
+    #[serde(default)]
+    pub reject_future_buckets: bool,
+}
+
+/// Token bucket parameters for rate limiting requests.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct DapRateLimit {
+    /// The number of requests permitted per second, on average.
+    pub rate: u64,
+
+    /// The maximum number of requests permitted in a burst.
+    pub burst: u64,
 }
 
 impl DapTaskConfig {
@@ -335,6 +617,32 @@ impl DapTaskConfig {
         }
     }
 
+    /// Check that a report's extensions are valid for this task. Each extension type may appear
+    /// at most once, and every extension other than taskprov must be listed in
+    /// `permitted_extensions`.
+    pub fn check_extensions(&self, extensions: &[Extension]) -> Result<(), TransitionFailure> {
+        let mut seen = HashSet::with_capacity(extensions.len());
+        for extension in extensions {
+            if !seen.insert(extension.type_code()) {
+                return Err(TransitionFailure::UnrecognizedMessage);
+            }
+            if let Extension::Unhandled { typ, .. } = extension {
+                if !self.permitted_extensions.contains(typ) {
+                    return Err(TransitionFailure::UnrecognizedMessage);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return `true` if a report with the given timestamp falls into a bucket that starts after
+    /// `now`. This only applies to time-interval tasks that set `reject_future_buckets`.
+    pub fn is_in_future_bucket(&self, time: Time, now: Time) -> bool {
+        self.reject_future_buckets
+            && matches!(self.query, DapQueryConfig::TimeInterval { .. })
+            && self.bucket_window(time) > now
+    }
+
     /// Return the greatest multiple of the time_precision which is less than or equal to the
     /// specified time.
     pub fn quantized_time_lower_bound(&self, time: Time) -> Time {
@@ -346,31 +654,93 @@ impl DapTaskConfig {
         self.quantized_time_lower_bound(time) + self.time_precision
     }
 
+    /// Return the duration of the buckets into which reports are aggregated for time-interval
+    /// queries. Unless configured otherwise, this is the task's `time_precision`. An invalid
+    /// bucket duration (see [`Self::is_bucket_duration_valid`]) is ignored, so that a bad config
+    /// cannot cause a division by zero or misaligned buckets.
+    pub fn bucket_duration(&self) -> Duration {
+        match self.query {
+            DapQueryConfig::TimeInterval {
+                bucket_duration: Some(bucket_duration),
+            } if self.is_bucket_duration_valid() => bucket_duration,
+            _ => self.time_precision,
+        }
+    }
+
+    /// Return the start of the bucket to which a report with the given timestamp is assigned for
+    /// time-interval queries.
+    pub fn bucket_window(&self, time: Time) -> Time {
+        time - (time % self.bucket_duration())
+    }
+
+    /// Check that the configured bucket duration, if any, is a positive multiple of the time
+    /// precision.
+    pub fn is_bucket_duration_valid(&self) -> bool {
+        match self.query {
+            DapQueryConfig::TimeInterval {
+                bucket_duration: Some(bucket_duration),
+            } => bucket_duration > 0 && bucket_duration.checked_rem(self.time_precision) == Some(0),
+            _ => true,
+        }
+    }
+
+    /// Leader: Check whether a pending report with the given timestamp has expired as of `now`.
+    pub fn is_pending_report_expired(
+        &self,
+        global_config: &DapGlobalConfig,
+        report_time: Time,
+        now: Time,
+    ) -> bool {
+        let ttl = self
+            .pending_report_ttl
+            .unwrap_or(global_config.report_storage_epoch_duration);
+        report_time < now.saturating_sub(ttl)
+    }
+
     /// Compute the "batch span" of a set of output shares and, for each buckent in the span,
-    /// aggregate the output shares into an aggregate share.
+    /// aggregate the output shares into an aggregate share. Returns an error if the partial batch
+    /// selector is not compatible with the task's query type.
     pub fn batch_span_for_out_shares<'a>(
         &self,
         part_batch_sel: &'a PartialBatchSelector,
         out_shares: Vec<DapOutputShare>,
     ) -> Result<HashMap<DapBatchBucket<'a>, DapAggregateShare>, DapError> {
+        Ok(self
+            .batch_span_for_out_shares_with_checksums(part_batch_sel, out_shares)?
+            .into_iter()
+            .map(|(bucket, (agg_share, _report_checksums))| (bucket, agg_share))
+            .collect())
+    }
+
+    /// Like `batch_span_for_out_shares()`, except that each bucket's aggregate share is
+    /// accompanied by the checksums of the reports aggregated into it. These allow the aggregate
+    /// store to detect when a report would be counted more than once.
+    #[allow(clippy::type_complexity)]
+    pub fn batch_span_for_out_shares_with_checksums<'a>(
+        &self,
+        part_batch_sel: &'a PartialBatchSelector,
+        out_shares: Vec<DapOutputShare>,
+    ) -> Result<HashMap<DapBatchBucket<'a>, (DapAggregateShare, Vec<[u8; 32]>)>, DapError> {
         if !self.query.is_valid_part_batch_sel(part_batch_sel) {
             return Err(DapError::fatal(
                 "partial batch selector not compatible with task",
             ));
         }
 
-        let mut span: HashMap<DapBatchBucket<'a>, DapAggregateShare> = HashMap::new();
+        let mut span: HashMap<DapBatchBucket<'a>, (DapAggregateShare, Vec<[u8; 32]>)> =
+            HashMap::new();
         for out_share in out_shares.into_iter() {
             let bucket = match part_batch_sel {
                 PartialBatchSelector::TimeInterval => DapBatchBucket::TimeInterval {
-                    batch_window: self.quantized_time_lower_bound(out_share.time),
+                    batch_window: self.bucket_window(out_share.time),
                 },
                 PartialBatchSelector::FixedSizeByBatchId { batch_id } => {
                     DapBatchBucket::FixedSize { batch_id }
                 }
             };
 
-            let agg_share = span.entry(bucket).or_default();
+            let (agg_share, report_checksums) = span.entry(bucket).or_default();
+            report_checksums.push(out_share.checksum);
             agg_share.merge(DapAggregateShare {
                 report_count: 1,
                 min_time: out_share.time,
@@ -397,11 +767,12 @@ impl DapTaskConfig {
             BatchSelector::TimeInterval {
                 batch_interval: Interval { start, duration },
             } => {
-                let windows = duration / self.time_precision;
+                let bucket_duration = self.bucket_duration();
+                let windows = duration / bucket_duration;
                 let mut span = HashSet::with_capacity(windows as usize);
                 for i in 0..windows {
                     span.insert(DapBatchBucket::TimeInterval {
-                        batch_window: start + i * self.time_precision,
+                        batch_window: start + i * bucket_duration,
                     });
                 }
                 Ok(span)
@@ -412,6 +783,17 @@ impl DapTaskConfig {
         }
     }
 
+    /// Like `batch_span_for_sel()`, except that the buckets are returned in a form that can be
+    /// passed to each [`DapAggregator`](crate::roles::DapAggregator) method that operates on the
+    /// batch, so that the span need only be computed once.
+    pub fn batch_span<'a>(
+        &self,
+        batch_sel: &'a BatchSelector,
+    ) -> Result<DapBatchSpan<'a>, DapError> {
+        let buckets = self.batch_span_for_sel(batch_sel)?.into_iter().collect();
+        Ok(DapBatchSpan { batch_sel, buckets })
+    }
+
     /// Return the batch span of a set of reports with the given metadata.
     pub fn batch_span_for_meta<'a>(
         &self,
@@ -428,7 +810,7 @@ impl DapTaskConfig {
         for metadata in report_meta {
             let bucket = match part_batch_sel {
                 PartialBatchSelector::TimeInterval => DapBatchBucket::TimeInterval {
-                    batch_window: self.quantized_time_lower_bound(metadata.time),
+                    batch_window: self.bucket_window(metadata.time),
                 },
                 PartialBatchSelector::FixedSizeByBatchId { batch_id } => {
                     DapBatchBucket::FixedSize { batch_id }
@@ -449,7 +831,7 @@ impl DapTaskConfig {
         report_count: u64,
     ) -> Result<bool, DapAbort> {
         match self.query {
-            DapQueryConfig::TimeInterval => (),
+            DapQueryConfig::TimeInterval { .. } => (),
             DapQueryConfig::FixedSize { max_batch_size } => {
                 if report_count > max_batch_size {
                     return Err(DapAbort::InvalidBatchSize {
@@ -833,6 +1215,34 @@ pub struct DapResponse {
     pub version: DapVersion,
     pub media_type: DapMediaType,
     pub payload: Vec<u8>,
+
+    /// If set, then the response may be cached by the client for this many seconds.
+    pub cache_max_age: Option<Duration>,
+}
+
+impl DapResponse {
+    /// Get the HTTP headers to send with the response. This includes the content type and any
+    /// header required by the DAP version (see [`DapVersion::response_version_header`]).
+    pub fn headers(&self) -> Result<Vec<(&'static str, String)>, DapError> {
+        let content_type = self
+            .media_type
+            .as_str_for_version(self.version)
+            .ok_or_else(|| {
+                DapError::Fatal(format!(
+                    "failed to construct content-type for media type {:?} and version {:?}",
+                    self.media_type, self.version
+                ))
+            })?;
+
+        let mut headers = vec![("Content-Type", content_type.to_string())];
+        if let Some(version_header) = self.version.response_version_header()? {
+            headers.push(version_header);
+        }
+        if let Some(max_age) = self.cache_max_age {
+            headers.push(("Cache-Control", format!("max-age={max_age}")));
+        }
+        Ok(headers)
+    }
 }
 
 /// Status of a collect job.
@@ -841,6 +1251,11 @@ pub struct DapResponse {
 pub enum DapCollectJob {
     Done(Collection),
     Pending,
+    /// The collect job finished, but its result has since been deleted.
+    Expired,
+    /// The collect job was abandoned because its batch overlaps a batch that was collected by
+    /// another job.
+    Abandoned,
     Unknown,
 }
 
@@ -876,7 +1291,9 @@ impl MetaAggregationJobId<'_> {
         match version {
             DapVersion::Draft02 => Self::Draft02(Cow::Owned(Draft02AggregationJobId(rng.gen()))),
             DapVersion::Draft04 => Self::Draft04(Cow::Owned(AggregationJobId(rng.gen()))),
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         }
     }
 
@@ -919,6 +1336,8 @@ impl MetaAggregationJobId<'_> {
 }
 
 pub mod aborts;
+#[cfg(test)]
+mod aborts_test;
 pub mod auth;
 pub mod constants;
 #[cfg(test)]
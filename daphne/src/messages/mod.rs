@@ -12,7 +12,6 @@ use prio::codec::{
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
     convert::{TryFrom, TryInto},
     fmt,
     io::{Cursor, Read},
@@ -102,7 +101,9 @@ impl TaskId {
         match version {
             DapVersion::Draft02 => Some(self.clone()),
             DapVersion::Draft04 => None,
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         }
     }
 }
@@ -123,7 +124,7 @@ pub enum Extension {
 
 impl Extension {
     /// Return the type code associated with the extension
-    fn type_code(&self) -> u16 {
+    pub(crate) fn type_code(&self) -> u16 {
         match self {
             Self::Taskprov { .. } => EXTENSION_TASKPROV,
             Self::Unhandled { typ, .. } => *typ,
@@ -185,26 +186,17 @@ impl ParameterizedDecode<DapVersion> for ReportMetadata {
         version: &DapVersion,
         bytes: &mut Cursor<&[u8]>,
     ) -> Result<Self, CodecError> {
-        let metadata = Self {
+        // Duplicate and unrecognized extensions are checked against the task config once the task
+        // is known, so that a bad extension causes only this report to be rejected. See
+        // `DapTaskConfig::check_extensions()`.
+        Ok(Self {
             id: ReportId::decode(bytes)?,
             time: Time::decode(bytes)?,
             extensions: match version {
                 DapVersion::Draft02 => decode_u16_items(&(), bytes)?,
                 _ => Vec::new(),
             },
-        };
-        // Check for duplicate extensions and unknown extensions.
-        let mut seen: HashSet<u16> = HashSet::new();
-        for extension in &metadata.extensions {
-            if !seen.insert(extension.type_code()) {
-                return Err(CodecError::UnexpectedValue);
-            }
-            if matches!(extension, Extension::Unhandled { .. }) {
-                // Unrecognized extensions are an error.
-                return Err(CodecError::UnexpectedValue);
-            }
-        }
-        Ok(metadata)
+        })
     }
 }
 
@@ -427,7 +419,9 @@ impl ParameterizedEncode<DapVersion> for AggregationJobInitReq {
                 encode_u16_bytes(bytes, &self.agg_param);
             }
             DapVersion::Draft04 => encode_u32_bytes(bytes, &self.agg_param),
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         self.part_batch_sel.encode(bytes);
         encode_u32_items(bytes, version, &self.report_shares);
@@ -446,7 +440,9 @@ impl ParameterizedDecode<DapVersion> for AggregationJobInitReq {
                 decode_u16_bytes(bytes)?,
             ),
             DapVersion::Draft04 => (None, None, decode_u32_bytes(bytes)?),
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
 
         Ok(Self {
@@ -487,7 +483,9 @@ impl ParameterizedEncode<DapVersion> for AggregationJobContinueReq {
                     .expect("draft04: missing round")
                     .encode(bytes);
             }
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         encode_u32_items(bytes, &(), &self.transitions);
     }
@@ -505,7 +503,9 @@ impl ParameterizedDecode<DapVersion> for AggregationJobContinueReq {
                 None,
             ),
             DapVersion::Draft04 => (None, None, Some(u16::decode(bytes)?)),
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         Ok(Self {
             draft02_task_id,
@@ -705,6 +705,17 @@ pub enum Query {
     FixedSizeCurrentBatch,
 }
 
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimeInterval { .. } => write!(f, "time_interval"),
+            Self::FixedSizeByBatchId { .. } | Self::FixedSizeCurrentBatch => {
+                write!(f, "fixed_size")
+            }
+        }
+    }
+}
+
 impl ParameterizedEncode<DapVersion> for Query {
     fn encode_with_param(&self, version: &DapVersion, bytes: &mut Vec<u8>) {
         match self {
@@ -788,7 +799,9 @@ impl ParameterizedEncode<DapVersion> for CollectionReq {
                     .encode(bytes);
             }
             DapVersion::Draft04 => {}
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         }
         self.query.encode_with_param(version, bytes);
         match version {
@@ -807,7 +820,9 @@ impl ParameterizedDecode<DapVersion> for CollectionReq {
         let draft02_task_id = match version {
             DapVersion::Draft02 => Some(TaskId::decode(bytes)?),
             DapVersion::Draft04 => None,
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         Ok(Self {
             draft02_task_id,
@@ -844,7 +859,9 @@ impl ParameterizedEncode<DapVersion> for Collection {
                     .expect("draft04: missing interval")
                     .encode(bytes);
             }
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         encode_u32_items(bytes, &(), &self.encrypted_agg_shares);
     }
@@ -895,7 +912,9 @@ impl ParameterizedEncode<DapVersion> for AggregateShareReq {
                 self.batch_sel.encode_with_param(version, bytes);
                 encode_u32_bytes(bytes, &self.agg_param);
             }
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         self.report_count.encode(bytes);
         bytes.extend_from_slice(&self.checksum);
@@ -918,7 +937,9 @@ impl ParameterizedDecode<DapVersion> for AggregateShareReq {
                 BatchSelector::decode_with_param(version, bytes)?,
                 decode_u32_bytes(bytes)?,
             ),
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {version:?}")
+            }
         };
         Ok(Self {
             draft02_task_id,
@@ -1161,21 +1182,12 @@ impl Encode for PlaintextInputShare {
 
 impl Decode for PlaintextInputShare {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
-        let share = Self {
+        // Extensions are checked against the task config after decoding. See
+        // `DapTaskConfig::check_extensions()`.
+        Ok(Self {
             extensions: decode_u16_items(&(), bytes)?,
             payload: decode_u32_bytes(bytes)?,
-        };
-        // Check for duplicate extensions and unknown extensions.
-        let mut seen: HashSet<u16> = HashSet::new();
-        for extension in &share.extensions {
-            if !seen.insert(extension.type_code()) {
-                return Err(CodecError::UnexpectedValue);
-            }
-            if matches!(extension, Extension::Unhandled { .. }) {
-                return Err(CodecError::UnexpectedValue);
-            }
-        }
-        Ok(share)
+        })
     }
 }
 
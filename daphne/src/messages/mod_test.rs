@@ -83,8 +83,12 @@ fn read_report_with_unknown_extensions_draft02() {
         ],
     };
     let version = DapVersion::Draft02;
-    assert!(
-        Report::get_decoded_with_param(&version, &report.get_encoded_with_param(&version)).is_err()
+
+    // Unrecognized extensions are not rejected when decoding; whether they are permitted depends on
+    // the task.
+    assert_eq!(
+        Report::get_decoded_with_param(&version, &report.get_encoded_with_param(&version)).unwrap(),
+        report
     );
 }
 
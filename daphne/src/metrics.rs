@@ -3,12 +3,16 @@
 
 //! Daphne metrics.
 
-use crate::DapError;
+use crate::{DapError, DapQueryConfig, DapVersion};
 use prometheus::{
-    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, IntCounterVec,
-    IntGaugeVec, Registry,
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry, HistogramVec, IntCounterVec, IntGaugeVec, Registry,
 };
 
+/// Metrics shared by the Leader and Helper. Metrics that are recorded by both roles are labeled
+/// with the role of the code path that recorded them, so that a deployment running both roles can
+/// tell them apart. (The label is empty for requests that are not specific to either role, such as
+/// HPKE config requests.)
 pub struct DaphneMetrics {
     /// Inbound request metrics: Successful requests served, broken down by type.
     inbound_request_counter: IntCounterVec,
@@ -19,6 +23,16 @@ pub struct DaphneMetrics {
 
     /// Helper: Number of running aggregation jobs.
     aggregation_job_gauge: IntGaugeVec,
+
+    /// Leader: Time (in seconds) taken to run an aggregation job, from the moment the reports are
+    /// fetched to the moment the output shares are committed.
+    aggregation_job_duration_histogram: HistogramVec,
+
+    /// Leader: Round-trip time (in seconds) of requests sent to the Helper.
+    helper_request_duration_histogram: HistogramVec,
+
+    /// HPKE decryption failures, broken down by cause.
+    hpke_decrypt_failure_counter: IntCounterVec,
 }
 
 impl DaphneMetrics {
@@ -34,21 +48,44 @@ impl DaphneMetrics {
         let inbound_request_counter = register_int_counter_vec_with_registry!(
             format!("{front}inbound_request_counter"),
             "Total number of successful inbound requests.",
-            &["host", "type"],
+            &["host", "role", "type"],
             registry
         )?;
 
         let report_counter = register_int_counter_vec_with_registry!(
             format!("{front}report_counter"),
             "Total number reports rejected, aggregated, and collected.",
-            &["host", "status"],
+            &["host", "role", "status"],
             registry
         )?;
 
         let aggregation_job_gauge = register_int_gauge_vec_with_registry!(
             format!("{front}aggregation_job_gauge"),
             "Number of running aggregation jobs.",
-            &["host"],
+            &["host", "role"],
+            registry
+        )?;
+
+        let aggregation_job_duration_histogram = register_histogram_vec_with_registry!(
+            format!("{front}aggregation_job_duration_seconds"),
+            "Time taken to run an aggregation job.",
+            &["host", "version", "query_type"],
+            vec![0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0],
+            registry
+        )?;
+
+        let helper_request_duration_histogram = register_histogram_vec_with_registry!(
+            format!("{front}helper_request_duration_seconds"),
+            "Round-trip time of requests sent to the Helper.",
+            &["host", "version", "query_type"],
+            vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0],
+            registry
+        )?;
+
+        let hpke_decrypt_failure_counter = register_int_counter_vec_with_registry!(
+            format!("{front}hpke_decrypt_failure_counter"),
+            "Total number of HPKE decryption failures.",
+            &["host", "role", "cause"],
             registry
         )?;
 
@@ -56,6 +93,9 @@ impl DaphneMetrics {
             inbound_request_counter,
             report_counter,
             aggregation_job_gauge,
+            aggregation_job_duration_histogram,
+            helper_request_duration_histogram,
+            hpke_decrypt_failure_counter,
         })
     }
 
@@ -63,6 +103,7 @@ impl DaphneMetrics {
         ContextualizedDaphneMetrics {
             metrics: self,
             host,
+            role: None,
         }
     }
 }
@@ -70,9 +111,26 @@ impl DaphneMetrics {
 pub struct ContextualizedDaphneMetrics<'req> {
     metrics: &'req DaphneMetrics,
     host: &'req str,
+    role: Option<DaphneRole>,
 }
 
 impl ContextualizedDaphneMetrics<'_> {
+    /// Label the metrics recorded in this context with the role of the Aggregator.
+    pub fn with_role(self, role: DaphneRole) -> Self {
+        Self {
+            role: Some(role),
+            ..self
+        }
+    }
+
+    fn role_str(&self) -> &'static str {
+        match self.role {
+            Some(DaphneRole::Leader) => "leader",
+            Some(DaphneRole::Helper) => "helper",
+            None => "",
+        }
+    }
+
     pub fn inbound_req_inc(&self, request_type: DaphneRequestType) {
         let request_type_str = match request_type {
             DaphneRequestType::HpkeConfig => "hpke_config",
@@ -83,30 +141,74 @@ impl ContextualizedDaphneMetrics<'_> {
 
         self.metrics
             .inbound_request_counter
-            .with_label_values(&[self.host, request_type_str])
+            .with_label_values(&[self.host, self.role_str(), request_type_str])
             .inc();
     }
 
     pub fn report_inc_by(&self, status: &str, val: u64) {
         self.metrics
             .report_counter
-            .with_label_values(&[self.host, status])
+            .with_label_values(&[self.host, self.role_str(), status])
             .inc_by(val);
     }
 
     pub fn agg_job_inc(&self) {
         self.metrics
             .aggregation_job_gauge
-            .with_label_values(&[self.host])
+            .with_label_values(&[self.host, self.role_str()])
             .inc();
     }
 
     pub fn agg_job_dec(&self) {
         self.metrics
             .aggregation_job_gauge
-            .with_label_values(&[self.host])
+            .with_label_values(&[self.host, self.role_str()])
             .dec();
     }
+
+    pub fn agg_job_duration_observe(
+        &self,
+        version: DapVersion,
+        query: &DapQueryConfig,
+        duration_secs: f64,
+    ) {
+        self.metrics
+            .aggregation_job_duration_histogram
+            .with_label_values(&[self.host, version.as_ref(), &query.to_string()])
+            .observe(duration_secs);
+    }
+
+    pub fn helper_request_duration_observe(
+        &self,
+        version: DapVersion,
+        query: &DapQueryConfig,
+        duration_secs: f64,
+    ) {
+        self.metrics
+            .helper_request_duration_histogram
+            .with_label_values(&[self.host, version.as_ref(), &query.to_string()])
+            .observe(duration_secs);
+    }
+
+    pub fn hpke_decrypt_failure_inc(&self, cause: HpkeDecryptFailure) {
+        let cause_str = match cause {
+            HpkeDecryptFailure::UnknownConfigId => "unknown_config_id",
+            HpkeDecryptFailure::DecryptError => "decrypt_error",
+        };
+
+        self.metrics
+            .hpke_decrypt_failure_counter
+            .with_label_values(&[self.host, self.role_str(), cause_str])
+            .inc();
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DaphneRole {
+    /// The Aggregator is acting as the Leader.
+    Leader,
+    /// The Aggregator is acting as the Helper.
+    Helper,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -120,3 +222,13 @@ pub enum DaphneRequestType {
     /// DAP collect request.
     Collect,
 }
+
+#[derive(Clone, Copy, Debug)]
+pub enum HpkeDecryptFailure {
+    /// The ciphertext's HPKE config ID is not recognized, e.g., because the sender used a stale
+    /// config.
+    UnknownConfigId,
+    /// The HPKE config is recognized, but the ciphertext could not be opened, e.g., because it is
+    /// corrupted or forged.
+    DecryptError,
+}
@@ -4,7 +4,7 @@
 //! Trait definitions for Daphne backends.
 
 use crate::{
-    constants::DapMediaType,
+    constants::{DapEndpoint, DapMediaType},
     hpke::HpkeDecrypter,
     messages::{
         constant_time_eq, decode_base64url, AggregateShare, AggregateShareReq,
@@ -13,17 +13,17 @@ use crate::{
         PartialBatchSelector, Query, Report, ReportId, ReportMetadata, TaskId, Time,
         TransitionFailure, TransitionVar,
     },
-    metrics::{DaphneMetrics, DaphneRequestType},
-    DapAbort, DapAggregateShare, DapCollectJob, DapError, DapGlobalConfig, DapHelperState,
-    DapHelperTransition, DapLeaderProcessTelemetry, DapLeaderTransition, DapOutputShare,
-    DapQueryConfig, DapRequest, DapResource, DapResponse, DapTaskConfig, DapVersion,
-    MetaAggregationJobId,
+    metrics::{ContextualizedDaphneMetrics, DaphneMetrics, DaphneRequestType, DaphneRole},
+    DapAbort, DapAggregateShare, DapBatchSpan, DapCollectJob, DapError, DapGlobalConfig,
+    DapHelperState, DapHelperTransition, DapLeaderProcessTelemetry, DapLeaderTransition,
+    DapOutputShare, DapQueryConfig, DapRequest, DapResource, DapResponse, DapTaskConfig,
+    DapVersion, MetaAggregationJobId,
 };
 use async_trait::async_trait;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
 use std::borrow::Cow;
 use std::collections::HashMap;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use url::Url;
 
 /// A party in the DAP protocol who is authorized to send requests to another party.
@@ -97,19 +97,28 @@ where
     /// Get the current time (number of seconds since the beginning of UNIX time).
     fn get_current_time(&self) -> Time;
 
+    /// Get the current time in milliseconds since the beginning of UNIX time. This is only used to
+    /// measure latency.
+    fn get_current_time_millis(&self) -> u64;
+
     /// Check whether the batch determined by the collect request would overlap with a previous
-    /// batch.
+    /// batch. If `collect_id` is set, then buckets that were collected by that collect job (see
+    /// [`mark_collected`](Self::mark_collected)) don't count as overlapping.
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        collect_id: Option<&CollectionJobId>,
+        batch_span: &DapBatchSpan<'_>,
     ) -> Result<bool, DapError>;
 
     /// Check whether the given batch ID has been observed before. This is called by the Leader
     /// (resp. Helper) in response to a CollectReq (resp. AggregateShareReq) for fixed-size tasks.
     async fn batch_exists(&self, task_id: &TaskId, batch_id: &BatchId) -> Result<bool, DapError>;
 
-    /// Store a set of output shares.
+    /// Store a set of output shares. Returns an error if the partial batch selector does not match
+    /// the task's query type, in which case nothing is stored. Implementations get this check by
+    /// computing the batch span with [`DapTaskConfig::batch_span_for_out_shares`] before writing
+    /// anything.
     async fn put_out_shares(
         &self,
         task_id: &TaskId,
@@ -117,28 +126,42 @@ where
         out_shares: Vec<DapOutputShare>,
     ) -> Result<(), DapError>;
 
-    /// Fetch the aggregate share for the given batch.
+    /// Fetch the aggregate share for the given batch. For time-interval queries, buckets within
+    /// the batch interval for which no reports were aggregated contribute nothing to the aggregate
+    /// share; it is up to the caller to check that the batch is large enough.
     async fn get_agg_share(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        batch_span: &DapBatchSpan<'_>,
     ) -> Result<DapAggregateShare, DapError>;
 
     /// Ensure a set of reorts can be aggregated. Return a transition failure for each report
     /// that must be rejected early, due to the repot being replayed, the bucket that contains the
     /// report being collected, etc.
+    ///
+    /// Reports are marked as processed by the aggregation job with ID `agg_job_id`. A report that
+    /// was previously marked by the same aggregation job is not considered replayed; this allows
+    /// an aggregation job to be re-initialized after the Helper loses its state.
     async fn check_early_reject<'b>(
         &self,
         task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
         part_batch_sel: &'b PartialBatchSelector,
         report_meta: impl Iterator<Item = &'b ReportMetadata>,
     ) -> Result<HashMap<ReportId, TransitionFailure>, DapError>;
 
-    /// Mark a batch as collected.
+    /// Mark a batch as collected by the given collect job. If any part of the batch was already
+    /// collected by another job, then return a "batchOverlap" abort without marking the rest of
+    /// the batch. Checking and marking must happen atomically so that concurrent requests to
+    /// collect the same batch can't both succeed.
+    ///
+    /// Marking a batch again for the same collect job succeeds, so that the job can be retried if
+    /// it fails after the batch was marked.
     async fn mark_collected(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        collect_id: &CollectionJobId,
+        batch_span: &DapBatchSpan<'_>,
     ) -> Result<(), DapError>;
 
     /// Handle HTTP GET to `/hpke_config?task_id=<task_id>`.
@@ -153,21 +176,38 @@ where
 
         let metrics = self.metrics().with_host(req.host());
 
-        // Parse the task ID from the query string, ensuring that it is the only query parameter.
+        // Parse the task ID and, optionally, the ID of the requested HPKE config from the query
+        // string. No other query parameters are allowed.
         let mut id = None;
+        let mut hpke_config_id = None;
         for (k, v) in req.url.query_pairs() {
-            if k != "task_id" {
-                return Err(DapAbort::BadRequest("unexpected query parameter".into()));
+            match k.as_ref() {
+                "task_id" => {
+                    let bytes = decode_base64url(v.as_bytes()).ok_or(DapAbort::BadRequest(
+                        "failed to parse query parameter as URL-safe Base64".into(),
+                    ))?;
+                    id = Some(TaskId(bytes));
+                }
+                "id" => {
+                    hpke_config_id = Some(v.parse::<u8>().map_err(|_| {
+                        DapAbort::BadRequest("failed to parse HPKE config ID".into())
+                    })?);
+                }
+                _ => return Err(DapAbort::BadRequest("unexpected query parameter".into())),
             }
-
-            let bytes = decode_base64url(v.as_bytes()).ok_or(DapAbort::BadRequest(
-                "failed to parse query parameter as URL-safe Base64".into(),
-            ))?;
-
-            id = Some(TaskId(bytes))
         }
 
-        let hpke_config = self.get_hpke_config_for(req.version, id.as_ref()).await?;
+        // If a config ID is specified, then respond with that config, even if it's not the one
+        // that is currently advertised.
+        let hpke_config = match hpke_config_id {
+            Some(hpke_config_id) => self
+                .get_hpke_config_by_id(req.version, id.as_ref(), hpke_config_id)
+                .await?
+                .ok_or_else(|| {
+                    DapAbort::NotFound(format!("HPKE config {hpke_config_id} is not recognized"))
+                })?,
+            None => self.get_hpke_config_for(req.version, id.as_ref()).await?,
+        };
 
         if let Some(task_id) = id {
             let task_config = self
@@ -186,7 +226,7 @@ where
 
         let payload = match req.version {
             DapVersion::Draft02 => hpke_config.as_ref().get_encoded(),
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft09 => {
                 let hpke_config_list = HpkeConfigList {
                     hpke_configs: vec![hpke_config.as_ref().clone()],
                 };
@@ -197,11 +237,18 @@ where
             _ => unreachable!("unhandled version {:?}", req.version),
         };
 
+        // Let Clients cache the config, but not beyond the end of its validity window.
+        let mut cache_max_age = self.get_global_config().hpke_config_max_age;
+        if let Some(not_after) = self.hpke_config_not_after(&hpke_config) {
+            cache_max_age = cache_max_age.min(not_after.saturating_sub(self.get_current_time()));
+        }
+
         metrics.inbound_req_inc(DaphneRequestType::HpkeConfig);
         Ok(DapResponse {
             version: req.version,
             media_type: DapMediaType::HpkeConfigList,
             payload,
+            cache_max_age: Some(cache_max_age),
         })
     }
 
@@ -303,6 +350,15 @@ where
         collect_resp: &Collection,
     ) -> Result<(), DapError>;
 
+    /// Remove a collect job from the queue without completing it. This is done if the job can
+    /// never complete because its batch overlaps a batch that was collected by another job.
+    /// Polling the job afterwards yields [`DapCollectJob::Abandoned`].
+    async fn abandon_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> Result<(), DapError>;
+
     /// Send an HTTP POST request.
     async fn send_http_post(&self, req: DapRequest<S>) -> Result<DapResponse, DapError>;
 
@@ -312,18 +368,34 @@ where
     /// Handle HTTP POST to `/upload`. The input is the encoded report sent in the body of the HTTP
     /// request.
     async fn http_post_upload(&'srv self, req: &'req DapRequest<S>) -> Result<(), DapAbort> {
-        let metrics = self.metrics().with_host(req.host());
+        let metrics = self
+            .metrics()
+            .with_host(req.host())
+            .with_role(DaphneRole::Leader);
         debug!("upload for task {}", req.task_id()?);
 
         // Check whether the DAP version indicated by the sender is supported.
         if req.version == DapVersion::Unknown {
             return Err(DapAbort::version_unknown());
         }
+        if !req.version.is_fully_supported() {
+            return Err(DapAbort::version_not_implemented(req.version));
+        }
 
-        check_request_content_type(req, DapMediaType::Report)?;
+        check_request_content_type(req, DapEndpoint::Upload, DapMediaType::Report)?;
 
         let report = Report::get_decoded_with_param(&req.version, req.payload.as_ref())?;
         debug!("report id is {}", report.report_metadata.id);
+
+        // draft02: The task ID is encoded in the report as well as conveyed by the request, e.g.,
+        // by the path of `/v02/tasks/:task_id/reports`. If they disagree, then the report was
+        // misrouted. From draft04 on, the task ID is conveyed only by the request. It is bound to
+        // the input shares by HPKE, so a misrouted report is rejected when it is aggregated.
+        if let Some(ref report_task_id) = report.draft02_task_id {
+            if report_task_id != req.task_id()? {
+                return Err(DapAbort::UnrecognizedTask);
+            }
+        }
         let task_config = self
             .get_task_config_considering_taskprov(
                 req.version,
@@ -341,28 +413,69 @@ where
             ));
         }
 
+        // A quiescing task no longer accepts reports. Reports already uploaded are still drained by
+        // aggregation and collection.
+        if task_config.as_ref().quiescing {
+            return Err(DapAbort::ReportRejected {
+                detail: "task is quiescing and no longer accepts reports".into(),
+            });
+        }
+
+        // Clients are only required to authorize their uploads if the task says so.
+        if task_config.as_ref().client_auth {
+            if let Some(reason) = self.unauthorized_reason(req).await? {
+                error!("aborted unauthorized upload request: {reason}");
+                return Err(DapAbort::UnauthorizedRequest {
+                    detail: reason,
+                    task_id: req.task_id()?.clone(),
+                });
+            }
+        }
+
         if report.encrypted_input_shares.len() != 2 {
             // TODO spec: Decide if this behavior should be specified.
             return Err(DapAbort::UnrecognizedMessage);
         }
 
-        // Check that the indicated HpkeConfig is present.
+        // draft02: The extensions are carried in the clear, so they can be checked now. Otherwise
+        // they are encrypted and are checked once the report is aggregated.
+        if let Err(failure) = task_config
+            .as_ref()
+            .check_extensions(&report.report_metadata.extensions)
+        {
+            return Err(DapAbort::report_rejected(failure));
+        }
+
+        // Check that the indicated HpkeConfig is present. This gives the Client immediate feedback
+        // that it should refetch the HPKE config. The config may still be removed before the
+        // report is aggregated, so the check in `hpke_decrypt()` remains as a backstop.
         //
         // TODO spec: It's not clear if this behavior is MUST, SHOULD, or MAY.
         if !self
             .can_hpke_decrypt(req.task_id()?, report.encrypted_input_shares[0].config_id)
             .await?
         {
-            return Err(DapAbort::ReportRejected {
-                detail: "No current HPKE configuration matches the indicated ID.".into(),
-            });
+            return Err(DapAbort::report_rejected(
+                TransitionFailure::HpkeUnknownConfigId,
+            ));
         }
 
-        // Check that the task has not expired.
-        if report.report_metadata.time >= task_config.as_ref().expiration {
+        // Check that the task has not expired. A report may be timestamped before the task
+        // expires but not be uploaded until after, in which case it is also rejected.
+        let expiration = task_config.as_ref().expiration;
+        if report.report_metadata.time >= expiration || self.get_current_time() >= expiration {
             return Err(DapAbort::ReportTooLate);
         }
 
+        // Optionally reject reports whose bucket has not started yet. Such a report would
+        // otherwise sit in a bucket that can't be collected until the bucket has elapsed.
+        if task_config
+            .as_ref()
+            .is_in_future_bucket(report.report_metadata.time, self.get_current_time())
+        {
+            return Err(DapAbort::report_rejected(TransitionFailure::ReportTooEarly));
+        }
+
         // Store the report for future processing. At this point, the report may be rejected if
         // the Leader detects that the report was replayed or pertains to a batch that has already
         // been collected.
@@ -377,7 +490,10 @@ where
     /// [`CollectResp`](crate::messages::CollectResp).
     async fn http_post_collect(&'srv self, req: &'req DapRequest<S>) -> Result<Url, DapAbort> {
         let now = self.get_current_time();
-        let metrics = self.metrics().with_host(req.host());
+        let metrics = self
+            .metrics()
+            .with_host(req.host())
+            .with_role(DaphneRole::Leader);
         let task_id = req.task_id()?;
         debug!("collect for task {task_id}");
 
@@ -385,8 +501,11 @@ where
         if req.version == DapVersion::Unknown {
             return Err(DapAbort::version_unknown());
         }
+        if !req.version.is_fully_supported() {
+            return Err(DapAbort::version_not_implemented(req.version));
+        }
 
-        check_request_content_type(req, DapMediaType::CollectReq)?;
+        check_request_content_type(req, DapEndpoint::Collect, DapMediaType::CollectReq)?;
 
         if let Some(reason) = self.unauthorized_reason(req).await? {
             error!("aborted unauthorized collect request: {reason}");
@@ -409,6 +528,19 @@ where
             return Err(DapAbort::version_mismatch(req.version, task_config.version));
         }
 
+        // Check that the query type matches the task before acting on the query.
+        match (&task_config.query, &collect_req.query) {
+            (DapQueryConfig::TimeInterval { .. }, Query::TimeInterval { .. })
+            | (
+                DapQueryConfig::FixedSize { .. },
+                Query::FixedSizeByBatchId { .. } | Query::FixedSizeCurrentBatch,
+            ) => (),
+            (query_config, query) => {
+                return Err(DapAbort::query_mismatch(task_id, query_config, query))
+            }
+        }
+
+        let by_batch_id = matches!(collect_req.query, Query::FixedSizeByBatchId { .. });
         if collect_req.query == Query::FixedSizeCurrentBatch {
             // This is where we assign the current batch, and convert the
             // Query::FixedSizeCurrentBatch into a Query::FixedSizeByBatchId.
@@ -427,16 +559,45 @@ where
         // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
         // collected batches.
         let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
-        check_batch(
+        let batch_span = check_batch(
             self,
             task_config,
             task_id,
+            None,
             &batch_selector,
             &collect_req.agg_param,
             now,
         )
         .await?;
 
+        // Ensure the batch doesn't span too many buckets.
+        let global_config = self.get_global_config();
+        let bucket_count = batch_span.len() as u64;
+        if !global_config.permits_batch_span(bucket_count) {
+            return Err(DapAbort::BatchInvalid {
+                detail: format!(
+                    "The queried batch spans {bucket_count} buckets, but at most {} are permitted.",
+                    global_config.max_batch_span.unwrap_or_default()
+                ),
+                task_id: task_id.clone(),
+            });
+        }
+
+        // A Collector that names a fixed-size batch may do so before the batch is full. Tell it to
+        // come back later rather than starting a collection job for an undersized batch.
+        if by_batch_id {
+            let agg_share = self.get_agg_share(task_id, &batch_span).await?;
+            if agg_share.report_count < task_config.min_batch_size {
+                return Err(DapAbort::BatchNotReady {
+                    detail: format!(
+                        "The queried batch contains {} reports, but at least {} are required.",
+                        agg_share.report_count, task_config.min_batch_size
+                    ),
+                    task_id: task_id.clone(),
+                });
+            }
+        }
+
         // draft02 compatibility: In draft02, the collection job ID is generated as a result of the
         // initial collection request, whereas in the latest draft, the collection job ID is parsed
         // from the request path.
@@ -474,21 +635,28 @@ where
         reports: Vec<Report>,
         host: &str,
     ) -> Result<u64, DapAbort> {
-        let metrics = self.metrics().with_host(host);
+        // draft09: The ping-pong aggregation flow is not yet implemented.
+        if !task_config.version.is_fully_supported() {
+            return Err(DapAbort::version_not_implemented(task_config.version));
+        }
+        let metrics = self.metrics().with_host(host).with_role(DaphneRole::Leader);
+        let start = self.get_current_time_millis();
 
         // Filter out early rejected reports.
         //
         // TODO Add a test similar to http_post_aggregate_init_expired_task() in roles_test.rs that
         // verifies that the Leader properly checks for expiration. This will require extending the
         // test framework to run run_agg_job() directly.
+        let agg_job_id = MetaAggregationJobId::gen_for_version(&task_config.version);
         let early_rejects = self
             .check_early_reject(
                 task_id,
+                &agg_job_id,
                 part_batch_sel,
                 reports.iter().map(|report| &report.report_metadata),
             )
             .await?;
-        let reports = reports
+        let reports: Vec<Report> = reports
             .into_iter()
             .filter(|report| {
                 if let Some(failure) = early_rejects.get(&report.report_metadata.id) {
@@ -499,23 +667,79 @@ where
             })
             .collect();
 
+        // Run the aggregation job. If the Helper lost its state for the job after initialization,
+        // then it responds to the AggregationJobContinueReq with "unrecognizedAggregationJob". In
+        // this case, re-initialize the job once with the same ID and reports. The reports were
+        // marked as processed by this job, so they are not rejected as replays; and no output
+        // shares have been committed yet, so nothing is counted twice.
+        let mut restarted = false;
+        let out_shares = loop {
+            match self
+                .run_agg_job_once(
+                    task_id,
+                    task_config,
+                    &agg_job_id,
+                    part_batch_sel,
+                    reports.clone(),
+                    &metrics,
+                )
+                .await
+            {
+                Err(DapAbort::UnrecognizedAggregationJob { .. }) if !restarted => {
+                    warn!(
+                        "aggregation job {} unrecognized by the Helper; re-initializing",
+                        agg_job_id.to_base64url()
+                    );
+                    restarted = true;
+                }
+                res => break res?,
+            }
+        };
+        let Some(out_shares) = out_shares else {
+            return Ok(0);
+        };
+
+        // Commit the output shares.
+        let out_shares_count = out_shares.len() as u64;
+        self.put_out_shares(task_id, part_batch_sel, out_shares)
+            .await?;
+
+        metrics.report_inc_by("aggregated", out_shares_count);
+        metrics.agg_job_duration_observe(
+            task_config.version,
+            &task_config.query,
+            self.get_current_time_millis().saturating_sub(start) as f64 / 1000.0,
+        );
+        Ok(out_shares_count)
+    }
+
+    /// Run the Initialization and Continuation phases of an aggregation job for the given reports.
+    /// Return the output shares to be committed, or `None` if there is nothing to commit.
+    async fn run_agg_job_once(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        agg_job_id: &MetaAggregationJobId<'_>,
+        part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+        metrics: &ContextualizedDaphneMetrics<'_>,
+    ) -> Result<Option<Vec<DapOutputShare>>, DapAbort> {
         // Prepare AggregationJobInitReq.
-        let agg_job_id = MetaAggregationJobId::gen_for_version(&task_config.version);
         let transition = task_config
             .vdaf
             .produce_agg_job_init_req(
                 self,
                 task_id,
                 task_config,
-                &agg_job_id,
+                agg_job_id,
                 part_batch_sel,
                 reports,
-                &metrics,
+                metrics,
             )
             .await?;
         let (state, agg_job_init_req) = match transition {
             DapLeaderTransition::Continue(state, agg_job_init_req) => (state, agg_job_init_req),
-            DapLeaderTransition::Skip => return Ok(0),
+            DapLeaderTransition::Skip => return Ok(None),
             DapLeaderTransition::Uncommitted(..) => {
                 return Err(DapError::fatal("unexpected state transition (uncommitted)").into())
             }
@@ -538,7 +762,7 @@ where
             task_config,
             &url_path,
             DapMediaType::AggregationJobInitReq,
-            DapMediaType::AggregationJobResp,
+            DapMediaType::agg_job_init_resp_for_version(task_config.version),
             agg_job_id.for_request_path(),
             agg_job_init_req.get_encoded_with_param(&task_config.version),
             is_put
@@ -548,17 +772,17 @@ where
         // Prepare AggreagteContinueReq.
         let transition = task_config.vdaf.handle_agg_job_resp(
             task_id,
-            &agg_job_id,
+            agg_job_id,
             state,
             agg_job_resp,
             task_config.version,
-            &metrics,
+            metrics,
         )?;
         let (uncommited, agg_job_cont_req) = match transition {
             DapLeaderTransition::Uncommitted(uncommited, agg_job_cont_req) => {
                 (uncommited, agg_job_cont_req)
             }
-            DapLeaderTransition::Skip => return Ok(0),
+            DapLeaderTransition::Skip => return Ok(None),
             DapLeaderTransition::Continue(..) => {
                 return Err(DapError::fatal("unexpected state transition (continue)").into())
             }
@@ -578,17 +802,11 @@ where
         );
         let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload)?;
 
-        // Commit the output shares.
         let out_shares =
             task_config
                 .vdaf
-                .handle_final_agg_job_resp(uncommited, agg_job_resp, &metrics)?;
-        let out_shares_count = out_shares.len() as u64;
-        self.put_out_shares(task_id, part_batch_sel, out_shares)
-            .await?;
-
-        metrics.report_inc_by("aggregated", out_shares_count);
-        Ok(out_shares_count)
+                .handle_final_agg_job_resp(uncommited, agg_job_resp, metrics)?;
+        Ok(Some(out_shares))
     }
 
     /// Handle a pending collect request. If the results are ready, then compute the aggregate
@@ -602,11 +820,21 @@ where
         collect_req: &CollectionReq,
         host: &str,
     ) -> Result<u64, DapAbort> {
-        let metrics = self.metrics().with_host(host);
+        // draft09: The collection flow is not yet implemented.
+        if !task_config.version.is_fully_supported() {
+            return Err(DapAbort::version_not_implemented(task_config.version));
+        }
+        let metrics = self.metrics().with_host(host).with_role(DaphneRole::Leader);
 
         debug!("collecting id {collect_id}");
         let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
-        let leader_agg_share = self.get_agg_share(task_id, &batch_selector).await?;
+        let batch_span = task_config.batch_span(&batch_selector)?;
+        let leader_agg_share = self.get_agg_share(task_id, &batch_span).await?;
+
+        // If no reports have been aggregated yet, then the batch is not ready.
+        if leader_agg_share.empty() {
+            return Ok(0);
+        }
 
         // Check the batch size. If not not ready, then return early.
         //
@@ -681,11 +909,15 @@ where
             interval,
             encrypted_agg_shares: vec![leader_enc_agg_share, agg_share_resp.encrypted_agg_share],
         };
-        self.finish_collect_job(task_id, collect_id, &collection)
+
+        // Mark reports as collected. This must happen before the collect job is finished: if
+        // another collect job for an overlapping batch was accepted concurrently and has already
+        // collected some of these reports, then this job must not release its result. If this job
+        // already marked the batch before failing to finish, then marking it again succeeds.
+        self.mark_collected(task_id, collect_id, &batch_span)
             .await?;
 
-        // Mark reports as collected.
-        self.mark_collected(task_id, &agg_share_req.batch_sel)
+        self.finish_collect_job(task_id, collect_id, &collection)
             .await?;
 
         metrics.report_inc_by("collected", agg_share_req.report_count);
@@ -745,7 +977,7 @@ where
                 .await?
                 .ok_or(DapAbort::UnrecognizedTask)?;
 
-            telem.reports_collected += self
+            match self
                 .run_collect_job(
                     &task_id,
                     &collect_id,
@@ -753,7 +985,17 @@ where
                     &collect_req,
                     host,
                 )
-                .await?;
+                .await
+            {
+                Ok(reports_collected) => telem.reports_collected += reports_collected,
+                // The batch overlaps a batch that was collected by another job, so this job can
+                // never finish. Abandon it so that it doesn't hold up the rest of the queue.
+                Err(e @ DapAbort::BatchOverlap { .. }) => {
+                    warn!("abandoning collect job {collect_id}: {e}");
+                    self.abandon_collect_job(&task_id, &collect_id).await?;
+                }
+                Err(e) => return Err(e),
+            }
         }
 
         Ok(telem)
@@ -782,6 +1024,23 @@ where
         agg_job_id: &MetaAggregationJobId,
     ) -> Result<Option<DapHelperState>, DapError>;
 
+    /// Fetch the Helper's aggregation-flow state for an aggregation job that is being continued.
+    /// If the Helper has no state associated with the given task and aggregation job, then the
+    /// request references an aggregation job that was never initialized (or has already been
+    /// completed), so abort with "unrecognizedAggregationJob".
+    async fn try_get_helper_state(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+    ) -> Result<DapHelperState, DapAbort> {
+        self.get_helper_state(task_id, agg_job_id)
+            .await?
+            .ok_or_else(|| DapAbort::UnrecognizedAggregationJob {
+                task_id: task_id.clone(),
+                agg_job_id_base64url: agg_job_id.to_base64url(),
+            })
+    }
+
     /// Handle an HTTP POST to `/aggregate`. The input is either an AggregationJobInitReq or
     /// AggregationJobContinueReq and the response is an AggregationJobResp.
     ///
@@ -790,12 +1049,31 @@ where
         &'srv self,
         req: &'req DapRequest<S>,
     ) -> Result<DapResponse, DapAbort> {
-        let metrics = self.metrics().with_host(req.host());
+        let metrics = self
+            .metrics()
+            .with_host(req.host())
+            .with_role(DaphneRole::Helper);
 
         // Check whether the DAP version indicated by the sender is supported.
         if req.version == DapVersion::Unknown {
             return Err(DapAbort::version_unknown());
         }
+        if !req.version.is_fully_supported() {
+            return Err(DapAbort::version_not_implemented(req.version));
+        }
+
+        if !matches!(
+            req.media_type,
+            DapMediaType::AggregationJobInitReq | DapMediaType::AggregationJobContinueReq
+        ) {
+            return Err(DapAbort::content_type(
+                req,
+                &[
+                    DapEndpoint::AggregationJobInit,
+                    DapEndpoint::AggregationJobContinue,
+                ],
+            ));
+        }
 
         let task_id = req.task_id()?;
 
@@ -878,6 +1156,19 @@ where
                     return Err(DapAbort::version_mismatch(req.version, task_config.version));
                 }
 
+                // Ensure the aggregation job isn't too large.
+                if let Some(max_reports) = task_config
+                    .max_reports_per_agg_job
+                    .or(global_config.max_reports_per_agg_job)
+                {
+                    let num_reports = agg_job_init_req.report_shares.len() as u64;
+                    if num_reports > max_reports {
+                        return Err(DapAbort::BadRequest(format!(
+                            "aggregation job contains {num_reports} reports, but at most {max_reports} are permitted"
+                        )));
+                    }
+                }
+
                 // Ensure we know which batch the request pertains to.
                 check_part_batch(
                     task_id,
@@ -888,6 +1179,7 @@ where
 
                 let early_rejects_future = self.check_early_reject(
                     task_id,
+                    &agg_job_id,
                     &agg_job_init_req.part_batch_sel,
                     agg_job_init_req
                         .report_shares
@@ -967,8 +1259,9 @@ where
                 metrics.inbound_req_inc(DaphneRequestType::Aggregate);
                 Ok(DapResponse {
                     version: req.version,
-                    media_type: DapMediaType::AggregationJobResp,
+                    media_type: DapMediaType::agg_job_init_resp_for_version(req.version),
                     payload: agg_job_resp.get_encoded(),
+                    cache_max_age: None,
                 })
             }
             DapMediaType::AggregationJobContinueReq => {
@@ -1005,12 +1298,7 @@ where
                     _ => unreachable!("unhandled resource {:?}", req.resource),
                 };
 
-                let state = self.get_helper_state(task_id, &agg_job_id).await?.ok_or(
-                    DapAbort::UnrecognizedAggregationJob {
-                        task_id: task_id.clone(),
-                        agg_job_id_base64url: agg_job_id.to_base64url(),
-                    },
-                )?;
+                let state = self.try_get_helper_state(task_id, &agg_job_id).await?;
                 let part_batch_sel = state.part_batch_sel.clone();
                 let transition = task_config.vdaf.handle_agg_job_cont_req(
                     task_id,
@@ -1039,6 +1327,7 @@ where
                     version: req.version,
                     media_type: DapMediaType::agg_job_cont_resp_for_version(task_config.version),
                     payload: agg_job_resp.get_encoded(),
+                    cache_max_age: None,
                 })
             }
             //TODO spec: Specify this behavior.
@@ -1055,14 +1344,24 @@ where
         req: &'req DapRequest<S>,
     ) -> Result<DapResponse, DapAbort> {
         let now = self.get_current_time();
-        let metrics = self.metrics().with_host(req.host());
+        let metrics = self
+            .metrics()
+            .with_host(req.host())
+            .with_role(DaphneRole::Helper);
 
         // Check whether the DAP version indicated by the sender is supported.
         if req.version == DapVersion::Unknown {
             return Err(DapAbort::version_unknown());
         }
+        if !req.version.is_fully_supported() {
+            return Err(DapAbort::version_not_implemented(req.version));
+        }
 
-        check_request_content_type(req, DapMediaType::AggregateShareReq)?;
+        check_request_content_type(
+            req,
+            DapEndpoint::AggregateShare,
+            DapMediaType::AggregateShareReq,
+        )?;
 
         let task_id = req.task_id()?;
 
@@ -1088,19 +1387,19 @@ where
 
         // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
         // collected batches.
-        check_batch(
+        let collect_id = helper_collect_id(&agg_share_req);
+        let batch_span = check_batch(
             self,
             task_config,
             task_id,
+            Some(&collect_id),
             &agg_share_req.batch_sel,
             &agg_share_req.agg_param,
             now,
         )
         .await?;
 
-        let agg_share = self
-            .get_agg_share(task_id, &agg_share_req.batch_sel)
-            .await?;
+        let agg_share = self.get_agg_share(task_id, &batch_span).await?;
 
         // Check that we have aggreagted the same set of reports as the Leader.
         if agg_share_req.report_count != agg_share.report_count
@@ -1116,6 +1415,14 @@ where
             });
         }
 
+        // Refuse to produce an aggregate share for a batch that doesn't contain any reports.
+        if agg_share.empty() {
+            return Err(DapAbort::BatchInvalid {
+                detail: "The batch does not contain any aggregated reports.".into(),
+                task_id: task_id.clone(),
+            });
+        }
+
         // Check the batch size.
         if !task_config
             .is_report_count_compatible(task_id, agg_share.report_count)
@@ -1131,7 +1438,7 @@ where
         }
 
         // Mark each aggregated report as collected.
-        self.mark_collected(task_id, &agg_share_req.batch_sel)
+        self.mark_collected(task_id, &collect_id, &batch_span)
             .await?;
 
         let encrypted_agg_share = task_config.vdaf.produce_helper_encrypted_agg_share(
@@ -1152,6 +1459,7 @@ where
             version: req.version,
             media_type: DapMediaType::AggregateShare,
             payload: agg_share_resp.get_encoded(),
+            cache_max_age: None,
         })
     }
 }
@@ -1179,19 +1487,21 @@ fn check_part_batch(
     Ok(())
 }
 
-async fn check_batch<'srv, 'req, S>(
+/// Check that the batch is valid for the task and doesn't overlap with a previously collected
+/// batch. On success, return the batch span so that it can be reused for the rest of the request.
+async fn check_batch<'srv, 'req, 'b, S>(
     agg: &impl DapAggregator<'srv, 'req, S>,
     task_config: &DapTaskConfig,
     task_id: &TaskId,
-    batch_sel: &BatchSelector,
+    collect_id: Option<&CollectionJobId>,
+    batch_sel: &'b BatchSelector,
     agg_param: &[u8],
     now: Time,
-) -> Result<(), DapAbort>
+) -> Result<DapBatchSpan<'b>, DapAbort>
 where
     'srv: 'req,
 {
     let global_config = agg.get_global_config();
-    let batch_overlapping = agg.is_batch_overlapping(task_id, batch_sel);
 
     // Check that the aggreation parameter is suitable for the given VDAF.
     if !task_config.vdaf.is_valid_agg_param(agg_param) {
@@ -1202,12 +1512,15 @@ where
     // Check that the batch boundaries are valid.
     match (&task_config.query, batch_sel) {
         (DapQueryConfig::TimeInterval { .. }, BatchSelector::TimeInterval { batch_interval }) => {
-            if batch_interval.start % task_config.time_precision != 0
-                || batch_interval.duration % task_config.time_precision != 0
-                || batch_interval.duration < task_config.time_precision
+            // Reports are aggregated in buckets, so the boundaries of the batch interval must be
+            // aligned to the bucket duration. This is a multiple of the time precision.
+            let bucket_duration = task_config.bucket_duration();
+            if batch_interval.start % bucket_duration != 0
+                || batch_interval.duration % bucket_duration != 0
+                || batch_interval.duration < bucket_duration
             {
                 return Err(DapAbort::BatchInvalid {
-                    detail: format!("The queried batch interval ({batch_interval:?}) is too small or its boundaries are misaligned. The time precision for this task is {}s.", task_config.time_precision),
+                    detail: format!("The queried batch interval ({batch_interval:?}) is too small or its boundaries are misaligned. The time precision for this task is {}s and the bucket duration is {bucket_duration}s.", task_config.time_precision),
                     task_id: task_id.clone(),
                 });
             }
@@ -1255,11 +1568,29 @@ where
     };
 
     // Check that the batch does not overlap with any previously collected batch.
-    if batch_overlapping.await? {
+    let batch_span = task_config.batch_span(batch_sel)?;
+    if agg
+        .is_batch_overlapping(task_id, collect_id, &batch_span)
+        .await?
+    {
         return Err(DapAbort::batch_overlap(task_id, batch_sel));
     }
 
-    Ok(())
+    Ok(batch_span)
+}
+
+/// Derive the ID with which the Helper marks a batch as collected for an AggregateShareReq. The
+/// Helper doesn't learn the ID of the Leader's collect job, but the Leader sends the same batch
+/// selector and aggregation parameter each time it retries the job, so the retry is recognized.
+/// No more reports can be aggregated into a batch once it is collected, so the aggregate share is
+/// the same each time.
+fn helper_collect_id(agg_share_req: &AggregateShareReq) -> CollectionJobId {
+    let mut bytes = agg_share_req.batch_sel.get_encoded();
+    bytes.extend_from_slice(&agg_share_req.agg_param);
+    let digest = ring::digest::digest(&ring::digest::SHA256, &bytes);
+    let mut collect_id = CollectionJobId::default();
+    collect_id.0.copy_from_slice(&digest.as_ref()[..16]);
+    collect_id
 }
 
 /// Check for transition failures due to:
@@ -1312,10 +1643,11 @@ fn check_response_content_type(resp: &DapResponse, expected: DapMediaType) -> Re
 
 fn check_request_content_type<S>(
     req: &DapRequest<S>,
+    endpoint: DapEndpoint,
     expected: DapMediaType,
 ) -> Result<(), DapAbort> {
     if req.media_type != expected {
-        Err(DapAbort::content_type(req, expected))
+        Err(DapAbort::content_type(req, &[endpoint]))
     } else {
         Ok(())
     }
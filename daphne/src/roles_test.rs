@@ -5,13 +5,14 @@ use crate::{
     assert_metrics_include, assert_metrics_include_auxiliary_function, async_test_version,
     async_test_versions,
     auth::BearerToken,
-    constants::DapMediaType,
+    constants::{expected_media_type, DapEndpoint, DapMediaType},
     hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
         taskprov, AggregateShareReq, AggregationJobContinueReq, AggregationJobInitReq,
         AggregationJobResp, BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq,
-        Extension, HpkeKemId, Interval, PartialBatchSelector, Query, Report, ReportId,
-        ReportMetadata, ReportShare, TaskId, Time, Transition, TransitionFailure, TransitionVar,
+        Extension, HpkeAeadId, HpkeConfig, HpkeConfigList, HpkeKdfId, HpkeKemId, Interval,
+        PartialBatchSelector, Query, Report, ReportId, ReportMetadata, ReportShare, TaskId, Time,
+        Transition, TransitionFailure, TransitionVar,
     },
     metrics::DaphneMetrics,
     roles::{early_metadata_check, DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
@@ -19,9 +20,9 @@ use crate::{
     test_version, test_versions,
     testing::{AggStore, DapBatchBucketOwned, MockAggregator, MockAggregatorReportSelector},
     vdaf::VdafVerifyKey,
-    DapAbort, DapAggregateShare, DapCollectJob, DapGlobalConfig, DapMeasurement, DapQueryConfig,
-    DapRequest, DapResource, DapTaskConfig, DapVersion, MetaAggregationJobId, Prio3Config,
-    VdafConfig,
+    DapAbort, DapAggregateShare, DapBatchBucket, DapCollectJob, DapError, DapGlobalConfig,
+    DapMeasurement, DapQueryConfig, DapRequest, DapResource, DapTaskConfig, DapVersion,
+    MetaAggregationJobId, Prio3Config, VdafConfig,
 };
 use assert_matches::assert_matches;
 use matchit::Router;
@@ -30,8 +31,11 @@ use prio::codec::{Decode, ParameterizedEncode};
 use rand::{thread_rng, Rng};
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::SystemTime,
     vec,
 };
@@ -53,6 +57,7 @@ struct Test {
     leader: Arc<MockAggregator>,
     helper: Arc<MockAggregator>,
     collector_token: BearerToken,
+    backup_collector_token: BearerToken,
     time_interval_task_id: TaskId,
     fixed_size_task_id: TaskId,
     expired_task_id: TaskId,
@@ -77,8 +82,14 @@ impl Test {
             min_batch_interval_start: 259200,
             max_batch_interval_end: 259200,
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+            supported_hpke_kdfs: vec![HpkeKdfId::HkdfSha256],
+            supported_hpke_aeads: vec![HpkeAeadId::Aes128Gcm],
             allow_taskprov: true,
             taskprov_version: TaskprovVersion::Draft02,
+            rejection_counts_flush_interval: None,
+            max_batch_span: None,
+            max_reports_per_agg_job: None,
+            hpke_config_max_age: 3600,
         };
 
         // Task Parameters that the Leader and Helper must agree on.
@@ -99,12 +110,23 @@ impl Test {
             DapTaskConfig {
                 version,
                 collector_hpke_config: collector_hpke_receiver_config.config.clone(),
+                upload_rate_limit: None,
+                max_reports_per_agg_job: None,
+                client_auth: false,
+                task_scoped_hpke_config: false,
+                quiescing: false,
+                pending_report_ttl: None,
+                collect_poll_retry_after: None,
+                permitted_extensions: Vec::new(),
+                reject_future_buckets: false,
                 leader_url: leader_url.clone(),
                 helper_url: helper_url.clone(),
                 time_precision,
                 expiration: now + 3600,
                 min_batch_size: 1,
-                query: DapQueryConfig::TimeInterval,
+                query: DapQueryConfig::TimeInterval {
+                    bucket_duration: None,
+                },
                 vdaf: vdaf_config.clone(),
                 vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
             },
@@ -114,6 +136,15 @@ impl Test {
             DapTaskConfig {
                 version,
                 collector_hpke_config: collector_hpke_receiver_config.config.clone(),
+                upload_rate_limit: None,
+                max_reports_per_agg_job: None,
+                client_auth: false,
+                task_scoped_hpke_config: false,
+                quiescing: false,
+                pending_report_ttl: None,
+                collect_poll_retry_after: None,
+                permitted_extensions: Vec::new(),
+                reject_future_buckets: false,
                 leader_url: leader_url.clone(),
                 helper_url: helper_url.clone(),
                 time_precision,
@@ -129,12 +160,23 @@ impl Test {
             DapTaskConfig {
                 version,
                 collector_hpke_config: collector_hpke_receiver_config.config.clone(),
+                upload_rate_limit: None,
+                max_reports_per_agg_job: None,
+                client_auth: false,
+                task_scoped_hpke_config: false,
+                quiescing: false,
+                pending_report_ttl: None,
+                collect_poll_retry_after: None,
+                permitted_extensions: Vec::new(),
+                reject_future_buckets: false,
                 leader_url,
                 helper_url,
                 time_precision,
                 expiration: now, // Expires this second
                 min_batch_size: 1,
-                query: DapQueryConfig::TimeInterval,
+                query: DapQueryConfig::TimeInterval {
+                    bucket_duration: None,
+                },
                 vdaf: vdaf_config,
                 vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
             },
@@ -143,6 +185,8 @@ impl Test {
         // Authorization tokens, used for all tasks.
         let leader_token = BearerToken::from("this is a bearer token!");
         let collector_token = BearerToken::from("This is a DIFFERENT token.");
+        let backup_collector_token = BearerToken::from("This token is for a backup Collector.");
+        let client_token = BearerToken::from("Yet another token, this one for the Client.");
 
         // taskprov: VDAF verification key.
         let taskprov_vdaf_verify_key_init = rng.gen::<[u8; 32]>();
@@ -157,7 +201,10 @@ impl Test {
             global_config: global_config.clone(),
             tasks: Arc::new(Mutex::new(tasks.clone())),
             leader_token: leader_token.clone(),
+            rotated_leader_token: Mutex::new(None),
             collector_token: None,
+            additional_collector_tokens: Vec::new(),
+            client_token: None,
             hpke_receiver_config_list: helper_hpke_receiver_config_list,
             report_store: Arc::new(Mutex::new(HashMap::new())),
             leader_state_store: Arc::new(Mutex::new(HashMap::new())),
@@ -167,6 +214,8 @@ impl Test {
             taskprov_vdaf_verify_key_init,
             metrics: DaphneMetrics::register(&prometheus_registry, Some("test_helper")).unwrap(),
             peer: None,
+            lose_helper_state: AtomicBool::new(false),
+            fail_finish_collect_job: AtomicBool::new(false),
         });
 
         let leader_hpke_receiver_config_list = global_config
@@ -178,7 +227,10 @@ impl Test {
             tasks: Arc::new(Mutex::new(tasks.clone())),
             hpke_receiver_config_list: leader_hpke_receiver_config_list,
             leader_token,
+            rotated_leader_token: Mutex::new(None),
             collector_token: Some(collector_token.clone()),
+            additional_collector_tokens: vec![backup_collector_token.clone()],
+            client_token: Some(client_token),
             report_store: Arc::new(Mutex::new(HashMap::new())),
             leader_state_store: Arc::new(Mutex::new(HashMap::new())),
             helper_state_store: Arc::new(Mutex::new(HashMap::new())),
@@ -187,6 +239,8 @@ impl Test {
             taskprov_vdaf_verify_key_init,
             metrics: DaphneMetrics::register(&prometheus_registry, Some("test_leader")).unwrap(),
             peer: Some(Arc::clone(&helper)),
+            lose_helper_state: AtomicBool::new(false),
+            fail_finish_collect_job: AtomicBool::new(false),
         });
 
         Self {
@@ -194,6 +248,7 @@ impl Test {
             leader,
             helper,
             collector_token,
+            backup_collector_token,
             time_interval_task_id,
             fixed_size_task_id,
             expired_task_id,
@@ -326,6 +381,10 @@ impl Test {
     }
 
     async fn gen_test_report(&self, task_id: &TaskId) -> Report {
+        self.gen_test_report_at(task_id, self.now).await
+    }
+
+    async fn gen_test_report_at(&self, task_id: &TaskId, time: Time) -> Report {
         let version = self.leader.unchecked_get_task_config(task_id).await.version;
 
         // Construct HPKE config list.
@@ -349,7 +408,7 @@ impl Test {
         vdaf_config
             .produce_report(
                 &hpke_config_list,
-                self.now,
+                time,
                 task_id,
                 DapMeasurement::U64(1),
                 self.version,
@@ -562,6 +621,28 @@ async fn http_post_aggregate_init_unauthorized_request(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_init_unauthorized_request }
 
+async fn http_post_aggregate_init_rotated_leader_token(version: DapVersion) {
+    let t = Test::new(version);
+    let mut req = t
+        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, Vec::default())
+        .await;
+    let old_token = BearerToken::from("the Leader's token before rotation".to_string());
+    req.sender_auth = Some(old_token.clone());
+
+    // Expect success while the old token is within its grace period.
+    *t.helper.rotated_leader_token.lock().unwrap() = Some((old_token.clone(), t.now + 60));
+    t.helper.http_post_aggregate(&req).await.unwrap();
+
+    // Expect failure once the grace period has elapsed.
+    *t.helper.rotated_leader_token.lock().unwrap() = Some((old_token, t.now));
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+}
+
+async_test_versions! { http_post_aggregate_init_rotated_leader_token }
+
 // Test that the Helper rejects reports past the expiration date.
 async fn http_post_aggregate_init_expired_task(version: DapVersion) {
     let t = Test::new(version);
@@ -587,6 +668,50 @@ async fn http_post_aggregate_init_expired_task(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_init_expired_task }
 
+// Test that the Helper rejects aggregation jobs with too many reports.
+async fn http_post_aggregate_init_too_many_reports(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let set_max_reports = |max_reports| {
+        t.helper
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .max_reports_per_agg_job = Some(max_reports);
+    };
+
+    let mut report_shares = Vec::new();
+    for _ in 0..2 {
+        let report = t.gen_test_report(task_id).await;
+        report_shares.push(ReportShare {
+            report_metadata: report.report_metadata,
+            public_share: report.public_share,
+            encrypted_input_share: report.encrypted_input_shares[1].clone(),
+        });
+    }
+
+    set_max_reports(1);
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, report_shares.clone())
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "aggregation job contains 2 reports, but at most 1 are permitted")
+    );
+
+    set_max_reports(2);
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, report_shares)
+        .await;
+    let resp = t.helper.http_post_aggregate(&req).await.unwrap();
+    let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload).unwrap();
+    assert_eq!(agg_job_resp.transitions.len(), 2);
+}
+
+async_test_versions! { http_post_aggregate_init_too_many_reports }
+
 // Test that the Helper rejects reports with a bad round number.
 async fn http_post_aggregate_bad_round(version: DapVersion) {
     let t = Test::new(version);
@@ -718,6 +843,113 @@ async fn http_get_hpke_config_missing_task_id(version: DapVersion) {
 
 async_test_versions! { http_get_hpke_config_missing_task_id }
 
+async fn http_get_hpke_config_cache_max_age(version: DapVersion) {
+    let t = Test::new(version);
+    let req = DapRequest {
+        version,
+        media_type: DapMediaType::HpkeConfigList,
+        task_id: Some(t.time_interval_task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: Vec::new(),
+        url: Url::parse(&format!(
+            "http://aggregator.biz/{}/hpke_config?task_id={}",
+            version.as_ref(),
+            t.time_interval_task_id.to_base64url()
+        ))
+        .unwrap(),
+        sender_auth: None,
+    };
+
+    // The config doesn't expire, so it may be cached for the configured maximum duration.
+    let resp = t.leader.http_get_hpke_config(&req).await.unwrap();
+    assert_eq!(resp.cache_max_age, Some(3600));
+    assert!(resp
+        .headers()
+        .unwrap()
+        .contains(&("Cache-Control", "max-age=3600".to_string())));
+}
+
+async_test_versions! { http_get_hpke_config_cache_max_age }
+
+fn hpke_config_by_id_req(
+    t: &Test,
+    version: DapVersion,
+    hpke_config_id: u8,
+) -> DapRequest<BearerToken> {
+    DapRequest {
+        version,
+        media_type: DapMediaType::HpkeConfigList,
+        task_id: Some(t.time_interval_task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: Vec::new(),
+        url: Url::parse(&format!(
+            "http://aggregator.biz/{}/hpke_config?task_id={}&id={hpke_config_id}",
+            version.as_ref(),
+            t.time_interval_task_id.to_base64url()
+        ))
+        .unwrap(),
+        sender_auth: None,
+    }
+}
+
+async fn http_get_hpke_config_by_id(version: DapVersion) {
+    let t = Test::new(version);
+    let expected = t
+        .leader
+        .hpke_receiver_config_list
+        .last()
+        .unwrap()
+        .config
+        .clone();
+    let req = hpke_config_by_id_req(&t, version, expected.id);
+
+    let resp = t.leader.http_get_hpke_config(&req).await.unwrap();
+    let hpke_config = match version {
+        DapVersion::Draft02 => HpkeConfig::get_decoded(&resp.payload).unwrap(),
+        _ => {
+            let mut hpke_config_list = HpkeConfigList::get_decoded(&resp.payload).unwrap();
+            assert_eq!(hpke_config_list.hpke_configs.len(), 1);
+            hpke_config_list.hpke_configs.pop().unwrap()
+        }
+    };
+    assert_eq!(hpke_config, expected);
+}
+
+async_test_versions! { http_get_hpke_config_by_id }
+
+async fn http_get_hpke_config_by_id_not_found(version: DapVersion) {
+    let t = Test::new(version);
+    let hpke_config_id = (0..=u8::MAX)
+        .find(|id| {
+            !t.leader
+                .hpke_receiver_config_list
+                .iter()
+                .any(|hpke_receiver_config| hpke_receiver_config.config.id == *id)
+        })
+        .unwrap();
+    let req = hpke_config_by_id_req(&t, version, hpke_config_id);
+
+    assert_matches!(
+        t.leader.http_get_hpke_config(&req).await,
+        Err(DapAbort::NotFound(..))
+    );
+}
+
+async_test_versions! { http_get_hpke_config_by_id_not_found }
+
+async fn http_get_hpke_config_by_id_malformed(version: DapVersion) {
+    let t = Test::new(version);
+    let mut req = hpke_config_by_id_req(&t, version, 0);
+    req.url.set_query(Some("id=256"));
+
+    assert_matches!(
+        t.leader.http_get_hpke_config(&req).await,
+        Err(DapAbort::BadRequest(..))
+    );
+}
+
+async_test_versions! { http_get_hpke_config_by_id_malformed }
+
 async fn http_post_aggregate_cont_unauthorized_request(version: DapVersion) {
     let t = Test::new(version);
     let agg_job_id = MetaAggregationJobId::gen_for_version(&version);
@@ -827,6 +1059,39 @@ async fn http_post_aggregate_share_invalid_batch_sel(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_share_invalid_batch_sel }
 
+async fn http_post_aggregate_share_fail_empty_batch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Leader sends aggregate share request for a batch to which no reports have been aggregated.
+    let req = t
+        .leader_authorized_req_with_version(
+            task_id,
+            None,
+            task_config.version,
+            DapMediaType::AggregateShareReq,
+            AggregateShareReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                batch_sel: BatchSelector::try_from(
+                    task_config.query_for_current_batch_window(t.now),
+                )
+                .unwrap(),
+                agg_param: Vec::default(),
+                report_count: 0,
+                checksum: [0; 32],
+            },
+            task_config.helper_url.join("aggregate_share").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+        DapAbort::BatchInvalid { task_id: got, .. } if &got == task_id
+    );
+}
+
+async_test_versions! { http_post_aggregate_share_fail_empty_batch }
+
 async fn http_post_collect_unauthorized_request(version: DapVersion) {
     let mut rng = thread_rng();
     let t = Test::new(version);
@@ -908,10 +1173,150 @@ async fn http_post_aggregate_failure_hpke_decrypt_error(version: DapVersion) {
         transition.var,
         TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
     );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_hpke_decrypt_failure_counter{cause="decrypt_error",host="helper.org",role="helper"}"#: 1,
+    });
 }
 
 async_test_versions! { http_post_aggregate_failure_hpke_decrypt_error }
 
+// The task ID is bound to the input shares by HPKE. From draft04 on, it is not encoded in the
+// report, so a report that was uploaded for another task is only detected when the Helper fails
+// to decrypt its input share.
+async fn http_post_aggregate_failure_report_for_other_task(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(&t.fixed_size_task_id).await;
+    let report_shares = vec![ReportShare {
+        report_metadata: report.report_metadata,
+        public_share: report.public_share,
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, report_shares)
+        .await;
+
+    let agg_job_resp =
+        AggregationJobResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+    );
+}
+
+async_test_versions! { http_post_aggregate_failure_report_for_other_task }
+
+async fn http_post_aggregate_failure_hpke_unknown_config_id(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let (report_metadata, public_share, mut encrypted_input_share) = (
+        report.report_metadata,
+        report.public_share,
+        report.encrypted_input_shares[1].clone(),
+    );
+    // Cause the config ID to be unrecognized, as if the Client used a stale config.
+    encrypted_input_share.config_id = (0..=u8::MAX)
+        .find(|id| {
+            !t.helper
+                .hpke_receiver_config_list
+                .iter()
+                .any(|config| config.config.id == *id)
+        })
+        .unwrap();
+    let report_shares = vec![ReportShare {
+        report_metadata,
+        public_share,
+        encrypted_input_share,
+    }];
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, report_shares)
+        .await;
+
+    // Get AggregationJobResp and then extract the transition data from inside.
+    let agg_job_resp =
+        AggregationJobResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    let transition = &agg_job_resp.transitions[0];
+
+    // Expect failure due to unknown HPKE config ID.
+    assert_matches!(
+        transition.var,
+        TransitionVar::Failed(TransitionFailure::HpkeUnknownConfigId)
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_hpke_decrypt_failure_counter{cause="unknown_config_id",host="helper.org",role="helper"}"#: 1,
+    });
+}
+
+async_test_versions! { http_post_aggregate_failure_hpke_unknown_config_id }
+
+async fn put_out_shares_part_batch_sel_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+
+    // Time-interval task, fixed-size partial batch selector.
+    assert_matches!(
+        t.helper
+            .put_out_shares(
+                &t.time_interval_task_id,
+                &PartialBatchSelector::FixedSizeByBatchId {
+                    batch_id: BatchId([1; 32]),
+                },
+                Vec::new(),
+            )
+            .await,
+        Err(DapError::Fatal(s)) => assert_eq!(s, "partial batch selector not compatible with task")
+    );
+
+    // Fixed-size task, time-interval partial batch selector.
+    assert_matches!(
+        t.helper
+            .put_out_shares(
+                &t.fixed_size_task_id,
+                &PartialBatchSelector::TimeInterval,
+                Vec::new(),
+            )
+            .await,
+        Err(DapError::Fatal(s)) => assert_eq!(s, "partial batch selector not compatible with task")
+    );
+
+    // Nothing was stored.
+    assert!(t.helper.agg_store.lock().unwrap().is_empty());
+}
+
+async_test_versions! { put_out_shares_part_batch_sel_mismatch }
+
+async fn hpke_suite_allow_list(version: DapVersion) {
+    let t = Test::new(version);
+    let hpke_config = &t.helper.hpke_receiver_config_list[0].config;
+    assert!(t.helper.global_config.allows_hpke_config(hpke_config));
+
+    // A config whose AEAD is no longer allowed is not accepted.
+    let mut global_config = t.helper.global_config.clone();
+    global_config.supported_hpke_aeads = vec![HpkeAeadId::NotImplemented(0xffff)];
+    assert!(!global_config.allows_hpke_config(hpke_config));
+
+    // Generation fails if either allow-list is empty.
+    global_config.supported_hpke_aeads.clear();
+    assert_matches!(
+        global_config.gen_hpke_receiver_config_list(0).next(),
+        Some(Err(DapError::Fatal(s))) => assert!(s.contains("no HPKE AEAD is allowed"))
+    );
+    let mut global_config = t.helper.global_config.clone();
+    global_config.supported_hpke_kdfs.clear();
+    assert_matches!(
+        global_config.gen_hpke_receiver_config_list(0).next(),
+        Some(Err(DapError::Fatal(s))) => assert!(s.contains("no HPKE KDF is allowed"))
+    );
+}
+
+async_test_versions! { hpke_suite_allow_list }
+
 async fn http_post_aggregate_transition_continue(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
@@ -963,9 +1368,10 @@ async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
             .lock()
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
-        report_store
-            .processed
-            .insert(report.report_metadata.id.clone());
+        report_store.processed.insert(
+            report.report_metadata.id.clone(),
+            "some_other_agg_job".into(),
+        );
     }
 
     // Get AggregationJobResp and then extract the transition data from inside.
@@ -981,9 +1387,9 @@ async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_report_counter{host="helper.org",status="rejected_report_replayed"}"#: 1,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="rejected_report_replayed"}"#: 1,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="aggregate"}"#: 1,
+        r#"test_helper_aggregation_job_gauge{host="helper.org",role="helper"}"#: 1,
     });
 }
 
@@ -1022,6 +1428,7 @@ async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
             AggStore {
                 agg_share: DapAggregateShare::default(),
                 collected: true,
+                collect_id: None,
             },
         );
     }
@@ -1039,9 +1446,9 @@ async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_report_counter{host="helper.org",status="rejected_batch_collected"}"#: 1,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="rejected_batch_collected"}"#: 1,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="aggregate"}"#: 1,
+        r#"test_helper_aggregation_job_gauge{host="helper.org",role="helper"}"#: 1,
     });
 }
 
@@ -1092,6 +1499,30 @@ async fn http_post_aggregate_fail_send_cont_req(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_fail_send_cont_req }
 
+async fn http_post_aggregate_cont_unrecognized_agg_job_problem_details(version: DapVersion) {
+    let t = Test::new(version);
+    let agg_job_id = MetaAggregationJobId::gen_for_version(&version);
+    let req = t
+        .gen_test_agg_job_cont_req(&agg_job_id, Vec::default(), version)
+        .await;
+
+    // The Helper has no state for the aggregation job, so the continuation is rejected with a
+    // problem document identifying the aggregation job.
+    let err = t.helper.http_post_aggregate(&req).await.unwrap_err();
+    let problem_details = err.into_problem_details();
+    assert_eq!(
+        problem_details.typ.as_deref(),
+        Some("urn:ietf:params:ppm:dap:error:unrecognizedAggregationJob")
+    );
+    assert_eq!(
+        problem_details.task_id,
+        Some(t.time_interval_task_id.to_base64url())
+    );
+    assert_eq!(problem_details.agg_job_id, Some(agg_job_id.to_base64url()));
+}
+
+async_test_versions! { http_post_aggregate_cont_unrecognized_agg_job_problem_details }
+
 async fn http_post_upload_fail_send_invalid_report(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
@@ -1130,42 +1561,271 @@ async fn http_post_upload_fail_send_invalid_report(version: DapVersion) {
 
 async_test_versions! { http_post_upload_fail_send_invalid_report }
 
-// Test that the Leader rejects reports past the expiration date.
-async fn http_post_upload_task_expired(version: DapVersion) {
+// Test that a request with the wrong content-type is rejected with a detail indicating the
+// content-type expected by the endpoint.
+async fn http_post_fail_wrong_media_type(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.expired_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
-
-    let report = t.gen_test_report(task_id).await;
-    let req = DapRequest {
-        version: task_config.version,
-        media_type: DapMediaType::Report,
-        task_id: Some(task_id.clone()),
-        resource: DapResource::Undefined,
-        payload: report.get_encoded_with_param(&version),
-        url: task_config.leader_url.join("upload").unwrap(),
-        sender_auth: None,
+    let task_id = &t.time_interval_task_id;
+    let wrong_media_type = DapMediaType::Invalid("application/octet-stream".into());
+    let assert_detail_has = |err: DapAbort, endpoints: &[DapEndpoint]| {
+        assert_matches!(err, DapAbort::UnsupportedMediaType(detail) => {
+            for endpoint in endpoints {
+                let want = expected_media_type(*endpoint, version).unwrap();
+                assert!(detail.contains(want), "{detail:?} does not contain {want:?}");
+            }
+        });
     };
 
-    assert_matches!(
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.media_type = wrong_media_type.clone();
+    assert_detail_has(
         t.leader.http_post_upload(&req).await.unwrap_err(),
-        DapAbort::ReportTooLate
+        &[DapEndpoint::Upload],
     );
-}
-
-async_test_versions! { http_post_upload_task_expired }
-
-async fn get_reports_empty_response(version: DapVersion) {
-    let t = Test::new(version);
-    let task_id = &t.time_interval_task_id;
-
-    let report = t.gen_test_report(task_id).await;
-    let req = t.gen_test_upload_req(report.clone(), task_id).await;
 
-    // Upload report.
-    t.leader
-        .http_post_upload(&req)
-        .await
+    let mut req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::default(),
+                agg_param: Vec::default(),
+            },
+            t.leader
+                .unchecked_get_task_config(task_id)
+                .await
+                .leader_url
+                .join("collect")
+                .unwrap(),
+        )
+        .await;
+    req.media_type = wrong_media_type.clone();
+    assert_detail_has(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        &[DapEndpoint::Collect],
+    );
+
+    let mut req = t
+        .gen_test_agg_job_init_req(task_id, version, Vec::default())
+        .await;
+    req.media_type = wrong_media_type.clone();
+    assert_detail_has(
+        t.helper.http_post_aggregate(&req).await.unwrap_err(),
+        &[
+            DapEndpoint::AggregationJobInit,
+            DapEndpoint::AggregationJobContinue,
+        ],
+    );
+
+    let mut req = t.gen_test_agg_share_req(0, [0; 32]).await;
+    req.media_type = wrong_media_type;
+    assert_detail_has(
+        t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+        &[DapEndpoint::AggregateShare],
+    );
+}
+
+async_test_versions! { http_post_fail_wrong_media_type }
+
+// Test that a request with a missing content-type or with a DAP media type meant for another
+// endpoint is rejected as unsupported.
+async fn http_post_upload_fail_missing_or_misdirected_media_type(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.media_type = DapMediaType::Missing;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnsupportedMediaType(..))
+    );
+
+    req.media_type = DapMediaType::CollectReq;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnsupportedMediaType(..))
+    );
+}
+
+async_test_versions! { http_post_upload_fail_missing_or_misdirected_media_type }
+
+// draft02: The task ID is encoded in the report. Reject the report if it names a task other than
+// the one indicated by the request.
+async fn http_post_upload_fail_task_id_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.draft02_task_id = Some(t.fixed_size_task_id.clone());
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedTask)
+    );
+}
+
+async_test_version! { http_post_upload_fail_task_id_mismatch, Draft02 }
+
+// draft02: Extensions are carried in the clear, so the Leader checks them during upload. Reject
+// reports with duplicate extensions or with extensions the task doesn't permit.
+async fn http_post_upload_fail_invalid_extensions(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let extension = Extension::Unhandled {
+        typ: 0xfff,
+        payload: b"some extension".to_vec(),
+    };
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.report_metadata.extensions = vec![extension.clone()];
+    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::ReportRejected { .. })
+    );
+
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .permitted_extensions = vec![0xfff];
+
+    report.report_metadata.extensions = vec![extension.clone(), extension];
+    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::ReportRejected { .. })
+    );
+
+    report.report_metadata.extensions.pop();
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+}
+
+async_test_version! { http_post_upload_fail_invalid_extensions, Draft02 }
+
+async fn http_post_upload_fail_future_bucket(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .reject_future_buckets = true;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // The report falls into the next bucket, which hasn't started yet.
+    let report = t
+        .gen_test_report_at(
+            task_id,
+            task_config.bucket_window(t.now) + task_config.bucket_duration(),
+        )
+        .await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::ReportRejected { .. })
+    );
+
+    // A report in the current bucket is accepted.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_upload_fail_future_bucket }
+
+// Test that the Leader rejects reports encrypted under an HPKE config it does not have.
+async fn http_post_upload_fail_unknown_hpke_config_id(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let mut report = t.gen_test_report(task_id).await;
+    let unknown_config_id = (0..=u8::MAX)
+        .find(|id| {
+            !t.leader
+                .hpke_receiver_config_list
+                .iter()
+                .any(|receiver_config| receiver_config.config.id == *id)
+        })
+        .unwrap();
+    report.encrypted_input_shares[0].config_id = unknown_config_id;
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::ReportRejected { detail } => assert_eq!(detail, "No current HPKE configuration matches the indicated ID.")
+    );
+}
+
+async_test_versions! { http_post_upload_fail_unknown_hpke_config_id }
+
+// Test that the Leader rejects reports past the expiration date.
+async fn http_post_upload_task_expired(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.expired_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = DapRequest {
+        version: task_config.version,
+        media_type: DapMediaType::Report,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: report.get_encoded_with_param(&version),
+        url: task_config.leader_url.join("upload").unwrap(),
+        sender_auth: None,
+    };
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::ReportTooLate
+    );
+}
+
+async_test_versions! { http_post_upload_task_expired }
+
+// Test that the Leader rejects reports uploaded after the task expired, even if the report was
+// generated before the expiration date.
+async fn http_post_upload_task_expired_before_upload(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.expired_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    assert!(t.leader.get_current_time() >= task_config.expiration);
+
+    let report = t
+        .gen_test_report_at(task_id, task_config.expiration - 1)
+        .await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::ReportTooLate
+    );
+}
+
+async_test_versions! { http_post_upload_task_expired_before_upload }
+
+async fn get_reports_empty_response(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+
+    // Upload report.
+    t.leader
+        .http_post_upload(&req)
+        .await
         .expect("upload failed unexpectedly");
 
     // Get one report. This should return with the report that was uploaded earlier.
@@ -1354,6 +2014,45 @@ async fn http_post_collect_fail_invalid_batch_interval(version: DapVersion) {
 
 async_test_versions! { http_post_collect_fail_invalid_batch_interval }
 
+async fn http_post_collect_fail_batch_span_too_large(version: DapVersion) {
+    let mut t = Test::new(version);
+    Arc::get_mut(&mut t.leader)
+        .unwrap()
+        .global_config
+        .max_batch_span = Some(1);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a batch interval that spans two buckets.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch spans too many buckets.
+    assert_matches!(err, DapAbort::BatchInvalid { task_id: got, .. } if &got == task_id);
+}
+
+async_test_versions! { http_post_collect_fail_batch_span_too_large }
+
 async fn http_post_collect_succeed_max_batch_interval(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
@@ -1386,6 +2085,37 @@ async fn http_post_collect_succeed_max_batch_interval(version: DapVersion) {
 
 async_test_versions! { http_post_collect_succeed_max_batch_interval }
 
+// Test that any of the Collectors configured for a task may collect.
+async fn http_post_collect_backup_collector(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let mut req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now),
+                        duration: task_config.time_precision,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    req.sender_auth = Some(t.backup_collector_token.clone());
+
+    t.leader.http_post_collect(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_collect_backup_collector }
+
 // Send a collect request with an overlapping batch interval.
 async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion) {
     let t = Test::new(version);
@@ -1415,73 +2145,643 @@ async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion)
 
 async_test_versions! { http_post_collect_fail_overlapping_batch_interval }
 
-// Test a successful collect request submission.
-// This checks that the Leader reponds with the collect ID with the ID associated to the request.
-async fn http_post_collect_success(version: DapVersion) {
+// Simulate two concurrent collections of the same batch, both of which pass the overlap check
+// before either marks the batch as collected. Only one of them may succeed, though it may mark the
+// batch again if it is retried.
+async fn helper_concurrent_collections_of_same_batch(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Collector: Create a CollectReq.
-    let collector_collect_req = CollectionReq {
-        draft02_task_id: task_id.for_request_payload(&version),
-        query: task_config.query_for_current_batch_window(t.now),
-        agg_param: Vec::default(),
-    };
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
-            task_id,
-            collector_collect_req.clone(),
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
-
-    // Leader: Handle the CollectReq received from Collector.
-    let url = t.leader.http_post_collect(&req).await.unwrap();
-    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
-    let (_leader_task_id, leader_collect_id, leader_collect_req) = &resp[0];
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
 
-    // Check that the CollectReq sent by Collector is the same that is received by Leader.
-    assert_eq!(&collector_collect_req, leader_collect_req);
+    let query = task_config.query_for_current_batch_window(t.now);
+    let batch_sel = BatchSelector::try_from(query).unwrap();
+    let batch_span = task_config.batch_span(&batch_sel).unwrap();
+    let first_id = CollectionJobId(thread_rng().gen());
+    let second_id = CollectionJobId(thread_rng().gen());
+    for collect_id in [&first_id, &second_id] {
+        assert!(!t
+            .helper
+            .is_batch_overlapping(task_id, Some(collect_id), &batch_span)
+            .await
+            .unwrap());
+        t.helper.get_agg_share(task_id, &batch_span).await.unwrap();
+    }
 
-    // Check that the collect_id included in the URI is the same with the one received
-    // by Leader.
-    let path = url.path().to_string();
-    let mut router = Router::new();
-    router
-        .insert("/:version/collect/task/:task_id/req/:collect_id", true)
+    t.helper
+        .mark_collected(task_id, &first_id, &batch_span)
+        .await
         .unwrap();
-    let url_match = router.at(&path).unwrap();
-    let collector_collect_id = url_match.params.get("collect_id").unwrap();
-    assert_eq!(
-        collector_collect_id.to_string(),
-        leader_collect_id.to_base64url()
+    assert_matches!(
+        t.helper
+            .mark_collected(task_id, &second_id, &batch_span)
+            .await,
+        Err(DapError::Abort(DapAbort::BatchOverlap { .. }))
+    );
+    t.helper
+        .mark_collected(task_id, &first_id, &batch_span)
+        .await
+        .unwrap();
+}
+
+async_test_versions! { helper_concurrent_collections_of_same_batch }
+
+// A batch that overlaps a collected batch is rejected without marking any of its buckets, so that
+// the rest of the batch can still be collected.
+async fn mark_collected_overlapping_batch_marks_nothing(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let time_precision = task_config.time_precision;
+    let start = task_config.quantized_time_lower_bound(t.now) - time_precision;
+
+    // Aggregate a report in each of two adjacent buckets.
+    for time in [start, start + time_precision] {
+        let report = t.gen_test_report_at(task_id, time).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    let batch_sel = |start, duration| BatchSelector::TimeInterval {
+        batch_interval: Interval { start, duration },
+    };
+    let first = batch_sel(start, time_precision);
+    let second = batch_sel(start + time_precision, time_precision);
+    let both = batch_sel(start, 2 * time_precision);
+
+    let collect_id = |byte| CollectionJobId([byte; 16]);
+    t.helper
+        .mark_collected(
+            task_id,
+            &collect_id(1),
+            &task_config.batch_span(&second).unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_matches!(
+        t.helper
+            .mark_collected(
+                task_id,
+                &collect_id(2),
+                &task_config.batch_span(&both).unwrap()
+            )
+            .await,
+        Err(DapError::Abort(DapAbort::BatchOverlap { .. }))
+    );
+    assert!(!t
+        .helper
+        .is_batch_overlapping(task_id, None, &task_config.batch_span(&first).unwrap())
+        .await
+        .unwrap());
+}
+
+async_test_versions! { mark_collected_overlapping_batch_marks_nothing }
+
+// The Leader accepts two collect jobs for the same batch before either is processed. Only the
+// first one to be processed produces a result; the other is abandoned.
+async fn leader_concurrent_collect_jobs_for_same_batch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let host = task_config.leader_url.host_str().unwrap();
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector->Leader: Initialize both collection jobs.
+    let query = task_config.query_for_current_batch_window(t.now);
+    for _ in 0..2 {
+        let req = t
+            .collector_authorized_req(
+                task_config.version,
+                DapMediaType::CollectReq,
+                task_id,
+                CollectionReq {
+                    draft02_task_id: task_id.for_request_payload(&task_config.version),
+                    query: query.clone(),
+                    agg_param: Vec::default(),
+                },
+                task_config.helper_url.join("collect").unwrap(),
+            )
+            .await;
+        t.leader.http_post_collect(&req).await.unwrap();
+    }
+    let collect_jobs = t.leader.get_pending_collect_jobs().await.unwrap();
+    assert_eq!(collect_jobs.len(), 2);
+
+    // Leader->Helper: Run both collection jobs.
+    let (_, first_id, first_req) = &collect_jobs[0];
+    t.leader
+        .run_collect_job(task_id, first_id, &task_config, first_req, host)
+        .await
+        .unwrap();
+    let (_, second_id, second_req) = &collect_jobs[1];
+    assert_matches!(
+        t.leader
+            .run_collect_job(task_id, second_id, &task_config, second_req, host)
+            .await,
+        Err(DapAbort::BatchOverlap { .. })
+    );
+
+    assert_matches!(
+        t.leader.poll_collect_job(task_id, first_id).await.unwrap(),
+        DapCollectJob::Done(..)
+    );
+    assert_matches!(
+        t.leader.poll_collect_job(task_id, second_id).await.unwrap(),
+        DapCollectJob::Pending
+    );
+
+    // The job that can't finish doesn't hold up the queue.
+    t.leader
+        .process(&MockAggregatorReportSelector(task_id.clone()), host)
+        .await
+        .unwrap();
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+    assert_matches!(
+        t.leader.poll_collect_job(task_id, second_id).await.unwrap(),
+        DapCollectJob::Abandoned
+    );
+}
+
+async_test_versions! { leader_concurrent_collect_jobs_for_same_batch }
+
+// If finishing a collect job fails after the batch was marked as collected, then the job is
+// retried the next time the queue is processed.
+async fn leader_collect_job_retried_after_finish_fails(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let host = task_config.leader_url.host_str().unwrap();
+    let report_sel = MockAggregatorReportSelector(task_id.clone());
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector->Leader: Initialize the collection job.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&task_config.version),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+    let collect_jobs = t.leader.get_pending_collect_jobs().await.unwrap();
+    assert_eq!(collect_jobs.len(), 1);
+    let (_, collect_id, _) = &collect_jobs[0];
+
+    // The batch is marked as collected, but the job fails to finish.
+    t.leader
+        .fail_finish_collect_job
+        .store(true, Ordering::SeqCst);
+    assert!(t.leader.process(&report_sel, host).await.is_err());
+    assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Pending
+    );
+
+    // The retry completes.
+    let telem = t.leader.process(&report_sel, host).await.unwrap();
+    assert_eq!(telem.reports_collected, 1);
+    assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Done(..)
+    );
+}
+
+async_test_versions! { leader_collect_job_retried_after_finish_fails }
+
+// The precomputed batch span consists of the same buckets as the batch span computed from the
+// batch selector directly.
+async fn batch_span_matches_batch_span_for_sel(version: DapVersion) {
+    let t = Test::new(version);
+
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: task_config.quantized_time_lower_bound(t.now),
+            duration: 3 * task_config.time_precision,
+        },
+    };
+    let batch_span = task_config.batch_span(&batch_sel).unwrap();
+    assert_eq!(batch_span.len(), 3);
+    assert_eq!(
+        batch_span.buckets().iter().cloned().collect::<HashSet<_>>(),
+        task_config.batch_span_for_sel(&batch_sel).unwrap()
+    );
+
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    let batch_sel = BatchSelector::FixedSizeByBatchId {
+        batch_id: BatchId(thread_rng().gen()),
+    };
+    let batch_span = task_config.batch_span(&batch_sel).unwrap();
+    assert_eq!(batch_span.len(), 1);
+    assert_eq!(
+        batch_span.buckets().iter().cloned().collect::<HashSet<_>>(),
+        task_config.batch_span_for_sel(&batch_sel).unwrap()
+    );
+
+    // Both fail for batch selectors that aren't compatible with the task.
+    assert!(task_config
+        .batch_span(&BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: 0,
+                duration: task_config.time_precision,
+            },
+        })
+        .is_err());
+}
+
+async_test_versions! { batch_span_matches_batch_span_for_sel }
+
+// For time-interval tasks, reports are aggregated in buckets of the configured duration.
+async fn batch_span_for_bucket_duration(version: DapVersion) {
+    let t = Test::new(version);
+    let mut rng = thread_rng();
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let time_precision = task_config.time_precision;
+
+    // By default, the bucket duration is the time precision.
+    assert_eq!(task_config.bucket_duration(), time_precision);
+
+    for bucket_duration in [2 * time_precision, 24 * time_precision] {
+        task_config.query = DapQueryConfig::TimeInterval {
+            bucket_duration: Some(bucket_duration),
+        };
+        assert!(task_config.is_bucket_duration_valid());
+        assert_eq!(task_config.bucket_duration(), bucket_duration);
+
+        let start = task_config.bucket_window(t.now);
+        assert_eq!(start % bucket_duration, 0);
+        let batch_sel = BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start,
+                duration: 3 * bucket_duration,
+            },
+        };
+        let batch_span = task_config.batch_span(&batch_sel).unwrap();
+        assert_eq!(batch_span.len(), 3);
+        assert_eq!(
+            batch_span.buckets().iter().cloned().collect::<HashSet<_>>(),
+            task_config.batch_span_for_sel(&batch_sel).unwrap()
+        );
+
+        // Reports whose timestamps fall into the same bucket are assigned to it.
+        let report_meta =
+            [start, start + bucket_duration - 1, start + bucket_duration].map(|time| {
+                ReportMetadata {
+                    id: ReportId(rng.gen()),
+                    time,
+                    extensions: Vec::new(),
+                }
+            });
+        let span = task_config
+            .batch_span_for_meta(&PartialBatchSelector::TimeInterval, report_meta.iter())
+            .unwrap();
+        assert_eq!(span.len(), 2);
+        assert_eq!(
+            span[&DapBatchBucket::TimeInterval {
+                batch_window: start
+            }]
+                .len(),
+            2
+        );
+        assert_eq!(
+            span[&DapBatchBucket::TimeInterval {
+                batch_window: start + bucket_duration
+            }]
+                .len(),
+            1
+        );
+    }
+
+    // The bucket duration must be a positive multiple of the time precision. Otherwise it is
+    // ignored.
+    for bucket_duration in [0, time_precision + 1] {
+        task_config.query = DapQueryConfig::TimeInterval {
+            bucket_duration: Some(bucket_duration),
+        };
+        assert!(!task_config.is_bucket_duration_valid());
+        assert_eq!(task_config.bucket_duration(), time_precision);
+        assert_eq!(
+            task_config.bucket_window(t.now),
+            task_config.quantized_time_lower_bound(t.now)
+        );
+    }
+}
+
+async_test_versions! { batch_span_for_bucket_duration }
+
+// Time-interval query configs without a bucket duration keep the encoding used before the query
+// type had any parameters.
+#[test]
+fn query_config_serialization() {
+    for (query_config, json) in [
+        (
+            DapQueryConfig::TimeInterval {
+                bucket_duration: None,
+            },
+            r#""time_interval""#,
+        ),
+        (
+            DapQueryConfig::TimeInterval {
+                bucket_duration: Some(7200),
+            },
+            r#"{"time_interval":{"bucket_duration":7200}}"#,
+        ),
+        (
+            DapQueryConfig::FixedSize { max_batch_size: 2 },
+            r#"{"fixed_size":{"max_batch_size":2}}"#,
+        ),
+    ] {
+        assert_eq!(serde_json::to_string(&query_config).unwrap(), json);
+        assert_eq!(
+            serde_json::from_str::<DapQueryConfig>(json).unwrap(),
+            query_config
+        );
+    }
+
+    assert_eq!(
+        serde_json::from_str::<DapQueryConfig>(r#"{"time_interval":{}}"#).unwrap(),
+        DapQueryConfig::TimeInterval {
+            bucket_duration: None
+        }
+    );
+}
+
+// Pending reports expire once they are older than the task's TTL, which defaults to the report
+// storage epoch duration.
+async fn pending_report_expiry(version: DapVersion) {
+    let t = Test::new(version);
+    let global_config = t.leader.get_global_config();
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let epoch = global_config.report_storage_epoch_duration;
+
+    assert!(!task_config.is_pending_report_expired(global_config, t.now - epoch, t.now));
+    assert!(task_config.is_pending_report_expired(global_config, t.now - epoch - 1, t.now));
+
+    task_config.pending_report_ttl = Some(60);
+    assert!(!task_config.is_pending_report_expired(global_config, t.now - 60, t.now));
+    assert!(task_config.is_pending_report_expired(global_config, t.now - 61, t.now));
+}
+
+async_test_versions! { pending_report_expiry }
+
+// An expired pending report of a fixed-size task is dropped instead of aggregated, and the batch
+// it was assigned to no longer counts it, so the batch is filled by the next report.
+async fn leader_get_reports_drops_expired_fixed_size_report(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .pending_report_ttl = Some(60);
+    let report_sel = MockAggregatorReportSelector(task_id.clone());
+
+    let report = t.gen_test_report_at(task_id, t.now - 120).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    let (_task_id, part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
+    assert!(reports.is_empty());
+    let batch_id = match part_batch_sel {
+        PartialBatchSelector::FixedSizeByBatchId { batch_id } => batch_id,
+        _ => panic!("unexpected partial batch selector"),
+    };
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    let (_task_id, part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(
+        part_batch_sel,
+        PartialBatchSelector::FixedSizeByBatchId { batch_id }
+    );
+}
+
+async_test_versions! { leader_get_reports_drops_expired_fixed_size_report }
+
+// The boundaries of a queried batch interval must be aligned to the bucket duration.
+async fn http_post_collect_fail_misaligned_bucket_duration(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let bucket_duration = {
+        let mut tasks = t.leader.tasks.lock().unwrap();
+        let task_config = tasks.get_mut(task_id).unwrap();
+        task_config.query = DapQueryConfig::TimeInterval {
+            bucket_duration: Some(2 * task_config.time_precision),
+        };
+        task_config.bucket_duration()
+    };
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let collect_req = |start| CollectionReq {
+        draft02_task_id: task_id.for_request_payload(&version),
+        query: Query::TimeInterval {
+            batch_interval: Interval {
+                start,
+                duration: bucket_duration,
+            },
+        },
+        agg_param: Vec::default(),
+    };
+
+    // The batch interval is aligned to the time precision, but not to the bucket duration.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            collect_req(task_config.bucket_window(t.now) + task_config.time_precision),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await,
+        Err(DapAbort::BatchInvalid { .. })
+    );
+
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            collect_req(task_config.bucket_window(t.now)),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_collect_fail_misaligned_bucket_duration }
+
+// Test a successful collect request submission.
+// This checks that the Leader reponds with the collect ID with the ID associated to the request.
+async fn http_post_collect_success(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq.
+    let collector_collect_req = CollectionReq {
+        draft02_task_id: task_id.for_request_payload(&version),
+        query: task_config.query_for_current_batch_window(t.now),
+        agg_param: Vec::default(),
+    };
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            collector_collect_req.clone(),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let url = t.leader.http_post_collect(&req).await.unwrap();
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (_leader_task_id, leader_collect_id, leader_collect_req) = &resp[0];
+
+    // Check that the CollectReq sent by Collector is the same that is received by Leader.
+    assert_eq!(&collector_collect_req, leader_collect_req);
+
+    // Check that the collect_id included in the URI is the same with the one received
+    // by Leader.
+    let path = url.path().to_string();
+    let mut router = Router::new();
+    router
+        .insert("/:version/collect/task/:task_id/req/:collect_id", true)
+        .unwrap();
+    let url_match = router.at(&path).unwrap();
+    let collector_collect_id = url_match.params.get("collect_id").unwrap();
+    assert_eq!(
+        collector_collect_id.to_string(),
+        leader_collect_id.to_base64url()
+    );
+}
+
+async_test_versions! { http_post_collect_success }
+
+// Test that the Leader handles queries from the Collector properly.
+async fn http_post_collect_invalid_query(version: DapVersion) {
+    let mut rng = thread_rng();
+    let t = Test::new(version);
+
+    // Leader expects "time_interval" query, but Collector sent "fixed_size".
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            DapMediaType::CollectReq,
+            &t.time_interval_task_id,
+            CollectionReq {
+                draft02_task_id: t.time_interval_task_id.for_request_payload(&version),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: BatchId(rng.gen()),
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::QueryMismatch { .. }
+    );
+
+    // Collector indicates unrecognized batch ID.
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            DapMediaType::CollectReq,
+            &t.fixed_size_task_id,
+            CollectionReq {
+                draft02_task_id: t.fixed_size_task_id.for_request_payload(&version),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: BatchId(rng.gen()), // Unrecognized batch ID
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::BatchInvalid { .. }
     );
 }
 
-async_test_versions! { http_post_collect_success }
+async_test_versions! { http_post_collect_invalid_query }
 
-// Test that the Leader handles queries from the Collector properly.
-async fn http_post_collect_invalid_query(version: DapVersion) {
-    let mut rng = thread_rng();
+// Test that the Leader rejects a collect request whose query type doesn't match the task's.
+async fn http_post_collect_fail_query_mismatch(version: DapVersion) {
     let t = Test::new(version);
 
-    // Leader expects "time_interval" query, but Collector sent "fixed_size".
-    let task_config = t
-        .leader
-        .unchecked_get_task_config(&t.time_interval_task_id)
-        .await;
+    // Time-interval query for a fixed-size task.
+    let task_id = &t.fixed_size_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
     let req = t
         .collector_authorized_req(
             task_config.version,
             DapMediaType::CollectReq,
-            &t.time_interval_task_id,
+            task_id,
             CollectionReq {
-                draft02_task_id: t.time_interval_task_id.for_request_payload(&version),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: BatchId(rng.gen()),
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now),
+                        duration: task_config.time_precision,
+                    },
                 },
                 agg_param: Vec::default(),
             },
@@ -1493,20 +2793,65 @@ async fn http_post_collect_invalid_query(version: DapVersion) {
         DapAbort::QueryMismatch { .. }
     );
 
-    // Collector indicates unrecognized batch ID.
-    let task_config = t
-        .leader
-        .unchecked_get_task_config(&t.fixed_size_task_id)
+    // Fixed-size query for a time-interval task. (draft02 doesn't support querying the current
+    // batch.)
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: if version == DapVersion::Draft02 {
+                    Query::FixedSizeByBatchId {
+                        batch_id: BatchId(thread_rng().gen()),
+                    }
+                } else {
+                    Query::FixedSizeCurrentBatch
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
         .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::QueryMismatch { .. }
+    );
+}
+
+async_test_versions! { http_post_collect_fail_query_mismatch }
+
+// Test that the Leader tells the Collector to come back later if it names a fixed-size batch that
+// has not yet aggregated enough reports.
+async fn http_post_collect_fail_batch_not_ready(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .min_batch_size = 2;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
     let req = t
         .collector_authorized_req(
             task_config.version,
             DapMediaType::CollectReq,
-            &t.fixed_size_task_id,
+            task_id,
             CollectionReq {
-                draft02_task_id: t.fixed_size_task_id.for_request_payload(&version),
+                draft02_task_id: task_id.for_request_payload(&version),
                 query: Query::FixedSizeByBatchId {
-                    batch_id: BatchId(rng.gen()), // Unrecognized batch ID
+                    batch_id: t.leader.current_batch_id(task_id, &task_config).unwrap(),
                 },
                 agg_param: Vec::default(),
             },
@@ -1515,11 +2860,11 @@ async fn http_post_collect_invalid_query(version: DapVersion) {
         .await;
     assert_matches!(
         t.leader.http_post_collect(&req).await.unwrap_err(),
-        DapAbort::BatchInvalid { .. }
+        DapAbort::BatchNotReady { .. }
     );
 }
 
-async_test_versions! { http_post_collect_invalid_query }
+async_test_versions! { http_post_collect_fail_batch_not_ready }
 
 // Test HTTP POST requests with a wrong DAP version.
 async fn http_post_fail_unknown_version(version: DapVersion) {
@@ -1534,11 +2879,36 @@ async fn http_post_fail_unknown_version(version: DapVersion) {
     req.url = task_config.leader_url.join("upload").unwrap();
 
     let err = t.leader.http_post_upload(&req).await.unwrap_err();
-    assert_matches!(err, DapAbort::BadRequest(details) => assert_eq!(details, "DAP version of request is not recognized"));
+    assert_matches!(err, DapAbort::UnsupportedVersion(details) => assert_eq!(details, "DAP version of request is not recognized"));
 }
 
 async_test_versions! { http_post_fail_unknown_version }
 
+// Test that requests for draft09, whose flows are not yet implemented, are rejected.
+async fn http_post_fail_draft09_not_implemented(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.version = DapVersion::Draft09;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::UnsupportedVersion(..)
+    );
+
+    let mut req = t
+        .gen_test_agg_job_init_req(task_id, version, Vec::default())
+        .await;
+    req.version = DapVersion::Draft09;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await.unwrap_err(),
+        DapAbort::UnsupportedVersion(..)
+    );
+}
+
+async_test_versions! { http_post_fail_draft09_not_implemented }
+
 async fn http_post_upload(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
@@ -1554,6 +2924,75 @@ async fn http_post_upload(version: DapVersion) {
 
 async_test_versions! { http_post_upload }
 
+async fn http_post_upload_client_auth(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .client_auth = true;
+
+    // Expect failure due to missing bearer token.
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+
+    // Expect failure due to incorrect bearer token.
+    req.sender_auth = Some(BearerToken::from("incorrect auth token!".to_string()));
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+
+    // Expect success with the Client's bearer token.
+    req.sender_auth = t.leader.client_token.clone();
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
+}
+
+async_test_versions! { http_post_upload_client_auth }
+
+// A quiescing task rejects new uploads, but reports uploaded before the task started quiescing are
+// still aggregated and collected.
+async fn http_post_upload_fail_quiescing(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .quiescing = true;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::ReportRejected { detail }) if detail.contains("quiescing")
+    );
+
+    t.run_agg_job(task_id).await.unwrap();
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+}
+
+async_test_versions! { http_post_upload_fail_quiescing }
+
 async fn e2e_time_interval(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
@@ -1573,18 +3012,128 @@ async fn e2e_time_interval(version: DapVersion) {
     t.run_col_job(task_id, &query).await.unwrap();
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 2,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="aggregate"}"#: 2,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="collect"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="collected"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="collected"}"#: 1,
+        r#"test_helper_aggregation_job_gauge{host="helper.org",role="helper"}"#: 0,
+        (format!(r#"test_leader_aggregation_job_duration_seconds_count{{host="leader.com",query_type="time_interval",version="{version}"}}"#)): 1,
     });
 }
 
 async_test_versions! { e2e_time_interval }
 
+// The Helper loses its state for the aggregation job after initialization. The Leader should
+// re-initialize the job with the same reports, and each report should be counted exactly once.
+async fn e2e_time_interval_helper_state_loss(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    // Client: Send upload request to Leader.
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Run aggregation job, during which the Helper loses its state.
+    t.leader.lose_helper_state.store(true, Ordering::SeqCst);
+    t.run_agg_job(task_id).await.unwrap();
+    assert!(!t.leader.lose_helper_state.load(Ordering::SeqCst));
+
+    // Collector: Create collection job and poll result.
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="collected"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="collected"}"#: 1,
+    });
+}
+
+async_test_versions! { e2e_time_interval_helper_state_loss }
+
+// Collect over a time window in which some sub-intervals have no reports.
+async fn e2e_time_interval_sparse_window(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let time_precision = task_config.time_precision;
+
+    // Client: Send reports in the first and last sub-intervals of the window, leaving the middle
+    // one empty.
+    for time in [t.now - 2 * time_precision, t.now] {
+        let report = t.gen_test_report_at(task_id, time).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+
+    // Leader: Run aggregation jobs. Each job aggregates a single report.
+    for _ in 0..2 {
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    // Collector: Create collection job and poll result.
+    let query = Query::TimeInterval {
+        batch_interval: Interval {
+            start: task_config.quantized_time_lower_bound(t.now) - 2 * time_precision,
+            duration: 3 * time_precision,
+        },
+    };
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="collected"}"#: 2,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="collected"}"#: 2,
+    });
+}
+
+async_test_versions! { e2e_time_interval_sparse_window }
+
+// Collect over a time window in which some sub-intervals have no reports and the total number of
+// reports is below the minimum batch size.
+async fn e2e_time_interval_sparse_window_below_min_batch_size(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .min_batch_size = 3;
+    }
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let time_precision = task_config.time_precision;
+
+    for time in [t.now - 2 * time_precision, t.now] {
+        let report = t.gen_test_report_at(task_id, time).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+    for _ in 0..2 {
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    let query = Query::TimeInterval {
+        batch_interval: Interval {
+            start: task_config.quantized_time_lower_bound(t.now) - 2 * time_precision,
+            duration: 3 * time_precision,
+        },
+    };
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    // The collection job is not complete until enough reports have been aggregated.
+    assert_eq!(t.leader.get_pending_collect_jobs().await.unwrap().len(), 1);
+}
+
+async_test_versions! { e2e_time_interval_sparse_window_below_min_batch_size }
+
 async fn e2e_fixed_size(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.fixed_size_task_id;
@@ -1606,18 +3155,96 @@ async fn e2e_fixed_size(version: DapVersion) {
     t.run_col_job(task_id, &query).await.unwrap();
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 2,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="aggregate"}"#: 2,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="collect"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="collected"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="collected"}"#: 1,
+        r#"test_helper_aggregation_job_gauge{host="helper.org",role="helper"}"#: 0,
+        (format!(r#"test_leader_aggregation_job_duration_seconds_count{{host="leader.com",query_type="fixed_size",version="{version}"}}"#)): 1,
     });
 }
 
 async_test_versions! { e2e_fixed_size }
 
+// Test that the Leader rejects a report whose taskprov extension doesn't match the task ID, e.g.,
+// because the Client targets a different taskprov draft. Following section 5.1 of the taskprov
+// draft, the task is unrecognized.
+async fn http_post_upload_taskprov_task_id_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+    let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+
+    let taskprov_ext_payload = taskprov::TaskConfig {
+        task_info: "cool task".as_bytes().to_vec(),
+        aggregator_endpoints: vec![
+            taskprov::UrlBytes {
+                bytes: b"https://leader.com/".to_vec(),
+            },
+            taskprov::UrlBytes {
+                bytes: b"http://helper.org:8788/".to_vec(),
+            },
+        ],
+        query_config: taskprov::QueryConfig {
+            time_precision: 3600,
+            max_batch_query_count: 1,
+            min_batch_size: 1,
+            var: taskprov::QueryConfigVar::FixedSize { max_batch_size: 2 },
+        },
+        task_expiration: t.now + 86400 * 14,
+        vdaf_config: taskprov::VdafConfig {
+            dp_config: taskprov::DpConfig::None,
+            var: taskprov::VdafTypeVar::Prio3Aes128Count,
+        },
+    }
+    .get_encoded_with_param(&t.leader.global_config.taskprov_version);
+
+    // The task ID was not derived from the extension as specified by our taskprov version.
+    let task_id = TaskId(thread_rng().gen());
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(version, Some(&t.time_interval_task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(version, Some(&t.time_interval_task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+    let report = vdaf
+        .produce_report_with_extensions(
+            &hpke_config_list,
+            t.now,
+            &task_id,
+            DapMeasurement::U64(1),
+            vec![Extension::Taskprov {
+                payload: taskprov_ext_payload,
+            }],
+            version,
+        )
+        .unwrap();
+
+    let req = DapRequest {
+        version,
+        media_type: DapMediaType::Report,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: report.get_encoded_with_param(&version),
+        url: Url::parse("https://leader.com/upload").unwrap(),
+        sender_auth: None,
+    };
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedTask)
+    );
+}
+
+async_test_version! { http_post_upload_taskprov_task_id_mismatch, Draft02 }
+
 async fn e2e_taskprov(version: DapVersion) {
     let t = Test::new(version);
     let vdaf = VdafConfig::Prio3(Prio3Config::Count);
@@ -1696,6 +3323,20 @@ async fn e2e_taskprov(version: DapVersion) {
 
     // The Leader is now configured with the task.
     let task_config = t.leader.unchecked_get_task_config(&taskprov_id).await;
+    assert!(crate::taskprov::is_taskprov_task(
+        t.leader.global_config.taskprov_version,
+        &t.leader.taskprov_vdaf_verify_key_init,
+        &taskprov_id,
+        &task_config,
+    ));
+    assert!(!crate::taskprov::is_taskprov_task(
+        t.leader.global_config.taskprov_version,
+        &t.leader.taskprov_vdaf_verify_key_init,
+        &t.fixed_size_task_id,
+        &t.leader
+            .unchecked_get_task_config(&t.fixed_size_task_id)
+            .await,
+    ));
 
     // Collector: Create collection job and poll result.
     let query = Query::FixedSizeByBatchId {
@@ -1707,13 +3348,13 @@ async fn e2e_taskprov(version: DapVersion) {
     t.run_col_job(&taskprov_id, &query).await.unwrap();
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 2,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="aggregate"}"#: 2,
+        r#"test_helper_inbound_request_counter{host="helper.org",role="helper",type="collect"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="collected"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="collected"}"#: 1,
+        r#"test_helper_aggregation_job_gauge{host="helper.org",role="helper"}"#: 0,
     });
 }
 
@@ -16,6 +16,7 @@ use ring::{
 };
 use serde::{Deserialize, Serialize};
 use std::str;
+use tracing::warn;
 use url::Url;
 
 /// DAP taskprov version.
@@ -112,6 +113,34 @@ pub(crate) fn compute_vdaf_verify_key(
     )
 }
 
+/// Check whether the task's VDAF verification key is the one derived from the taskprov secret
+/// `verify_key_init`, i.e., whether the task was most likely configured via taskprov.
+pub fn is_taskprov_task(
+    version: TaskprovVersion,
+    verify_key_init: &[u8; 32],
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+) -> bool {
+    if matches!(version, TaskprovVersion::Unknown) {
+        return false;
+    }
+
+    let vdaf_type = match &task_config.vdaf {
+        VdafConfig::Prio3(Prio3Config::Count) => VdafType::Prio3Aes128Count,
+        VdafConfig::Prio3(Prio3Config::Sum { .. }) => VdafType::Prio3Aes128Sum,
+        VdafConfig::Prio3(Prio3Config::Histogram { .. }) => VdafType::Prio3Aes128Histogram,
+        VdafConfig::Prio2 { .. } => return false,
+    };
+
+    match (
+        compute_vdaf_verify_key(version, verify_key_init, task_id, vdaf_type),
+        &task_config.vdaf_verify_key,
+    ) {
+        (VdafVerifyKey::Prio3(expected), VdafVerifyKey::Prio3(actual)) => expected == *actual,
+        _ => false,
+    }
+}
+
 /// Opt out due to invalid configuration.
 //
 // TODO taskprov spec: Decide if this should be a different error type.
@@ -176,25 +205,35 @@ impl From<QueryConfigVar> for DapQueryConfig {
             QueryConfigVar::FixedSize { max_batch_size } => DapQueryConfig::FixedSize {
                 max_batch_size: max_batch_size.into(),
             },
-            QueryConfigVar::TimeInterval => DapQueryConfig::TimeInterval,
+            QueryConfigVar::TimeInterval => DapQueryConfig::TimeInterval {
+                bucket_duration: None,
+            },
         }
     }
 }
 
-impl From<VdafTypeVar> for VdafConfig {
-    fn from(var: VdafTypeVar) -> Self {
-        match var {
-            VdafTypeVar::Prio3Aes128Count => VdafConfig::Prio3(Prio3Config::Count),
-            VdafTypeVar::Prio3Aes128Histogram { buckets } => {
-                VdafConfig::Prio3(Prio3Config::Histogram { buckets })
-            }
-            VdafTypeVar::Prio3Aes128Sum { bit_length } => VdafConfig::Prio3(Prio3Config::Sum {
-                bits: bit_length.into(),
-            }),
-            VdafTypeVar::Poplar1Aes128 { .. } | VdafTypeVar::NotImplemented(..) => {
-                unreachable!("VDAF not implemented")
-            }
+/// Convert the VDAF indicated by a taskprov task configuration into a [`VdafConfig`]. Opt out if
+/// the VDAF is not supported.
+//
+// TODO Support Poplar1. This requires a Poplar1 variant of `VdafConfig` and plumbing for the
+// aggregation parameter, neither of which exist yet.
+fn vdaf_config_from_taskprov(task_id: &TaskId, var: VdafTypeVar) -> Result<VdafConfig, DapError> {
+    match var {
+        VdafTypeVar::Prio3Aes128Count => Ok(VdafConfig::Prio3(Prio3Config::Count)),
+        VdafTypeVar::Prio3Aes128Histogram { buckets } => {
+            Ok(VdafConfig::Prio3(Prio3Config::Histogram { buckets }))
         }
+        VdafTypeVar::Prio3Aes128Sum { bit_length } => Ok(VdafConfig::Prio3(Prio3Config::Sum {
+            bits: bit_length.into(),
+        })),
+        VdafTypeVar::Poplar1Aes128 { .. } => Err(malformed_task_config(
+            task_id,
+            "The task config indicates Poplar1, which is not supported".into(),
+        )),
+        VdafTypeVar::NotImplemented(typ) => Err(malformed_task_config(
+            task_id,
+            format!("The task config indicates an unrecognized VDAF (0x{typ:08x})"),
+        )),
     }
 }
 
@@ -217,6 +256,7 @@ impl DapTaskConfig {
             ));
         }
         let vdaf_type = VdafType::from(task_config.vdaf_config.var.clone());
+        let vdaf = vdaf_config_from_taskprov(task_id, task_config.vdaf_config.var)?;
         Ok(DapTaskConfig {
             version: dap_version,
             leader_url: url_from_bytes(task_id, &task_config.aggregator_endpoints[0].bytes)?,
@@ -225,7 +265,7 @@ impl DapTaskConfig {
             expiration: task_config.task_expiration,
             min_batch_size: task_config.query_config.min_batch_size.into(),
             query: DapQueryConfig::from(task_config.query_config.var),
-            vdaf: VdafConfig::from(task_config.vdaf_config.var),
+            vdaf,
             vdaf_verify_key: compute_vdaf_verify_key(
                 taskprov_version,
                 vdaf_verify_key_init,
@@ -233,10 +273,96 @@ impl DapTaskConfig {
                 vdaf_type,
             ),
             collector_hpke_config: collector_hpke_config.clone(),
+            upload_rate_limit: None,
+            max_reports_per_agg_job: None,
+            client_auth: false,
+            task_scoped_hpke_config: false,
+            quiescing: false,
+            pending_report_ttl: None,
+            collect_poll_retry_after: None,
+            permitted_extensions: Vec::new(),
+            reject_future_buckets: false,
         })
     }
 }
 
+/// Criteria an Aggregator uses to decide whether to opt in to a task provisioned via taskprov.
+/// Each criterion is optional; if none are set, then every task is accepted.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TaskprovPolicy {
+    /// If set, then the task's VDAF must be one of these.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_vdafs: Option<Vec<VdafConfig>>,
+
+    /// If set, then the maximum batch size of fixed-size tasks must not exceed this value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_size: Option<u64>,
+
+    /// If set, then the minimum batch size of the task must be at least this value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_batch_size: Option<u64>,
+
+    /// If set, then the task's Leader must be one of these.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_leader_urls: Option<Vec<Url>>,
+
+    /// If set, then violations of the policy are logged, but the Aggregator opts in anyway. This
+    /// is useful for evaluating a policy before enforcing it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl TaskprovPolicy {
+    /// Check the task configuration against the policy. If the task violates the policy, then
+    /// return a description of the violation. In dry-run mode, the violation is logged and `None`
+    /// is returned.
+    pub fn opt_out_reason(&self, task_config: &DapTaskConfig) -> Option<String> {
+        let reason = self.violation(task_config)?;
+        if self.dry_run {
+            warn!("taskprov policy (dry run): would opt out of task: {reason}");
+            None
+        } else {
+            Some(reason)
+        }
+    }
+
+    fn violation(&self, task_config: &DapTaskConfig) -> Option<String> {
+        if let Some(ref allowed_vdafs) = self.allowed_vdafs {
+            if !allowed_vdafs.contains(&task_config.vdaf) {
+                return Some(format!("VDAF {:?} is not allowed", task_config.vdaf));
+            }
+        }
+
+        if let (Some(limit), DapQueryConfig::FixedSize { max_batch_size }) =
+            (self.max_batch_size, &task_config.query)
+        {
+            if *max_batch_size > limit {
+                return Some(format!(
+                    "maximum batch size {max_batch_size} exceeds the limit of {limit}"
+                ));
+            }
+        }
+
+        if let Some(limit) = self.min_batch_size {
+            if task_config.min_batch_size < limit {
+                return Some(format!(
+                    "minimum batch size {} is less than the limit of {limit}",
+                    task_config.min_batch_size
+                ));
+            }
+        }
+
+        if let Some(ref allowed_leader_urls) = self.allowed_leader_urls {
+            if !allowed_leader_urls.contains(&task_config.leader_url) {
+                return Some(format!("Leader {} is not allowed", task_config.leader_url));
+            }
+        }
+
+        None
+    }
+}
+
 impl ReportMetadata {
     /// Does this metatdata have a taskprov extension and does it match the specified id?
     pub fn is_taskprov(&self, version: TaskprovVersion, task_id: &TaskId) -> bool {
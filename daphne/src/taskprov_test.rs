@@ -2,11 +2,17 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{
-    messages::taskprov::VdafType,
-    messages::TaskId,
-    taskprov::{compute_vdaf_verify_key, TaskprovVersion},
+    hpke::HpkeReceiverConfig,
+    messages::taskprov::{
+        DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafType,
+        VdafTypeVar,
+    },
+    messages::{HpkeKemId, TaskId},
+    taskprov::{compute_vdaf_verify_key, TaskprovPolicy, TaskprovVersion},
     vdaf::VdafVerifyKey,
+    DapAbort, DapError, DapTaskConfig, DapVersion, Prio3Config,
 };
+use assert_matches::assert_matches;
 
 #[test]
 fn check_vdaf_key_computation() {
@@ -35,3 +41,134 @@ fn check_vdaf_key_computation() {
         _ => unreachable!(),
     }
 }
+
+#[test]
+fn try_from_taskprov_opts_out_of_unsupported_vdaf() {
+    let task_id = TaskId([7; 32]);
+    let collector_hpke_config = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+        .unwrap()
+        .config;
+
+    for var in [
+        VdafTypeVar::Poplar1Aes128 { bit_length: 32 },
+        VdafTypeVar::NotImplemented(1337),
+    ] {
+        let task_config = TaskConfig {
+            task_info: "cool task".as_bytes().to_vec(),
+            aggregator_endpoints: vec![
+                UrlBytes {
+                    bytes: b"https://leader.com/".to_vec(),
+                },
+                UrlBytes {
+                    bytes: b"https://helper.com/".to_vec(),
+                },
+            ],
+            query_config: QueryConfig {
+                time_precision: 3600,
+                max_batch_query_count: 1,
+                min_batch_size: 1,
+                var: QueryConfigVar::TimeInterval,
+            },
+            task_expiration: 1337,
+            vdaf_config: VdafConfig {
+                dp_config: DpConfig::None,
+                var,
+            },
+        };
+
+        let res = DapTaskConfig::try_from_taskprov(
+            DapVersion::Draft02,
+            TaskprovVersion::Draft02,
+            &task_id,
+            task_config,
+            &[0; 32],
+            &collector_hpke_config,
+        );
+        assert_matches!(
+            res.err(),
+            Some(DapError::Abort(DapAbort::InvalidTask { task_id: got, .. })) if got == task_id
+        );
+    }
+}
+
+#[test]
+fn taskprov_policy_opt_out_reason() {
+    let task_id = TaskId([7; 32]);
+    let collector_hpke_config = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+        .unwrap()
+        .config;
+    let task_config = DapTaskConfig::try_from_taskprov(
+        DapVersion::Draft02,
+        TaskprovVersion::Draft02,
+        &task_id,
+        TaskConfig {
+            task_info: "cool task".as_bytes().to_vec(),
+            aggregator_endpoints: vec![
+                UrlBytes {
+                    bytes: b"https://leader.com/".to_vec(),
+                },
+                UrlBytes {
+                    bytes: b"https://helper.com/".to_vec(),
+                },
+            ],
+            query_config: QueryConfig {
+                time_precision: 3600,
+                max_batch_query_count: 1,
+                min_batch_size: 10,
+                var: QueryConfigVar::FixedSize {
+                    max_batch_size: 1000,
+                },
+            },
+            task_expiration: 1337,
+            vdaf_config: VdafConfig {
+                dp_config: DpConfig::None,
+                var: VdafTypeVar::Prio3Aes128Count,
+            },
+        },
+        &[0; 32],
+        &collector_hpke_config,
+    )
+    .unwrap();
+
+    // The default policy accepts every task.
+    assert_eq!(TaskprovPolicy::default().opt_out_reason(&task_config), None);
+
+    // A policy the task satisfies.
+    let policy = TaskprovPolicy {
+        allowed_vdafs: Some(vec![crate::VdafConfig::Prio3(Prio3Config::Count)]),
+        max_batch_size: Some(1000),
+        min_batch_size: Some(10),
+        allowed_leader_urls: Some(vec!["https://leader.com/".parse().unwrap()]),
+        dry_run: false,
+    };
+    assert_eq!(policy.opt_out_reason(&task_config), None);
+
+    // Each criterion is enforced.
+    for policy in [
+        TaskprovPolicy {
+            allowed_vdafs: Some(vec![crate::VdafConfig::Prio3(Prio3Config::Sum { bits: 8 })]),
+            ..policy.clone()
+        },
+        TaskprovPolicy {
+            max_batch_size: Some(999),
+            ..policy.clone()
+        },
+        TaskprovPolicy {
+            min_batch_size: Some(11),
+            ..policy.clone()
+        },
+        TaskprovPolicy {
+            allowed_leader_urls: Some(vec!["https://other-leader.com/".parse().unwrap()]),
+            ..policy
+        },
+    ] {
+        assert!(policy.opt_out_reason(&task_config).is_some());
+
+        // In dry-run mode, the violation is only logged.
+        let policy = TaskprovPolicy {
+            dry_run: true,
+            ..policy
+        };
+        assert_eq!(policy.opt_out_reason(&task_config), None);
+    }
+}
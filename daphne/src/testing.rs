@@ -8,13 +8,13 @@ use crate::{
     constants::DapMediaType,
     hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
-        AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq,
+        AggregationJobId, BatchId, Collection, CollectionJobId, CollectionReq,
         Draft02AggregationJobId, HpkeCiphertext, HpkeConfig, PartialBatchSelector, Report,
         ReportId, ReportMetadata, TaskId, Time, TransitionFailure,
     },
-    metrics::DaphneMetrics,
+    metrics::{DaphneMetrics, DaphneRole, HpkeDecryptFailure},
     roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
-    taskprov, DapAbort, DapAggregateShare, DapBatchBucket, DapCollectJob, DapError,
+    taskprov, DapAbort, DapAggregateShare, DapBatchBucket, DapBatchSpan, DapCollectJob, DapError,
     DapGlobalConfig, DapHelperState, DapOutputShare, DapQueryConfig, DapRequest, DapResponse,
     DapTaskConfig, DapVersion, MetaAggregationJobId,
 };
@@ -24,10 +24,13 @@ use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::{Borrow, Cow},
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, VecDeque},
     hash::Hash,
     ops::DerefMut,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::SystemTime,
 };
 use url::Url;
@@ -89,7 +92,10 @@ pub(crate) struct MockAggregator {
     pub(crate) tasks: Arc<Mutex<HashMap<TaskId, DapTaskConfig>>>,
     pub(crate) hpke_receiver_config_list: Vec<HpkeReceiverConfig>,
     pub(crate) leader_token: BearerToken,
-    pub(crate) collector_token: Option<BearerToken>, // Not set by Helper
+    pub(crate) rotated_leader_token: Mutex<Option<(BearerToken, Time)>>, // Token, grace period end
+    pub(crate) collector_token: Option<BearerToken>,                     // Not set by Helper
+    pub(crate) additional_collector_tokens: Vec<BearerToken>,            // Not set by Helper
+    pub(crate) client_token: Option<BearerToken>,                        // Not set by Helper
     pub(crate) report_store: Arc<Mutex<HashMap<TaskId, ReportStore>>>,
     pub(crate) leader_state_store: Arc<Mutex<HashMap<TaskId, LeaderState>>>,
     pub(crate) helper_state_store: Arc<Mutex<HashMap<HelperStateInfo, DapHelperState>>>,
@@ -101,15 +107,26 @@ pub(crate) struct MockAggregator {
     // Leader: Reference to peer. Used to simulate HTTP requests from Leader to Helper, i.e.,
     // implement `DapLeader::send_http_post()` for `MockAggregator`. Not set by the Helper.
     pub(crate) peer: Option<Arc<MockAggregator>>,
+
+    // Leader: If set, then the Helper's state for the next aggregation job is deleted before the
+    // job is continued, simulating loss of the Helper's state. The flag is cleared once the state
+    // has been deleted.
+    pub(crate) lose_helper_state: AtomicBool,
+
+    // Leader: If set, then the next call to `finish_collect_job()` fails, simulating a transient
+    // storage error. The flag is cleared once the call has failed.
+    pub(crate) fail_finish_collect_job: AtomicBool,
 }
 
 impl MockAggregator {
     /// Conducts checks on a received report to see whether:
     /// 1) the report falls into a batch that has been already collected, or
-    /// 2) the report has been submitted by the client in the past.
+    /// 2) the report has been submitted by the client in the past. If `agg_job_id` is provided,
+    ///    then a report that was processed by that aggregation job is not considered replayed.
     async fn check_report_early_fail(
         &self,
         task_id: &TaskId,
+        agg_job_id: Option<&str>,
         bucket: &DapBatchBucketOwned,
         metadata: &ReportMetadata,
     ) -> Option<TransitionFailure> {
@@ -127,17 +144,25 @@ impl MockAggregator {
             .lock()
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
-        if report_store.processed.contains(&metadata.id) {
-            return Some(TransitionFailure::ReportReplayed);
+        match report_store.processed.get(&metadata.id) {
+            Some(processed_by) if Some(processed_by.as_str()) != agg_job_id => {
+                return Some(TransitionFailure::ReportReplayed)
+            }
+            _ => (),
         }
 
         None
     }
 
     fn get_hpke_receiver_config_for(&self, hpke_config_id: u8) -> Option<&HpkeReceiverConfig> {
+        // Configs whose KDF or AEAD is not allowed are treated as unknown.
         self.hpke_receiver_config_list
             .iter()
             .find(|&hpke_receiver_config| hpke_config_id == hpke_receiver_config.config.id)
+            .filter(|hpke_receiver_config| {
+                self.global_config
+                    .allows_hpke_config(&hpke_receiver_config.config)
+            })
     }
 
     /// Assign the report to a bucket.
@@ -184,8 +209,8 @@ impl MockAggregator {
 
             // For time-interval queries, the bucket is the batch window computed by truncating the
             // report timestamp.
-            DapQueryConfig::TimeInterval => Some(DapBatchBucketOwned::TimeInterval {
-                batch_window: task_config.quantized_time_lower_bound(report.report_metadata.time),
+            DapQueryConfig::TimeInterval { .. } => Some(DapBatchBucketOwned::TimeInterval {
+                batch_window: task_config.bucket_window(report.report_metadata.time),
             }),
         }
     }
@@ -234,6 +259,22 @@ impl<'a> BearerTokenProvider<'a> for MockAggregator {
         Ok(Some(&self.leader_token))
     }
 
+    async fn get_rotated_leader_bearer_tokens_for(
+        &'a self,
+        _task_id: &'a TaskId,
+    ) -> Result<Vec<BearerToken>, DapError> {
+        let rotated_leader_token = self
+            .rotated_leader_token
+            .lock()
+            .expect("rotated_leader_token: failed to lock");
+        Ok(match rotated_leader_token.as_ref() {
+            Some((token, grace_period_end)) if self.get_current_time() < *grace_period_end => {
+                vec![token.clone()]
+            }
+            _ => Vec::new(),
+        })
+    }
+
     async fn get_collector_bearer_token_for(
         &'a self,
         _task_id: &'a TaskId,
@@ -247,6 +288,27 @@ impl<'a> BearerTokenProvider<'a> for MockAggregator {
         }
     }
 
+    async fn get_collector_bearer_tokens_for(
+        &'a self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<Vec<BearerToken>>, DapError> {
+        let mut tokens = self
+            .get_collector_bearer_token_for(task_id)
+            .await?
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        tokens.extend(self.additional_collector_tokens.iter().cloned());
+        Ok(Some(tokens))
+    }
+
+    async fn get_client_bearer_token_for(
+        &'a self,
+        _task_id: &'a TaskId,
+    ) -> Result<Option<&'a BearerToken>, DapError> {
+        Ok(self.client_token.as_ref())
+    }
+
     fn is_taskprov_leader_bearer_token(&self, _token: &BearerToken) -> bool {
         // MockAggregator currently uses the same token for all tasks, regardless of how the task
         // is configured. As a result, we don't expect BearerTokenProver::bearer_token_authorized()
@@ -288,21 +350,61 @@ impl<'a> HpkeDecrypter<'a> for MockAggregator {
         Ok(&self.hpke_receiver_config_list[0].config)
     }
 
+    async fn get_hpke_config_by_id(
+        &'a self,
+        _version: DapVersion,
+        task_id: Option<&TaskId>,
+        hpke_config_id: u8,
+    ) -> Result<Option<&'a HpkeConfig>, DapError> {
+        // As in `get_hpke_config_for()`, simulate an Aggregator that requires the task ID.
+        if task_id.is_none() {
+            return Err(DapError::Abort(DapAbort::MissingTaskId));
+        }
+
+        Ok(self
+            .get_hpke_receiver_config_for(hpke_config_id)
+            .map(|hpke_receiver_config| &hpke_receiver_config.config))
+    }
+
     async fn can_hpke_decrypt(&self, _task_id: &TaskId, config_id: u8) -> Result<bool, DapError> {
         Ok(self.get_hpke_receiver_config_for(config_id).is_some())
     }
 
     async fn hpke_decrypt(
         &self,
-        _task_id: &TaskId,
+        task_id: &TaskId,
         info: &[u8],
         aad: &[u8],
         ciphertext: &HpkeCiphertext,
     ) -> Result<Vec<u8>, DapError> {
+        // The host of this Aggregator for the task, as would be reported by an inbound request.
+        // The Leader is distinguished from the Helper by having a peer.
+        let host = {
+            let tasks = self.tasks.lock().expect("tasks: failed to lock");
+            let url = tasks.get(task_id).map(|task_config| {
+                if self.peer.is_some() {
+                    &task_config.leader_url
+                } else {
+                    &task_config.helper_url
+                }
+            });
+            url.and_then(|url| url.host_str())
+                .unwrap_or("unspecified-host")
+                .to_string()
+        };
+        let role = if self.peer.is_some() {
+            DaphneRole::Leader
+        } else {
+            DaphneRole::Helper
+        };
+        let metrics = self.metrics.with_host(&host).with_role(role);
         if let Some(hpke_receiver_config) = self.get_hpke_receiver_config_for(ciphertext.config_id)
         {
-            Ok(hpke_receiver_config.decrypt(info, aad, &ciphertext.enc, &ciphertext.payload)?)
+            hpke_receiver_config
+                .decrypt(info, aad, &ciphertext.enc, &ciphertext.payload)
+                .inspect_err(|_| metrics.hpke_decrypt_failure_inc(HpkeDecryptFailure::DecryptError))
         } else {
+            metrics.hpke_decrypt_failure_inc(HpkeDecryptFailure::UnknownConfigId);
             Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
         }
     }
@@ -410,16 +512,19 @@ where
             .as_secs()
     }
 
+    fn get_current_time_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        collect_id: Option<&CollectionJobId>,
+        batch_span: &DapBatchSpan<'_>,
     ) -> Result<bool, DapError> {
-        let task_config = self
-            .get_task_config_for(Cow::Borrowed(task_id))
-            .await
-            .unwrap()
-            .expect("tasks: unrecognized task");
         let guard = self.agg_store.lock().expect("agg_store: failed to lock");
         let agg_store = if let Some(agg_store) = guard.get(task_id) {
             agg_store
@@ -427,15 +532,13 @@ where
             return Ok(false);
         };
 
-        for bucket in task_config.batch_span_for_sel(batch_sel)? {
-            if let Some(inner_agg_store) = agg_store.get(&bucket.to_owned_bucket()) {
-                if inner_agg_store.collected {
-                    return Ok(true);
-                }
-            }
-        }
-
-        Ok(false)
+        Ok(batch_span.buckets().iter().any(|bucket| {
+            matches!(
+                agg_store.get(&bucket.to_owned_bucket()),
+                Some(inner_agg_store) if inner_agg_store.collected
+                    && (collect_id.is_none() || inner_agg_store.collect_id.as_ref() != collect_id)
+            )
+        }))
     }
 
     async fn batch_exists(&self, task_id: &TaskId, batch_id: &BatchId) -> Result<bool, DapError> {
@@ -462,12 +565,11 @@ where
             .await?
             .ok_or_else(|| DapError::fatal("task not found"))?;
 
+        let span = task_config.batch_span_for_out_shares(part_batch_sel, out_shares)?;
+
         let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
         let agg_store = guard.entry(task_id.clone()).or_default();
-        for (bucket, agg_share_delta) in task_config
-            .batch_span_for_out_shares(part_batch_sel, out_shares)?
-            .into_iter()
-        {
+        for (bucket, agg_share_delta) in span.into_iter() {
             let inner_agg_store = agg_store.entry(bucket.to_owned_bucket()).or_default();
             inner_agg_store.agg_share.merge(agg_share_delta)?;
         }
@@ -478,25 +580,18 @@ where
     async fn get_agg_share(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        batch_span: &DapBatchSpan<'_>,
     ) -> Result<DapAggregateShare, DapError> {
-        let task_config = self
-            .get_task_config_for(Cow::Borrowed(task_id))
-            .await
-            .unwrap()
-            .expect("tasks: unrecognized task");
         let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
         let agg_store = guard.entry(task_id.clone()).or_default();
 
-        // Fetch aggregate shares.
+        // Fetch aggregate shares. Whether the batch was already collected is checked separately
+        // (see `is_batch_overlapping()` and `mark_collected()`), so that a collect job that is
+        // retried after marking the batch can fetch the aggregate share again.
         let mut agg_share = DapAggregateShare::default();
-        for bucket in task_config.batch_span_for_sel(batch_sel)? {
+        for bucket in batch_span.buckets() {
             if let Some(inner_agg_store) = agg_store.get(&bucket.to_owned_bucket()) {
-                if inner_agg_store.collected {
-                    return Err(DapError::Abort(DapAbort::batch_overlap(task_id, batch_sel)));
-                } else {
-                    agg_share.merge(inner_agg_store.agg_share.clone())?;
-                }
+                agg_share.merge(inner_agg_store.agg_share.clone())?;
             }
         }
 
@@ -506,6 +601,7 @@ where
     async fn check_early_reject<'b>(
         &self,
         task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
         part_batch_sel: &'b PartialBatchSelector,
         report_meta: impl Iterator<Item = &'b ReportMetadata>,
     ) -> Result<HashMap<ReportId, TransitionFailure>, DapError> {
@@ -515,12 +611,18 @@ where
             .unwrap()
             .expect("tasks: unrecognized task");
         let span = task_config.batch_span_for_meta(part_batch_sel, report_meta)?;
+        let agg_job_id = agg_job_id.to_base64url();
         let mut early_fails = HashMap::new();
         for (bucket, report_meta) in span.iter() {
             for metadata in report_meta.iter() {
                 // Check whether Report has been collected or replayed.
                 if let Some(transition_failure) = self
-                    .check_report_early_fail(task_id, &bucket.to_owned_bucket(), metadata)
+                    .check_report_early_fail(
+                        task_id,
+                        Some(&agg_job_id),
+                        &bucket.to_owned_bucket(),
+                        metadata,
+                    )
                     .await
                 {
                     early_fails.insert(metadata.id.clone(), transition_failure);
@@ -532,7 +634,10 @@ where
                     .lock()
                     .expect("report_store: failed to lock");
                 let report_store = guard.entry(task_id.clone()).or_default();
-                report_store.processed.insert(metadata.id.clone());
+                report_store
+                    .processed
+                    .entry(metadata.id.clone())
+                    .or_insert_with(|| agg_job_id.clone());
             }
         }
 
@@ -542,18 +647,33 @@ where
     async fn mark_collected(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        collect_id: &CollectionJobId,
+        batch_span: &DapBatchSpan<'_>,
     ) -> Result<(), DapError> {
-        let task_config = self.unchecked_get_task_config(task_id).await;
         let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
         let agg_store = guard.entry(task_id.clone()).or_default();
 
-        for bucket in task_config.batch_span_for_sel(batch_sel)? {
+        // Check every bucket before marking any, so that a batch that overlaps a collected batch
+        // leaves the remaining buckets available for collection.
+        if batch_span.buckets().iter().any(|bucket| {
+            matches!(
+                agg_store.get(&bucket.to_owned_bucket()),
+                Some(inner_agg_store) if inner_agg_store.collected
+                    && inner_agg_store.collect_id.as_ref() != Some(collect_id)
+            )
+        }) {
+            return Err(DapError::Abort(DapAbort::batch_overlap(
+                task_id,
+                batch_span.batch_sel(),
+            )));
+        }
+
+        for bucket in batch_span.buckets() {
             if let Some(inner_agg_store) = agg_store.get_mut(&bucket.to_owned_bucket()) {
                 inner_agg_store.collected = true;
+                inner_agg_store.collect_id = Some(collect_id.clone());
             }
         }
-
         Ok(())
     }
 
@@ -653,7 +773,7 @@ where
 
         // Check whether Report has been collected or replayed.
         if let Some(transition_failure) = self
-            .check_report_early_fail(task_id, bucket.borrow(), &report.report_metadata)
+            .check_report_early_fail(task_id, None, bucket.borrow(), &report.report_metadata)
             .await
         {
             return Err(DapError::Transition(transition_failure));
@@ -686,6 +806,16 @@ where
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
 
+        // Reports that have been pending for too long are dropped rather than aggregated.
+        let now = self.get_current_time();
+        let is_expired = |report: &Report| {
+            task_config.is_pending_report_expired(
+                &self.global_config,
+                report.report_metadata.time,
+                now,
+            )
+        };
+
         // For the task indicated by the report selector, choose a single report to aggregate.
         match task_config.query {
             DapQueryConfig::TimeInterval { .. } => {
@@ -697,6 +827,7 @@ where
                         break;
                     }
                 }
+                reports.retain(|report| !is_expired(report));
                 return Ok(HashMap::from([(
                     task_id.clone(),
                     HashMap::from([(PartialBatchSelector::TimeInterval, reports)]),
@@ -705,17 +836,42 @@ where
             DapQueryConfig::FixedSize { .. } => {
                 // Drain the batch that is being filled.
 
-                let bucket = if let Some(batch_id) = self.current_batch_id(task_id, &task_config) {
-                    DapBatchBucketOwned::FixedSize { batch_id }
+                let batch_id = if let Some(batch_id) = self.current_batch_id(task_id, &task_config)
+                {
+                    batch_id
                 } else {
                     return Ok(HashMap::default());
                 };
+                let bucket = DapBatchBucketOwned::FixedSize {
+                    batch_id: batch_id.clone(),
+                };
 
                 let queue = report_store
                     .pending
                     .get_mut(&bucket)
                     .expect("report_store: unknown bucket");
-                let reports = queue.drain(..1).collect();
+                let mut reports: Vec<Report> = queue.drain(..1).collect();
+
+                // Reports are assigned to a batch when they are uploaded, so the batch no longer
+                // counts the reports that are dropped.
+                let num_drained = reports.len();
+                reports.retain(|report| !is_expired(report));
+                let num_expired = (num_drained - reports.len()) as u64;
+                if num_expired > 0 {
+                    let mut guard = self
+                        .leader_state_store
+                        .lock()
+                        .expect("leader_state_store: failed to lock");
+                    let leader_state_store = guard
+                        .get_mut(task_id)
+                        .expect("leader_state_store: unrecognized task");
+                    for (id, report_count) in leader_state_store.batch_queue.iter_mut() {
+                        if *id == batch_id {
+                            *report_count -= num_expired;
+                        }
+                    }
+                }
+
                 return Ok(HashMap::from([(
                     task_id.clone(),
                     HashMap::from([(bucket.into(), reports)]),
@@ -786,6 +942,7 @@ where
             match collect_job_state {
                 CollectJobState::Pending(_) => Ok(DapCollectJob::Pending),
                 CollectJobState::Processed(resp) => Ok(DapCollectJob::Done(resp.clone())),
+                CollectJobState::Abandoned => Ok(DapCollectJob::Abandoned),
             }
         } else {
             Ok(DapCollectJob::Unknown)
@@ -822,6 +979,10 @@ where
         collect_id: &CollectionJobId,
         collect_resp: &Collection,
     ) -> Result<(), DapError> {
+        if self.fail_finish_collect_job.swap(false, Ordering::SeqCst) {
+            return Err(DapError::Storage("simulated storage failure".into()));
+        }
+
         let mut leader_state_store_mutex_guard = self
             .leader_state_store
             .lock()
@@ -860,22 +1021,57 @@ where
 
                 Ok(())
             }
-            CollectJobState::Processed(_) => {
+            CollectJobState::Processed(_) | CollectJobState::Abandoned => {
                 Err(DapError::fatal("tried to overwrite collect response"))
             }
         }
     }
 
+    async fn abandon_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> Result<(), DapError> {
+        let mut leader_state_store_mutex_guard = self
+            .leader_state_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        let leader_state = leader_state_store_mutex_guard
+            .get_mut(task_id)
+            .ok_or_else(|| DapError::fatal("collect job not found for task_id"))?;
+        let collect_job = leader_state
+            .collect_jobs
+            .get_mut(collect_id)
+            .ok_or_else(|| DapError::fatal("collect job not found for collect_id"))?;
+
+        if matches!(collect_job, CollectJobState::Pending(_)) {
+            *collect_job = CollectJobState::Abandoned;
+            leader_state.collect_ids.retain(|id| id != collect_id);
+        }
+        Ok(())
+    }
+
     async fn send_http_post(&self, req: DapRequest<BearerToken>) -> Result<DapResponse, DapError> {
+        let peer = self.peer.as_ref().expect("peer not configured");
         match req.media_type {
-            DapMediaType::AggregationJobInitReq | DapMediaType::AggregationJobContinueReq => {
-                Ok(self
-                    .peer
-                    .as_ref()
-                    .expect("peer not configured")
-                    .http_post_aggregate(&req)
-                    .await
-                    .expect("peer aborted unexpectedly"))
+            DapMediaType::AggregationJobInitReq => Ok(peer
+                .http_post_aggregate(&req)
+                .await
+                .expect("peer aborted unexpectedly")),
+            DapMediaType::AggregationJobContinueReq => {
+                if self.lose_helper_state.swap(false, Ordering::SeqCst) {
+                    peer.helper_state_store
+                        .lock()
+                        .expect("helper_state_store: failed to lock")
+                        .clear();
+                }
+
+                // The Helper aborts if it has no state for the aggregation job. Propagate the
+                // abort so that the Leader can handle it.
+                peer.http_post_aggregate(&req).await.map_err(|e| match e {
+                    e @ DapAbort::UnrecognizedAggregationJob { .. } => DapError::Abort(e),
+                    e => panic!("peer aborted unexpectedly: {e:?}"),
+                })
             }
             DapMediaType::AggregateShareReq => Ok(self
                 .peer
@@ -914,13 +1110,15 @@ pub(crate) struct HelperStateInfo {
 #[derive(Default)]
 pub(crate) struct ReportStore {
     pub(crate) pending: HashMap<DapBatchBucketOwned, VecDeque<Report>>,
-    pub(crate) processed: HashSet<ReportId>,
+    /// The aggregation job (base64url-encoded ID) by which each report was processed.
+    pub(crate) processed: HashMap<ReportId, String>,
 }
 
 /// Stores the state of the collect job.
 pub(crate) enum CollectJobState {
     Pending(CollectionReq),
     Processed(Collection),
+    Abandoned,
 }
 
 /// LeaderState keeps track of the following:
@@ -935,11 +1133,12 @@ pub(crate) struct LeaderState {
 
 /// AggStore keeps track of the following:
 /// * Aggregate share
-/// * Whether this aggregate share has been collected
+/// * Whether this aggregate share has been collected, and if so, by which collect job
 #[derive(Default)]
 pub(crate) struct AggStore {
     pub(crate) agg_share: DapAggregateShare,
     pub(crate) collected: bool,
+    pub(crate) collect_id: Option<CollectionJobId>,
 }
 
 // These are declarative macros which let us generate a test point for
@@ -385,9 +385,19 @@ impl VdafConfig {
                 extensions: vec![],
                 payload: encoded_input_share,
             },
-            _ => PlaintextInputShare::get_decoded(&encoded_input_share)?,
+            _ => PlaintextInputShare::get_decoded(&encoded_input_share)
+                .map_err(|_| DapError::Transition(TransitionFailure::UnrecognizedMessage))?,
         };
 
+        // draft02: The extensions are carried by the report metadata rather than the input share.
+        let extensions = match task_config.version {
+            DapVersion::Draft02 => &metadata.extensions,
+            _ => &input_share.extensions,
+        };
+        task_config
+            .check_extensions(extensions)
+            .map_err(DapError::Transition)?;
+
         let agg_id = usize::from(!is_leader);
         match (self, &task_config.vdaf_verify_key) {
             (Self::Prio3(ref prio3_config), VdafVerifyKey::Prio3(ref verify_key)) => {
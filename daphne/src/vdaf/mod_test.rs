@@ -7,11 +7,11 @@ use crate::{
     hpke::HpkeReceiverConfig,
     messages::{
         AggregationJobContinueReq, AggregationJobInitReq, AggregationJobResp, BatchSelector,
-        HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, Interval,
+        Extension, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, Interval,
         PartialBatchSelector, Report, ReportId, ReportShare, TaskId, Time, Transition,
         TransitionFailure, TransitionVar,
     },
-    metrics::DaphneMetrics,
+    metrics::{DaphneMetrics, DaphneRole},
     test_version, test_versions, DapAbort, DapAggregateResult, DapAggregateShare, DapError,
     DapHelperState, DapHelperTransition, DapLeaderState, DapLeaderTransition, DapLeaderUncommitted,
     DapMeasurement, DapOutputShare, DapQueryConfig, DapTaskConfig, DapVersion,
@@ -228,7 +228,7 @@ async fn produce_agg_job_init_req_skip_hpke_decrypt_err(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_leader_report_counter{host="leader.com",status="rejected_hpke_decrypt_error"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="rejected_hpke_decrypt_error"}"#: 1,
     });
 }
 
@@ -247,7 +247,7 @@ async fn produce_agg_job_init_req_skip_hpke_unknown_config_id(version: DapVersio
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_leader_report_counter{host="leader.com",status="rejected_hpke_unknown_config_id"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="rejected_hpke_unknown_config_id"}"#: 1,
     });
 }
 
@@ -266,7 +266,7 @@ async fn produce_agg_job_init_req_skip_vdaf_prep_error(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_leader_report_counter{host="leader.com",status="rejected_vdaf_prep_error"}"#: 2,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="rejected_vdaf_prep_error"}"#: 2,
     });
 }
 
@@ -292,7 +292,7 @@ async fn handle_agg_job_init_req_hpke_decrypt_err(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_report_counter{host="helper.org",status="rejected_hpke_decrypt_error"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="rejected_hpke_decrypt_error"}"#: 1,
     });
 }
 
@@ -318,7 +318,7 @@ async fn handle_agg_job_init_req_hpke_unknown_config_id(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_report_counter{host="helper.org",status="rejected_hpke_unknown_config_id"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="rejected_hpke_unknown_config_id"}"#: 1,
     });
 }
 
@@ -363,12 +363,95 @@ async fn handle_agg_job_init_req_vdaf_prep_error(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_report_counter{host="helper.org",status="rejected_vdaf_prep_error"}"#: 2,
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="rejected_vdaf_prep_error"}"#: 2,
     });
 }
 
 async_test_versions! { handle_agg_job_init_req_vdaf_prep_error }
 
+async fn handle_agg_job_init_req_extension_not_permitted(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let report = t.produce_report_with_extensions(
+        DapMeasurement::U64(1),
+        vec![Extension::Unhandled {
+            typ: 0xfff,
+            payload: b"some extension".to_vec(),
+        }],
+        version,
+    );
+    let agg_req = AggregationJobInitReq {
+        draft02_task_id: t.task_id.for_request_payload(&version),
+        draft02_agg_job_id: t.agg_job_id.for_request_payload(),
+        agg_param: Vec::new(),
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_shares: vec![ReportShare {
+            report_metadata: report.report_metadata,
+            public_share: report.public_share,
+            encrypted_input_share: report.encrypted_input_shares[1].clone(),
+        }],
+    };
+
+    // The task doesn't permit the extension, so the report is rejected.
+    let (_, agg_job_resp) = t
+        .handle_agg_job_init_req(agg_req.clone())
+        .await
+        .unwrap_continue();
+    assert_eq!(agg_job_resp.transitions.len(), 1);
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::UnrecognizedMessage)
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_report_counter{host="helper.org",role="helper",status="rejected_unrecognized_message"}"#: 1,
+    });
+
+    // Once the task permits the extension, the report is accepted.
+    t.task_config.permitted_extensions = vec![0xfff];
+    let (_, agg_job_resp) = t.handle_agg_job_init_req(agg_req).await.unwrap_continue();
+    assert_eq!(agg_job_resp.transitions.len(), 1);
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Continued(..)
+    );
+}
+
+async_test_versions! { handle_agg_job_init_req_extension_not_permitted }
+
+async fn handle_agg_job_init_req_duplicate_extension(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    t.task_config.permitted_extensions = vec![0xfff];
+    let extension = Extension::Unhandled {
+        typ: 0xfff,
+        payload: b"some extension".to_vec(),
+    };
+    let report = t.produce_report_with_extensions(
+        DapMeasurement::U64(1),
+        vec![extension.clone(), extension],
+        version,
+    );
+    let agg_req = AggregationJobInitReq {
+        draft02_task_id: t.task_id.for_request_payload(&version),
+        draft02_agg_job_id: t.agg_job_id.for_request_payload(),
+        agg_param: Vec::new(),
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_shares: vec![ReportShare {
+            report_metadata: report.report_metadata,
+            public_share: report.public_share,
+            encrypted_input_share: report.encrypted_input_shares[1].clone(),
+        }],
+    };
+
+    let (_, agg_job_resp) = t.handle_agg_job_init_req(agg_req).await.unwrap_continue();
+    assert_eq!(agg_job_resp.transitions.len(), 1);
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::UnrecognizedMessage)
+    );
+}
+
+async_test_versions! { handle_agg_job_init_req_duplicate_extension }
+
 async fn agg_job_resp_abort_transition_out_of_order(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
@@ -524,6 +607,67 @@ async fn agg_job_cont_req(version: DapVersion) {
 
 async_test_versions! { agg_job_cont_req }
 
+// The aggregate share doesn't depend on the order in which the output shares are merged. The
+// Aggregators rely on this when merging per-bucket aggregate shares as they arrive.
+async fn agg_share_merge_order_independent(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(1),
+    ]);
+    let (leader_state, agg_job_init_req) =
+        t.produce_agg_job_init_req(reports).await.unwrap_continue();
+    let (helper_state, agg_job_resp) = t
+        .handle_agg_job_init_req(agg_job_init_req)
+        .await
+        .unwrap_continue();
+    let (_leader_uncommitted, agg_job_cont_req) = t
+        .handle_agg_job_resp(leader_state, agg_job_resp)
+        .unwrap_uncommitted();
+    let (helper_out_shares, _agg_job_resp) = t
+        .handle_agg_job_cont_req(helper_state, &agg_job_cont_req)
+        .unwrap_finish();
+
+    // Buckets in which no reports were aggregated yield an empty aggregate share.
+    let mut agg_shares = vec![DapAggregateShare::default()];
+    agg_shares.extend(
+        helper_out_shares
+            .into_iter()
+            .enumerate()
+            .map(|(i, out_share)| DapAggregateShare {
+                report_count: 1,
+                min_time: out_share.time + i as Time,
+                max_time: out_share.time + i as Time,
+                checksum: out_share.checksum,
+                data: Some(out_share.data),
+            }),
+    );
+
+    let merge = |agg_shares: Vec<DapAggregateShare>| {
+        let mut agg_share = DapAggregateShare::default();
+        for agg_share_delta in agg_shares {
+            agg_share.merge(agg_share_delta).unwrap();
+        }
+        agg_share
+    };
+    let forward = merge(agg_shares.clone());
+    let reverse = merge(agg_shares.into_iter().rev().collect());
+
+    assert_eq!(forward.report_count, 4);
+    assert_eq!(forward.report_count, reverse.report_count);
+    assert_eq!(forward.min_time, reverse.min_time);
+    assert_eq!(forward.max_time, reverse.max_time);
+    assert_eq!(forward.checksum, reverse.checksum);
+    assert_matches!(
+        (forward.data, reverse.data),
+        (Some(VdafAggregateShare::Field64(left)), Some(VdafAggregateShare::Field64(right))) if left == right
+    );
+}
+
+async_test_versions! { agg_share_merge_order_independent }
+
 async fn agg_job_cont_req_skip_vdaf_prep_error(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let mut reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
@@ -559,7 +703,7 @@ async fn agg_job_cont_req_skip_vdaf_prep_error(version: DapVersion) {
     );
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_leader_report_counter{host="leader.com",status="rejected_vdaf_prep_error"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",role="leader",status="rejected_vdaf_prep_error"}"#: 1,
     });
 }
 
@@ -769,10 +913,21 @@ impl Test {
                 time_precision: 500,
                 expiration: now + 500,
                 min_batch_size: 10,
-                query: DapQueryConfig::TimeInterval,
+                query: DapQueryConfig::TimeInterval {
+                    bucket_duration: None,
+                },
                 vdaf: vdaf.clone(),
                 vdaf_verify_key,
                 collector_hpke_config,
+                upload_rate_limit: None,
+                max_reports_per_agg_job: None,
+                client_auth: false,
+                task_scoped_hpke_config: false,
+                quiescing: false,
+                pending_report_ttl: None,
+                collect_poll_retry_after: None,
+                permitted_extensions: Vec::new(),
+                reject_future_buckets: false,
             },
             prometheus_registry,
             leader_metrics,
@@ -800,6 +955,25 @@ impl Test {
         reports
     }
 
+    fn produce_report_with_extensions(
+        &self,
+        measurement: DapMeasurement,
+        extensions: Vec<Extension>,
+        version: DapVersion,
+    ) -> Report {
+        self.task_config
+            .vdaf
+            .produce_report_with_extensions(
+                &self.client_hpke_config_list,
+                self.now,
+                &self.task_id,
+                measurement,
+                extensions,
+                version,
+            )
+            .unwrap()
+    }
+
     // Tweak the Helper's share so that decoding succeeds but preparation fails.
     fn produce_invalid_report_vdaf_prep_failure(
         &self,
@@ -891,7 +1065,8 @@ impl Test {
     ) -> DapLeaderTransition<AggregationJobInitReq> {
         let metrics = self
             .leader_metrics
-            .with_host(self.task_config.leader_url.host_str().unwrap());
+            .with_host(self.task_config.leader_url.host_str().unwrap())
+            .with_role(DaphneRole::Leader);
         self.task_config
             .vdaf
             .produce_agg_job_init_req(
@@ -913,7 +1088,8 @@ impl Test {
     ) -> DapHelperTransition<AggregationJobResp> {
         let metrics = self
             .helper_metrics
-            .with_host(self.task_config.helper_url.host_str().unwrap());
+            .with_host(self.task_config.helper_url.host_str().unwrap())
+            .with_role(DaphneRole::Helper);
         self.task_config
             .vdaf
             .handle_agg_job_init_req(
@@ -934,7 +1110,8 @@ impl Test {
     ) -> DapLeaderTransition<AggregationJobContinueReq> {
         let metrics = self
             .leader_metrics
-            .with_host(self.task_config.leader_url.host_str().unwrap());
+            .with_host(self.task_config.leader_url.host_str().unwrap())
+            .with_role(DaphneRole::Leader);
         self.task_config
             .vdaf
             .handle_agg_job_resp(
@@ -955,7 +1132,8 @@ impl Test {
     ) -> DapAbort {
         let metrics = self
             .leader_metrics
-            .with_host(self.task_config.leader_url.host_str().unwrap());
+            .with_host(self.task_config.leader_url.host_str().unwrap())
+            .with_role(DaphneRole::Leader);
         self.task_config
             .vdaf
             .handle_agg_job_resp(
@@ -976,7 +1154,8 @@ impl Test {
     ) -> DapHelperTransition<AggregationJobResp> {
         let metrics = self
             .helper_metrics
-            .with_host(self.task_config.helper_url.host_str().unwrap());
+            .with_host(self.task_config.helper_url.host_str().unwrap())
+            .with_role(DaphneRole::Helper);
         self.task_config
             .vdaf
             .handle_agg_job_cont_req(
@@ -996,7 +1175,8 @@ impl Test {
     ) -> DapAbort {
         let metrics = self
             .helper_metrics
-            .with_host(self.task_config.helper_url.host_str().unwrap());
+            .with_host(self.task_config.helper_url.host_str().unwrap())
+            .with_role(DaphneRole::Helper);
         self.task_config
             .vdaf
             .handle_agg_job_cont_req(
@@ -1016,7 +1196,8 @@ impl Test {
     ) -> Vec<DapOutputShare> {
         let metrics = self
             .leader_metrics
-            .with_host(self.task_config.leader_url.host_str().unwrap());
+            .with_host(self.task_config.leader_url.host_str().unwrap())
+            .with_role(DaphneRole::Leader);
         self.task_config
             .vdaf
             .handle_final_agg_job_resp(leader_uncommitted, agg_job_resp, &metrics)
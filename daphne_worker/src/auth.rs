@@ -3,10 +3,11 @@
 
 //! Authorization methods for Daphne-Worker.
 
-use daphne::auth::BearerToken;
+use daphne::{auth::BearerToken, DapError};
 use serde::{Deserialize, Serialize};
 
 /// HTTP client authorization for Daphne-Worker.
+#[derive(Debug)]
 pub(crate) enum DaphneWorkerAuth {
     /// Bearer token, expected to appear in the "dap-auth-token" header.
     BearerToken(BearerToken),
@@ -23,12 +24,14 @@ pub(crate) enum DaphneWorkerAuth {
     ///
     /// # Caveats
     ///
-    /// * For now, only the Helper supports TLS client auth; the Leader still expects a bearer
-    ///   token to be configured for the task.
-    ///
     /// * For now, TLS client auth is only enabled if the taskprov extension is configured.
     ///   Enabling this feature for other tasks will require a bit plumbing.
     ///
+    /// * If the Leader is configured to use TLS client auth for taskprov tasks, then its requests
+    ///   to the Helper are sent via the "DAP_LEADER_CERT" mTLS certificate binding. In this case,
+    ///   `cert_issuer` and `cert_subject` describe the certificate presented by the Leader:
+    ///   https://developers.cloudflare.com/workers/runtime-apis/mtls/
+    ///
     /// # Zone configuration
     ///
     /// 1. SSL/TLS -> Client Certificates -> Create Client Certificate: Configure a certificate with
@@ -91,6 +94,29 @@ pub(crate) enum DaphneWorkerAuthMethod {
     },
 }
 
+impl DaphneWorkerAuthMethod {
+    /// If this is TLS client auth, then return the credentials presented by outbound requests
+    /// authorized with this method, i.e., the expected issuer and the first of the valid subjects.
+    /// Return `None` if requests are authorized with a bearer token.
+    pub(crate) fn tls_client_auth(&self) -> Result<Option<DaphneWorkerAuth>, DapError> {
+        match self {
+            Self::BearerToken(..) => Ok(None),
+            Self::CfTlsClientAuth {
+                valid_cert_issuer,
+                valid_cert_subjects,
+            } => {
+                let cert_subject = valid_cert_subjects.first().ok_or_else(|| {
+                    DapError::fatal("no certificate subject configured for TLS client auth")
+                })?;
+                Ok(Some(DaphneWorkerAuth::CfTlsClientAuth {
+                    cert_issuer: valid_cert_issuer.clone(),
+                    cert_subject: cert_subject.clone(),
+                }))
+            }
+        }
+    }
+}
+
 impl AsRef<BearerToken> for DaphneWorkerAuthMethod {
     fn as_ref(&self) -> &BearerToken {
         match self {
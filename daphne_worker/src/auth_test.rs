@@ -1,7 +1,7 @@
 // Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::auth::DaphneWorkerAuthMethod;
+use crate::auth::{DaphneWorkerAuth, DaphneWorkerAuthMethod};
 use assert_matches::assert_matches;
 use daphne::auth::BearerToken;
 
@@ -37,3 +37,34 @@ fn daphne_worker_auth_method_json_serialiation() {
         }
     );
 }
+
+#[test]
+fn daphne_worker_auth_method_tls_client_auth() {
+    // Requests authorized with a bearer token don't present a TLS client certificate.
+    let daphne_worker_auth_method =
+        DaphneWorkerAuthMethod::BearerToken(BearerToken::from("the bearer token".to_string()));
+    assert_matches!(daphne_worker_auth_method.tls_client_auth(), Ok(None));
+
+    // Otherwise the certificate has the expected issuer and the first of the valid subjects.
+    let daphne_worker_auth_method = DaphneWorkerAuthMethod::CfTlsClientAuth {
+        valid_cert_issuer: "CN=Steve Kille,O=Isode Limited,C=GB".into(),
+        valid_cert_subjects: vec![
+            "OU=Sales+CN=J. Smith,O=Widget Inc.,C=US".into(),
+            "CN=L. Eagle,O=Sue\\, Grabbit and Runn,C=GB".into(),
+        ],
+    };
+    assert_matches!(
+        daphne_worker_auth_method.tls_client_auth(),
+        Ok(Some(DaphneWorkerAuth::CfTlsClientAuth { cert_issuer, cert_subject })) => {
+            assert_eq!(cert_issuer, "CN=Steve Kille,O=Isode Limited,C=GB");
+            assert_eq!(cert_subject, "OU=Sales+CN=J. Smith,O=Widget Inc.,C=US");
+        }
+    );
+
+    // At least one subject is required to present a certificate.
+    let daphne_worker_auth_method = DaphneWorkerAuthMethod::CfTlsClientAuth {
+        valid_cert_issuer: "CN=Steve Kille,O=Isode Limited,C=GB".into(),
+        valid_cert_subjects: Vec::new(),
+    };
+    assert_matches!(daphne_worker_auth_method.tls_client_auth(), Err(..));
+}
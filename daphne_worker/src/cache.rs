@@ -0,0 +1,108 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Caching of values read from KV whose lifetime and number are bounded. Values that are not
+//! backed by KV may also be pinned in the cache until a given time.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A value read from KV.
+pub(crate) struct CacheEntry<V> {
+    pub(crate) value: V,
+
+    /// Time (in seconds since the beginning of UNIX time) at which the value was read from KV.
+    fetched_at: u64,
+
+    /// Time (in seconds since the beginning of UNIX time) at which the value was last used. This
+    /// is used to decide which entry to evict when the cache is full.
+    last_used: AtomicU64,
+
+    /// If set, then the value is not backed by KV and is kept until this time (in seconds since
+    /// the beginning of UNIX time), regardless of the TTL. Until then, the entry is never evicted.
+    pinned_until: Option<u64>,
+}
+
+impl<V> CacheEntry<V> {
+    pub(crate) fn new(value: V, now: u64) -> Self {
+        Self {
+            value,
+            fetched_at: now,
+            last_used: AtomicU64::new(now),
+            pinned_until: None,
+        }
+    }
+
+    /// Check whether the value was read from KV less than `ttl` seconds before `now`, or, if it
+    /// is pinned, whether it is still pinned.
+    pub(crate) fn is_fresh(&self, now: u64, ttl: u64) -> bool {
+        match self.pinned_until {
+            Some(pinned_until) => now < pinned_until,
+            None => now < self.fetched_at.saturating_add(ttl),
+        }
+    }
+
+    fn is_pinned(&self) -> bool {
+        self.pinned_until.is_some()
+    }
+
+    fn is_pin_expired(&self, now: u64) -> bool {
+        matches!(self.pinned_until, Some(pinned_until) if pinned_until <= now)
+    }
+
+    /// Record that the value was used at time `now`.
+    pub(crate) fn touch(&self, now: u64) {
+        self.last_used.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+/// Insert a value into the cache. If the cache already holds `capacity` entries that are not
+/// pinned, then the least recently used of these is evicted first. Pinned entries whose time has
+/// passed are removed.
+pub(crate) fn cache_insert<K, V>(
+    cache: &mut HashMap<K, CacheEntry<V>>,
+    capacity: usize,
+    key: K,
+    value: V,
+    now: u64,
+) where
+    K: Clone + Eq + Hash,
+{
+    cache.retain(|_key, entry| !entry.is_pin_expired(now));
+    let unpinned = cache.values().filter(|entry| !entry.is_pinned()).count();
+    if !cache.contains_key(&key) && unpinned >= capacity {
+        let lru_key = cache
+            .iter()
+            .filter(|(_key, entry)| !entry.is_pinned())
+            .min_by_key(|(_key, entry)| entry.last_used.load(Ordering::Relaxed))
+            .map(|(key, _entry)| key.clone());
+        if let Some(lru_key) = lru_key {
+            cache.remove(&lru_key);
+        }
+    }
+
+    if capacity > 0 {
+        cache.insert(key, CacheEntry::new(value, now));
+    }
+}
+
+/// Insert a value that is not backed by KV into the cache. The entry is kept until
+/// `pinned_until` and does not count towards the capacity of the cache. Pinned entries whose time
+/// has passed are removed.
+pub(crate) fn cache_pin<K, V>(
+    cache: &mut HashMap<K, CacheEntry<V>>,
+    key: K,
+    value: V,
+    now: u64,
+    pinned_until: u64,
+) where
+    K: Eq + Hash,
+{
+    cache.retain(|_key, entry| !entry.is_pin_expired(now));
+    let mut entry = CacheEntry::new(value, now);
+    entry.pinned_until = Some(pinned_until);
+    cache.insert(key, entry);
+}
@@ -0,0 +1,71 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::cache::{cache_insert, cache_pin, CacheEntry};
+use std::collections::HashMap;
+
+#[test]
+fn cache_entry_expires_after_ttl() {
+    let entry = CacheEntry::new("value", 1000);
+    assert!(entry.is_fresh(1000, 60));
+    assert!(entry.is_fresh(1059, 60));
+    assert!(!entry.is_fresh(1060, 60));
+}
+
+#[test]
+fn cache_insert_evicts_least_recently_used() {
+    let mut cache: HashMap<&str, CacheEntry<u64>> = HashMap::new();
+    cache_insert(&mut cache, 2, "a", 1, 1000);
+    cache_insert(&mut cache, 2, "b", 2, 1001);
+
+    // "a" is used more recently than "b", so "b" is evicted to make room for "c".
+    cache.get("a").unwrap().touch(1002);
+    cache_insert(&mut cache, 2, "c", 3, 1003);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.contains_key("a"));
+    assert!(!cache.contains_key("b"));
+    assert!(cache.contains_key("c"));
+
+    // Replacing an existing entry doesn't evict anything.
+    cache_insert(&mut cache, 2, "c", 4, 1004);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get("a").unwrap().value, 1);
+    assert_eq!(cache.get("c").unwrap().value, 4);
+}
+
+#[test]
+fn cache_pinned_entry_not_evicted_until_unpinned() {
+    let mut cache: HashMap<&str, CacheEntry<u64>> = HashMap::new();
+    cache_pin(&mut cache, "pinned", 0, 1000, 2000);
+    assert!(cache.get("pinned").unwrap().is_fresh(1999, 60));
+
+    // The pinned entry doesn't count towards the capacity, so both "a" and "b" fit.
+    cache_insert(&mut cache, 2, "a", 1, 1001);
+    cache_insert(&mut cache, 2, "b", 2, 1002);
+    assert_eq!(cache.len(), 3);
+
+    // The pinned entry is the least recently used, but "a" is evicted instead.
+    cache_insert(&mut cache, 2, "c", 3, 1003);
+    assert!(cache.contains_key("pinned"));
+    assert!(!cache.contains_key("a"));
+    assert!(cache.contains_key("b"));
+    assert!(cache.contains_key("c"));
+}
+
+#[test]
+fn cache_pinned_entry_removed_once_unpinned() {
+    let mut cache: HashMap<&str, CacheEntry<u64>> = HashMap::new();
+    cache_pin(&mut cache, "pinned", 0, 1000, 2000);
+    assert!(!cache.get("pinned").unwrap().is_fresh(2000, u64::MAX));
+
+    // The entry is removed the next time the cache is updated.
+    cache_insert(&mut cache, 2, "a", 1, 1999);
+    assert!(cache.contains_key("pinned"));
+    cache_insert(&mut cache, 2, "b", 2, 2000);
+    assert!(!cache.contains_key("pinned"));
+    assert_eq!(cache.len(), 2);
+
+    cache_pin(&mut cache, "pinned", 0, 2000, 3000);
+    cache_pin(&mut cache, "other", 0, 3000, 4000);
+    assert!(!cache.contains_key("pinned"));
+}
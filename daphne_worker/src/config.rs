@@ -7,58 +7,137 @@
 
 use crate::{
     auth::{DaphneWorkerAuth, DaphneWorkerAuthMethod},
-    dap_err,
+    cache::{cache_insert, cache_pin, CacheEntry},
+    counter_buffer::CounterBuffer,
+    dap_err, dap_err_in,
     durable::{
-        durable_name_report_store, durable_name_task,
-        leader_batch_queue::{LeaderBatchQueueResult, DURABLE_LEADER_BATCH_QUEUE_CURRENT},
-        DurableConnector, BINDING_DAP_GARBAGE_COLLECTOR, BINDING_DAP_LEADER_BATCH_QUEUE,
-        DURABLE_DELETE_ALL,
+        aggregate_store::{DURABLE_AGGREGATE_STORE_COUNT, DURABLE_AGGREGATE_STORE_GET},
+        counters::{DURABLE_COUNTERS_GET, DURABLE_COUNTERS_MERGE},
+        leader_agg_job_queue::DURABLE_LEADER_AGG_JOB_QUEUE_FIND,
+        leader_batch_queue::{
+            BatchCount, LeaderBatchQueueResult, DURABLE_LEADER_BATCH_QUEUE_CURRENT,
+            DURABLE_LEADER_BATCH_QUEUE_PEEK,
+        },
+        leader_col_job_queue::DURABLE_LEADER_COL_JOB_QUEUE_GET,
+        reports_pending::{
+            DURABLE_REPORTS_PENDING_AUDIT, DURABLE_REPORTS_PENDING_CONTAINS,
+            DURABLE_REPORTS_PENDING_PURGE,
+        },
+        reports_processed::DURABLE_REPORTS_PROCESSED_CONTAINS,
+        AggStoreName, CountersName, DurableConnector, DurableName, DurableOrdered,
+        GarbageCollectorName, QueueName, ReportStoreName, TaskName, BINDING_DAP_AGGREGATE_STORE,
+        BINDING_DAP_COUNTERS, BINDING_DAP_GARBAGE_COLLECTOR, BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+        BINDING_DAP_LEADER_BATCH_QUEUE, BINDING_DAP_LEADER_COL_JOB_QUEUE,
+        BINDING_DAP_REPORTS_PENDING, BINDING_DAP_REPORTS_PROCESSED, DURABLE_DELETE_ALL,
+        DURABLE_PING, STATE_BINDINGS,
     },
     error_reporting::ErrorReporter,
+    helper_state_encryption::HelperStateEncryptionKey,
     int_err,
     metrics::DaphneWorkerMetrics,
-    InternalTestAddTask, InternalTestEndpointForTask, InternalTestRole,
+    now,
+    rejection_counts::rejection_counts_from_registry,
+    report_selector::{AdaptiveReportSelector, AdaptiveReportSelectorConfig},
+    request_body_limits::{RequestBody, RequestBodyLimits},
+    single_flight::SingleFlight,
+    storage_err, InternalTestAddHpkeConfig, InternalTestAddTask, InternalTestBatchFill,
+    InternalTestBucketAggShare, InternalTestCorruptedPendingReport, InternalTestEndpointForTask,
+    InternalTestReportStatus, InternalTestRole, InternalTestTaskBundle,
+    InternalTestTaskBundleEntry, InternalTestTaskInfo,
 };
 use daphne::{
-    aborts::DapAbort,
+    aborts::{DapAbort, ProblemDetails},
     auth::BearerToken,
     constants::DapMediaType,
-    hpke::HpkeReceiverConfig,
+    hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
-        decode_base64url_vec, AggregationJobId, BatchId, CollectionJobId, HpkeConfig,
-        ReportMetadata, TaskId,
+        decode_base64url_vec, encode_base64url, AggregationJobId, BatchId, BatchSelector,
+        CollectionJobId, CollectionReq, HpkeConfig, HpkeConfigList, Interval, ReportId,
+        ReportMetadata, TaskId, Time,
     },
-    DapError, DapGlobalConfig, DapQueryConfig, DapRequest, DapResource, DapResponse, DapTaskConfig,
-    DapVersion, Prio3Config, VdafConfig,
+    roles::DapAggregator,
+    taskprov::{is_taskprov_task, TaskprovPolicy},
+    DapAggregateShare, DapBatchBucket, DapError, DapGlobalConfig, DapQueryConfig, DapRequest,
+    DapResource, DapResponse, DapTaskConfig, DapVersion, Prio3Config, VdafConfig,
+};
+use futures::{
+    future::Either,
+    stream::{self, StreamExt, TryStreamExt},
+    Future,
 };
 use matchit::Router;
 use prio::{
-    codec::Decode,
+    codec::{Decode, Encode},
     vdaf::prg::{Prg, PrgSha3, Seed, SeedStream},
 };
 use prometheus::{Encoder, Registry};
+use rand::{thread_rng, Rng};
+use ring::signature::Ed25519KeyPair;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Cursor,
-    sync::{Arc, RwLock, RwLockReadGuard},
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard},
     time::Duration,
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 use worker::{kv::KvStore, *};
 
 pub(crate) const KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG: &str = "hpke_receiver_config";
 pub(crate) const KV_KEY_PREFIX_BEARER_TOKEN_LEADER: &str = "bearer_token/leader/task";
 pub(crate) const KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR: &str = "bearer_token/collector/task";
+pub(crate) const KV_KEY_PREFIX_BEARER_TOKEN_CLIENT: &str = "bearer_token/client/task";
+pub(crate) const KV_KEY_PREFIX_BEARER_TOKENS_ADDITIONAL_COLLECTORS: &str =
+    "bearer_token/additional_collectors/task";
 pub(crate) const KV_KEY_PREFIX_TASK_CONFIG: &str = "config/task";
 pub(crate) const KV_BINDING_DAP_CONFIG: &str = "DAP_CONFIG";
 
+/// Read by the health check. The key is never written.
+const KV_KEY_HEALTH_CHECK: &str = "health_check";
+
+/// Number of seconds for which a successful health check is remembered.
+const HEALTH_CHECK_CACHE_TTL: u64 = 10;
+
+/// Counter of the `CountersName::ReportsIngested` instance that holds the number of reports
+/// ingested.
+pub(crate) const COUNTER_REPORTS_INGESTED: &str = "reports_ingested";
+
+/// mTLS certificate binding used by the Leader to authorize its requests to the Helper with TLS
+/// client auth.
+const MTLS_BINDING_DAP_LEADER_CERT: &str = "DAP_LEADER_CERT";
+
 const DAP_BASE_URL: &str = "DAP_BASE_URL";
 
+/// Default value of `hpke_config_gc_grace_period` (one week).
+const DEFAULT_HPKE_CONFIG_GC_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Default value of `task_config_cache_ttl`.
+const DEFAULT_TASK_CONFIG_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Default value of `task_config_cache_capacity`.
+const DEFAULT_TASK_CONFIG_CACHE_CAPACITY: usize = 1000;
+
+/// Default value of `max_concurrent_durable_requests`. This is well under the number of
+/// subrequests a Worker may make per request.
+const DEFAULT_MAX_CONCURRENT_DURABLE_REQUESTS: usize = 32;
+
+/// Default value of `reports_pending_retry_after`.
+const DEFAULT_REPORTS_PENDING_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Default value of `pending_collection_jobs_retry_after`.
+const DEFAULT_PENDING_COLLECTION_JOBS_RETRY_AFTER: Duration = Duration::from_secs(60);
+
 const INT_ERR_PEER_ABORT: &str = "request aborted by peer";
 const INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE: &str = "peer response is missing media type";
 
+/// Delay before the first retry of a request to the Helper. The delay doubles with each
+/// subsequent retry.
+const HELPER_REQUEST_BACKOFF_BASE: Duration = Duration::from_millis(100);
+
+/// Upper bound on the exponent used to compute the retry delay.
+const HELPER_REQUEST_BACKOFF_MAX_SHIFT: u32 = 6;
+
 /// Long-lived parameters for tasks using draft-wang-ppm-dap-taskprov-02 ("taskprov").
 pub(crate) struct TaskprovConfig {
     /// HPKE collector configuration for all taskprov tasks.
@@ -72,6 +151,15 @@ pub(crate) struct TaskprovConfig {
 
     /// Leader: Method for authorizing Collector requests.
     pub(crate) collector_auth: Option<DaphneWorkerAuthMethod>,
+
+    /// Criteria for opting in to taskprov tasks.
+    pub(crate) policy: TaskprovPolicy,
+
+    /// If set, then taskprov task configs and the Leader's bearer token are kept in memory by the
+    /// isolate that provisioned them rather than written to KV. This avoids generating KV garbage,
+    /// but requests that don't carry the taskprov extension only succeed on an isolate that has
+    /// already seen the task.
+    pub(crate) in_memory: bool,
 }
 
 /// Parameters required for pushing Prometheus metrics.
@@ -86,7 +174,7 @@ struct MetricsPushConfig {
 /// Daphne-Worker configuration, including long-lived parameters used across DAP tasks.
 pub(crate) struct DaphneWorkerConfig {
     /// Indicates if DaphneWorker is used as the Leader.
-    is_leader: bool,
+    pub(crate) is_leader: bool,
 
     /// Global DAP configuration.
     pub(crate) global: DapGlobalConfig,
@@ -122,12 +210,108 @@ pub(crate) struct DaphneWorkerConfig {
     /// configured by the Leader.
     pub(crate) helper_state_store_garbage_collect_after_secs: Option<Duration>,
 
+    /// Helper: Keys used to encrypt the Helper's state at rest. The first key is used for
+    /// encryption; any key may be used for decryption, which allows keys to be rotated. If not
+    /// configured, then the state is stored in the clear. This field is not configured by the
+    /// Leader.
+    pub(crate) helper_state_encryption_keys: Option<Vec<HelperStateEncryptionKey>>,
+
+    /// Helper: If set, then the Helper's state is stored as base64 rather than hex, reducing its
+    /// size by a third. State stored with either encoding can be read.
+    pub(crate) helper_state_compact_encoding: bool,
+
     /// Additional time to wait before deletng an instance of ReportsProcessed. Added to the value
     /// of the `report_storage_epoch_duration` field of the global DAP configuration.
     pub(crate) processed_alarm_safety_interval: Duration,
 
     /// Metrics push configuration.
     metrics_push_config: Option<MetricsPushConfig>,
+
+    /// Leader: Maximum amount of time to wait for the Helper to respond to a request. If not
+    /// configured, then requests to the Helper do not time out.
+    pub(crate) helper_request_timeout: Option<Duration>,
+
+    /// Leader: Maximum number of times to retry a request to the Helper that failed due to a
+    /// transient error. Only requests that are safe to repeat are retried.
+    pub(crate) helper_request_max_retries: u32,
+
+    /// Leader: If configured, then the report selector is scaled based on aggregation latency.
+    pub(crate) adaptive_report_selector: Option<AdaptiveReportSelectorConfig>,
+
+    /// Time to wait after an HPKE receiver config expires before deleting it. Until then, the
+    /// config can still be used to decrypt reports that were encrypted before it expired.
+    pub(crate) hpke_config_gc_grace_period: Duration,
+
+    /// Amount of time for which a task config or Leader bearer token read from KV is cached by the
+    /// isolate.
+    pub(crate) task_config_cache_ttl: Duration,
+
+    /// Maximum number of task configs, and of Leader bearer tokens, cached by the isolate.
+    pub(crate) task_config_cache_capacity: usize,
+
+    /// Leader: Maximum number of reports that may be pending in a single instance of
+    /// ReportsPending. If the instance is at capacity, then uploads are rejected with a retriable
+    /// error until reports are drained. If not configured, then the number of reports is not
+    /// limited.
+    pub(crate) reports_pending_max_reports: Option<u64>,
+
+    /// Leader: Time a Client is asked to wait before retrying an upload that was rejected because
+    /// the ReportsPending instance was at capacity.
+    pub(crate) reports_pending_retry_after: Duration,
+
+    /// Location hints for durable objects, by binding. When an instance is created, it is placed
+    /// near the hinted location if possible, e.g., to reduce the latency of requests from a
+    /// region. Hints are best-effort and do not restrict where data is stored, so they are not a
+    /// means of data residency. If not configured, then instances are placed near the request that
+    /// first uses them.
+    pub(crate) durable_location_hints: Option<HashMap<String, String>>,
+
+    /// Leader: Amount of time for which the HPKE encapsulated keys of uploaded reports are
+    /// remembered. If configured, then an upload whose Leader share reuses a remembered key is
+    /// rejected. This costs two durable object requests per upload, so it is disabled by default.
+    pub(crate) hpke_enc_replay_cache_ttl: Option<Duration>,
+
+    /// Leader: Minimum number of seconds between flushes of the isolate's ingested report count to
+    /// the `Counters` durable object. If not configured, then ingested reports are not
+    /// counted.
+    pub(crate) reports_ingested_flush_interval: Option<u64>,
+
+    /// Maximum size of the body of a request, by media type.
+    pub(crate) request_body_limits: RequestBodyLimits,
+
+    /// Leader: Amount of time for which the result of a finished collection job is kept. If not
+    /// configured, then results are kept indefinitely.
+    pub(crate) collection_job_result_ttl: Option<Duration>,
+
+    /// Maximum number of requests to durable objects that are in flight at once when a request
+    /// fans out to many instances, e.g., one per bucket of a batch.
+    pub(crate) max_concurrent_durable_requests: usize,
+
+    /// If configured, then the HPKE config list can be obtained signed with this key, allowing
+    /// Clients that obtain it out-of-band to verify its integrity.
+    pub(crate) hpke_config_signing_key: Option<Ed25519KeyPair>,
+
+    /// Leader: Maximum number of collection jobs that may be pending for a single task. If the task
+    /// is at capacity, then new collection jobs are rejected with a retriable error until pending
+    /// jobs finish. If not configured, then the number of pending jobs is not limited.
+    pub(crate) max_pending_collection_jobs_per_task: Option<u64>,
+
+    /// Leader: Time a Collector is asked to wait before retrying a collection job that was
+    /// rejected because the task had the maximum number of pending collection jobs.
+    pub(crate) pending_collection_jobs_retry_after: Duration,
+
+    /// Leader: Minimum size of a collection response body for it to be compressed. If not
+    /// configured, then collection responses are never compressed.
+    pub(crate) collection_compression_min_bytes: Option<usize>,
+
+    /// Leader: Time a Collector polling a collection job that is still pending is asked to wait
+    /// before polling again, unless the task overrides it. If not configured (here or for the
+    /// task), then no Retry-After header is sent.
+    pub(crate) collect_poll_retry_after: Option<Duration>,
+
+    /// Leader: Upper bound on the random delay added to `collect_poll_retry_after`. This spreads
+    /// out polling by many Collectors, e.g., after a deployment restarts.
+    pub(crate) collect_poll_retry_after_jitter: Duration,
 }
 
 impl DaphneWorkerConfig {
@@ -250,11 +434,31 @@ impl DaphneWorkerConfig {
                 None
             };
 
+            const DAP_TASKPROV_POLICY: &str = "DAP_TASKPROV_POLICY";
+            let policy = if let Ok(policy) = env.var(DAP_TASKPROV_POLICY) {
+                serde_json::from_str(policy.to_string().as_ref()).map_err(|e| {
+                    Error::RustError(format!("Failed to parse {DAP_TASKPROV_POLICY}: {e}"))
+                })?
+            } else {
+                TaskprovPolicy::default()
+            };
+
+            const DAP_TASKPROV_IN_MEMORY: &str = "DAP_TASKPROV_IN_MEMORY";
+            let in_memory = if let Ok(in_memory) = env.var(DAP_TASKPROV_IN_MEMORY) {
+                in_memory.to_string().parse().map_err(|e| {
+                    Error::RustError(format!("Failed to parse {DAP_TASKPROV_IN_MEMORY}: {e}"))
+                })?
+            } else {
+                false
+            };
+
             Some(TaskprovConfig {
                 hpke_collector_config,
                 vdaf_verify_key_init,
                 leader_auth,
                 collector_auth,
+                policy,
+                in_memory,
             })
         } else {
             None
@@ -283,6 +487,38 @@ impl DaphneWorkerConfig {
             None
         };
 
+        const DAP_HELPER_STATE_ENCRYPTION_KEYS: &str = "DAP_HELPER_STATE_ENCRYPTION_KEYS";
+        let helper_state_encryption_keys = if is_leader {
+            None
+        } else if let Ok(keys) = env.secret(DAP_HELPER_STATE_ENCRYPTION_KEYS) {
+            let keys: Vec<HelperStateEncryptionKey> = serde_json::from_str(&keys.to_string())
+                .map_err(|e| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_HELPER_STATE_ENCRYPTION_KEYS}: {e}"
+                    ))
+                })?;
+            if keys.is_empty() {
+                return Err(Error::RustError(format!(
+                    "{DAP_HELPER_STATE_ENCRYPTION_KEYS} is empty"
+                )));
+            }
+            Some(keys)
+        } else {
+            None
+        };
+
+        const DAP_HELPER_STATE_COMPACT_ENCODING: &str = "DAP_HELPER_STATE_COMPACT_ENCODING";
+        let helper_state_compact_encoding =
+            if let Ok(compact) = env.var(DAP_HELPER_STATE_COMPACT_ENCODING) {
+                compact.to_string().parse().map_err(|e| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_HELPER_STATE_COMPACT_ENCODING}: {e}"
+                    ))
+                })?
+            } else {
+                false
+            };
+
         let processed_alarm_safety_interval = Duration::from_secs(
             env.var("DAP_PROCESSED_ALARM_SAFETY_INTERVAL")?
                 .to_string()
@@ -321,6 +557,275 @@ impl DaphneWorkerConfig {
             }
         };
 
+        const DAP_HELPER_REQUEST_TIMEOUT_MILLIS: &str = "DAP_HELPER_REQUEST_TIMEOUT_MILLIS";
+        let helper_request_timeout =
+            if let Ok(timeout_millis) = env.var(DAP_HELPER_REQUEST_TIMEOUT_MILLIS) {
+                Some(Duration::from_millis(
+                    timeout_millis.to_string().parse().map_err(|err| {
+                        Error::RustError(format!(
+                            "Failed to parse {DAP_HELPER_REQUEST_TIMEOUT_MILLIS}: {err}"
+                        ))
+                    })?,
+                ))
+            } else {
+                None
+            };
+
+        const DAP_HELPER_REQUEST_MAX_RETRIES: &str = "DAP_HELPER_REQUEST_MAX_RETRIES";
+        let helper_request_max_retries =
+            if let Ok(max_retries) = env.var(DAP_HELPER_REQUEST_MAX_RETRIES) {
+                max_retries.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_HELPER_REQUEST_MAX_RETRIES}: {err}"
+                    ))
+                })?
+            } else {
+                0
+            };
+
+        const DAP_ADAPTIVE_REPORT_SELECTOR: &str = "DAP_ADAPTIVE_REPORT_SELECTOR";
+        let adaptive_report_selector =
+            if let Ok(adaptive_report_selector) = env.var(DAP_ADAPTIVE_REPORT_SELECTOR) {
+                Some(
+                    serde_json::from_str(adaptive_report_selector.to_string().as_ref()).map_err(
+                        |e| {
+                            Error::RustError(format!(
+                                "Failed to parse {DAP_ADAPTIVE_REPORT_SELECTOR}: {e}"
+                            ))
+                        },
+                    )?,
+                )
+            } else {
+                None
+            };
+
+        const DAP_HPKE_CONFIG_GC_GRACE_PERIOD: &str = "DAP_HPKE_CONFIG_GC_GRACE_PERIOD";
+        let hpke_config_gc_grace_period =
+            if let Ok(grace_period) = env.var(DAP_HPKE_CONFIG_GC_GRACE_PERIOD) {
+                Duration::from_secs(grace_period.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_HPKE_CONFIG_GC_GRACE_PERIOD}: {err}"
+                    ))
+                })?)
+            } else {
+                DEFAULT_HPKE_CONFIG_GC_GRACE_PERIOD
+            };
+
+        const DAP_TASK_CONFIG_CACHE_TTL_SECS: &str = "DAP_TASK_CONFIG_CACHE_TTL_SECS";
+        let task_config_cache_ttl = if let Ok(ttl) = env.var(DAP_TASK_CONFIG_CACHE_TTL_SECS) {
+            Duration::from_secs(ttl.to_string().parse().map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to parse {DAP_TASK_CONFIG_CACHE_TTL_SECS}: {err}"
+                ))
+            })?)
+        } else {
+            DEFAULT_TASK_CONFIG_CACHE_TTL
+        };
+
+        const DAP_TASK_CONFIG_CACHE_CAPACITY: &str = "DAP_TASK_CONFIG_CACHE_CAPACITY";
+        let task_config_cache_capacity =
+            if let Ok(capacity) = env.var(DAP_TASK_CONFIG_CACHE_CAPACITY) {
+                let capacity = capacity.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_TASK_CONFIG_CACHE_CAPACITY}: {err}"
+                    ))
+                })?;
+                // A task config is read back from the cache after it is fetched from KV, so the
+                // cache must be able to hold at least one entry.
+                if capacity == 0 {
+                    return Err(Error::RustError(format!(
+                        "{DAP_TASK_CONFIG_CACHE_CAPACITY} must be positive"
+                    )));
+                }
+                capacity
+            } else {
+                DEFAULT_TASK_CONFIG_CACHE_CAPACITY
+            };
+
+        const DAP_REPORTS_PENDING_MAX_REPORTS: &str = "DAP_REPORTS_PENDING_MAX_REPORTS";
+        let reports_pending_max_reports =
+            if let Ok(max_reports) = env.var(DAP_REPORTS_PENDING_MAX_REPORTS) {
+                Some(max_reports.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_REPORTS_PENDING_MAX_REPORTS}: {err}"
+                    ))
+                })?)
+            } else {
+                None
+            };
+
+        const DAP_REPORTS_PENDING_RETRY_AFTER_SECS: &str = "DAP_REPORTS_PENDING_RETRY_AFTER_SECS";
+        let reports_pending_retry_after =
+            if let Ok(retry_after) = env.var(DAP_REPORTS_PENDING_RETRY_AFTER_SECS) {
+                Duration::from_secs(retry_after.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_REPORTS_PENDING_RETRY_AFTER_SECS}: {err}"
+                    ))
+                })?)
+            } else {
+                DEFAULT_REPORTS_PENDING_RETRY_AFTER
+            };
+
+        const DAP_HPKE_ENC_REPLAY_CACHE_TTL_SECS: &str = "DAP_HPKE_ENC_REPLAY_CACHE_TTL_SECS";
+        let hpke_enc_replay_cache_ttl = if let Ok(ttl) = env.var(DAP_HPKE_ENC_REPLAY_CACHE_TTL_SECS)
+        {
+            Some(Duration::from_secs(ttl.to_string().parse().map_err(
+                |err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_HPKE_ENC_REPLAY_CACHE_TTL_SECS}: {err}"
+                    ))
+                },
+            )?))
+        } else {
+            None
+        };
+
+        const DAP_REPORTS_INGESTED_FLUSH_INTERVAL_SECS: &str =
+            "DAP_REPORTS_INGESTED_FLUSH_INTERVAL_SECS";
+        let reports_ingested_flush_interval =
+            if let Ok(interval) = env.var(DAP_REPORTS_INGESTED_FLUSH_INTERVAL_SECS) {
+                Some(interval.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_REPORTS_INGESTED_FLUSH_INTERVAL_SECS}: {err}"
+                    ))
+                })?)
+            } else {
+                None
+            };
+
+        const DAP_DURABLE_LOCATION_HINTS: &str = "DAP_DURABLE_LOCATION_HINTS";
+        let durable_location_hints = if let Ok(hints) = env.var(DAP_DURABLE_LOCATION_HINTS) {
+            Some(
+                parse_durable_location_hints(hints.to_string().as_ref()).map_err(|e| {
+                    Error::RustError(format!("Failed to parse {DAP_DURABLE_LOCATION_HINTS}: {e}"))
+                })?,
+            )
+        } else {
+            None
+        };
+
+        const DAP_REQUEST_BODY_LIMITS: &str = "DAP_REQUEST_BODY_LIMITS";
+        let request_body_limits = if let Ok(limits) = env.var(DAP_REQUEST_BODY_LIMITS) {
+            serde_json::from_str(limits.to_string().as_ref()).map_err(|e| {
+                Error::RustError(format!("Failed to parse {DAP_REQUEST_BODY_LIMITS}: {e}"))
+            })?
+        } else {
+            RequestBodyLimits::default()
+        };
+
+        const DAP_COLLECTION_JOB_RESULT_TTL_SECS: &str = "DAP_COLLECTION_JOB_RESULT_TTL_SECS";
+        let collection_job_result_ttl = if let Ok(ttl) = env.var(DAP_COLLECTION_JOB_RESULT_TTL_SECS)
+        {
+            Some(Duration::from_secs(ttl.to_string().parse().map_err(
+                |err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_COLLECTION_JOB_RESULT_TTL_SECS}: {err}"
+                    ))
+                },
+            )?))
+        } else {
+            None
+        };
+
+        const DAP_MAX_CONCURRENT_DURABLE_REQUESTS: &str = "DAP_MAX_CONCURRENT_DURABLE_REQUESTS";
+        let max_concurrent_durable_requests =
+            if let Ok(max) = env.var(DAP_MAX_CONCURRENT_DURABLE_REQUESTS) {
+                let max = max.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_MAX_CONCURRENT_DURABLE_REQUESTS}: {err}"
+                    ))
+                })?;
+                if max == 0 {
+                    return Err(Error::RustError(format!(
+                        "{DAP_MAX_CONCURRENT_DURABLE_REQUESTS} must be positive"
+                    )));
+                }
+                max
+            } else {
+                DEFAULT_MAX_CONCURRENT_DURABLE_REQUESTS
+            };
+
+        const DAP_HPKE_CONFIG_SIGNING_KEY: &str = "DAP_HPKE_CONFIG_SIGNING_KEY";
+        let hpke_config_signing_key = if let Ok(seed) = env.secret(DAP_HPKE_CONFIG_SIGNING_KEY) {
+            let seed = hex::decode(seed.to_string()).map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to decode {DAP_HPKE_CONFIG_SIGNING_KEY}: {err}"
+                ))
+            })?;
+            Some(Ed25519KeyPair::from_seed_unchecked(&seed).map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to parse {DAP_HPKE_CONFIG_SIGNING_KEY}: {err}"
+                ))
+            })?)
+        } else {
+            None
+        };
+
+        const DAP_COLLECTION_COMPRESSION_MIN_BYTES: &str = "DAP_COLLECTION_COMPRESSION_MIN_BYTES";
+        let collection_compression_min_bytes =
+            if let Ok(min_bytes) = env.var(DAP_COLLECTION_COMPRESSION_MIN_BYTES) {
+                Some(min_bytes.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_COLLECTION_COMPRESSION_MIN_BYTES}: {err}"
+                    ))
+                })?)
+            } else {
+                None
+            };
+
+        const DAP_MAX_PENDING_COLLECTION_JOBS_PER_TASK: &str =
+            "DAP_MAX_PENDING_COLLECTION_JOBS_PER_TASK";
+        let max_pending_collection_jobs_per_task =
+            if let Ok(max) = env.var(DAP_MAX_PENDING_COLLECTION_JOBS_PER_TASK) {
+                Some(max.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_MAX_PENDING_COLLECTION_JOBS_PER_TASK}: {err}"
+                    ))
+                })?)
+            } else {
+                None
+            };
+
+        const DAP_PENDING_COLLECTION_JOBS_RETRY_AFTER_SECS: &str =
+            "DAP_PENDING_COLLECTION_JOBS_RETRY_AFTER_SECS";
+        let pending_collection_jobs_retry_after =
+            if let Ok(retry_after) = env.var(DAP_PENDING_COLLECTION_JOBS_RETRY_AFTER_SECS) {
+                Duration::from_secs(retry_after.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_PENDING_COLLECTION_JOBS_RETRY_AFTER_SECS}: {err}"
+                    ))
+                })?)
+            } else {
+                DEFAULT_PENDING_COLLECTION_JOBS_RETRY_AFTER
+            };
+
+        const DAP_COLLECT_POLL_RETRY_AFTER_SECS: &str = "DAP_COLLECT_POLL_RETRY_AFTER_SECS";
+        let collect_poll_retry_after =
+            if let Ok(retry_after) = env.var(DAP_COLLECT_POLL_RETRY_AFTER_SECS) {
+                Some(Duration::from_secs(
+                    retry_after.to_string().parse().map_err(|err| {
+                        Error::RustError(format!(
+                            "Failed to parse {DAP_COLLECT_POLL_RETRY_AFTER_SECS}: {err}"
+                        ))
+                    })?,
+                ))
+            } else {
+                None
+            };
+
+        const DAP_COLLECT_POLL_RETRY_AFTER_JITTER_SECS: &str =
+            "DAP_COLLECT_POLL_RETRY_AFTER_JITTER_SECS";
+        let collect_poll_retry_after_jitter =
+            if let Ok(jitter) = env.var(DAP_COLLECT_POLL_RETRY_AFTER_JITTER_SECS) {
+                Duration::from_secs(jitter.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_COLLECT_POLL_RETRY_AFTER_JITTER_SECS}: {err}"
+                    ))
+                })?)
+            } else {
+                Duration::ZERO
+            };
+
         Ok(Self {
             global,
             deployment,
@@ -333,8 +838,30 @@ impl DaphneWorkerConfig {
             default_version,
             admin_token,
             helper_state_store_garbage_collect_after_secs,
+            helper_state_encryption_keys,
+            helper_state_compact_encoding,
             processed_alarm_safety_interval,
             metrics_push_config,
+            helper_request_timeout,
+            helper_request_max_retries,
+            adaptive_report_selector,
+            hpke_config_gc_grace_period,
+            task_config_cache_ttl,
+            task_config_cache_capacity,
+            reports_pending_max_reports,
+            reports_pending_retry_after,
+            durable_location_hints,
+            hpke_enc_replay_cache_ttl,
+            reports_ingested_flush_interval,
+            request_body_limits,
+            collection_job_result_ttl,
+            max_concurrent_durable_requests,
+            hpke_config_signing_key,
+            max_pending_collection_jobs_per_task,
+            pending_collection_jobs_retry_after,
+            collection_compression_min_bytes,
+            collect_poll_retry_after,
+            collect_poll_retry_after_jitter,
         })
     }
 
@@ -344,20 +871,29 @@ impl DaphneWorkerConfig {
         task_config: &DapTaskConfig,
         task_id_hex: &str,
         metadata: &ReportMetadata,
-    ) -> String {
-        let mut shard_seed = [0; 8];
-        PrgSha3::seed_stream(
-            &self.report_shard_key,
-            b"report shard",
-            metadata.id.as_ref(),
-        )
-        .fill(&mut shard_seed);
-        let shard = u64::from_be_bytes(shard_seed) % self.report_shard_count;
+    ) -> ReportStoreName {
         let epoch = metadata.time - (metadata.time % self.global.report_storage_epoch_duration);
-        durable_name_report_store(&task_config.version, task_id_hex, epoch, shard)
+        ReportStoreName::new(
+            &task_config.version,
+            task_id_hex,
+            epoch,
+            self.report_shard(&metadata.id),
+        )
+    }
+
+    /// Compute the report storage shard to map a report to.
+    fn report_shard(&self, report_id: &ReportId) -> u64 {
+        let mut shard_seed = [0; 8];
+        PrgSha3::seed_stream(&self.report_shard_key, b"report shard", report_id.as_ref())
+            .fill(&mut shard_seed);
+        u64::from_be_bytes(shard_seed) % self.report_shard_count
     }
 }
 
+/// DAP version and task (or `None` for the global configs) for which an HPKE receiver config is
+/// advertised.
+type HpkeConfigScope = (DapVersion, Option<TaskId>);
+
 /// Daphne-Worker per-isolate state, which may be used by multiple requests. Includes long-lived configuration,
 /// cached responses from KV, etc.
 pub(crate) struct DaphneWorkerIsolateState {
@@ -370,14 +906,86 @@ pub(crate) struct DaphneWorkerIsolateState {
     /// receiver config for the first time from Cloudflare KV.
     hpke_receiver_configs: Arc<RwLock<HashMap<HpkeReceiverKvKey, HpkeReceiverConfig>>>,
 
-    /// Laeder bearer token per task.
-    leader_bearer_tokens: Arc<RwLock<HashMap<TaskId, BearerToken>>>,
+    /// HPKE receiver config advertised per version and task (or `None` for the global configs).
+    /// Entries expire after `task_config_cache_ttl` so that rotations made by other isolates are
+    /// picked up, and the number of entries is bounded by `task_config_cache_capacity`.
+    hpke_config_selections: Arc<RwLock<HashMap<HpkeConfigScope, CacheEntry<HpkeReceiverKvKey>>>>,
+
+    /// Leader bearer token per task, along with the token being rotated out, if any. Entries
+    /// expire after `task_config_cache_ttl` so that rotations made by other isolates are picked
+    /// up. Tasks without a token are cached as well, so that requests carrying an incorrect token
+    /// don't each cause a KV read.
+    leader_bearer_tokens:
+        Arc<RwLock<HashMap<TaskId, CacheEntry<Option<LeaderBearerTokenKvValue>>>>>,
 
     /// Collector bearer token per task.
     collector_bearer_tokens: Arc<RwLock<HashMap<TaskId, BearerToken>>>,
 
-    /// Task list.
-    tasks: Arc<RwLock<HashMap<TaskId, DapTaskConfig>>>,
+    /// Bearer tokens of any Collectors authorized per task in addition to the primary Collector.
+    additional_collector_bearer_tokens: Arc<RwLock<HashMap<TaskId, Vec<BearerToken>>>>,
+
+    /// Client bearer token per task.
+    client_bearer_tokens: Arc<RwLock<HashMap<TaskId, BearerToken>>>,
+
+    /// Task list. Entries expire after `task_config_cache_ttl` and the number of entries is
+    /// bounded by `task_config_cache_capacity`.
+    tasks: Arc<RwLock<HashMap<TaskId, CacheEntry<DapTaskConfig>>>>,
+
+    /// Taskprov: Tasks that are being provisioned by this isolate. Concurrent first sightings of
+    /// a task are serialized so that the task is only written to KV once.
+    pub(crate) taskprov_provisioning: Arc<SingleFlight<TaskId>>,
+
+    /// Leader: Report selector scaling, if configured. This is tracked per isolate.
+    pub(crate) adaptive_report_selector: Option<AdaptiveReportSelector>,
+
+    /// Rejection counts waiting to be persisted, if configured.
+    rejection_counts: Arc<CounterBuffer>,
+
+    /// Leader: Number of ingested reports waiting to be persisted, if configured.
+    pub(crate) reports_ingested: Arc<CounterBuffer>,
+
+    /// If set, then this time is used as the current time instead of the wall clock. This is only
+    /// set via the internal test API so that time bounds can be tested deterministically.
+    time_override: Arc<Mutex<Option<Time>>>,
+
+    /// Time of the last successful health check, if any.
+    health_checked_at: Arc<Mutex<Option<Time>>>,
+}
+
+/// Parse the location hints for durable objects, a JSON object mapping bindings to locations.
+/// Every binding must be the binding of a durable object class, so that a misspelled binding is
+/// not silently ignored.
+pub(crate) fn parse_durable_location_hints(
+    hints: &str,
+) -> std::result::Result<HashMap<String, String>, String> {
+    let hints: HashMap<String, String> = serde_json::from_str(hints).map_err(|e| e.to_string())?;
+    for binding in hints.keys() {
+        if binding != BINDING_DAP_GARBAGE_COLLECTOR.as_str()
+            && !STATE_BINDINGS.contains(&binding.as_str())
+        {
+            return Err(format!("unrecognized durable object binding: {binding}"));
+        }
+    }
+    Ok(hints)
+}
+
+/// If at least `interval` seconds have elapsed since the buffer was last flushed, add its counts to
+/// the given `Counters` instance in the background. If this fails, then the counts are put back so
+/// that they are retried on the next flush.
+fn flush_counters(env: &Env, buffer: Arc<CounterBuffer>, name: CountersName, interval: u64) {
+    if let Some(counts) = buffer.take_if_due(now(), interval) {
+        let env = Env::from(wasm_bindgen::JsValue::clone(env));
+        wasm_bindgen_futures::spawn_local(async move {
+            let durable = DurableConnector::new(&env);
+            let res: Result<()> = durable
+                .post(BINDING_DAP_COUNTERS, DURABLE_COUNTERS_MERGE, name, &counts)
+                .await;
+            if let Err(e) = res {
+                error!("failed to persist {}: {e}", name.as_str());
+                buffer.restore(counts);
+            }
+        });
+    }
 }
 
 impl DaphneWorkerIsolateState {
@@ -387,13 +995,27 @@ impl DaphneWorkerIsolateState {
         // TODO Configure this client to use HTTPS only, except if running in a test environment.
         let client = reqwest_wasm::Client::new();
 
+        let adaptive_report_selector = config
+            .adaptive_report_selector
+            .clone()
+            .map(AdaptiveReportSelector::new);
+
         Ok(Self {
             config,
             client,
+            adaptive_report_selector,
+            rejection_counts: Arc::new(CounterBuffer::new(now())),
+            reports_ingested: Arc::new(CounterBuffer::new(now())),
             hpke_receiver_configs: Arc::new(RwLock::new(HashMap::new())),
+            hpke_config_selections: Arc::new(RwLock::new(HashMap::new())),
             leader_bearer_tokens: Arc::new(RwLock::new(HashMap::new())),
             collector_bearer_tokens: Arc::new(RwLock::new(HashMap::new())),
+            additional_collector_bearer_tokens: Arc::new(RwLock::new(HashMap::new())),
+            client_bearer_tokens: Arc::new(RwLock::new(HashMap::new())),
+            taskprov_provisioning: Arc::new(SingleFlight::new()),
             tasks: Arc::new(RwLock::new(HashMap::new())),
+            time_override: Arc::new(Mutex::new(None)),
+            health_checked_at: Arc::new(Mutex::new(None)),
         })
     }
 }
@@ -490,12 +1112,53 @@ impl<'srv> DaphneWorkerRequestState<'srv> {
         Ok(())
     }
 
+    /// Flush the isolate's counters: if configured, add the rejections counted while handling the
+    /// request to the isolate's rejection counts, then persist any buffers whose flush interval has
+    /// elapsed. The counts are persisted in the background so that the response is not delayed.
+    pub(crate) fn maybe_flush_counters(&self, env: &Env) {
+        if let Some(interval) = self
+            .isolate_state
+            .config
+            .global
+            .rejection_counts_flush_interval
+        {
+            let rejection_counts = &self.isolate_state.rejection_counts;
+            for (reason, count) in rejection_counts_from_registry(&self.prometheus_registry) {
+                rejection_counts.record(reason, count);
+            }
+            flush_counters(
+                env,
+                Arc::clone(rejection_counts),
+                CountersName::RejectionCounts,
+                interval,
+            );
+        }
+
+        if let Some(interval) = self.isolate_state.config.reports_ingested_flush_interval {
+            flush_counters(
+                env,
+                Arc::clone(&self.isolate_state.reports_ingested),
+                CountersName::ReportsIngested,
+                interval,
+            );
+        }
+    }
+
     pub(crate) fn dap_abort_to_worker_response(&self, e: DapAbort) -> Result<Response> {
-        let status = if matches!(e, DapAbort::Internal(..)) {
-            self.error_reporter.report_abort(&e);
-            500
-        } else {
-            400
+        let mut headers = Headers::new();
+        let status = match e {
+            DapAbort::Internal(..) => {
+                self.error_reporter.report_abort(&e);
+                500
+            }
+            DapAbort::NotFound(..) => 404,
+            DapAbort::PayloadTooLarge { .. } => 413,
+            DapAbort::UnsupportedMediaType(..) => 415,
+            DapAbort::RateLimited { retry_after } => {
+                headers.set("Retry-After", &retry_after.to_string())?;
+                429
+            }
+            _ => 400,
         };
         self.metrics
             .dap_abort_counter
@@ -506,7 +1169,6 @@ impl<'srv> DaphneWorkerRequestState<'srv> {
             "request aborted: {}",
             serde_json::to_string(&problem_details)?
         );
-        let mut headers = Headers::new();
         headers.set("Content-Type", "application/problem+json")?;
         Ok(Response::from_json(&problem_details)?
             .with_status(status)
@@ -523,6 +1185,11 @@ pub(crate) struct DaphneWorker<'srv> {
 impl<'srv> DaphneWorker<'srv> {
     pub(crate) fn durable(&self) -> DurableConnector<'_> {
         DurableConnector::new(self.env)
+            .with_request_counter(
+                &self.state.metrics.durable_request_counter,
+                &self.state.host,
+            )
+            .with_location_hints(self.config().durable_location_hints.as_ref())
     }
 
     pub(crate) fn kv(&self) -> Result<KvStore> {
@@ -539,7 +1206,7 @@ impl<'srv> DaphneWorker<'srv> {
 
     /// Set a key/value pair unless the key already exists. If the key exists, then return the current
     /// value. Otherwise return nothing.
-    async fn kv_set_if_not_exists<K, V>(
+    pub(crate) async fn kv_set_if_not_exists<K, V>(
         &self,
         kv_key_prefix: &str,
         kv_key_suffix: &K,
@@ -628,143 +1295,1528 @@ impl<'srv> DaphneWorker<'srv> {
         .await
     }
 
-    /// Retrieve from KV the Leader's bearer token for the given task.
-    pub(crate) async fn get_leader_bearer_token<'a>(
-        &'a self,
-        task_id: &'a TaskId,
-    ) -> Result<Option<GuardedBearerToken>> {
-        self.kv_get_cached(
-            &self.isolate_state().leader_bearer_tokens,
-            KV_KEY_PREFIX_BEARER_TOKEN_LEADER,
-            Cow::Borrowed(task_id),
-        )
-        .await
+    /// Get the KV key of the HPKE receiver config that was last selected for advertising for the
+    /// given version and task, unless the selection was made more than `task_config_cache_ttl`
+    /// ago.
+    pub(crate) fn get_hpke_config_selection(
+        &self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+    ) -> Result<Option<HpkeReceiverKvKey>> {
+        let now = now();
+        let ttl = self.config().task_config_cache_ttl.as_secs();
+        let guarded_map = self
+            .isolate_state()
+            .hpke_config_selections
+            .read()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for reading: {e}")))?;
+
+        match guarded_map.get(&(version, task_id.cloned())) {
+            Some(entry) if entry.is_fresh(now, ttl) => {
+                entry.touch(now);
+                Ok(Some(entry.value.clone()))
+            }
+            _ => Ok(None),
+        }
     }
 
-    /// Set a leader bearer token for the given task.
-    pub(crate) async fn set_leader_bearer_token(
+    /// Record that the HPKE receiver config indicated by `hpke_receiver_kv_key` is the one to
+    /// advertise for its version and task.
+    pub(crate) fn cache_hpke_config_selection(
         &self,
-        task_id: &TaskId,
-        token: &BearerToken,
-    ) -> Result<Option<BearerToken>> {
-        self.kv_set_if_not_exists(KV_KEY_PREFIX_BEARER_TOKEN_LEADER, task_id, token.clone())
-            .await
+        hpke_receiver_kv_key: HpkeReceiverKvKey,
+    ) -> Result<()> {
+        let mut guarded_map = self
+            .isolate_state()
+            .hpke_config_selections
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?;
+        cache_insert(
+            &mut guarded_map,
+            self.config().task_config_cache_capacity,
+            (
+                hpke_receiver_kv_key.version,
+                hpke_receiver_kv_key.task_id.clone(),
+            ),
+            hpke_receiver_kv_key,
+            now(),
+        );
+        Ok(())
     }
 
-    /// Retrieve from KV the Collector's bearer token for the given task.
-    pub(crate) async fn get_collector_bearer_token<'a>(
-        &'a self,
-        task_id: &'a TaskId,
-    ) -> Result<Option<GuardedBearerToken>> {
-        self.kv_get_cached(
-            &self.isolate_state().collector_bearer_tokens,
-            KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR,
-            Cow::Borrowed(task_id),
-        )
-        .await
+    /// Get the HPKE receiver config with the given ID for decrypting reports for a task. If the
+    /// task uses task-scoped configs, then these are checked first. Otherwise, or if no such
+    /// config exists, the global config is used. Configs whose KDF or AEAD is not allowed by the
+    /// global config are ignored.
+    pub(crate) async fn get_hpke_receiver_config_for_task(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        hpke_config_id: u8,
+    ) -> Result<Option<GuardedHpkeReceiverConfig>> {
+        let global = &self.config().global;
+        if task_config.task_scoped_hpke_config {
+            if let Some(hpke_receiver_config) = self
+                .get_hpke_receiver_config(HpkeReceiverKvKey {
+                    task_id: Some(task_id.clone()),
+                    version: task_config.version,
+                    hpke_config_id,
+                })
+                .await?
+                .filter(|config| global.allows_hpke_config(&config.value().config))
+            {
+                return Ok(Some(hpke_receiver_config));
+            }
+        }
+
+        Ok(self
+            .get_hpke_receiver_config(HpkeReceiverKvKey {
+                task_id: None,
+                version: task_config.version,
+                hpke_config_id,
+            })
+            .await?
+            .filter(|config| global.allows_hpke_config(&config.value().config)))
     }
 
-    /// Retrieve from KV the configuration for the given task.
-    pub(crate) async fn get_task_config<'req>(
-        &'srv self,
+    /// Read the HPKE receiver config indicated by `hpke_receiver_kv_key` from KV, bypassing the
+    /// cache, and update the cached copy. This ensures that changes to the config's validity
+    /// window, e.g., due to rotation by another isolate, are observed.
+    pub(crate) async fn refresh_hpke_receiver_config(
+        &self,
+        hpke_receiver_kv_key: HpkeReceiverKvKey,
+    ) -> Result<Option<GuardedHpkeReceiverConfig>> {
+        let kv_key = format!("{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{hpke_receiver_kv_key}");
+        let hpke_receiver_config = self.kv()?.get(&kv_key).json::<HpkeReceiverConfig>().await?;
+
+        let map = &self.isolate_state().hpke_receiver_configs;
+        {
+            let mut guarded_map = map
+                .write()
+                .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?;
+            match hpke_receiver_config {
+                Some(hpke_receiver_config) => {
+                    guarded_map.insert(hpke_receiver_kv_key.clone(), hpke_receiver_config)
+                }
+                None => guarded_map.remove(&hpke_receiver_kv_key),
+            };
+        }
+
+        let guarded_map = map
+            .read()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for reading: {e}")))?;
+
+        if guarded_map.contains_key(&hpke_receiver_kv_key) {
+            Ok(Some(Guarded {
+                guarded_map,
+                key: Cow::Owned(hpke_receiver_kv_key),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// List the KV keys of the HPKE receiver configs stored in KV for the given task, or of the
+    /// global configs if `task_id` is `None`.
+    pub(crate) async fn list_hpke_receiver_kv_keys(
+        &self,
+        task_id: Option<&TaskId>,
+    ) -> std::result::Result<Vec<HpkeReceiverKvKey>, DapError> {
+        let kv_store = self.kv().map_err(dap_err)?;
+        let prefix = HpkeReceiverKvKey::kv_prefix(task_id);
+        let mut hpke_receiver_kv_keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut builder = kv_store.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor {
+                builder = builder.cursor(cursor);
+            }
+            let res = builder
+                .execute()
+                .await
+                .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?;
+
+            for kv_key in res.keys {
+                hpke_receiver_kv_keys.push(HpkeReceiverKvKey::try_from_name(&kv_key.name)?);
+            }
+
+            if res.list_complete {
+                break;
+            }
+            cursor = res.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(hpke_receiver_kv_keys)
+    }
+
+    /// Generate a new HPKE receiver config for each supported KEM and store them in KV, scoped to
+    /// the given task if `task_id` is set. Config IDs are chosen so that they don't collide with
+    /// (and thus overwrite) the IDs in `taken_config_ids`, e.g., that of a config that is scheduled
+    /// to become valid later. If a config with the chosen ID is already stored, then another ID is
+    /// tried. Return the ID of the first config.
+    ///
+    /// Note that the existence check and the write are not atomic, since KV has no compare-and-set.
+    /// Configs generated concurrently for the same task may therefore overwrite one another.
+    pub(crate) async fn gen_hpke_receiver_configs(
+        &self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+        mut taken_config_ids: HashSet<u8>,
+    ) -> std::result::Result<u8, DapError> {
+        // For now, expect that only one KEM algorithm is supported and that only one config will
+        // be used at anyone time.
+        if self.config().global.supported_hpke_kems.len() != 1 {
+            return Err(DapError::Fatal(
+                "The number of supported HPKE KEMs must be 1".to_string(),
+            ));
+        }
+
+        let (kdf_id, aead_id) = self.config().global.hpke_kdf_and_aead()?;
+        let mut hpke_config_id = None;
+        for kem_id in self.config().global.supported_hpke_kems.iter() {
+            loop {
+                let hpke_receiver_config = HpkeReceiverConfig::gen_excluding(
+                    &mut thread_rng(),
+                    *kem_id,
+                    kdf_id,
+                    aead_id,
+                    &taken_config_ids,
+                )?;
+                let id = hpke_receiver_config.config.id;
+                taken_config_ids.insert(id);
+                if self
+                    .kv_set_if_not_exists(
+                        KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
+                        &HpkeReceiverKvKey {
+                            task_id: task_id.cloned(),
+                            version,
+                            hpke_config_id: id,
+                        },
+                        hpke_receiver_config,
+                    )
+                    .await
+                    .map_err(dap_err)?
+                    .is_none()
+                {
+                    hpke_config_id.get_or_insert(id);
+                    break;
+                }
+            }
+        }
+
+        Ok(hpke_config_id.unwrap())
+    }
+
+    /// Retrieve from KV the Leader's bearer tokens for the given task, including the token being
+    /// rotated out, if any. The result is cached for `task_config_cache_ttl`, even if the task has
+    /// no token.
+    async fn get_leader_bearer_tokens(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Option<LeaderBearerTokenKvValue>> {
+        let now = now();
+        let ttl = self.config().task_config_cache_ttl.as_secs();
+
+        // If the tokens are cached and fresh, then return immediately.
+        {
+            let guarded_map = self
+                .isolate_state()
+                .leader_bearer_tokens
+                .read()
+                .map_err(|e| Error::RustError(format!("Failed to lock map for reading: {e}")))?;
+
+            if let Some(entry) = guarded_map.get(task_id) {
+                if entry.is_fresh(now, ttl) {
+                    entry.touch(now);
+                    return Ok(entry.value.clone());
+                }
+            }
+        }
+
+        // Otherwise, read the tokens from KV and cache them before returning.
+        let tokens = self
+            .kv()?
+            .get(&format!("{KV_KEY_PREFIX_BEARER_TOKEN_LEADER}/{task_id}"))
+            .json::<LeaderBearerTokenKvValue>()
+            .await?;
+        self.cache_leader_bearer_tokens(task_id, tokens.clone())?;
+        Ok(tokens)
+    }
+
+    /// Update the cached Leader bearer tokens for the given task.
+    fn cache_leader_bearer_tokens(
+        &self,
+        task_id: &TaskId,
+        tokens: Option<LeaderBearerTokenKvValue>,
+    ) -> Result<()> {
+        let mut guarded_map = self
+            .isolate_state()
+            .leader_bearer_tokens
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?;
+        cache_insert(
+            &mut guarded_map,
+            self.config().task_config_cache_capacity,
+            task_id.clone(),
+            tokens,
+            now(),
+        );
+        Ok(())
+    }
+
+    /// Retrieve from KV the Leader's bearer token for the given task.
+    pub(crate) async fn get_leader_bearer_token(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Option<BearerToken>> {
+        Ok(self
+            .get_leader_bearer_tokens(task_id)
+            .await?
+            .map(|tokens| tokens.current().clone()))
+    }
+
+    /// Set a leader bearer token for the given task. If a token is already set, then return the
+    /// existing token. In either case, the token stored in KV is cached.
+    pub(crate) async fn set_leader_bearer_token(
+        &self,
+        task_id: &TaskId,
+        token: &BearerToken,
+    ) -> Result<Option<BearerToken>> {
+        let tokens = LeaderBearerTokenKvValue::Token(token.clone());
+        let existing = self
+            .kv_set_if_not_exists(KV_KEY_PREFIX_BEARER_TOKEN_LEADER, task_id, tokens.clone())
+            .await?;
+        let existing_token = existing.as_ref().map(|tokens| tokens.current().clone());
+        self.cache_leader_bearer_tokens(task_id, Some(existing.unwrap_or(tokens)))?;
+        Ok(existing_token)
+    }
+
+    /// Keep a leader bearer token for the given task in memory without writing it to KV. The token
+    /// is kept until the task expires.
+    pub(crate) fn pin_leader_bearer_token(
+        &self,
+        task_id: &TaskId,
+        token: &BearerToken,
+        task_expiration: Time,
+    ) -> Result<()> {
+        let mut guarded_map = self
+            .isolate_state()
+            .leader_bearer_tokens
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?;
+        cache_pin(
+            &mut guarded_map,
+            task_id.clone(),
+            Some(LeaderBearerTokenKvValue::Token(token.clone())),
+            now(),
+            task_expiration,
+        );
+        Ok(())
+    }
+
+    /// Replace the Leader's bearer token for the given task with `token`. The old token remains
+    /// valid for `grace_period` so that requests already in flight, or sent by a peer that has
+    /// not yet picked up the new token, are not rejected.
+    ///
+    /// Both tokens are stored in a single KV value, so they are always read together. However,
+    /// other isolates only see the rotation once KV has propagated it (up to a minute) and their
+    /// cached tokens have expired (`task_config_cache_ttl`). Until then, they keep accepting and
+    /// sending only the old token. Hence the Helper should rotate first; the Leader should follow
+    /// once the Helper's isolates have picked up the new token; and the grace period should cover
+    /// the time it takes for the Leader's isolates to do the same.
+    pub(crate) async fn rotate_leader_bearer_token(
+        &self,
+        task_id: &TaskId,
+        token: BearerToken,
+        grace_period: Duration,
+    ) -> Result<()> {
+        let kv_store = self.kv()?;
+        let kv_key = format!("{KV_KEY_PREFIX_BEARER_TOKEN_LEADER}/{task_id}");
+        let previous: LeaderBearerTokenKvValue =
+            kv_store.get(&kv_key).json().await?.ok_or_else(|| {
+                int_err("rotate_leader_bearer_token: Leader bearer token not configured for task")
+            })?;
+
+        let tokens = LeaderBearerTokenKvValue::Rotating(LeaderBearerTokenRotation {
+            previous: previous.current().clone(),
+            current: token,
+            grace_period_end: now() + grace_period.as_secs(),
+        });
+        kv_store.put(&kv_key, &tokens)?.execute().await?;
+
+        self.cache_leader_bearer_tokens(task_id, Some(tokens))
+    }
+
+    /// Retrieve from KV the Leader's bearer token for the given task that is being rotated out, if
+    /// its grace period has not yet elapsed. This is read together with the current token and
+    /// cached along with it.
+    pub(crate) async fn get_rotated_leader_bearer_tokens(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Vec<BearerToken>> {
+        Ok(self
+            .get_leader_bearer_tokens(task_id)
+            .await?
+            .and_then(|tokens| tokens.previous(now()).cloned())
+            .into_iter()
+            .collect())
+    }
+
+    /// Retrieve from KV the Collector's bearer token for the given task.
+    pub(crate) async fn get_collector_bearer_token<'a>(
+        &'a self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<GuardedBearerToken>> {
+        self.kv_get_cached(
+            &self.isolate_state().collector_bearer_tokens,
+            KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR,
+            Cow::Borrowed(task_id),
+        )
+        .await
+    }
+
+    /// Retrieve from KV the bearer tokens of the Collectors authorized for the given task in
+    /// addition to the primary Collector.
+    pub(crate) async fn get_additional_collector_bearer_tokens<'a>(
+        &'a self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<Guarded<'a, TaskId, Vec<BearerToken>>>> {
+        self.kv_get_cached(
+            &self.isolate_state().additional_collector_bearer_tokens,
+            KV_KEY_PREFIX_BEARER_TOKENS_ADDITIONAL_COLLECTORS,
+            Cow::Borrowed(task_id),
+        )
+        .await
+    }
+
+    /// Retrieve from KV the Client's bearer token for the given task.
+    pub(crate) async fn get_client_bearer_token<'a>(
+        &'a self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<GuardedBearerToken>> {
+        self.kv_get_cached(
+            &self.isolate_state().client_bearer_tokens,
+            KV_KEY_PREFIX_BEARER_TOKEN_CLIENT,
+            Cow::Borrowed(task_id),
+        )
+        .await
+    }
+
+    /// Retrieve from KV the configuration for the given task. The config is cached for
+    /// `task_config_cache_ttl`. Returns `None` if the task is not recognized; use this where an
+    /// unrecognized task is not an error.
+    pub(crate) async fn get_task_config<'req>(
+        &'srv self,
         task_id: Cow<'req, TaskId>,
     ) -> Result<Option<GuardedDapTaskConfig<'req>>>
     where
         'srv: 'req,
     {
-        self.kv_get_cached(
-            &self.isolate_state().tasks,
-            KV_KEY_PREFIX_TASK_CONFIG,
-            task_id,
-        )
-        .await
+        let tasks = &self.isolate_state().tasks;
+        let now = now();
+        let ttl = self.config().task_config_cache_ttl.as_secs();
+
+        // If the config is cached and fresh, then return immediately.
+        {
+            let guarded_map = tasks
+                .read()
+                .map_err(|e| Error::RustError(format!("Failed to lock map for reading: {e}")))?;
+
+            if let Some(entry) = guarded_map.get(task_id.as_ref()) {
+                if entry.is_fresh(now, ttl) {
+                    entry.touch(now);
+                    self.task_config_cache_inc("hit");
+                    return Ok(Some(Guarded {
+                        guarded_map,
+                        key: task_id,
+                    }));
+                }
+            }
+        }
+        self.task_config_cache_inc("miss");
+
+        // Otherwise, read the config from KV and cache it before returning.
+        let kv_key = format!("{KV_KEY_PREFIX_TASK_CONFIG}/{task_id}");
+        let task_config = self.kv()?.get(&kv_key).json::<DapTaskConfig>().await?;
+        self.cache_task_config(task_id.as_ref(), task_config)?;
+
+        let guarded_map = tasks
+            .read()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for reading: {e}")))?;
+
+        if guarded_map.get(task_id.as_ref()).is_some() {
+            Ok(Some(Guarded {
+                guarded_map,
+                key: task_id,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Update the cached configuration for the given task. If `task_config` is `None`, then the
+    /// task is removed from the cache.
+    fn cache_task_config(
+        &self,
+        task_id: &TaskId,
+        task_config: Option<DapTaskConfig>,
+    ) -> Result<()> {
+        let mut guarded_map = self
+            .isolate_state()
+            .tasks
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?;
+        if let Some(task_config) = task_config {
+            cache_insert(
+                &mut guarded_map,
+                self.config().task_config_cache_capacity,
+                task_id.clone(),
+                task_config,
+                now(),
+            );
+        } else {
+            guarded_map.remove(task_id);
+        }
+        Ok(())
     }
 
-    /// Define a task in KV
+    fn task_config_cache_inc(&self, outcome: &str) {
+        self.state
+            .metrics
+            .task_config_cache_counter
+            .with_label_values(&[&self.state.host, outcome])
+            .inc();
+    }
+
+    /// Define a task in KV. If the task is already defined, then return the existing config. In
+    /// either case, the config stored in KV is cached.
     pub(crate) async fn set_task_config(
         &self,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
     ) -> Result<Option<DapTaskConfig>> {
-        self.kv_set_if_not_exists(KV_KEY_PREFIX_TASK_CONFIG, task_id, task_config.clone())
+        let existing = self
+            .kv_set_if_not_exists(KV_KEY_PREFIX_TASK_CONFIG, task_id, task_config.clone())
+            .await?;
+        self.cache_task_config(
+            task_id,
+            Some(existing.clone().unwrap_or_else(|| task_config.clone())),
+        )?;
+        Ok(existing)
+    }
+
+    /// Keep the configuration for the given task in memory without writing it to KV. The config
+    /// is not subject to `task_config_cache_ttl` or `task_config_cache_capacity`; instead, it is
+    /// kept until the task expires.
+    pub(crate) fn pin_task_config(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> Result<()> {
+        let mut guarded_map = self
+            .isolate_state()
+            .tasks
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?;
+        cache_pin(
+            &mut guarded_map,
+            task_id.clone(),
+            task_config.clone(),
+            now(),
+            task_config.expiration,
+        );
+        Ok(())
+    }
+
+    /// Try retrieving from KV the configuration for the given task. If the task is not
+    /// recognized, then return an "unrecognizedTask" abort; if the config could not be read, then
+    /// return a storage error.
+    pub(crate) async fn try_get_task_config<'req>(
+        &'srv self,
+        task_id: &'req TaskId,
+    ) -> std::result::Result<GuardedDapTaskConfig<'req>, DapError>
+    where
+        'srv: 'req,
+    {
+        self.get_task_config(Cow::Borrowed(task_id))
+            .await
+            .map_err(|e| DapError::Storage(format!("failed to read task config: {e}")))?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))
+    }
+
+    /// Check that the backing stores are reachable by reading a key from KV and pinging the DO
+    /// instance reserved for health checks in the given colo. Neither operation modifies any
+    /// state. A successful check is remembered by the isolate for `HEALTH_CHECK_CACHE_TTL`
+    /// seconds so that frequent probes don't each hit the backing stores.
+    pub(crate) async fn health_check(&self, colo: &str) -> std::result::Result<(), DapError> {
+        let now = now();
+        let fresh_until = self
+            .isolate_state()
+            .health_checked_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .map(|checked_at| checked_at.saturating_add(HEALTH_CHECK_CACHE_TTL));
+        if matches!(fresh_until, Some(fresh_until) if now < fresh_until) {
+            return Ok(());
+        }
+
+        // The key doesn't need to exist: it's enough that KV responds.
+        self.kv()
+            .map_err(dap_err)?
+            .get(KV_KEY_HEALTH_CHECK)
+            .text()
             .await
+            .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?;
+
+        self.durable()
+            .get::<_, ()>(
+                BINDING_DAP_GARBAGE_COLLECTOR,
+                DURABLE_PING,
+                GarbageCollectorName::health_check(colo),
+            )
+            .await
+            .map_err(|e| DapError::Storage(format!("durable object: {e}")))?;
+
+        *self
+            .isolate_state()
+            .health_checked_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(now);
+        Ok(())
+    }
+
+    /// Clear all persistant durable objects storage.
+    ///
+    /// TODO(cjpatton) Gate this to non-prod deployments. (Prod should do migration.)
+    pub(crate) async fn internal_delete_all(&self) -> std::result::Result<(), DapError> {
+        let durable = self.durable();
+        let future_delete_durable = durable.post(
+            BINDING_DAP_GARBAGE_COLLECTOR,
+            DURABLE_DELETE_ALL,
+            GarbageCollectorName::Collector,
+            &(),
+        );
+
+        let kv_store = self.kv().map_err(dap_err)?;
+        for kv_key in kv_store
+            .list()
+            .execute()
+            .await
+            .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?
+            .keys
+        {
+            kv_store
+                .delete(kv_key.name.as_str())
+                .await
+                .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?;
+            trace!("deleted KV item {}", kv_key.name);
+        }
+
+        future_delete_durable.await.map_err(storage_err)?;
+
+        // Restore the wall clock in case a previous test fixed the time.
+        self.set_time_override(None);
+        Ok(())
+    }
+
+    /// Get the batch ID for the oldest batch that has not been collected. This method is only
+    /// applicable to fixed-size tasks.
+    pub(crate) async fn internal_current_batch(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<BatchId, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        if !matches!(task_config.as_ref().query, DapQueryConfig::FixedSize { .. }) {
+            return Err(DapError::fatal("query type mismatch"));
+        }
+
+        let res: LeaderBatchQueueResult = self
+            .durable()
+            .get(
+                BINDING_DAP_LEADER_BATCH_QUEUE,
+                DURABLE_LEADER_BATCH_QUEUE_CURRENT,
+                TaskName::new(&task_config.as_ref().version, &task_id.to_hex()),
+            )
+            .await
+            .map_err(storage_err)?;
+
+        match res {
+            LeaderBatchQueueResult::Ok(batch_id) => Ok(batch_id),
+
+            // TODO spec: If we end up taking the current batch semantics of
+            // https://github.com/ietf-wg-ppm/draft-ietf-ppm-dap/pull/313, then we'll need to
+            // define an error type for this case.
+            LeaderBatchQueueResult::EmptyQueue => Err(DapError::fatal("empty batch queue")),
+        }
+    }
+
+    /// Await the given requests to durable objects, with at most `max_concurrent_durable_requests`
+    /// of them in flight at once. The responses are returned in the order of the requests.
+    pub(crate) async fn try_join_all_durable<F, O>(
+        &self,
+        requests: impl IntoIterator<Item = F>,
+    ) -> Result<Vec<O>>
+    where
+        F: Future<Output = Result<O>>,
+    {
+        stream::iter(requests)
+            .buffered(self.config().max_concurrent_durable_requests)
+            .try_collect()
+            .await
+    }
+
+    /// Count the reports that have been aggregated for the given task. For time-interval tasks, the
+    /// count is scoped to the given time window, widened to multiples of the task's bucket duration.
+    /// For fixed-size tasks, the count is scoped to the given batch.
+    pub(crate) async fn internal_aggregated_report_count(
+        &self,
+        task_id: &TaskId,
+        batch_sel: BatchSelector,
+    ) -> std::result::Result<u64, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_config = task_config.as_ref();
+        let batch_sel = match batch_sel {
+            BatchSelector::TimeInterval { batch_interval } => {
+                let start = task_config.bucket_window(batch_interval.start);
+                let end = task_config.bucket_window(
+                    batch_interval
+                        .end()
+                        .saturating_add(task_config.bucket_duration() - 1),
+                );
+                BatchSelector::TimeInterval {
+                    batch_interval: Interval {
+                        start,
+                        duration: end.saturating_sub(start),
+                    },
+                }
+            }
+            batch_sel => batch_sel,
+        };
+        let batch_span = task_config.batch_span(&batch_sel)?;
+
+        // Only fetch the report count of each bucket, not its aggregate share.
+        let durable = self.durable();
+        let report_counts: Vec<u64> = self
+            .try_join_all_durable(batch_span.buckets().iter().map(|bucket| {
+                durable.get(
+                    BINDING_DAP_AGGREGATE_STORE,
+                    DURABLE_AGGREGATE_STORE_COUNT,
+                    AggStoreName::new(&task_config.version, &task_id.to_hex(), bucket),
+                )
+            }))
+            .await
+            .map_err(storage_err)?;
+        Ok(report_counts.into_iter().sum())
+    }
+
+    /// Estimate the cost of collecting the given batch without issuing any durable requests. The
+    /// cost is the number of buckets in the batch span, each of which is stored in its own
+    /// `AggregateStore` instance. The estimate indicates whether the span is permitted by
+    /// `max_batch_span`, in which case a collect request for the batch would be accepted.
+    pub(crate) async fn internal_collection_estimate(
+        &self,
+        task_id: &TaskId,
+        batch_sel: BatchSelector,
+    ) -> std::result::Result<CollectionEstimate, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_config = task_config.as_ref();
+        if !task_config.query.is_valid_batch_sel(&batch_sel) {
+            return Err(DapError::Abort(DapAbort::query_mismatch(
+                task_id,
+                &task_config.query,
+                &batch_sel,
+            )));
+        }
+        let bucket_count = task_config.batch_span_for_sel(&batch_sel)?.len() as u64;
+        let max_batch_span = self.config().global.max_batch_span;
+        Ok(CollectionEstimate {
+            bucket_count,
+            max_batch_span,
+            permitted: self.config().global.permits_batch_span(bucket_count),
+        })
+    }
+
+    /// Mark a batch as collected out of band, e.g., to recover from a collection job that only
+    /// partially completed. Subsequent attempts to collect an overlapping batch are rejected, as are
+    /// reports that land in it.
+    pub(crate) async fn internal_mark_collected(
+        &self,
+        task_id: &TaskId,
+        batch_sel: BatchSelector,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_config = task_config.as_ref();
+        if !task_config.query.is_valid_batch_sel(&batch_sel) {
+            return Err(DapError::Abort(DapAbort::query_mismatch(
+                task_id,
+                &task_config.query,
+                &batch_sel,
+            )));
+        }
+        let batch_span = task_config.batch_span(&batch_sel)?;
+        warn!(
+            "task {}: manually marking batch {batch_sel:?} ({} buckets) as collected",
+            task_id.to_base64url(),
+            batch_span.len()
+        );
+        // The batch isn't collected by any collect job, so it is marked under a fresh ID that no
+        // job will use.
+        let collect_id = CollectionJobId(thread_rng().gen());
+        self.mark_collected(task_id, &collect_id, &batch_span).await
+    }
+
+    /// Get the aggregate share of each bucket spanned by the given batch, rather than the merged
+    /// total that is sent in response to an aggregate share request. This is intended for
+    /// debugging and reconciliation.
+    pub(crate) async fn internal_agg_shares_by_bucket(
+        &self,
+        task_id: &TaskId,
+        batch_sel: BatchSelector,
+    ) -> std::result::Result<Vec<InternalTestBucketAggShare>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_config = task_config.as_ref();
+        if !task_config.query.is_valid_batch_sel(&batch_sel) {
+            return Err(DapError::Abort(DapAbort::query_mismatch(
+                task_id,
+                &task_config.query,
+                &batch_sel,
+            )));
+        }
+        let batch_span = task_config.batch_span(&batch_sel)?;
+
+        let durable = self.durable();
+        let task_id_hex = task_id.to_hex();
+        let agg_shares: Vec<DapAggregateShare> = self
+            .try_join_all_durable(batch_span.buckets().iter().map(|bucket| {
+                durable.get(
+                    BINDING_DAP_AGGREGATE_STORE,
+                    DURABLE_AGGREGATE_STORE_GET,
+                    AggStoreName::new(&task_config.version, &task_id_hex, bucket),
+                )
+            }))
+            .await
+            .map_err(storage_err)?;
+
+        Ok(batch_span
+            .buckets()
+            .iter()
+            .zip(agg_shares)
+            .map(|(bucket, agg_share)| {
+                let (batch_window, batch_id) = match bucket {
+                    DapBatchBucket::TimeInterval { batch_window } => (Some(*batch_window), None),
+                    DapBatchBucket::FixedSize { batch_id } => (None, Some(batch_id.to_base64url())),
+                };
+                InternalTestBucketAggShare {
+                    batch_window,
+                    batch_id,
+                    agg_share,
+                }
+            })
+            .collect())
+    }
+
+    /// Get the pending collection jobs for the given task (oldest jobs first).
+    pub(crate) async fn get_pending_collect_jobs_for_task(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<Vec<(CollectionJobId, CollectionReq)>, DapError> {
+        let res: Vec<(TaskId, CollectionJobId, CollectionReq)> = self
+            .durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_GET,
+                QueueName::new(0),
+                Some(task_id),
+            )
+            .await
+            .map_err(storage_err)?;
+        Ok(res
+            .into_iter()
+            .map(|(_task_id, collect_id, collect_req)| (collect_id, collect_req))
+            .collect())
+    }
+
+    /// Get the number of reports assigned so far to each batch that has not yet been collected,
+    /// oldest first, along with the number of reports required to complete the batch. This is
+    /// only applicable to fixed-size tasks and does not modify the batch queue.
+    pub(crate) async fn internal_peek_batch_queue(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<Vec<InternalTestBatchFill>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        if !matches!(task_config.as_ref().query, DapQueryConfig::FixedSize { .. }) {
+            return Err(DapError::fatal("query type mismatch"));
+        }
+
+        let batch_counts: Vec<BatchCount> = self
+            .durable()
+            .get(
+                BINDING_DAP_LEADER_BATCH_QUEUE,
+                DURABLE_LEADER_BATCH_QUEUE_PEEK,
+                TaskName::new(&task_config.as_ref().version, &task_id.to_hex()),
+            )
+            .await
+            .map_err(storage_err)?;
+
+        Ok(batch_counts
+            .into_iter()
+            .map(|batch_count| InternalTestBatchFill {
+                batch_id: batch_count.batch_id.to_base64url(),
+                report_count: batch_count.report_count,
+                min_batch_size: task_config.as_ref().min_batch_size,
+            })
+            .collect())
+    }
+
+    /// Check that each report waiting in `ReportsPending` for the given task can be decoded. Each
+    /// entry that fails to decode is returned along with a description of the problem. This
+    /// method is only applicable to the Leader.
+    pub(crate) async fn internal_audit_reports_pending(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<Vec<InternalTestCorruptedPendingReport>, DapError> {
+        if !self.config().is_leader {
+            return Err(DapError::fatal(
+                "reports pending audit is only valid for the leader",
+            ));
+        }
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+        let epoch_duration = self.config().global.report_storage_epoch_duration;
+
+        // Reports may be pending for any epoch in the range of valid report times.
+        let now = self.current_time();
+        let start = self.least_valid_report_time(now);
+        let end = self.greatest_valid_report_time(now);
+        let mut durable_names = Vec::new();
+        let mut epoch = start - (start % epoch_duration);
+        while epoch <= end {
+            for shard in 0..self.config().report_shard_count {
+                durable_names.push(ReportStoreName::new(
+                    &task_config.as_ref().version,
+                    &task_id_hex,
+                    epoch,
+                    shard,
+                ));
+            }
+            epoch += epoch_duration;
+        }
+
+        let durable = self.durable();
+        let mut requests = Vec::with_capacity(durable_names.len());
+        for durable_name in durable_names.iter() {
+            requests.push(durable.get::<_, Vec<(String, String)>>(
+                BINDING_DAP_REPORTS_PENDING,
+                DURABLE_REPORTS_PENDING_AUDIT,
+                durable_name.clone(),
+            ));
+        }
+        let responses = self
+            .try_join_all_durable(requests)
+            .await
+            .map_err(storage_err)?;
+
+        let mut corrupted = Vec::new();
+        for (durable_name, entries) in durable_names.into_iter().zip(responses.into_iter()) {
+            for (key, reason) in entries {
+                error!("corrupted pending report: {durable_name}: {key}: {reason}");
+                corrupted.push(InternalTestCorruptedPendingReport {
+                    durable_name: durable_name.to_string(),
+                    key,
+                    reason,
+                });
+            }
+        }
+        Ok(corrupted)
+    }
+
+    /// Delete the `ReportsPending` instances for an expired task, along with their entries in the
+    /// aggregation job queue. Reports held by these instances can no longer be aggregated, so this
+    /// frees their storage. Return the number of instances that were deleted. This method is only
+    /// applicable to the Leader.
+    ///
+    /// The task must have expired: a task that merely has no recent activity may still receive
+    /// reports, so it is left untouched.
+    pub(crate) async fn internal_sweep_expired_task(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<usize, DapError> {
+        if !self.config().is_leader {
+            return Err(DapError::fatal(
+                "expired task sweep is only valid for the leader",
+            ));
+        }
+        let task_config = self.try_get_task_config(task_id).await?;
+        if task_config.as_ref().expiration > self.current_time() {
+            return Err(DapError::Abort(DapAbort::BadRequest(
+                "task has not expired".into(),
+            )));
+        }
+
+        // Each non-empty `ReportsPending` instance has a job in the aggregation job queue, so the
+        // queue tells us which instances to delete. Instance names are prefixed by the task.
+        let name_prefix = format!(
+            "{}/",
+            TaskName::new(&task_config.as_ref().version, &task_id.to_hex())
+        );
+        let durable = self.durable();
+        // NOTE There is only one agg job queue for now.
+        let agg_jobs: Vec<DurableOrdered<String>> = durable
+            .post(
+                BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+                DURABLE_LEADER_AGG_JOB_QUEUE_FIND,
+                QueueName::new(0),
+                &name_prefix,
+            )
+            .await
+            .map_err(storage_err)?;
+
+        let mut requests = Vec::with_capacity(agg_jobs.len());
+        for agg_job in agg_jobs.iter() {
+            requests.push(durable.post::<_, _, bool>(
+                BINDING_DAP_REPORTS_PENDING,
+                DURABLE_REPORTS_PENDING_PURGE,
+                ReportStoreName::from_agg_job_queue(agg_job.as_ref().clone()),
+                &(),
+            ));
+        }
+        let responses = self
+            .try_join_all_durable(requests)
+            .await
+            .map_err(storage_err)?;
+        let deleted = responses.into_iter().filter(|non_empty| *non_empty).count();
+        info!(
+            "swept {deleted} reports pending instances for expired task {}",
+            task_id.to_base64url()
+        );
+        Ok(deleted)
+    }
+
+    /// Mark a task as quiescing so that it no longer accepts uploads. Pending reports are still
+    /// aggregated and collected, which allows the task to be drained before it is removed. Other
+    /// isolates may continue to accept uploads until their cached task config expires.
+    pub(crate) async fn quiesce_task(&self, task_id: &TaskId) -> std::result::Result<(), DapError> {
+        if !self.config().is_leader {
+            return Err(DapError::fatal(
+                "quiescing a task is only valid for the leader",
+            ));
+        }
+        let mut task_config = self.try_get_task_config(task_id).await?.as_ref().clone();
+        task_config.quiescing = true;
+
+        let kv_key = format!("{KV_KEY_PREFIX_TASK_CONFIG}/{task_id}");
+        self.kv()
+            .map_err(dap_err)?
+            .put(&kv_key, &task_config)
+            .map_err(|e| storage_err(e.into()))?
+            .execute()
+            .await
+            .map_err(|e| storage_err(e.into()))?;
+        self.cache_task_config(task_id, Some(task_config))
+            .map_err(dap_err)?;
+        info!("task {} is quiescing", task_id.to_base64url());
+        Ok(())
+    }
+
+    /// Sign the HPKE config list advertised for the given task, or the global one if no task is
+    /// given. Returns `None` if no signing key is configured.
+    pub(crate) async fn signed_hpke_config_list(
+        &'srv self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+    ) -> std::result::Result<Option<SignedHpkeConfigList>, DapError> {
+        let Some(ref signing_key) = self.config().hpke_config_signing_key else {
+            return Ok(None);
+        };
+        let hpke_config = self.get_hpke_config_for(version, task_id).await?;
+        let hpke_config_list = HpkeConfigList {
+            hpke_configs: vec![hpke_config.as_ref().clone()],
+        }
+        .get_encoded();
+        let signature = signing_key.sign(&hpke_config_list);
+        Ok(Some(SignedHpkeConfigList {
+            hpke_config_list: encode_base64url(&hpke_config_list),
+            signature: encode_base64url(signature),
+            signature_algorithm: HPKE_CONFIG_SIGNATURE_ALGORITHM,
+        }))
+    }
+
+    /// Determine whether a report is pending (Leader only), has been processed, or is unknown to
+    /// this Aggregator. The report's timestamp is not known, so each epoch in the range of valid
+    /// report times is checked.
+    pub(crate) async fn report_status(
+        &self,
+        task_id: &TaskId,
+        report_id: &ReportId,
+    ) -> std::result::Result<InternalTestReportStatus, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+        let report_id_hex = report_id.to_hex();
+        let epoch_duration = self.config().global.report_storage_epoch_duration;
+        let shard = self.config().report_shard(report_id);
+
+        let now = self.current_time();
+        let start = self.least_valid_report_time(now);
+        let end = self.greatest_valid_report_time(now);
+        let mut durable_names = Vec::new();
+        let mut epoch = start - (start % epoch_duration);
+        while epoch <= end {
+            durable_names.push(ReportStoreName::new(
+                &task_config.as_ref().version,
+                &task_id_hex,
+                epoch,
+                shard,
+            ));
+            epoch += epoch_duration;
+        }
+
+        let durable = self.durable();
+        let mut requests = Vec::with_capacity(durable_names.len());
+        for durable_name in durable_names.iter() {
+            requests.push(durable.post::<_, _, bool>(
+                BINDING_DAP_REPORTS_PROCESSED,
+                DURABLE_REPORTS_PROCESSED_CONTAINS,
+                durable_name.clone(),
+                &report_id_hex,
+            ));
+        }
+        let responses = self
+            .try_join_all_durable(requests)
+            .await
+            .map_err(storage_err)?;
+        if responses.into_iter().any(|processed| processed) {
+            return Ok(InternalTestReportStatus::Processed);
+        }
+
+        if self.config().is_leader {
+            let mut requests = Vec::with_capacity(durable_names.len());
+            for durable_name in durable_names.iter() {
+                requests.push(durable.post::<_, _, bool>(
+                    BINDING_DAP_REPORTS_PENDING,
+                    DURABLE_REPORTS_PENDING_CONTAINS,
+                    durable_name.clone(),
+                    &report_id_hex,
+                ));
+            }
+            let responses = self
+                .try_join_all_durable(requests)
+                .await
+                .map_err(storage_err)?;
+            if responses.into_iter().any(|pending| pending) {
+                return Ok(InternalTestReportStatus::Pending);
+            }
+        }
+
+        Ok(InternalTestReportStatus::Unknown)
+    }
+
+    /// List the IDs of the tasks whose configs are stored in KV.
+    async fn kv_list_task_ids(&self) -> std::result::Result<Vec<TaskId>, DapError> {
+        let kv_store = self.kv().map_err(dap_err)?;
+        let prefix = format!("{KV_KEY_PREFIX_TASK_CONFIG}/");
+        let mut task_ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut builder = kv_store.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor {
+                builder = builder.cursor(cursor);
+            }
+            let res = builder
+                .execute()
+                .await
+                .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?;
+
+            for kv_key in res.keys {
+                let task_id = kv_key
+                    .name
+                    .strip_prefix(&prefix)
+                    .and_then(|task_id_hex| hex::decode(task_id_hex).ok())
+                    .and_then(|bytes| TaskId::get_decoded(&bytes).ok())
+                    .ok_or_else(|| {
+                        DapError::Fatal(format!("kv_store: malformed task key {}", kv_key.name))
+                    })?;
+                task_ids.push(task_id);
+            }
+
+            if res.list_complete {
+                break;
+            }
+            cursor = res.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(task_ids)
+    }
+
+    /// List the tasks configured in KV, including those that were configured via taskprov.
+    pub(crate) async fn internal_list_tasks(
+        &self,
+    ) -> std::result::Result<Vec<InternalTestTaskInfo>, DapError> {
+        let mut tasks = Vec::new();
+        for task_id in self.kv_list_task_ids().await? {
+            let task_config = match self.get_task_config(Cow::Borrowed(&task_id)).await {
+                Ok(Some(task_config)) => task_config,
+                // The task may have been deleted since we listed the keys.
+                Ok(None) => continue,
+                Err(e) => return Err(dap_err(e)),
+            };
+            let task_config = task_config.as_ref();
+
+            let taskprov = self.config().taskprov.as_ref().map_or(false, |taskprov| {
+                is_taskprov_task(
+                    self.config().global.taskprov_version,
+                    &taskprov.vdaf_verify_key_init,
+                    &task_id,
+                    task_config,
+                )
+            });
+
+            tasks.push(InternalTestTaskInfo {
+                task_id: task_id.to_base64url(),
+                version: task_config.version,
+                query: task_config.query.clone(),
+                expiration: task_config.expiration,
+                taskprov,
+            });
+        }
+        Ok(tasks)
     }
 
-    /// Try retrieving from KV the configuration for the given task. Return an error if the
-    /// indicated task is not recognized.
-    pub(crate) async fn try_get_task_config<'req>(
-        &'srv self,
-        task_id: &'req TaskId,
-    ) -> std::result::Result<GuardedDapTaskConfig<'req>, DapError>
-    where
-        'srv: 'req,
-    {
-        self.get_task_config(Cow::Borrowed(task_id))
-            .await
-            .map_err(dap_err)?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))
+    /// Export the task configs stored in KV along with their bearer tokens, e.g., to back up a
+    /// deployment or to clone it into another environment. Values are read from KV rather than
+    /// from the isolate's caches. Tasks are sorted by ID so that the export is deterministic.
+    pub(crate) async fn internal_export_tasks(
+        &self,
+    ) -> std::result::Result<InternalTestTaskBundle, DapError> {
+        let kv_store = self.kv().map_err(dap_err)?;
+        let mut task_ids = self.kv_list_task_ids().await?;
+        task_ids.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut tasks = Vec::with_capacity(task_ids.len());
+        for task_id in task_ids {
+            let task_config: DapTaskConfig = match kv_store
+                .get(&format!("{KV_KEY_PREFIX_TASK_CONFIG}/{task_id}"))
+                .json()
+                .await
+                .map_err(|e| storage_err(e.into()))?
+            {
+                Some(task_config) => task_config,
+                // The task may have been deleted since we listed the keys.
+                None => continue,
+            };
+
+            let get_bearer_token = |kv_key_prefix: &str| {
+                kv_store
+                    .get(&format!("{kv_key_prefix}/{task_id}"))
+                    .json::<BearerToken>()
+            };
+            tasks.push(InternalTestTaskBundleEntry {
+                task_id: task_id.to_base64url(),
+                task_config,
+                leader_bearer_token: kv_store
+                    .get(&format!("{KV_KEY_PREFIX_BEARER_TOKEN_LEADER}/{task_id}"))
+                    .json::<LeaderBearerTokenKvValue>()
+                    .await
+                    .map_err(|e| storage_err(e.into()))?
+                    .map(|tokens| tokens.current().clone()),
+                collector_bearer_token: get_bearer_token(KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR)
+                    .await
+                    .map_err(|e| storage_err(e.into()))?,
+                additional_collector_bearer_tokens: kv_store
+                    .get(&format!(
+                        "{KV_KEY_PREFIX_BEARER_TOKENS_ADDITIONAL_COLLECTORS}/{task_id}"
+                    ))
+                    .json::<Vec<BearerToken>>()
+                    .await
+                    .map_err(|e| storage_err(e.into()))?
+                    .unwrap_or_default(),
+                client_bearer_token: get_bearer_token(KV_KEY_PREFIX_BEARER_TOKEN_CLIENT)
+                    .await
+                    .map_err(|e| storage_err(e.into()))?,
+            });
+        }
+        Ok(InternalTestTaskBundle { tasks })
     }
 
-    /// Clear all persistant durable objects storage.
-    ///
-    /// TODO(cjpatton) Gate this to non-prod deployments. (Prod should do migration.)
-    pub(crate) async fn internal_delete_all(&self) -> std::result::Result<(), DapError> {
-        let durable = self.durable();
-        let future_delete_durable = durable.post(
-            BINDING_DAP_GARBAGE_COLLECTOR,
-            DURABLE_DELETE_ALL,
-            "garbage_collector".to_string(),
-            &(),
-        );
+    /// Import a bundle produced by `internal_export_tasks()` into KV. Task configs and bearer
+    /// tokens are overwritten if they already exist, so importing the same bundle more than once
+    /// has no further effect. Bearer tokens that are absent from the bundle are left unchanged.
+    pub(crate) async fn internal_import_tasks(
+        &self,
+        bundle: InternalTestTaskBundle,
+    ) -> std::result::Result<(), DapError> {
+        // Check the whole bundle before writing anything. Imported configs are held to the same
+        // checks as configs added with `internal_add_task()`.
+        let tasks = bundle
+            .tasks
+            .into_iter()
+            .map(|entry| {
+                let Some(task_id) = TaskId::try_from_base64url(&entry.task_id) else {
+                    return Err(DapError::Abort(DapAbort::BadRequest(format!(
+                        "malformed task ID: {}",
+                        entry.task_id
+                    ))));
+                };
+                if !entry.task_config.is_bucket_duration_valid() {
+                    return Err(DapError::Abort(DapAbort::BadRequest(format!(
+                        "invalid config for task {}: bucket duration must be a positive multiple of the time precision",
+                        entry.task_id
+                    ))));
+                }
+                Ok((task_id, entry))
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
 
         let kv_store = self.kv().map_err(dap_err)?;
-        for kv_key in kv_store
-            .list()
-            .execute()
-            .await
-            .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?
-            .keys
-        {
+        for (task_id, entry) in tasks {
             kv_store
-                .delete(kv_key.name.as_str())
+                .put(
+                    &format!("{KV_KEY_PREFIX_TASK_CONFIG}/{task_id}"),
+                    &entry.task_config,
+                )
+                .map_err(|e| storage_err(e.into()))?
+                .execute()
                 .await
-                .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?;
-            trace!("deleted KV item {}", kv_key.name);
-        }
+                .map_err(|e| storage_err(e.into()))?;
+            self.cache_task_config(&task_id, Some(entry.task_config))
+                .map_err(dap_err)?;
+
+            if let Some(token) = entry.leader_bearer_token {
+                let tokens = LeaderBearerTokenKvValue::Token(token);
+                kv_store
+                    .put(
+                        &format!("{KV_KEY_PREFIX_BEARER_TOKEN_LEADER}/{task_id}"),
+                        &tokens,
+                    )
+                    .map_err(|e| storage_err(e.into()))?
+                    .execute()
+                    .await
+                    .map_err(|e| storage_err(e.into()))?;
+                self.cache_leader_bearer_tokens(&task_id, Some(tokens))
+                    .map_err(dap_err)?;
+            }
+
+            for (kv_key_prefix, cache, token) in [
+                (
+                    KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR,
+                    &self.isolate_state().collector_bearer_tokens,
+                    entry.collector_bearer_token,
+                ),
+                (
+                    KV_KEY_PREFIX_BEARER_TOKEN_CLIENT,
+                    &self.isolate_state().client_bearer_tokens,
+                    entry.client_bearer_token,
+                ),
+            ] {
+                let Some(token) = token else {
+                    continue;
+                };
+                kv_store
+                    .put(&format!("{kv_key_prefix}/{task_id}"), &token)
+                    .map_err(|e| storage_err(e.into()))?
+                    .execute()
+                    .await
+                    .map_err(|e| storage_err(e.into()))?;
+                cache
+                    .write()
+                    .map_err(|e| DapError::Fatal(format!("Failed to lock map for writing: {e}")))?
+                    .insert(task_id.clone(), token);
+            }
 
-        future_delete_durable.await.map_err(dap_err)?;
+            if !entry.additional_collector_bearer_tokens.is_empty() {
+                kv_store
+                    .put(
+                        &format!("{KV_KEY_PREFIX_BEARER_TOKENS_ADDITIONAL_COLLECTORS}/{task_id}"),
+                        &entry.additional_collector_bearer_tokens,
+                    )
+                    .map_err(|e| storage_err(e.into()))?
+                    .execute()
+                    .await
+                    .map_err(|e| storage_err(e.into()))?;
+                self.isolate_state()
+                    .additional_collector_bearer_tokens
+                    .write()
+                    .map_err(|e| DapError::Fatal(format!("Failed to lock map for writing: {e}")))?
+                    .insert(task_id.clone(), entry.additional_collector_bearer_tokens);
+            }
+            info!("imported task {}", task_id.to_base64url());
+        }
         Ok(())
     }
 
-    /// Get the batch ID for the oldest batch that has not been collected. This method is only
-    /// applicable to fixed-size tasks.
-    pub(crate) async fn internal_current_batch(
+    /// Get the rejection counts persisted so far, broken down by failure reason.
+    pub(crate) async fn internal_rejection_counts(
         &self,
-        task_id: &TaskId,
-    ) -> std::result::Result<BatchId, DapError> {
-        let task_config = self.try_get_task_config(task_id).await?;
-        if !matches!(task_config.as_ref().query, DapQueryConfig::FixedSize { .. }) {
-            return Err(DapError::fatal("query type mismatch"));
-        }
+    ) -> std::result::Result<HashMap<String, u64>, DapError> {
+        self.durable()
+            .get(
+                BINDING_DAP_COUNTERS,
+                DURABLE_COUNTERS_GET,
+                CountersName::RejectionCounts,
+            )
+            .await
+            .map_err(storage_err)
+    }
 
-        let res: LeaderBatchQueueResult = self
+    /// Get the number of reports ingested across all tasks that has been persisted so far.
+    pub(crate) async fn reports_ingested(&self) -> std::result::Result<u64, DapError> {
+        let counts: HashMap<String, u64> = self
             .durable()
             .get(
-                BINDING_DAP_LEADER_BATCH_QUEUE,
-                DURABLE_LEADER_BATCH_QUEUE_CURRENT,
-                durable_name_task(&task_config.as_ref().version, &task_id.to_hex()),
+                BINDING_DAP_COUNTERS,
+                DURABLE_COUNTERS_GET,
+                CountersName::ReportsIngested,
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(storage_err)?;
+        Ok(counts
+            .get(COUNTER_REPORTS_INGESTED)
+            .copied()
+            .unwrap_or_default())
+    }
 
-        match res {
-            LeaderBatchQueueResult::Ok(batch_id) => Ok(batch_id),
+    /// Rotate the HPKE receiver config for the given version: generate a fresh config, make it the
+    /// active config, and return its ID. The previously active configs expire immediately, but are
+    /// kept in KV until the garbage collection grace period has elapsed so that reports encrypted
+    /// under them can still be decrypted. Configs for which the grace period has elapsed are
+    /// deleted.
+    ///
+    /// If `task_id` is set, then the task-scoped configs of that task are rotated instead of the
+    /// global configs. The task must have opted in to task-scoped configs.
+    pub(crate) async fn internal_rotate_hpke_config(
+        &self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+    ) -> std::result::Result<u8, DapError> {
+        if let Some(task_id) = task_id {
+            let task_scoped = self
+                .get_task_config(Cow::Borrowed(task_id))
+                .await
+                .map_err(dap_err)?
+                .ok_or_else(|| DapError::Abort(DapAbort::UnrecognizedTask))?
+                .as_ref()
+                .task_scoped_hpke_config;
+            if !task_scoped {
+                return Err(DapError::Abort(DapAbort::BadRequest(
+                    "task does not use task-scoped HPKE configs".into(),
+                )));
+            }
+        }
 
-            // TODO spec: If we end up taking the current batch semantics of
-            // https://github.com/ietf-wg-ppm/draft-ietf-ppm-dap/pull/313, then we'll need to
-            // define an error type for this case.
-            LeaderBatchQueueResult::EmptyQueue => Err(DapError::fatal("empty batch queue")),
+        let kv_store = self.kv().map_err(dap_err)?;
+        let now = now();
+        let grace_period = self.config().hpke_config_gc_grace_period.as_secs();
+
+        let mut taken_config_ids = HashSet::new();
+        let mut active_configs = Vec::new();
+        for hpke_receiver_kv_key in self.list_hpke_receiver_kv_keys(task_id).await? {
+            let kv_key = format!("{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{hpke_receiver_kv_key}");
+            let hpke_receiver_config = match kv_store
+                .get(&kv_key)
+                .json::<HpkeReceiverConfig>()
+                .await
+                .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?
+            {
+                Some(hpke_receiver_config) => hpke_receiver_config,
+                // The config may have been deleted since we listed the keys.
+                None => continue,
+            };
+
+            if hpke_receiver_config.is_expired_past_grace_period(now, grace_period) {
+                kv_store
+                    .delete(&kv_key)
+                    .await
+                    .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?;
+                self.isolate_state()
+                    .hpke_receiver_configs
+                    .write()
+                    .map_err(|e| DapError::Fatal(format!("Failed to lock map for writing: {e}")))?
+                    .remove(&hpke_receiver_kv_key);
+                info!("deleted expired HPKE receiver config {kv_key}");
+                continue;
+            }
+
+            if hpke_receiver_kv_key.version != version {
+                continue;
+            }
+            taken_config_ids.insert(hpke_receiver_kv_key.hpke_config_id);
+            if hpke_receiver_config.is_valid_at(now) {
+                active_configs.push((kv_key, hpke_receiver_kv_key, hpke_receiver_config));
+            }
+        }
+
+        // Generate the new config before expiring the old ones so that there is always an active
+        // config.
+        let hpke_config_id = self
+            .gen_hpke_receiver_configs(version, task_id, taken_config_ids)
+            .await?;
+
+        for (kv_key, hpke_receiver_kv_key, mut hpke_receiver_config) in active_configs {
+            hpke_receiver_config.not_after = Some(now);
+            kv_store
+                .put(&kv_key, &hpke_receiver_config)
+                .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?
+                .execute()
+                .await
+                .map_err(|e| DapError::Storage(format!("kv_store: {e}")))?;
+            self.isolate_state()
+                .hpke_receiver_configs
+                .write()
+                .map_err(|e| DapError::Fatal(format!("Failed to lock map for writing: {e}")))?
+                .insert(hpke_receiver_kv_key, hpke_receiver_config);
+            info!("expired HPKE receiver config {kv_key}");
         }
+        self.cache_hpke_config_selection(HpkeReceiverKvKey {
+            task_id: task_id.cloned(),
+            version,
+            hpke_config_id,
+        })
+        .map_err(dap_err)?;
+
+        Ok(hpke_config_id)
+    }
+
+    /// Store the given HPKE receiver config in KV, replacing any config with the same ID.
+    pub(crate) async fn internal_add_hpke_config(
+        &self,
+        version: DapVersion,
+        cmd: InternalTestAddHpkeConfig,
+    ) -> Result<()> {
+        let task_id = cmd
+            .task_id
+            .map(|task_id| {
+                TaskId::try_from_base64url(task_id)
+                    .ok_or_else(|| int_err("task ID is not valid URL-safe base64"))
+            })
+            .transpose()?;
+        let hpke_receiver_kv_key = HpkeReceiverKvKey {
+            task_id,
+            version,
+            hpke_config_id: cmd.hpke_receiver_config.config.id,
+        };
+
+        let kv_key = format!("{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{hpke_receiver_kv_key}");
+        self.kv()?
+            .put(&kv_key, &cmd.hpke_receiver_config)?
+            .execute()
+            .await?;
+        self.isolate_state()
+            .hpke_receiver_configs
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?
+            .insert(hpke_receiver_kv_key.clone(), cmd.hpke_receiver_config);
+
+        // Select the config to advertise again, so that the new config is taken into account.
+        self.isolate_state()
+            .hpke_config_selections
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?
+            .remove(&(hpke_receiver_kv_key.version, hpke_receiver_kv_key.task_id));
+        info!("added HPKE receiver config {kv_key}");
+        Ok(())
     }
 
     /// Get the URL to use for this endpoint, as required by
@@ -836,7 +2888,7 @@ impl<'srv> DaphneWorker<'srv> {
         // Leader authentication token.
         let token = BearerToken::from(cmd.leader_authentication_token);
         if self
-            .kv_set_if_not_exists(KV_KEY_PREFIX_BEARER_TOKEN_LEADER, &task_id, token)
+            .set_leader_bearer_token(&task_id, &token)
             .await?
             .is_some()
         {
@@ -874,32 +2926,99 @@ impl<'srv> DaphneWorker<'srv> {
             }
         };
 
+        // Authentication tokens of any Collectors in addition to the primary Collector.
+        match (cmd.role, cmd.additional_collector_authentication_tokens) {
+            (_, tokens) if tokens.is_empty() => (),
+            (InternalTestRole::Leader, tokens) => {
+                let tokens = tokens
+                    .into_iter()
+                    .map(BearerToken::from)
+                    .collect::<Vec<_>>();
+                if self
+                    .kv_set_if_not_exists(
+                        KV_KEY_PREFIX_BEARER_TOKENS_ADDITIONAL_COLLECTORS,
+                        &task_id,
+                        tokens,
+                    )
+                    .await?
+                    .is_some()
+                {
+                    return Err(int_err(format!(
+                        "command failed: token already exists for the given task ({}) and bearer role (additional collectors)",
+                        cmd.task_id
+                    )));
+                }
+            }
+            (InternalTestRole::Helper, _) => {
+                return Err(int_err(
+                    "command failed: unexpected collector authentication tokens",
+                ));
+            }
+        };
+
+        // Client authentication token. If set, then uploads for the task must carry it.
+        let client_auth = match (cmd.role, cmd.client_authentication_token) {
+            (InternalTestRole::Leader, Some(token_string)) => {
+                let token = BearerToken::from(token_string);
+                if self
+                    .kv_set_if_not_exists(KV_KEY_PREFIX_BEARER_TOKEN_CLIENT, &task_id, token)
+                    .await?
+                    .is_some()
+                {
+                    return Err(int_err(format!(
+                        "command failed: token already exists for the given task ({}) and bearer role (client)",
+                        cmd.task_id
+                    )));
+                }
+                true
+            }
+            (InternalTestRole::Helper, Some(..)) => {
+                return Err(int_err(
+                    "command failed: unexpected client authentication token",
+                ));
+            }
+            (_, None) => false,
+        };
+
         // Query configuraiton.
-        let query = match (cmd.query_type, cmd.max_batch_size) {
-            (1, None) => DapQueryConfig::TimeInterval,
-            (1, Some(..)) => return Err(int_err("command failed: unexpected max batch size")),
-            (2, Some(max_batch_size)) => DapQueryConfig::FixedSize { max_batch_size },
-            (2, None) => return Err(int_err("command failed: missing max batch size")),
+        let query = match (cmd.query_type, cmd.max_batch_size, cmd.bucket_duration) {
+            (1, None, bucket_duration) => DapQueryConfig::TimeInterval { bucket_duration },
+            (1, Some(..), _) => return Err(int_err("command failed: unexpected max batch size")),
+            (2, Some(max_batch_size), None) => DapQueryConfig::FixedSize { max_batch_size },
+            (2, None, _) => return Err(int_err("command failed: missing max batch size")),
+            (2, _, Some(..)) => return Err(int_err("command failed: unexpected bucket duration")),
             _ => return Err(int_err("command failed: unrecognized query type")),
         };
 
+        let task_config = DapTaskConfig {
+            version,
+            leader_url: cmd.leader,
+            helper_url: cmd.helper,
+            time_precision: cmd.time_precision,
+            expiration: cmd.task_expiration,
+            min_batch_size: cmd.min_batch_size,
+            query,
+            vdaf,
+            vdaf_verify_key,
+            collector_hpke_config,
+            upload_rate_limit: cmd.upload_rate_limit,
+            max_reports_per_agg_job: cmd.max_reports_per_agg_job,
+            client_auth,
+            task_scoped_hpke_config: cmd.task_scoped_hpke_config,
+            quiescing: cmd.quiescing,
+            pending_report_ttl: cmd.pending_report_ttl,
+            collect_poll_retry_after: cmd.collect_poll_retry_after,
+            permitted_extensions: cmd.permitted_extensions,
+            reject_future_buckets: cmd.reject_future_buckets,
+        };
+        if !task_config.is_bucket_duration_valid() {
+            return Err(int_err(
+                "command failed: bucket duration must be a positive multiple of the time precision",
+            ));
+        }
+
         if self
-            .kv_set_if_not_exists(
-                KV_KEY_PREFIX_TASK_CONFIG,
-                &task_id,
-                DapTaskConfig {
-                    version,
-                    leader_url: cmd.leader,
-                    helper_url: cmd.helper,
-                    time_precision: cmd.time_precision,
-                    expiration: cmd.task_expiration,
-                    min_batch_size: cmd.min_batch_size,
-                    query,
-                    vdaf,
-                    vdaf_verify_key,
-                    collector_hpke_config,
-                },
-            )
+            .set_task_config(&task_id, &task_config)
             .await?
             .is_some()
         {
@@ -925,15 +3044,23 @@ impl<'srv> DaphneWorker<'srv> {
         Ok(DapVersion::from(version))
     }
 
+    /// Convert a Worker request into a DAP request. The request is rejected if its body exceeds
+    /// the limit for the given kind of body, in which case the body is not read in full.
     pub(crate) async fn worker_request_to_dap<D>(
         &self,
         mut req: Request,
         ctx: &RouteContext<D>,
-    ) -> Result<DapRequest<DaphneWorkerAuth>> {
-        let version = self.extract_version_parameter(&req)?;
+        body: RequestBody,
+    ) -> std::result::Result<DapRequest<DaphneWorkerAuth>, DapAbort> {
+        let worker_err = |e| DapAbort::from(dap_err_in("worker_request_to_dap")(e));
+        let version = self.extract_version_parameter(&req).map_err(worker_err)?;
 
         // Determine the authorization method used by the sender.
-        let bearer_token = req.headers().get("DAP-Auth-Token")?.map(BearerToken::from);
+        let bearer_token = req
+            .headers()
+            .get("DAP-Auth-Token")
+            .map_err(worker_err)?
+            .map(BearerToken::from);
         let mut tls_client_auth = req.cf().tls_client_auth();
         if let Some(auth) = &tls_client_auth {
             // The runtime gives us a tls_client_auth whether the communication was secured by it or
@@ -959,10 +3086,31 @@ impl<'srv> DaphneWorker<'srv> {
             }
         };
 
-        let content_type = req.headers().get("Content-Type")?;
+        let content_type = req.headers().get("Content-Type").map_err(worker_err)?;
         let media_type = DapMediaType::from_str_for_version(version, content_type.as_deref());
 
-        let payload = req.bytes().await?;
+        // Reject oversized requests before reading the body if the sender declares its length, and
+        // otherwise stop reading the body as soon as it exceeds the limit.
+        let limit = self.config().request_body_limits.max_bytes(body);
+        let content_length = req
+            .headers()
+            .get("Content-Length")
+            .map_err(worker_err)?
+            .and_then(|content_length| content_length.parse::<u64>().ok());
+        if matches!(content_length, Some(content_length) if content_length > limit) {
+            return Err(DapAbort::PayloadTooLarge { limit });
+        }
+        let mut payload = Vec::new();
+        if req.inner().body().is_some() {
+            let mut chunks = req.stream().map_err(worker_err)?;
+            while let Some(chunk) = chunks.next().await {
+                let chunk = chunk.map_err(worker_err)?;
+                if u64::try_from(payload.len() + chunk.len()).map_or(true, |len| len > limit) {
+                    return Err(DapAbort::PayloadTooLarge { limit });
+                }
+                payload.extend_from_slice(&chunk);
+            }
+        }
 
         let (task_id, resource) = match version {
             DapVersion::Draft02 => {
@@ -974,10 +3122,17 @@ impl<'srv> DaphneWorker<'srv> {
                 //
                 // TODO spec: Consider moving the task ID out of the payload. Right now we're parsing it
                 // twice so that we have a reference to the task ID before parsing the entire message.
+                //
+                // If the path conveys a task ID as well, as for `/v02/tasks/:task_id/reports`, then
+                // it takes precedence, so that the handler can reject a payload for another task.
                 let mut r = Cursor::new(payload.as_ref());
-                (TaskId::decode(&mut r).ok(), DapResource::Undefined)
+                let task_id = ctx
+                    .param("task_id")
+                    .and_then(TaskId::try_from_base64url)
+                    .or_else(|| TaskId::decode(&mut r).ok());
+                (task_id, DapResource::Undefined)
             }
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft09 => {
                 let task_id = ctx.param("task_id").and_then(TaskId::try_from_base64url);
                 let resource = match media_type {
                     DapMediaType::AggregationJobInitReq
@@ -1010,7 +3165,9 @@ impl<'srv> DaphneWorker<'srv> {
 
                 (task_id, resource)
             }
-            DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
+            // The path indicates a version we don't support. Don't attempt to parse the task ID
+            // or resource; the request will be rejected by the handler.
+            DapVersion::Unknown => (None, DapResource::Undefined),
         };
 
         Ok(DapRequest {
@@ -1018,12 +3175,33 @@ impl<'srv> DaphneWorker<'srv> {
             task_id,
             resource,
             payload,
-            url: req.url()?,
+            url: req.url().map_err(worker_err)?,
             media_type,
             sender_auth,
         })
     }
 
+    /// Return the current time (in seconds since the beginning of UNIX time). This is the wall
+    /// clock, unless the time was fixed by `set_time_override()`.
+    pub(crate) fn current_time(&self) -> Time {
+        let time_override = *self
+            .isolate_state()
+            .time_override
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        time_override.unwrap_or_else(now)
+    }
+
+    /// Fix the time returned by `current_time()` for this isolate, or restore the wall clock if
+    /// `time` is `None`.
+    pub(crate) fn set_time_override(&self, time: Option<Time>) {
+        *self
+            .isolate_state()
+            .time_override
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = time;
+    }
+
     pub(crate) fn least_valid_report_time(&self, now: u64) -> u64 {
         now.saturating_sub(self.config().global.report_storage_epoch_duration)
     }
@@ -1032,14 +3210,15 @@ impl<'srv> DaphneWorker<'srv> {
         now.saturating_add(self.config().global.report_storage_max_future_time_skew)
     }
 
-    // Generic HTTP POST/PUT
+    // Generic HTTP POST/PUT. If a timeout is provided, then the request is aborted if the peer
+    // does not respond within the allotted time. Requests that are safe to repeat are retried on
+    // transient failures (network errors or 5xx responses), within the same time budget.
     pub(crate) async fn send_http(
         &self,
         req: DapRequest<DaphneWorkerAuth>,
         is_put: bool,
+        timeout: Option<Duration>,
     ) -> std::result::Result<DapResponse, DapError> {
-        let (payload, url) = (req.payload, req.url);
-
         let mut headers = reqwest_wasm::header::HeaderMap::new();
 
         let content_type = req
@@ -1059,7 +3238,7 @@ impl<'srv> DaphneWorker<'srv> {
             })?,
         );
 
-        if let Some(DaphneWorkerAuth::BearerToken(bearer_token)) = req.sender_auth {
+        if let Some(DaphneWorkerAuth::BearerToken(ref bearer_token)) = req.sender_auth {
             headers.insert(
                 reqwest_wasm::header::HeaderName::from_static("dap-auth-token"),
                 reqwest_wasm::header::HeaderValue::from_str(bearer_token.as_ref()).map_err(
@@ -1068,64 +3247,247 @@ impl<'srv> DaphneWorker<'srv> {
             );
         }
 
-        let client = &self.isolate_state().client;
-        let reqwest_req = if is_put {
-            client.put(url.as_str())
+        // The Helper deduplicates aggregation job initialization and aggregate share requests, so
+        // these are safe to retry.
+        let max_retries = match req.media_type {
+            DapMediaType::AggregationJobInitReq | DapMediaType::AggregateShareReq => {
+                self.config().helper_request_max_retries
+            }
+            _ => 0,
+        };
+
+        // Look up the query type so that the round-trip time can be broken down by it.
+        let query = if let Some(ref task_id) = req.task_id {
+            self.get_task_config(Cow::Borrowed(task_id))
+                .await
+                .map_err(dap_err)?
+                .map(|task_config| task_config.as_ref().query.clone())
         } else {
-            client.post(url.as_str())
-        }
-        .body(payload)
-        .headers(headers);
+            None
+        };
+
+        let peer = req.url.host_str().unwrap_or("unknown").to_string();
+        let fetch = async {
+            let mut retries = 0;
+            loop {
+                match self
+                    .send_http_attempt(&req, &headers, is_put, query.as_ref())
+                    .await
+                {
+                    Err(HttpAttemptError::Transient(e)) if retries < max_retries => {
+                        let backoff = HELPER_REQUEST_BACKOFF_BASE
+                            .saturating_mul(1 << retries.min(HELPER_REQUEST_BACKOFF_MAX_SHIFT));
+                        retries += 1;
+                        warn!(
+                            "{}: transient failure, retrying in {}ms ({retries}/{max_retries}): {e}",
+                            req.url,
+                            backoff.as_millis()
+                        );
+                        self.state
+                            .metrics
+                            .outbound_request_retry_counter
+                            .with_label_values(&[&self.state.host, &peer])
+                            .inc();
+                        Delay::from(backoff).await;
+                    }
+                    Ok(resp) => return Ok(resp),
+                    Err(HttpAttemptError::Transient(e) | HttpAttemptError::Permanent(e)) => {
+                        return Err(e)
+                    }
+                }
+            }
+        };
+
+        let res = if let Some(timeout) = timeout {
+            futures::pin_mut!(fetch);
+            match futures::future::select(fetch, Delay::from(timeout)).await {
+                Either::Left((res, _)) => res,
+                Either::Right(((), _)) => {
+                    self.state
+                        .metrics
+                        .outbound_request_timeout_counter
+                        .with_label_values(&[&self.state.host, &peer])
+                        .inc();
+                    error!(
+                        "{}: request timed out after {}ms",
+                        req.url,
+                        timeout.as_millis()
+                    );
+                    Err(DapError::Fatal(format!(
+                        "request to {peer} timed out after {}ms",
+                        timeout.as_millis()
+                    )))
+                }
+            }
+        } else {
+            fetch.await
+        };
 
+        self.state
+            .metrics
+            .outbound_request_counter
+            .with_label_values(&[
+                &self.state.host,
+                &peer,
+                if res.is_ok() { "ok" } else { "error" },
+            ])
+            .inc();
+        res
+    }
+
+    // Make one attempt at sending the HTTP request.
+    async fn send_http_attempt(
+        &self,
+        req: &DapRequest<DaphneWorkerAuth>,
+        headers: &reqwest_wasm::header::HeaderMap,
+        is_put: bool,
+        query: Option<&DapQueryConfig>,
+    ) -> std::result::Result<DapResponse, HttpAttemptError> {
+        let url = &req.url;
         let start = Date::now().as_millis();
-        let reqwest_resp = reqwest_req
-            .send()
-            .await
-            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        let (status, content_type, payload) =
+            if let Some(DaphneWorkerAuth::CfTlsClientAuth { .. }) = req.sender_auth {
+                self.send_http_attempt_with_tls_client_auth(req, headers, is_put)
+                    .await?
+            } else {
+                let client = &self.isolate_state().client;
+                let reqwest_req = if is_put {
+                    client.put(url.as_str())
+                } else {
+                    client.post(url.as_str())
+                }
+                .body(req.payload.clone())
+                .headers(headers.clone());
+
+                let reqwest_resp = reqwest_req
+                    .send()
+                    .await
+                    .map_err(|e| HttpAttemptError::Transient(DapError::Fatal(e.to_string())))?;
+                let status = reqwest_resp.status().as_u16();
+                let content_type = reqwest_resp
+                    .headers()
+                    .get(reqwest_wasm::header::CONTENT_TYPE)
+                    .map(|content_type| content_type.to_str().map(str::to_string))
+                    .transpose()
+                    .map_err(|e| DapError::Fatal(e.to_string()))?;
+                let payload = reqwest_resp
+                    .bytes()
+                    .await
+                    .map_err(|e| HttpAttemptError::Transient(DapError::Fatal(e.to_string())))?
+                    .to_vec();
+                (status, content_type, payload)
+            };
         let end = Date::now().as_millis();
         info!("request to {} completed in {}ms", url, end - start);
-        let status = reqwest_resp.status();
+        if let Some(query) = query {
+            self.state
+                .metrics
+                .daphne
+                .with_host(&self.state.host)
+                .helper_request_duration_observe(
+                    req.version,
+                    query,
+                    end.saturating_sub(start) as f64 / 1000.0,
+                );
+        }
         if status == 200 {
-            // Translate the reqwest response into a Worker response.
-            let content_type = reqwest_resp
-                .headers()
-                .get(reqwest_wasm::header::CONTENT_TYPE)
-                .ok_or_else(|| DapError::fatal(INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE))?
-                .to_str()
-                .map_err(|e| DapError::Fatal(e.to_string()))?;
-            let media_type = DapMediaType::from_str_for_version(req.version, Some(content_type));
-
-            let payload = reqwest_resp
-                .bytes()
-                .await
-                .map_err(|e| DapError::Fatal(e.to_string()))?
-                .to_vec();
+            // Translate the peer's response into a DAP response.
+            let content_type = content_type
+                .ok_or_else(|| DapError::fatal(INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE))?;
+            let media_type = DapMediaType::from_str_for_version(req.version, Some(&content_type));
 
             Ok(DapResponse {
                 version: req.version,
                 payload,
                 media_type,
+                cache_max_age: None,
             })
         } else {
-            error!("{}: request failed: {:?}", url, reqwest_resp);
-            if status == 400 {
-                if let Some(content_type) = reqwest_resp
-                    .headers()
-                    .get(reqwest_wasm::header::CONTENT_TYPE)
+            error!("{}: request failed with status {status}", url);
+            if (500..600).contains(&status) {
+                return Err(HttpAttemptError::Transient(DapError::fatal(
+                    INT_ERR_PEER_ABORT,
+                )));
+            }
+            let is_problem_details = content_type.map_or(false, |content_type| {
+                content_type == "application/problem+json"
+            });
+            if (400..500).contains(&status) && is_problem_details {
+                let text =
+                    String::from_utf8(payload).map_err(|e| DapError::Fatal(e.to_string()))?;
+                error!("Problem details: {text}");
+
+                // If the peer indicated a DAP abort, then propagate it to the caller.
+                if let Some(abort) = serde_json::from_str::<ProblemDetails>(&text)
+                    .ok()
+                    .and_then(DapAbort::from_problem_details)
                 {
-                    if content_type == "application/problem+json" {
-                        error!(
-                            "Problem details: {}",
-                            reqwest_resp
-                                .text()
-                                .await
-                                .map_err(|e| DapError::Fatal(e.to_string()))?
-                        );
-                    }
+                    return Err(DapError::Abort(abort).into());
                 }
             }
-            Err(DapError::fatal(INT_ERR_PEER_ABORT))
+            Err(DapError::fatal(INT_ERR_PEER_ABORT).into())
+        }
+    }
+
+    /// Send the HTTP request via the "DAP_LEADER_CERT" mTLS certificate binding so that the
+    /// connection is authenticated with the Leader's TLS client certificate. Return the status,
+    /// content type, and body of the response.
+    ///
+    /// This is only used for requests authorized with TLS client auth, which for now is only the
+    /// case for the Leader's requests for taskprov tasks (see [`DaphneWorkerAuth`]).
+    async fn send_http_attempt_with_tls_client_auth(
+        &self,
+        req: &DapRequest<DaphneWorkerAuth>,
+        headers: &reqwest_wasm::header::HeaderMap,
+        is_put: bool,
+    ) -> std::result::Result<(u16, Option<String>, Vec<u8>), HttpAttemptError> {
+        let fetcher = self
+            .env
+            .service(MTLS_BINDING_DAP_LEADER_CERT)
+            .map_err(|e| {
+                DapError::Fatal(format!(
+                    "TLS client auth requires the {MTLS_BINDING_DAP_LEADER_CERT} binding: {e}"
+                ))
+            })?;
+
+        let mut worker_headers = Headers::new();
+        for (name, value) in headers {
+            let value = value.to_str().map_err(|e| DapError::Fatal(e.to_string()))?;
+            worker_headers.set(name.as_str(), value).map_err(dap_err)?;
         }
+        let mut init = RequestInit::new();
+        init.with_method(if is_put { Method::Put } else { Method::Post })
+            .with_headers(worker_headers)
+            .with_body(Some(
+                js_sys::Uint8Array::from(req.payload.as_slice()).into(),
+            ));
+
+        let mut resp = fetcher
+            .fetch(req.url.as_str(), Some(init))
+            .await
+            .map_err(|e| HttpAttemptError::Transient(DapError::Fatal(e.to_string())))?;
+        let status = resp.status_code();
+        let content_type = resp.headers().get("content-type").map_err(dap_err)?;
+        let payload = resp
+            .bytes()
+            .await
+            .map_err(|e| HttpAttemptError::Transient(DapError::Fatal(e.to_string())))?;
+        Ok((status, content_type, payload))
+    }
+}
+
+/// Outcome of a failed attempt to send an HTTP request to a peer.
+enum HttpAttemptError {
+    /// The failure may not recur if the request is sent again.
+    Transient(DapError),
+
+    /// The failure is expected to recur if the request is sent again.
+    Permanent(DapError),
+}
+
+impl From<DapError> for HttpAttemptError {
+    fn from(e: DapError) -> Self {
+        Self::Permanent(e)
     }
 }
 
@@ -1145,6 +3507,70 @@ impl<K: Clone + Eq + std::hash::Hash, V> Guarded<'_, K, V> {
     }
 }
 
+/// Identifies the algorithm used to sign HPKE config lists.
+const HPKE_CONFIG_SIGNATURE_ALGORITHM: &str = "ed25519";
+
+/// An HPKE config list signed by the Aggregator, for Clients that obtain it out-of-band.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct SignedHpkeConfigList {
+    hpke_config_list: String, // base64url
+    signature: String,        // base64url
+    signature_algorithm: &'static str,
+}
+
+/// The Leader's bearer token for a task, as stored in KV. While the token is being rotated, the
+/// previous token is stored alongside the current one.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum LeaderBearerTokenKvValue {
+    Rotating(LeaderBearerTokenRotation),
+    Token(BearerToken),
+}
+
+impl LeaderBearerTokenKvValue {
+    /// The token the Leader uses for new requests.
+    pub(crate) fn current(&self) -> &BearerToken {
+        match self {
+            Self::Rotating(rotation) => &rotation.current,
+            Self::Token(token) => token,
+        }
+    }
+
+    /// The token being rotated out, if its grace period has not elapsed by `now`.
+    pub(crate) fn previous(&self, now: Time) -> Option<&BearerToken> {
+        match self {
+            Self::Rotating(rotation) if now < rotation.grace_period_end => Some(&rotation.previous),
+            _ => None,
+        }
+    }
+}
+
+/// A rotation of the Leader's bearer token for a task.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct LeaderBearerTokenRotation {
+    pub(crate) previous: BearerToken,
+    pub(crate) current: BearerToken,
+
+    /// The time after which `previous` is no longer valid.
+    pub(crate) grace_period_end: Time,
+}
+
+/// Estimated cost of collecting a batch.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct CollectionEstimate {
+    /// Number of buckets spanned by the batch selector.
+    bucket_count: u64,
+
+    /// Maximum number of buckets permitted, if configured.
+    max_batch_span: Option<u64>,
+
+    /// Whether a collect request for the batch would be permitted.
+    permitted: bool,
+}
+
 pub(crate) type GuardedHpkeReceiverConfig<'a> = Guarded<'a, HpkeReceiverKvKey, HpkeReceiverConfig>;
 
 impl AsRef<HpkeConfig> for GuardedHpkeReceiverConfig<'_> {
@@ -1161,11 +3587,11 @@ impl AsRef<BearerToken> for GuardedBearerToken<'_> {
     }
 }
 
-pub(crate) type GuardedDapTaskConfig<'a> = Guarded<'a, TaskId, DapTaskConfig>;
+pub(crate) type GuardedDapTaskConfig<'a> = Guarded<'a, TaskId, CacheEntry<DapTaskConfig>>;
 
 impl AsRef<DapTaskConfig> for GuardedDapTaskConfig<'_> {
     fn as_ref(&self) -> &DapTaskConfig {
-        self.value()
+        &self.value().value
     }
 }
 
@@ -1182,18 +3608,46 @@ pub(crate) enum DaphneWorkerDeployment {
     Dev,
 }
 
+/// Identifies an HPKE receiver config in KV. Configs are either global, i.e., used for all tasks
+/// that don't opt in to task-scoped configs, or scoped to a single task.
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub(crate) struct HpkeReceiverKvKey {
+    pub(crate) task_id: Option<TaskId>,
     pub(crate) version: DapVersion,
     pub(crate) hpke_config_id: u8,
 }
 
 impl HpkeReceiverKvKey {
+    /// Return the prefix of the KV names of the HPKE receiver configs for the given task, or of the
+    /// global configs if `task_id` is `None`.
+    pub(crate) fn kv_prefix(task_id: Option<&TaskId>) -> String {
+        match task_id {
+            Some(task_id) => format!(
+                "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/task/{}/version/",
+                task_id.to_base64url()
+            ),
+            None => format!("{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/version/"),
+        }
+    }
+
     fn parse_from_name(name: &str) -> Option<Self> {
-        let mut iter = name.split('/');
+        let mut iter = name.split('/').peekable();
+
+        // Read "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}".
+        if iter.next()? != KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG {
+            return None;
+        }
+
+        // Read and parse the task ID, if any.
+        let task_id = if iter.peek() == Some(&"task") {
+            iter.next();
+            Some(TaskId::try_from_base64url(iter.next()?)?)
+        } else {
+            None
+        };
 
-        // Read "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/version".
-        if iter.next()? != KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG || iter.next()? != "version" {
+        // Read "version".
+        if iter.next()? != "version" {
             return None;
         }
 
@@ -1217,6 +3671,7 @@ impl HpkeReceiverKvKey {
         }
 
         Some(HpkeReceiverKvKey {
+            task_id,
             version,
             hpke_config_id,
         })
@@ -1230,6 +3685,9 @@ impl HpkeReceiverKvKey {
 
 impl std::fmt::Display for HpkeReceiverKvKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(task_id) = &self.task_id {
+            write!(f, "task/{}/", task_id.to_base64url())?;
+        }
         write!(
             f,
             "version/{}/config_id/{}",
@@ -0,0 +1,81 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::config::{
+    parse_durable_location_hints, HpkeReceiverKvKey, LeaderBearerTokenKvValue,
+    LeaderBearerTokenRotation, KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
+};
+use daphne::{auth::BearerToken, messages::TaskId, DapVersion};
+
+#[test]
+fn hpke_receiver_kv_key_roundtrip() {
+    let task_id = TaskId([7; 32]);
+    for hpke_receiver_kv_key in [
+        HpkeReceiverKvKey {
+            task_id: None,
+            version: DapVersion::Draft02,
+            hpke_config_id: 23,
+        },
+        HpkeReceiverKvKey {
+            task_id: Some(task_id.clone()),
+            version: DapVersion::Draft04,
+            hpke_config_id: 23,
+        },
+    ] {
+        let name = format!("{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{hpke_receiver_kv_key}");
+
+        // Listing the configs for a scope only finds the configs in that scope.
+        let prefix = HpkeReceiverKvKey::kv_prefix(hpke_receiver_kv_key.task_id.as_ref());
+        assert!(name.starts_with(&prefix));
+        let other_prefix = HpkeReceiverKvKey::kv_prefix(match hpke_receiver_kv_key.task_id {
+            Some(..) => None,
+            None => Some(&task_id),
+        });
+        assert!(!name.starts_with(&other_prefix));
+
+        assert!(HpkeReceiverKvKey::try_from_name(&name).unwrap() == hpke_receiver_kv_key);
+    }
+
+    assert!(HpkeReceiverKvKey::try_from_name(&format!(
+        "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/task/not-a-task-id/version/v04/config_id/23"
+    ))
+    .is_err());
+}
+
+#[test]
+fn leader_bearer_token_kv_value_reads_plain_token() {
+    // Tokens written before rotation was supported are stored as a plain bearer token.
+    let token = BearerToken::from("leader token");
+    let tokens: LeaderBearerTokenKvValue =
+        serde_json::from_str(&serde_json::to_string(&token).unwrap()).unwrap();
+    assert!(tokens.current() == &token);
+    assert!(tokens.previous(0).is_none());
+}
+
+#[test]
+fn leader_bearer_token_kv_value_previous_expires() {
+    let tokens = LeaderBearerTokenKvValue::Rotating(LeaderBearerTokenRotation {
+        previous: BearerToken::from("old token"),
+        current: BearerToken::from("new token"),
+        grace_period_end: 1000,
+    });
+    let tokens: LeaderBearerTokenKvValue =
+        serde_json::from_str(&serde_json::to_string(&tokens).unwrap()).unwrap();
+    assert!(tokens.current() == &BearerToken::from("new token"));
+    assert!(tokens.previous(999) == Some(&BearerToken::from("old token")));
+    assert!(tokens.previous(1000).is_none());
+}
+
+#[test]
+fn durable_location_hints_reject_unrecognized_binding() {
+    let hints = parse_durable_location_hints(
+        r#"{"DAP_AGGREGATE_STORE": "weur", "DAP_GARBAGE_COLLECTOR": "enam"}"#,
+    )
+    .unwrap();
+    assert_eq!(hints["DAP_AGGREGATE_STORE"], "weur");
+    assert_eq!(hints["DAP_GARBAGE_COLLECTOR"], "enam");
+
+    // A misspelled binding would otherwise be ignored without notice.
+    assert!(parse_durable_location_hints(r#"{"DAP_AGGREGATES_STORE": "weur"}"#).is_err());
+    assert!(parse_durable_location_hints(r#"["DAP_AGGREGATE_STORE"]"#).is_err());
+}
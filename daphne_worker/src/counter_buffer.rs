@@ -0,0 +1,68 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Buffering of named counts before they are persisted to a `Counters` durable object.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+/// Counts accumulated by this isolate that have not yet been persisted.
+pub(crate) struct CounterBuffer {
+    /// Number of events recorded for each counter since the last flush.
+    pending: Mutex<HashMap<String, u64>>,
+
+    /// Time (in seconds since the beginning of UNIX time) of the last flush.
+    last_flush: AtomicU64,
+}
+
+impl CounterBuffer {
+    pub(crate) fn new(now: u64) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            last_flush: AtomicU64::new(now),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, u64>> {
+        // The map is always left in a consistent state, so it's safe to keep using it even if a
+        // thread panicked while holding the lock.
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Add `count` to the given counter.
+    pub(crate) fn record(&self, counter: String, count: u64) {
+        let mut pending = self.lock();
+        let total = pending.entry(counter).or_default();
+        *total = total.saturating_add(count);
+    }
+
+    /// If at least `interval` seconds have elapsed since the last flush and there are counts
+    /// pending, then take the pending counts so that they can be flushed.
+    pub(crate) fn take_if_due(&self, now: u64, interval: u64) -> Option<HashMap<String, u64>> {
+        let last_flush = self.last_flush.load(Ordering::Relaxed);
+        if now < last_flush.saturating_add(interval) {
+            return None;
+        }
+
+        let mut pending = self.lock();
+        if pending.is_empty() {
+            return None;
+        }
+        self.last_flush.store(now, Ordering::Relaxed);
+        Some(std::mem::take(&mut *pending))
+    }
+
+    /// Put back counts that could not be flushed so that they are retried on the next flush.
+    pub(crate) fn restore(&self, counts: HashMap<String, u64>) {
+        for (counter, count) in counts {
+            self.record(counter, count);
+        }
+    }
+}
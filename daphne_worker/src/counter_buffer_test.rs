@@ -0,0 +1,52 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{counter_buffer::CounterBuffer, durable::counters::merge_counts};
+use std::collections::HashMap;
+
+#[test]
+fn counter_buffer_flushes_once_interval_elapsed() {
+    // Counts persisted by the durable object.
+    let mut persisted = HashMap::new();
+
+    // Nothing is flushed until the interval elapses.
+    let buffer = CounterBuffer::new(1000);
+    buffer.record("report_replayed".into(), 2);
+    assert_eq!(buffer.take_if_due(1030, 60), None);
+    buffer.record("report_replayed".into(), 1);
+    buffer.record("batch_collected".into(), 4);
+    merge_counts(&mut persisted, buffer.take_if_due(1060, 60).unwrap());
+
+    // Nothing is pending immediately after a flush.
+    assert_eq!(buffer.take_if_due(2000, 60), None);
+
+    // Later flushes add to the persisted counts.
+    buffer.record("report_replayed".into(), 5);
+    buffer.record("task_expired".into(), 1);
+    merge_counts(&mut persisted, buffer.take_if_due(2060, 60).unwrap());
+
+    assert_eq!(
+        persisted,
+        HashMap::from([
+            ("report_replayed".to_string(), 8),
+            ("batch_collected".to_string(), 4),
+            ("task_expired".to_string(), 1),
+        ])
+    );
+}
+
+#[test]
+fn counter_buffer_restored_after_failed_flush() {
+    let buffer = CounterBuffer::new(0);
+    buffer.record("report_replayed".into(), 3);
+    let counts = buffer.take_if_due(60, 60).unwrap();
+
+    // Flushing failed, so the counts are put back and included in the next flush along with any
+    // counts recorded in the meantime.
+    buffer.record("report_replayed".into(), 1);
+    buffer.restore(counts);
+    assert_eq!(
+        buffer.take_if_due(120, 60),
+        Some(HashMap::from([("report_replayed".to_string(), 4)]))
+    );
+}
@@ -9,38 +9,50 @@
 use crate::{
     auth::{DaphneWorkerAuth, DaphneWorkerAuthMethod},
     config::{
-        DaphneWorker, GuardedBearerToken, GuardedDapTaskConfig, GuardedHpkeReceiverConfig,
-        HpkeReceiverKvKey, KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
+        DaphneWorker, GuardedDapTaskConfig, GuardedHpkeReceiverConfig, HpkeReceiverKvKey,
+        COUNTER_REPORTS_INGESTED,
     },
-    dap_err,
+    dap_err_in,
     durable::{
         aggregate_store::{
+            AggregateStoreMergeReq, AggregateStoreMergeResult,
             DURABLE_AGGREGATE_STORE_CHECK_COLLECTED, DURABLE_AGGREGATE_STORE_GET,
             DURABLE_AGGREGATE_STORE_MARK_COLLECTED, DURABLE_AGGREGATE_STORE_MERGE,
         },
-        durable_name_agg_store, durable_name_queue, durable_name_task,
-        helper_state_store::{
-            durable_helper_state_name, DURABLE_HELPER_STATE_GET, DURABLE_HELPER_STATE_PUT,
+        helper_state_store::{DURABLE_HELPER_STATE_GET, DURABLE_HELPER_STATE_PUT},
+        hpke_enc_replay_cache::{
+            HpkeEncReplayCacheRequest, HpkeEncReplayCacheResult,
+            DURABLE_HPKE_ENC_REPLAY_CACHE_CHECK, DURABLE_HPKE_ENC_REPLAY_CACHE_PUT,
         },
         leader_agg_job_queue::DURABLE_LEADER_AGG_JOB_QUEUE_GET,
         leader_batch_queue::{
-            BatchCount, DURABLE_LEADER_BATCH_QUEUE_ASSIGN, DURABLE_LEADER_BATCH_QUEUE_REMOVE,
+            BatchCount, BatchSizeBounds, DURABLE_LEADER_BATCH_QUEUE_ASSIGN,
+            DURABLE_LEADER_BATCH_QUEUE_REMOVE,
         },
         leader_col_job_queue::{
-            CollectQueueRequest, DURABLE_LEADER_COL_JOB_QUEUE_FINISH,
-            DURABLE_LEADER_COL_JOB_QUEUE_GET, DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT,
-            DURABLE_LEADER_COL_JOB_QUEUE_PUT,
+            CollectQueuePutResult, CollectQueueRequest, DURABLE_LEADER_COL_JOB_QUEUE_ABANDON,
+            DURABLE_LEADER_COL_JOB_QUEUE_FINISH, DURABLE_LEADER_COL_JOB_QUEUE_GET,
+            DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT, DURABLE_LEADER_COL_JOB_QUEUE_PUT,
         },
         reports_pending::{
             PendingReport, ReportsPendingResult, DURABLE_REPORTS_PENDING_GET,
             DURABLE_REPORTS_PENDING_PUT,
         },
-        reports_processed::DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
+        reports_processed::{
+            ReportsProcessedMarkAggregatedRequest, DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
+        },
+        upload_rate_limiter::{
+            UploadRateLimiterRequest, UploadRateLimiterResult, DURABLE_UPLOAD_RATE_LIMITER_TAKE,
+        },
+        AggStoreName, DurableName, HelperStateName, QueueName, ReportStoreName, TaskName,
         BINDING_DAP_AGGREGATE_STORE, BINDING_DAP_HELPER_STATE_STORE,
-        BINDING_DAP_LEADER_AGG_JOB_QUEUE, BINDING_DAP_LEADER_BATCH_QUEUE,
-        BINDING_DAP_LEADER_COL_JOB_QUEUE, BINDING_DAP_REPORTS_PENDING,
-        BINDING_DAP_REPORTS_PROCESSED,
+        BINDING_DAP_HPKE_ENC_REPLAY_CACHE, BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+        BINDING_DAP_LEADER_BATCH_QUEUE, BINDING_DAP_LEADER_COL_JOB_QUEUE,
+        BINDING_DAP_REPORTS_PENDING, BINDING_DAP_REPORTS_PROCESSED,
+        BINDING_DAP_UPLOAD_RATE_LIMITER,
     },
+    durable_err_in,
+    helper_state_encryption::{encode_helper_state_blob, open_helper_state, seal_helper_state},
     now, DaphneWorkerReportSelector,
 };
 use async_trait::async_trait;
@@ -50,40 +62,137 @@ use daphne::{
     constants::DapMediaType,
     hpke::HpkeDecrypter,
     messages::{
-        BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq, HpkeCiphertext,
-        PartialBatchSelector, Report, ReportId, ReportMetadata, TaskId, TransitionFailure,
+        BatchId, Collection, CollectionJobId, CollectionReq, HpkeCiphertext, PartialBatchSelector,
+        Report, ReportId, ReportMetadata, TaskId, Time, TransitionFailure,
     },
-    metrics::DaphneMetrics,
+    metrics::{DaphneMetrics, DaphneRole, HpkeDecryptFailure},
     roles::{early_metadata_check, DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
-    taskprov::get_taskprov_task_config,
-    DapAggregateShare, DapBatchBucket, DapCollectJob, DapError, DapGlobalConfig, DapHelperState,
-    DapOutputShare, DapQueryConfig, DapRequest, DapResponse, DapSender, DapTaskConfig, DapVersion,
-    MetaAggregationJobId,
+    taskprov::{get_taskprov_task_config, is_taskprov_task},
+    DapAggregateShare, DapBatchBucket, DapBatchSpan, DapCollectJob, DapError, DapGlobalConfig,
+    DapHelperState, DapOutputShare, DapQueryConfig, DapRequest, DapResponse, DapSender,
+    DapTaskConfig, DapVersion, MetaAggregationJobId,
 };
-use futures::future::try_join_all;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
+use rand::{thread_rng, Rng};
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
 use worker::*;
 
-pub(crate) fn dap_response_to_worker(resp: DapResponse) -> Result<Response> {
-    let mut headers = Headers::new();
-    headers.set(
-        "Content-Type",
-        resp.media_type
-            .as_str_for_version(resp.version)
-            .ok_or_else(|| {
-                Error::RustError(format!(
-                    "failed to construct content-type for media type {:?} and version {:?}",
-                    resp.media_type, resp.version
-                ))
-            })?,
-    )?;
-    let worker_resp = Response::from_bytes(resp.payload)?.with_headers(headers);
-    Ok(worker_resp)
+/// Conversion of a DAP response into a response that can be returned by the Worker.
+pub(crate) trait IntoWorkerResponse {
+    fn into_worker_response(self) -> Result<Response>;
+
+    /// Like `into_worker_response()`, except that the body is compressed if it is at least
+    /// `min_bytes` long and the client accepts a supported encoding, as indicated by the value of
+    /// its `Accept-Encoding` header. If `min_bytes` is not set, then the body is never compressed.
+    fn into_compressible_worker_response(
+        self,
+        accept_encoding: Option<&str>,
+        min_bytes: Option<usize>,
+    ) -> Result<Response>;
+}
+
+impl IntoWorkerResponse for DapResponse {
+    fn into_worker_response(self) -> Result<Response> {
+        self.into_compressible_worker_response(None, None)
+    }
+
+    fn into_compressible_worker_response(
+        self,
+        accept_encoding: Option<&str>,
+        min_bytes: Option<usize>,
+    ) -> Result<Response> {
+        let mut headers = Headers::new();
+        for (name, value) in self
+            .headers()
+            .map_err(|e| Error::RustError(e.to_string()))?
+        {
+            headers.set(name, &value)?;
+        }
+
+        // The Workers runtime encodes the body according to the `Content-Encoding` header, so we
+        // only need to select the encoding. The `Content-Type` header still indicates the DAP
+        // media type.
+        if let (Some(accept_encoding), Some(min_bytes)) = (accept_encoding, min_bytes) {
+            if self.payload.len() >= min_bytes {
+                if let Some(encoding) = ContentEncoding::negotiate(accept_encoding) {
+                    headers.set("Content-Encoding", encoding.as_str())?;
+                    headers.append("Vary", "Accept-Encoding")?;
+                }
+            }
+        }
+
+        Ok(Response::from_bytes(self.payload)?.with_headers(headers))
+    }
+}
+
+/// Add a random delay of at most `jitter` seconds to `base`, so that clients that are told to
+/// retry at the same time don't all do so at once.
+pub(crate) fn jittered_retry_after(base: u64, jitter: u64, rng: &mut impl Rng) -> u64 {
+    base.saturating_add(rng.gen_range(0..=jitter))
+}
+
+/// Encoding that may be applied to the body of a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Select an encoding accepted by the client, given the value of its `Accept-Encoding`
+    /// header. The encoding with the highest quality value is selected, with brotli preferred
+    /// over gzip if their quality values are equal. The wildcard `*` stands for gzip unless gzip
+    /// is listed explicitly. Codings with a quality value of zero are not acceptable. Returns
+    /// `None` if no supported encoding is acceptable.
+    pub(crate) fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut wildcard = None;
+        for coding in accept_encoding.split(',') {
+            let mut params = coding.split(';');
+            let name = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let q = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => match q.trim().parse::<f32>() {
+                    Ok(q) => q,
+                    // Ignore codings with a malformed quality value.
+                    Err(..) => continue,
+                },
+                None => 1.0,
+            };
+            match name.as_str() {
+                "br" => brotli = Some(q),
+                "gzip" | "x-gzip" => gzip = Some(q),
+                "*" => wildcard = Some(q),
+                _ => (),
+            }
+        }
+
+        let brotli = brotli.unwrap_or_default();
+        let gzip = gzip.or(wildcard).unwrap_or_default();
+        if brotli > 0.0 && brotli >= gzip {
+            Some(Self::Brotli)
+        } else if gzip > 0.0 {
+            Some(Self::Gzip)
+        } else {
+            None
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -93,63 +202,96 @@ impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
     async fn get_hpke_config_for(
         &'srv self,
         version: DapVersion,
-        _task_id: Option<&TaskId>,
+        task_id: Option<&TaskId>,
     ) -> std::result::Result<GuardedHpkeReceiverConfig<'srv>, DapError> {
-        let kv_store = self.kv().map_err(dap_err)?;
-        let keys = kv_store
-            .list()
-            .limit(1)
-            .prefix(KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG.to_string())
-            .execute()
-            .await
-            .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?;
+        let global = &self.config().global;
+        let now = now();
+
+        // If the task opted in to task-scoped HPKE receiver configs, then select among these.
+        // Otherwise, or if the task is unknown, select among the global configs.
+        let scope = match task_id {
+            Some(task_id) => self
+                .get_task_config(Cow::Borrowed(task_id))
+                .await
+                .map_err(dap_err_in("get_hpke_config_for"))?
+                .filter(|task_config| task_config.as_ref().task_scoped_hpke_config)
+                .map(|_| task_id.clone()),
+            None => None,
+        };
 
-        let hpke_receiver_kv_key = if keys.keys.is_empty() {
-            // Generate a new HPKE receiver config and store it in KV.
-            //
-            // For now, expect that only one KEM algorithm is supported and that only one config
-            // will be used at anyone time.
-            if self.config().global.supported_hpke_kems.len() != 1 {
-                return Err(DapError::Fatal(
-                    "The number of supported HPKE KEMs must be 1".to_string(),
-                ));
+        // If a config was selected recently and is still valid, then use it. The selection is
+        // cached for `task_config_cache_ttl`, after which configs that were expired by another
+        // isolate (e.g., by rotation) are no longer advertised.
+        if let Some(hpke_receiver_kv_key) = self
+            .get_hpke_config_selection(version, scope.as_ref())
+            .map_err(dap_err_in("get_hpke_config_for"))?
+        {
+            if let Some(hpke_receiver_config) = self
+                .get_hpke_receiver_config(hpke_receiver_kv_key)
+                .await
+                .map_err(dap_err_in("get_hpke_config_for"))?
+                .filter(|config| {
+                    config.value().is_valid_at(now)
+                        && global.allows_hpke_config(&config.value().config)
+                })
+            {
+                return Ok(hpke_receiver_config);
             }
+        }
 
-            let mut hpke_config_id = None;
-            for it in self
-                .config()
-                .global
-                .gen_hpke_receiver_config_list(rand::random())
+        // Otherwise, among the HPKE receiver configs for this version that are currently valid,
+        // select the one that became valid most recently. Ties are broken by config ID so that the
+        // selection is deterministic.
+        //
+        // Each config is re-read from KV so that configs that were expired by another isolate are
+        // not advertised.
+        let mut valid_kv_key = None;
+        let mut valid_order = None;
+        let mut taken_config_ids = HashSet::new();
+        for hpke_receiver_kv_key in self.list_hpke_receiver_kv_keys(scope.as_ref()).await? {
+            if hpke_receiver_kv_key.version != version {
+                continue;
+            }
+            let (is_valid, not_before) = match self
+                .refresh_hpke_receiver_config(hpke_receiver_kv_key.clone())
+                .await
+                .map_err(dap_err_in("get_hpke_config_for"))?
             {
-                let hpke_receiver_config = it.expect("failed to generate HPKE receiver config");
-                if hpke_config_id.is_none() {
-                    hpke_config_id = Some(hpke_receiver_config.config.id);
+                Some(hpke_receiver_config) => {
+                    let hpke_receiver_config = hpke_receiver_config.value();
+                    // A config whose KDF or AEAD is no longer allowed is not advertised.
+                    (
+                        hpke_receiver_config.is_valid_at(now)
+                            && global.allows_hpke_config(&hpke_receiver_config.config),
+                        hpke_receiver_config.not_before.unwrap_or(0),
+                    )
                 }
-                let new_kv_config_key = format!(
-                    "{}/{}",
-                    KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
-                    HpkeReceiverKvKey {
-                        version,
-                        hpke_config_id: hpke_receiver_config.config.id
-                    },
-                );
+                // The config may have been deleted since we listed the keys.
+                None => continue,
+            };
 
-                kv_store
-                    .put(&new_kv_config_key, hpke_receiver_config)
-                    .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?
-                    .execute()
-                    .await
-                    .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?;
+            taken_config_ids.insert(hpke_receiver_kv_key.hpke_config_id);
+            let order = Some((not_before, hpke_receiver_kv_key.hpke_config_id));
+            if is_valid && order > valid_order {
+                valid_order = order;
+                valid_kv_key = Some(hpke_receiver_kv_key);
             }
+        }
 
+        let hpke_receiver_kv_key = if let Some(hpke_receiver_kv_key) = valid_kv_key {
+            hpke_receiver_kv_key
+        } else {
+            // Generate a new HPKE receiver config and store it in KV.
             HpkeReceiverKvKey {
                 version,
-                hpke_config_id: hpke_config_id.unwrap(),
+                hpke_config_id: self
+                    .gen_hpke_receiver_configs(version, scope.as_ref(), taken_config_ids)
+                    .await?,
+                task_id: scope,
             }
-        } else {
-            // Return the first HPKE receiver config in the list.
-            HpkeReceiverKvKey::try_from_name(keys.keys[0].name.as_str())?
         };
+        self.cache_hpke_config_selection(hpke_receiver_kv_key.clone())
+            .map_err(dap_err_in("get_hpke_config_for"))?;
 
         // Fetch the indicated HPKE config from KV.
         //
@@ -158,23 +300,69 @@ impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
         Ok(self
             .get_hpke_receiver_config(hpke_receiver_kv_key)
             .await
-            .map_err(dap_err)?
+            .map_err(dap_err_in("get_hpke_config_for"))?
             .ok_or_else(|| DapError::fatal("empty HPKE receiver config list"))?)
     }
 
+    async fn get_hpke_config_by_id(
+        &'srv self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+        hpke_config_id: u8,
+    ) -> std::result::Result<Option<GuardedHpkeReceiverConfig<'srv>>, DapError> {
+        // As in `get_hpke_config_for()`, prefer the task-scoped config if the task opted in to
+        // task-scoped HPKE receiver configs. Otherwise, or if there is no such config, fall back
+        // to the global config.
+        let global = &self.config().global;
+        if let Some(task_id) = task_id {
+            let task_scoped = self
+                .get_task_config(Cow::Borrowed(task_id))
+                .await
+                .map_err(dap_err_in("get_hpke_config_by_id"))?
+                .map_or(false, |task_config| {
+                    task_config.as_ref().task_scoped_hpke_config
+                });
+            if task_scoped {
+                if let Some(hpke_receiver_config) = self
+                    .get_hpke_receiver_config(HpkeReceiverKvKey {
+                        task_id: Some(task_id.clone()),
+                        version,
+                        hpke_config_id,
+                    })
+                    .await
+                    .map_err(dap_err_in("get_hpke_config_by_id"))?
+                    .filter(|config| global.allows_hpke_config(&config.value().config))
+                {
+                    return Ok(Some(hpke_receiver_config));
+                }
+            }
+        }
+
+        Ok(self
+            .get_hpke_receiver_config(HpkeReceiverKvKey {
+                task_id: None,
+                version,
+                hpke_config_id,
+            })
+            .await
+            .map_err(dap_err_in("get_hpke_config_by_id"))?
+            .filter(|config| global.allows_hpke_config(&config.value().config)))
+    }
+
+    fn hpke_config_not_after(&self, hpke_config: &GuardedHpkeReceiverConfig<'srv>) -> Option<Time> {
+        hpke_config.value().not_after
+    }
+
     async fn can_hpke_decrypt(
         &self,
         task_id: &TaskId,
         config_id: u8,
     ) -> std::result::Result<bool, DapError> {
-        let version = self.try_get_task_config(task_id).await?.as_ref().version;
+        let task_config = self.try_get_task_config(task_id).await?;
         Ok(self
-            .get_hpke_receiver_config(HpkeReceiverKvKey {
-                version,
-                hpke_config_id: config_id,
-            })
+            .get_hpke_receiver_config_for_task(task_id, task_config.as_ref(), config_id)
             .await
-            .map_err(dap_err)?
+            .map_err(dap_err_in("can_hpke_decrypt"))?
             .is_some())
     }
 
@@ -185,22 +373,27 @@ impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
         aad: &[u8],
         ciphertext: &HpkeCiphertext,
     ) -> std::result::Result<Vec<u8>, DapError> {
-        let version = self.try_get_task_config(task_id).await?.as_ref().version;
+        let task_config = self.try_get_task_config(task_id).await?;
+        let role = if self.config().is_leader {
+            DaphneRole::Leader
+        } else {
+            DaphneRole::Helper
+        };
+        let metrics = self.metrics().with_host(&self.state.host).with_role(role);
         if let Some(hpke_receiver_config) = self
-            .get_hpke_receiver_config(HpkeReceiverKvKey {
-                version,
-                hpke_config_id: ciphertext.config_id,
-            })
+            .get_hpke_receiver_config_for_task(task_id, task_config.as_ref(), ciphertext.config_id)
             .await
-            .map_err(dap_err)?
+            .map_err(dap_err_in("hpke_decrypt"))?
         {
-            Ok(hpke_receiver_config.value().decrypt(
-                info,
-                aad,
-                &ciphertext.enc,
-                &ciphertext.payload,
-            )?)
+            hpke_receiver_config
+                .value()
+                .decrypt(info, aad, &ciphertext.enc, &ciphertext.payload)
+                .map_err(|e| {
+                    metrics.hpke_decrypt_failure_inc(HpkeDecryptFailure::DecryptError);
+                    e
+                })
         } else {
+            metrics.hpke_decrypt_failure_inc(HpkeDecryptFailure::UnknownConfigId);
             Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
         }
     }
@@ -208,22 +401,72 @@ impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
 
 #[async_trait(?Send)]
 impl<'srv> BearerTokenProvider<'srv> for DaphneWorker<'srv> {
-    type WrappedBearerToken = GuardedBearerToken<'srv>;
+    type WrappedBearerToken = BearerToken;
 
     async fn get_leader_bearer_token_for(
         &'srv self,
         task_id: &'srv TaskId,
-    ) -> std::result::Result<Option<GuardedBearerToken>, DapError> {
-        self.get_leader_bearer_token(task_id).await.map_err(dap_err)
+    ) -> std::result::Result<Option<BearerToken>, DapError> {
+        self.get_leader_bearer_token(task_id)
+            .await
+            .map_err(dap_err_in("get_leader_bearer_token_for"))
+    }
+
+    async fn get_rotated_leader_bearer_tokens_for(
+        &'srv self,
+        task_id: &'srv TaskId,
+    ) -> std::result::Result<Vec<BearerToken>, DapError> {
+        self.get_rotated_leader_bearer_tokens(task_id)
+            .await
+            .map_err(dap_err_in("get_rotated_leader_bearer_tokens_for"))
     }
 
     async fn get_collector_bearer_token_for(
         &'srv self,
         task_id: &'srv TaskId,
-    ) -> std::result::Result<Option<GuardedBearerToken>, DapError> {
-        self.get_collector_bearer_token(task_id)
+    ) -> std::result::Result<Option<BearerToken>, DapError> {
+        Ok(self
+            .get_collector_bearer_token(task_id)
             .await
-            .map_err(dap_err)
+            .map_err(dap_err_in("get_collector_bearer_token_for"))?
+            .map(|token| token.value().clone()))
+    }
+
+    async fn get_collector_bearer_tokens_for(
+        &'srv self,
+        task_id: &'srv TaskId,
+    ) -> std::result::Result<Option<Vec<BearerToken>>, DapError> {
+        let mut tokens = Vec::new();
+        if let Some(token) = self
+            .get_collector_bearer_token(task_id)
+            .await
+            .map_err(dap_err_in("get_collector_bearer_tokens_for"))?
+        {
+            tokens.push(token.value().clone());
+        }
+        if let Some(additional_tokens) = self
+            .get_additional_collector_bearer_tokens(task_id)
+            .await
+            .map_err(dap_err_in("get_collector_bearer_tokens_for"))?
+        {
+            tokens.extend(additional_tokens.value().iter().cloned());
+        }
+        Ok(if tokens.is_empty() {
+            None
+        } else {
+            Some(tokens)
+        })
+    }
+
+    async fn get_client_bearer_token_for(
+        &'srv self,
+        task_id: &'srv TaskId,
+    ) -> std::result::Result<Option<BearerToken>, DapError> {
+        Ok(self
+            .get_client_bearer_token(task_id)
+            .await
+            .map_err(dap_err_in("get_client_bearer_token_for"))?
+            .map(|token| token.value().clone()))
     }
 
     fn is_taskprov_leader_bearer_token(&self, token: &BearerToken) -> bool {
@@ -258,13 +501,32 @@ impl DapAuthorizedSender<DaphneWorkerAuth> for DaphneWorker<'_> {
         media_type: &DapMediaType,
         _payload: &[u8],
     ) -> std::result::Result<DaphneWorkerAuth, DapError> {
-        // TODO Add support for authorizing the request with TLS client certificates:
-        // https://developers.cloudflare.com/workers/runtime-apis/mtls/
+        // Requests from the Leader for taskprov tasks are authorized with the method configured
+        // for the Leader. If this is TLS client auth, then the request is sent via the mTLS
+        // certificate binding. Otherwise, the request carries the task's bearer token.
+        //
+        // TODO Support TLS client auth for tasks that are not configured by taskprov. For now,
+        // the outbound authorization method can't be configured per task, so these always use
+        // the task's bearer token.
+        if let Some(ref taskprov_config) = self.config().taskprov {
+            if let Some(tls_client_auth) = taskprov_config.leader_auth.tls_client_auth()? {
+                let task_config = self.try_get_task_config(task_id).await?;
+                if matches!(media_type.sender(), Some(DapSender::Leader))
+                    && is_taskprov_task(
+                        self.config().global.taskprov_version,
+                        &taskprov_config.vdaf_verify_key_init,
+                        task_id,
+                        task_config.as_ref(),
+                    )
+                {
+                    return Ok(tls_client_auth);
+                }
+            }
+        }
+
         Ok(DaphneWorkerAuth::BearerToken(
             self.authorize_with_bearer_token(task_id, media_type)
-                .await?
-                .value()
-                .clone(),
+                .await?,
         ))
     }
 }
@@ -310,10 +572,9 @@ where
                             }
                         },
                         Some(sender) => {
-                            // DapAggregator::authorized() is only called on requests from senders
-                            // that require authoriztion. These include the Collector and the
-                            // Leader; currently the Client does not require authorization. If at
-                            // some point we need this, we would check it here.
+                            // TLS client authentication is only supported for the Collector and
+                            // the Leader. Clients that are required to authorize their uploads
+                            // must use a bearer token.
                             return Ok(Some(format!(
                                 "Request denied from unexpected sender ({sender:?})."
                             )));
@@ -328,9 +589,12 @@ where
                     if cert_issuer != valid_cert_issuer
                         || !valid_cert_subjects.contains(cert_subject)
                     {
-                        debug!("issuer is '{cert_issuer}'; expected '{valid_cert_issuer}'");
                         debug!(
-                            "subject is '{cert_subject}'; expected one of {valid_cert_subjects:?}"
+                            cert_issuer = %cert_issuer,
+                            valid_cert_issuer = %valid_cert_issuer,
+                            cert_subject = %cert_subject,
+                            ?valid_cert_subjects,
+                            "unexpected issuer or subject in TLS client certificate"
                         );
                         return Ok(Some("Request denied due to unexpected subject or issuer in TLS client certificate.".into()));
                     }
@@ -355,10 +619,13 @@ where
 
     fn taskprov_opt_out_reason(
         &self,
-        _task_config: &DapTaskConfig,
+        task_config: &DapTaskConfig,
     ) -> std::result::Result<Option<String>, DapError> {
-        // For now we always opt-in.
-        Ok(None)
+        Ok(self
+            .config()
+            .taskprov
+            .as_ref()
+            .and_then(|taskprov| taskprov.policy.opt_out_reason(task_config)))
     }
 
     /// Get an existing task (whether an ordinary task or a previously created
@@ -373,7 +640,7 @@ where
         let found = self
             .get_task_config(task_id.clone())
             .await
-            .map_err(dap_err)?;
+            .map_err(dap_err_in("get_task_config_considering_taskprov"))?;
         if found.is_some() {
             return Ok(found);
         }
@@ -421,6 +688,45 @@ where
                 }));
             }
 
+            // If configured, keep the task in memory only. Each isolate provisions the task on its
+            // own, so there is no need to serialize first sightings.
+            if taskprov.in_memory {
+                if let DaphneWorkerAuthMethod::BearerToken(ref leader_bearer_token) =
+                    taskprov.leader_auth
+                {
+                    self.pin_leader_bearer_token(
+                        &taskprov_task_id,
+                        leader_bearer_token,
+                        task_config.expiration,
+                    )
+                    .map_err(dap_err_in("get_task_config_considering_taskprov"))?;
+                }
+                self.pin_task_config(&taskprov_task_id, &task_config)
+                    .map_err(dap_err_in("get_task_config_considering_taskprov"))?;
+                return self
+                    .get_task_config(Cow::Owned(taskprov_task_id))
+                    .await
+                    .map_err(dap_err_in("get_task_config_considering_taskprov"));
+            }
+
+            // Concurrent first sightings of the task in this isolate are serialized so that the
+            // task is only written to KV once. If another request is already provisioning the
+            // task, then wait for it to finish and use the task config it cached.
+            let provisioning = self
+                .isolate_state()
+                .taskprov_provisioning
+                .acquire(taskprov_task_id.clone())
+                .await;
+            if provisioning.waited() {
+                let found = self
+                    .get_task_config(Cow::Owned(taskprov_task_id.clone()))
+                    .await
+                    .map_err(dap_err_in("get_task_config_considering_taskprov"))?;
+                if found.is_some() {
+                    return Ok(found);
+                }
+            }
+
             // Write the leader bearer token to the KV.  We do this so authorize_with_bearer_token()
             // finds something.
             //
@@ -431,7 +737,7 @@ where
             {
                 self.set_leader_bearer_token(&taskprov_task_id, leader_bearer_token)
                     .await
-                    .map_err(dap_err)?;
+                    .map_err(dap_err_in("get_task_config_considering_taskprov"))?;
             }
 
             // Write the task config to the KV.
@@ -440,25 +746,32 @@ where
             // need collection at some point.
             self.set_task_config(&taskprov_task_id, &task_config)
                 .await
-                .map_err(dap_err)?;
+                .map_err(dap_err_in("get_task_config_considering_taskprov"))?;
+            drop(provisioning);
 
-            // Do the usual get again so we cache and return the right type.
+            // Get the task config again in order to return the right type. The config was cached by
+            // `set_task_config()`, so this doesn't hit KV.
             self.get_task_config(Cow::Owned(taskprov_task_id))
                 .await
-                .map_err(dap_err)
+                .map_err(dap_err_in("get_task_config_considering_taskprov"))
         } else {
             Ok(None)
         }
     }
 
     fn get_current_time(&self) -> u64 {
-        now()
+        self.current_time()
+    }
+
+    fn get_current_time_millis(&self) -> u64 {
+        Date::now().as_millis()
     }
 
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        collect_id: Option<&CollectionJobId>,
+        batch_span: &DapBatchSpan<'_>,
     ) -> std::result::Result<bool, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
 
@@ -467,17 +780,24 @@ where
         // shares that have already been marked collected.
         let durable = self.durable();
         let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
+        for bucket in batch_span.buckets() {
             let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.get(
+                AggStoreName::new(&task_config.as_ref().version, &task_id.to_hex(), bucket);
+            requests.push(durable.post(
                 BINDING_DAP_AGGREGATE_STORE,
                 DURABLE_AGGREGATE_STORE_CHECK_COLLECTED,
                 durable_name,
+                &collect_id,
             ));
         }
 
-        let responses: Vec<bool> = try_join_all(requests).await.map_err(dap_err)?;
+        let responses: Vec<bool> =
+            self.try_join_all_durable(requests)
+                .await
+                .map_err(durable_err_in(
+                    "is_batch_overlapping",
+                    BINDING_DAP_AGGREGATE_STORE,
+                ))?;
 
         for collected in responses {
             if collected {
@@ -500,14 +820,14 @@ where
             .get(
                 BINDING_DAP_AGGREGATE_STORE,
                 DURABLE_AGGREGATE_STORE_GET,
-                durable_name_agg_store(
+                AggStoreName::new(
                     &task_config.as_ref().version,
                     &task_id.to_hex(),
                     &DapBatchBucket::FixedSize { batch_id },
                 ),
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in("batch_exists", BINDING_DAP_AGGREGATE_STORE))?;
 
         Ok(!agg_share.empty())
     }
@@ -522,53 +842,100 @@ where
 
         let durable = self.durable();
         let mut requests = Vec::new();
-        for (bucket, agg_share) in task_config
+        for (bucket, (agg_share_delta, report_checksums)) in task_config
             .as_ref()
-            .batch_span_for_out_shares(part_batch_sel, out_shares)?
+            .batch_span_for_out_shares_with_checksums(part_batch_sel, out_shares)?
         {
             let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.post::<_, ()>(
+                AggStoreName::new(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
+            requests.push(durable.post::<_, _, AggregateStoreMergeResult>(
                 BINDING_DAP_AGGREGATE_STORE,
                 DURABLE_AGGREGATE_STORE_MERGE,
                 durable_name,
-                agg_share,
+                AggregateStoreMergeReq {
+                    agg_share_delta,
+                    report_checksums: report_checksums.iter().map(hex::encode).collect(),
+                },
             ));
         }
-        try_join_all(requests).await.map_err(dap_err)?;
+
+        // A report overlap means that a report got past replay protection and was aggregated by
+        // two different aggregation jobs, which is an invariant violation rather than an expected
+        // failure. The merges into the other buckets have been committed by then and can't be
+        // undone, so a retry of the aggregation job fails the same way. We don't try to recover;
+        // instead the overlapping reports are counted so that the violation is visible, and the
+        // job fails with a fatal error.
+        let overlap_count: usize = self
+            .try_join_all_durable(requests)
+            .await
+            .map_err(durable_err_in(
+                "put_out_shares",
+                BINDING_DAP_AGGREGATE_STORE,
+            ))?
+            .into_iter()
+            .map(|res| match res {
+                AggregateStoreMergeResult::ErrReportOverlap(overlap) => overlap.len(),
+                AggregateStoreMergeResult::Ok | AggregateStoreMergeResult::AlreadyMerged => 0,
+            })
+            .sum();
+        if overlap_count > 0 {
+            let role = if self.config().is_leader {
+                DaphneRole::Leader
+            } else {
+                DaphneRole::Helper
+            };
+            self.metrics()
+                .with_host(&self.state.host)
+                .with_role(role)
+                .report_inc_by("merge_overlap", overlap_count as u64);
+            return Err(DapError::Fatal(format!(
+                "aggregate store: merge would count {overlap_count} report(s) twice"
+            )));
+        }
         Ok(())
     }
 
     async fn get_agg_share(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        batch_span: &DapBatchSpan<'_>,
     ) -> std::result::Result<DapAggregateShare, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
 
         let durable = self.durable();
-        let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
+        let requests = stream::iter(batch_span.buckets()).map(|bucket| {
             let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.get(
+                AggStoreName::new(&task_config.as_ref().version, &task_id.to_hex(), bucket);
+            durable.get::<_, DapAggregateShare>(
                 BINDING_DAP_AGGREGATE_STORE,
                 DURABLE_AGGREGATE_STORE_GET,
                 durable_name,
-            ));
-        }
-        let responses: Vec<DapAggregateShare> = try_join_all(requests).await.map_err(dap_err)?;
-        let mut agg_share = DapAggregateShare::default();
-        for agg_share_delta in responses {
-            agg_share.merge(agg_share_delta)?;
-        }
+            )
+        });
 
-        Ok(agg_share)
+        // Fold each bucket's aggregate share into the result as soon as it arrives rather than
+        // waiting for all of them, so that large batches don't hold every share in memory at once.
+        // Merging is commutative, so the order in which the responses arrive doesn't matter.
+        //
+        // Buckets in which no reports were aggregated yield an empty aggregate share, which does
+        // not contribute to the result.
+        requests
+            .buffer_unordered(self.config().max_concurrent_durable_requests)
+            .map_err(durable_err_in("get_agg_share", BINDING_DAP_AGGREGATE_STORE))
+            .try_fold(
+                DapAggregateShare::default(),
+                |mut agg_share, agg_share_delta| async move {
+                    agg_share.merge(agg_share_delta)?;
+                    Ok(agg_share)
+                },
+            )
+            .await
     }
 
     async fn check_early_reject<'b>(
         &self,
         task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
         part_batch_sel: &'b PartialBatchSelector,
         report_meta: impl Iterator<Item = &'b ReportMetadata>,
     ) -> std::result::Result<HashMap<ReportId, TransitionFailure>, DapError> {
@@ -580,11 +947,12 @@ where
             .batch_span_for_meta(part_batch_sel, report_meta)?;
 
         // Coalesce reports pertaining to the same ReportsProcessed or AggregateStore instance.
-        let mut reports_processed_request_data: HashMap<String, Vec<String>> = HashMap::new();
+        let mut reports_processed_request_data: HashMap<ReportStoreName, Vec<String>> =
+            HashMap::new();
         let mut agg_store_request_name = Vec::new();
         let mut agg_store_request_bucket = Vec::new();
         for (bucket, report_meta) in span.iter() {
-            agg_store_request_name.push(durable_name_agg_store(
+            agg_store_request_name.push(AggStoreName::new(
                 &task_config.as_ref().version,
                 &task_id_hex,
                 bucket,
@@ -606,12 +974,16 @@ where
 
         // Send ReportsProcessed requests.
         let mut reports_processed_requests = Vec::new();
+        let agg_job_id_base64url = agg_job_id.to_base64url();
         for (durable_name, report_id_hex_set) in reports_processed_request_data.into_iter() {
             reports_processed_requests.push(durable.post(
                 BINDING_DAP_REPORTS_PROCESSED,
                 DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
                 durable_name,
-                report_id_hex_set,
+                ReportsProcessedMarkAggregatedRequest {
+                    agg_job_id_base64url: agg_job_id_base64url.clone(),
+                    report_id_hex_set,
+                },
             ));
         }
 
@@ -626,10 +998,13 @@ where
         }
 
         // Create the set of reports that have been processed.
-        let reports_processed_responses: Vec<Vec<String>> =
-            try_join_all(reports_processed_requests)
-                .await
-                .map_err(dap_err)?;
+        let reports_processed_responses: Vec<Vec<String>> = self
+            .try_join_all_durable(reports_processed_requests)
+            .await
+            .map_err(durable_err_in(
+                "check_early_reject",
+                BINDING_DAP_REPORTS_PROCESSED,
+            ))?;
         let mut reports_processed = HashSet::new();
         for response in reports_processed_responses.into_iter() {
             for report_id_hex in response.into_iter() {
@@ -638,8 +1013,13 @@ where
             }
         }
 
-        let agg_store_responses: Vec<bool> =
-            try_join_all(agg_store_requests).await.map_err(dap_err)?;
+        let agg_store_responses: Vec<bool> = self
+            .try_join_all_durable(agg_store_requests)
+            .await
+            .map_err(durable_err_in(
+                "check_early_reject",
+                BINDING_DAP_AGGREGATE_STORE,
+            ))?;
 
         // Decide which reports to reject early. A report will be rejected here if, for example,
         // it has been processed but not collected, or if it has not been proceessed but pertains
@@ -669,24 +1049,55 @@ where
     async fn mark_collected(
         &self,
         task_id: &TaskId,
-        batch_sel: &BatchSelector,
+        collect_id: &CollectionJobId,
+        batch_span: &DapBatchSpan<'_>,
     ) -> std::result::Result<(), DapError> {
+        // Check every bucket before marking any, so that a batch that overlaps a collected batch
+        // leaves the remaining buckets available for collection.
+        if self
+            .is_batch_overlapping(task_id, Some(collect_id), batch_span)
+            .await?
+        {
+            return Err(DapError::Abort(DapAbort::batch_overlap(
+                task_id,
+                batch_span.batch_sel(),
+            )));
+        }
+
         let task_config = self.try_get_task_config(task_id).await?;
 
         let durable = self.durable();
         let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
+        for bucket in batch_span.buckets() {
             let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.post::<_, ()>(
+                AggStoreName::new(&task_config.as_ref().version, &task_id.to_hex(), bucket);
+            requests.push(durable.post::<_, _, bool>(
                 BINDING_DAP_AGGREGATE_STORE,
                 DURABLE_AGGREGATE_STORE_MARK_COLLECTED,
                 durable_name,
-                &(),
+                collect_id,
             ));
         }
 
-        try_join_all(requests).await.map_err(dap_err)?;
+        // If another collect job collected any bucket in the batch since the check above, then
+        // this request must not succeed. Each bucket is checked and marked atomically, so at most
+        // one of the requests succeeds.
+        let responses: Vec<bool> =
+            self.try_join_all_durable(requests)
+                .await
+                .map_err(durable_err_in(
+                    "mark_collected",
+                    BINDING_DAP_AGGREGATE_STORE,
+                ))?;
+        if responses
+            .into_iter()
+            .any(|already_collected| already_collected)
+        {
+            return Err(DapError::Abort(DapAbort::batch_overlap(
+                task_id,
+                batch_span.batch_sel(),
+            )));
+        }
         Ok(())
     }
 
@@ -714,6 +1125,65 @@ where
         let task_config = self.try_get_task_config(task_id).await?;
         let task_id_hex = task_id.to_hex();
         let version = task_config.as_ref().version;
+
+        // Throttle uploads for the task, if configured.
+        if let Some(ref limit) = task_config.as_ref().upload_rate_limit {
+            let res: UploadRateLimiterResult = self
+                .durable()
+                .post(
+                    BINDING_DAP_UPLOAD_RATE_LIMITER,
+                    DURABLE_UPLOAD_RATE_LIMITER_TAKE,
+                    TaskName::new(&version, &task_id_hex),
+                    &UploadRateLimiterRequest {
+                        limit: limit.clone(),
+                        task_expiration: task_config.as_ref().expiration,
+                    },
+                )
+                .await
+                .map_err(durable_err_in(
+                    "put_report",
+                    BINDING_DAP_UPLOAD_RATE_LIMITER,
+                ))?;
+            if let UploadRateLimiterResult::ErrRetryAfter(retry_after) = res {
+                return Err(DapError::RateLimited { retry_after });
+            }
+        }
+
+        // Reject reports that reuse a recently seen HPKE encapsulated key, if configured. The key
+        // is only recorded once the report has been stored, so that a report that fails to be
+        // stored can be uploaded again.
+        let hpke_enc_replay_cache_req = match (
+            self.config().hpke_enc_replay_cache_ttl,
+            report.encrypted_input_shares.first(),
+        ) {
+            (Some(..), Some(ciphertext)) => Some(HpkeEncReplayCacheRequest {
+                enc_hex: hex::encode(&ciphertext.enc),
+                task_expiration: task_config.as_ref().expiration,
+            }),
+            _ => None,
+        };
+        if let Some(ref hpke_enc_replay_cache_req) = hpke_enc_replay_cache_req {
+            let res: HpkeEncReplayCacheResult = self
+                .durable()
+                .post(
+                    BINDING_DAP_HPKE_ENC_REPLAY_CACHE,
+                    DURABLE_HPKE_ENC_REPLAY_CACHE_CHECK,
+                    TaskName::new(&version, &task_id_hex),
+                    hpke_enc_replay_cache_req,
+                )
+                .await
+                .map_err(durable_err_in(
+                    "put_report",
+                    BINDING_DAP_HPKE_ENC_REPLAY_CACHE,
+                ))?;
+            if let HpkeEncReplayCacheResult::ErrReplayed = res {
+                return Err(DapError::Abort(DapAbort::ReportRejected {
+                    detail: "A report with the same HPKE encapsulated key was uploaded previously."
+                        .into(),
+                }));
+            }
+        }
+
         let pending_report = PendingReport {
             version,
             task_id: task_id.clone(),
@@ -732,10 +1202,37 @@ where
                 &pending_report,
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in("put_report", BINDING_DAP_REPORTS_PENDING))?;
 
         match res {
-            ReportsPendingResult::Ok => Ok(()),
+            ReportsPendingResult::Ok => {
+                // The report is stored, so record its encapsulated key. Concurrent uploads that
+                // reuse the same key may both get this far, as the replay cache is not definitive.
+                // If recording fails, then the report is accepted anyway: a retry by the Client
+                // would be rejected as a replay.
+                if let Some(hpke_enc_replay_cache_req) = hpke_enc_replay_cache_req {
+                    if let Err(e) = self
+                        .durable()
+                        .post::<_, _, HpkeEncReplayCacheResult>(
+                            BINDING_DAP_HPKE_ENC_REPLAY_CACHE,
+                            DURABLE_HPKE_ENC_REPLAY_CACHE_PUT,
+                            TaskName::new(&version, &task_id_hex),
+                            &hpke_enc_replay_cache_req,
+                        )
+                        .await
+                    {
+                        warn!("put_report: failed to record HPKE encapsulated key: {e}");
+                    }
+                }
+
+                // The count is persisted in the background once the response has been computed.
+                if self.config().reports_ingested_flush_interval.is_some() {
+                    self.isolate_state()
+                        .reports_ingested
+                        .record(COUNTER_REPORTS_INGESTED.into(), 1);
+                }
+                Ok(())
+            }
             ReportsPendingResult::ErrReportExists => {
                 // NOTE This check for report replay is not definitive. It's possible for two
                 // reports with the same ID to appear in two different ReportsPending instances.
@@ -744,6 +1241,9 @@ where
                 // would be too expensive to do during the upload sub-protocol.
                 Err(DapError::Transition(TransitionFailure::ReportReplayed))
             }
+            ReportsPendingResult::AtCapacity { retry_after } => {
+                Err(DapError::RateLimited { retry_after })
+            }
         }
     }
 
@@ -762,74 +1262,138 @@ where
             .post(
                 BINDING_DAP_LEADER_AGG_JOB_QUEUE,
                 DURABLE_LEADER_AGG_JOB_QUEUE_GET,
-                durable_name_queue(0),
+                QueueName::new(0),
                 &report_sel.max_agg_jobs,
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in(
+                "get_reports",
+                BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+            ))?;
 
         // Drain at most `report_sel.max_reports` from each ReportsPending instance and group them
-        // by task.
+        // by task. Each instance holds reports for a single task; once the task is known, keep
+        // draining the instance if the task's weight permits. Stop once the budget for all
+        // instances is exhausted.
         //
         // TODO Figure out if we can safely handle each instance in parallel.
         let mut reports_per_task: HashMap<TaskId, Vec<Report>> = HashMap::new();
+        let mut budget = report_sel.report_budget();
         for reports_pending_id_hex in res.into_iter() {
-            let reports_from_durable: Vec<PendingReport> = durable
-                .post_by_id_hex(
-                    BINDING_DAP_REPORTS_PENDING,
-                    DURABLE_REPORTS_PENDING_GET,
-                    reports_pending_id_hex,
-                    &report_sel.max_reports,
-                )
-                .await
-                .map_err(dap_err)?;
-
-            for pending_report in reports_from_durable {
-                let report_bytes = hex::decode(&pending_report.report_hex).map_err(|_| {
-                    DapError::fatal("response from ReportsPending is not valid hex")
-                })?;
-
-                let version = self
-                    .try_get_task_config(&pending_report.task_id)
-                    .await?
-                    .as_ref()
-                    .version;
-                let report = Report::get_decoded_with_param(&version, &report_bytes)?;
-                if let Some(reports) = reports_per_task.get_mut(&pending_report.task_id) {
-                    reports.push(report);
-                } else {
-                    reports_per_task.insert(pending_report.task_id.clone(), vec![report]);
+            let mut task_id = None;
+            let mut drained = 0;
+            let mut limit = report_sel.max_reports.min(budget);
+            while limit > 0 {
+                let reports_from_durable: Vec<PendingReport> = durable
+                    .post_by_id_hex(
+                        BINDING_DAP_REPORTS_PENDING.as_str(),
+                        DURABLE_REPORTS_PENDING_GET,
+                        reports_pending_id_hex.clone(),
+                        &limit,
+                    )
+                    .await
+                    .map_err(durable_err_in("get_reports", BINDING_DAP_REPORTS_PENDING))?;
+                let num_drained = reports_from_durable.len() as u64;
+                drained += num_drained;
+                budget = budget.saturating_sub(num_drained);
+
+                for pending_report in reports_from_durable {
+                    let report_bytes = hex::decode(&pending_report.report_hex).map_err(|_| {
+                        DapError::fatal("response from ReportsPending is not valid hex")
+                    })?;
+
+                    // The version is trusted: it is copied from the task config by `put_report()`
+                    // when the report is stored, and ReportsPending is only written by us.
+                    let version = pending_report.version;
+                    if version == DapVersion::Unknown {
+                        return Err(DapError::fatal(
+                            "response from ReportsPending has an unknown version",
+                        ));
+                    }
+                    let report = Report::get_decoded_with_param(&version, &report_bytes)?;
+                    if let Some(reports) = reports_per_task.get_mut(&pending_report.task_id) {
+                        reports.push(report);
+                    } else {
+                        reports_per_task.insert(pending_report.task_id.clone(), vec![report]);
+                    }
+                    task_id.get_or_insert(pending_report.task_id);
+                }
+
+                // The instance is empty.
+                if num_drained < limit {
+                    break;
                 }
+
+                limit = match task_id {
+                    Some(ref task_id) => report_sel
+                        .max_reports_for_task(task_id)
+                        .saturating_sub(drained)
+                        .min(budget),
+                    None => 0,
+                };
             }
         }
 
         let mut reports_per_task_part: HashMap<TaskId, HashMap<PartialBatchSelector, Vec<Report>>> =
             HashMap::new();
+        let now = self.get_current_time();
         for (task_id, mut reports) in reports_per_task.into_iter() {
-            let task_config = self
-                .get_task_config(Cow::Owned(task_id))
-                .await
-                .map_err(dap_err)?
-                .ok_or_else(|| DapError::fatal("unrecognized task"))?;
+            let task_config = self.try_get_task_config(&task_id).await?;
             let task_id_hex = task_config.key().to_hex();
+
+            // Drop reports that have been pending for too long. They have already been drained
+            // from ReportsPending, so this frees their storage. For fixed-size tasks, this happens
+            // before the remaining reports are assigned to batches by LeaderBatchQueue, so the
+            // batch counts never include dropped reports.
+            let num_drained = reports.len();
+            reports.retain(|report| {
+                !task_config.as_ref().is_pending_report_expired(
+                    &self.config().global,
+                    report.report_metadata.time,
+                    now,
+                )
+            });
+            let num_expired = (num_drained - reports.len()) as u64;
+            if num_expired > 0 {
+                info!(
+                    "task {}: dropped {num_expired} expired pending reports",
+                    task_id.to_base64url()
+                );
+                self.metrics()
+                    .with_host(&self.state.host)
+                    .with_role(DaphneRole::Leader)
+                    .report_inc_by("expired", num_expired);
+            }
+            if reports.is_empty() {
+                continue;
+            }
+
             let reports_per_part = reports_per_task_part
                 .entry(task_config.key().clone())
                 .or_default();
             match task_config.as_ref().query {
-                DapQueryConfig::TimeInterval => {
+                DapQueryConfig::TimeInterval { .. } => {
                     reports_per_part.insert(PartialBatchSelector::TimeInterval, reports);
                 }
-                DapQueryConfig::FixedSize { .. } => {
+                DapQueryConfig::FixedSize { max_batch_size } => {
+                    let min_batch_size = task_config.as_ref().min_batch_size;
+                    let bounds = BatchSizeBounds {
+                        min: min_batch_size as usize,
+                        max: max_batch_size.max(min_batch_size) as usize,
+                    };
                     let num_unassigned = reports.len();
                     let batch_assignments: Vec<BatchCount> = durable
                         .post(
                             BINDING_DAP_LEADER_BATCH_QUEUE,
                             DURABLE_LEADER_BATCH_QUEUE_ASSIGN,
-                            durable_name_task(&task_config.as_ref().version, &task_id_hex),
-                            &(task_config.as_ref().min_batch_size, num_unassigned),
+                            TaskName::new(&task_config.as_ref().version, &task_id_hex),
+                            &(bounds, num_unassigned),
                         )
                         .await
-                        .map_err(dap_err)?;
+                        .map_err(durable_err_in(
+                            "get_reports",
+                            BINDING_DAP_LEADER_BATCH_QUEUE,
+                        ))?;
                     for batch_count in batch_assignments.into_iter() {
                         let BatchCount {
                             batch_id,
@@ -856,9 +1420,9 @@ where
                 report_count += reports.len();
             }
             debug!(
-                "got {} reports for task {}",
+                task_id = %task_id.to_base64url(),
                 report_count,
-                task_id.to_base64url()
+                "got reports for task"
             );
         }
         Ok(reports_per_task_part)
@@ -878,17 +1442,30 @@ where
             task_id: task_id.clone(),
             collect_job_id: collect_job_id.clone(),
         };
-        let collect_id: CollectionJobId = self
+        let res: CollectQueuePutResult = self
             .durable()
             .post(
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_PUT,
-                durable_name_queue(0),
+                QueueName::new(0),
                 &collect_queue_req,
             )
             .await
-            .map_err(dap_err)?;
-        debug!("assigned collect_id {collect_id}");
+            .map_err(durable_err_in(
+                "init_collect_job",
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+            ))?;
+        let collect_id = match res {
+            CollectQueuePutResult::Ok(collect_id) => collect_id,
+            CollectQueuePutResult::AtCapacity { retry_after } => {
+                return Err(DapError::RateLimited { retry_after })
+            }
+        };
+        debug!(
+            task_id = %task_id.to_base64url(),
+            collect_id = %collect_id.to_base64url(),
+            "assigned collect_id"
+        );
 
         let url = task_config.as_ref().leader_url.clone();
 
@@ -914,11 +1491,14 @@ where
             .post(
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT,
-                durable_name_queue(0),
+                QueueName::new(0),
                 (&task_id, &collect_id),
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in(
+                "poll_collect_job",
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+            ))?;
         Ok(res)
     }
 
@@ -930,10 +1510,13 @@ where
             .get(
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_GET,
-                durable_name_queue(0),
+                QueueName::new(0),
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in(
+                "get_pending_collect_jobs",
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+            ))?;
         Ok(res)
     }
 
@@ -952,22 +1535,48 @@ where
                 .post(
                     BINDING_DAP_LEADER_BATCH_QUEUE,
                     DURABLE_LEADER_BATCH_QUEUE_REMOVE,
-                    durable_name_task(&task_config.as_ref().version, &task_id.to_hex()),
+                    TaskName::new(&task_config.as_ref().version, &task_id.to_hex()),
                     batch_id.to_hex(),
                 )
                 .await
-                .map_err(dap_err)?;
+                .map_err(durable_err_in(
+                    "finish_collect_job",
+                    BINDING_DAP_LEADER_BATCH_QUEUE,
+                ))?;
         }
 
         durable
             .post(
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_FINISH,
-                durable_name_queue(0),
+                QueueName::new(0),
                 (task_id, collect_id, collect_resp),
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in(
+                "finish_collect_job",
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+            ))?;
+        Ok(())
+    }
+
+    async fn abandon_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> std::result::Result<(), DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_ABANDON,
+                QueueName::new(0),
+                (task_id, collect_id),
+            )
+            .await
+            .map_err(durable_err_in(
+                "abandon_collect_job",
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+            ))?;
         Ok(())
     }
 
@@ -975,14 +1584,16 @@ where
         &self,
         req: DapRequest<DaphneWorkerAuth>,
     ) -> std::result::Result<DapResponse, DapError> {
-        self.send_http(req, false).await
+        self.send_http(req, false, self.config().helper_request_timeout)
+            .await
     }
 
     async fn send_http_put(
         &self,
         req: DapRequest<DaphneWorkerAuth>,
     ) -> std::result::Result<DapResponse, DapError> {
-        self.send_http(req, true).await
+        self.send_http(req, true, self.config().helper_request_timeout)
+            .await
     }
 }
 
@@ -998,16 +1609,31 @@ where
         helper_state: &DapHelperState,
     ) -> std::result::Result<(), DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
-        let helper_state_hex = hex::encode(helper_state.get_encoded(&task_config.as_ref().vdaf)?);
+        let durable_name = HelperStateName::new(&task_config.as_ref().version, task_id, agg_job_id);
+        let helper_state_data = helper_state.get_encoded(&task_config.as_ref().vdaf)?;
+        let compact = self.config().helper_state_compact_encoding;
+        let helper_state_blob = match self.config().helper_state_encryption_keys {
+            // The first key is used for encryption. The ciphertext is bound to the DO instance.
+            Some(ref keys) => seal_helper_state(
+                &keys[0],
+                durable_name.as_str().as_bytes(),
+                &helper_state_data,
+                compact,
+            )?,
+            None => encode_helper_state_blob(&helper_state_data, compact),
+        };
         self.durable()
             .post(
                 BINDING_DAP_HELPER_STATE_STORE,
                 DURABLE_HELPER_STATE_PUT,
-                durable_helper_state_name(&task_config.as_ref().version, task_id, agg_job_id),
-                helper_state_hex,
+                durable_name,
+                helper_state_blob,
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in(
+                "put_helper_state",
+                BINDING_DAP_HELPER_STATE_STORE,
+            ))?;
         Ok(())
     }
 
@@ -1017,21 +1643,30 @@ where
         agg_job_id: &MetaAggregationJobId,
     ) -> std::result::Result<Option<DapHelperState>, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
+        let durable_name = HelperStateName::new(&task_config.as_ref().version, task_id, agg_job_id);
         let res: Option<String> = self
             .durable()
             .post(
                 BINDING_DAP_HELPER_STATE_STORE,
                 DURABLE_HELPER_STATE_GET,
-                durable_helper_state_name(&task_config.as_ref().version, task_id, agg_job_id),
+                durable_name.clone(),
                 (),
             )
             .await
-            .map_err(dap_err)?;
+            .map_err(durable_err_in(
+                "get_helper_state",
+                BINDING_DAP_HELPER_STATE_STORE,
+            ))?;
 
         match res {
-            Some(helper_state_hex) => {
+            Some(helper_state_blob) => {
+                let keys = self
+                    .config()
+                    .helper_state_encryption_keys
+                    .as_deref()
+                    .unwrap_or_default();
                 let data =
-                    hex::decode(helper_state_hex).map_err(|e| DapError::Fatal(e.to_string()))?;
+                    open_helper_state(keys, durable_name.as_str().as_bytes(), &helper_state_blob)?;
                 let helper_state = DapHelperState::get_decoded(&task_config.as_ref().vdaf, &data)?;
                 Ok(Some(helper_state))
             }
@@ -1039,3 +1674,40 @@ where
         }
     }
 }
+
+impl DaphneWorker<'_> {
+    /// Leader: Compute the value of the Retry-After header sent to a Collector polling a
+    /// collection job for the given task that is still pending. The base value is taken from the
+    /// task config, if set, and otherwise from the Daphne-Worker configuration. Returns `None` if
+    /// neither is set.
+    pub(crate) async fn collect_poll_retry_after(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<Option<u64>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let Some(base) = task_config
+            .as_ref()
+            .collect_poll_retry_after
+            .or_else(|| self.config().collect_poll_retry_after.map(|d| d.as_secs()))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(jittered_retry_after(
+            base,
+            self.config().collect_poll_retry_after_jitter.as_secs(),
+            &mut thread_rng(),
+        )))
+    }
+
+    /// Leader: Respond to a Collector polling a collection job for the given task that is still
+    /// pending.
+    pub(crate) async fn pending_collect_job_response(&self, task_id: &TaskId) -> Result<Response> {
+        let mut headers = Headers::new();
+        match self.collect_poll_retry_after(task_id).await {
+            Ok(Some(retry_after)) => headers.set("Retry-After", &retry_after.to_string())?,
+            Ok(None) => (),
+            Err(e) => return self.state.dap_abort_to_worker_response(e.into()),
+        }
+        Ok(Response::empty()?.with_status(202).with_headers(headers))
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::dap::{jittered_retry_after, ContentEncoding};
+use rand::thread_rng;
+
+#[test]
+fn content_encoding_negotiate() {
+    assert_eq!(
+        ContentEncoding::negotiate("gzip, deflate, br"),
+        Some(ContentEncoding::Brotli)
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("GZIP"),
+        Some(ContentEncoding::Gzip)
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("br;q=0, gzip;q=0.5"),
+        Some(ContentEncoding::Gzip)
+    );
+    assert_eq!(ContentEncoding::negotiate("*"), Some(ContentEncoding::Gzip));
+
+    // The encoding with the highest quality value is preferred.
+    assert_eq!(
+        ContentEncoding::negotiate("br;q=0.5, gzip"),
+        Some(ContentEncoding::Gzip)
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("br;q=0.1, *"),
+        Some(ContentEncoding::Gzip)
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("gzip;q=0.8, br;q=0.8"),
+        Some(ContentEncoding::Brotli)
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("gzip;q=0.2, *;q=0.9, br;q=0.5"),
+        Some(ContentEncoding::Brotli)
+    );
+
+    // No supported encoding is acceptable.
+    assert_eq!(ContentEncoding::negotiate("identity"), None);
+    assert_eq!(ContentEncoding::negotiate("gzip;q=0"), None);
+    assert_eq!(ContentEncoding::negotiate(""), None);
+}
+
+#[test]
+fn jittered_retry_after_within_bounds() {
+    let mut rng = thread_rng();
+    assert_eq!(jittered_retry_after(30, 0, &mut rng), 30);
+    for _ in 0..100 {
+        let retry_after = jittered_retry_after(30, 10, &mut rng);
+        assert!((30..=40).contains(&retry_after), "{retry_after}");
+    }
+    assert_eq!(jittered_retry_after(u64::MAX, 10, &mut rng), u64::MAX);
+}
@@ -3,35 +3,90 @@
 
 use crate::{
     config::DaphneWorkerConfig,
-    durable::{state_get_or_default, BINDING_DAP_AGGREGATE_STORE},
+    durable::{state_get, state_get_or_default, BINDING_DAP_AGGREGATE_STORE, MAX_KEYS},
     initialize_tracing, int_err,
 };
-use daphne::DapAggregateShare;
+use daphne::{messages::CollectionJobId, DapAggregateShare, DapError};
+use futures::future::{try_join, try_join_all};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
 use worker::*;
 
 pub(crate) const DURABLE_AGGREGATE_STORE_GET: &str = "/internal/do/aggregate_store/get";
+pub(crate) const DURABLE_AGGREGATE_STORE_COUNT: &str = "/internal/do/aggregate_store/count";
 pub(crate) const DURABLE_AGGREGATE_STORE_MERGE: &str = "/internal/do/aggregate_store/merge";
 pub(crate) const DURABLE_AGGREGATE_STORE_MARK_COLLECTED: &str =
     "/internal/do/aggregate_store/mark_collected";
 pub(crate) const DURABLE_AGGREGATE_STORE_CHECK_COLLECTED: &str =
     "/internal/do/aggregate_store/check_collected";
 
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct AggregateStoreMergeReq {
+    pub(crate) agg_share_delta: DapAggregateShare,
+
+    /// Hex-encoded checksums of the reports aggregated into `agg_share_delta`.
+    pub(crate) report_checksums: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AggregateStoreMergeResult {
+    /// The aggregate share was merged.
+    Ok,
+
+    /// Every report was already included in the aggregate share, so the merge was ignored. This
+    /// happens when an aggregation job is retried.
+    AlreadyMerged,
+
+    /// Some of the reports were already included in the aggregate share, so merging would have
+    /// counted them twice. The merge was rejected. Carries the checksums of the offending reports.
+    ErrReportOverlap(Vec<String>),
+}
+
 /// Durable Object (DO) for storing aggregate shares for a bucket of reports.
 ///
 /// This object defines the following API endpoints:
 ///
 /// - `DURABLE_AGGREGATE_STORE_GET`: Return the current value of the aggregate share.
+/// - `DURABLE_AGGREGATE_STORE_COUNT`: Return the number of reports aggregated into the aggregate
+///   share, without the share itself.
 /// - `DURABLE_AGGREGATE_STORE_MERGE`: Update the aggregate share.
-/// - `DURABLE_AGGREGATE_STORE_MARK_COLLECTED`: Mark the bucket as having been collected.
+/// - `DURABLE_AGGREGATE_STORE_MARK_COLLECTED`: Mark the bucket as having been collected by a
+///   collect job. Returns a boolean indicating if the bucket was already collected by another job;
+///   since the check and the update happen in a single request, at most one of several concurrent
+///   jobs sees `false`. Marking the bucket again for the same job succeeds, so that the job can be
+///   retried.
 /// - `DURABLE_AGGREGATE_STORE_CHECK_COLLECTED`: Return a boolean indicating if the bucket has been
-///   collected.
+///   collected, optionally ignoring a collection by a given job.
 ///
 /// The schema for the data stored by this DO is as follows:
 ///
 /// ```text
-/// [Aggregate share] agg_share -> DapAggregateShare
-/// [Collected flag]  collected -> bool
+/// [Aggregate share]  agg_share -> DapAggregateShare
+/// [Collected flag]   collected -> bool
+/// [Collected by]     collected_by -> CollectionJobId
+/// [Report inclusion] report/<checksum> -> bool
 /// ```
+///
+/// where `<checksum>` is the hex-encoded checksum of a report that has been merged into the
+/// aggregate share. These are used to ensure that no report is counted twice. Like the report IDs
+/// stored by `ReportsProcessed`, they are deleted by the instance's alarm once the report storage
+/// epoch (plus a safety interval) has elapsed since the first merge, after which the reports can
+/// no longer be aggregated. The aggregate share and the collected flag are kept. Buckets marked
+/// collected before `collected_by` was stored are treated as collected by some other job.
+///
+/// Several aggregation jobs may merge into the same bucket at once. `DURABLE_AGGREGATE_STORE_MERGE`
+/// is a read-modify-write of `agg_share` that awaits nothing but storage operations, so the
+/// object's input gate keeps other requests from interleaving with it. Concurrent merges are
+/// therefore applied one after another, and none of them is lost.
+///
+/// Each instance holds a single aggregate share that every merge is folded into, so there is
+/// nothing to compact within an instance. Nor are instances combined: a batch interval may cover
+/// any subset of buckets, and each bucket has its own collected flag, so merging the state of
+/// adjacent buckets would lose information needed for collection. The number of instances a
+/// collection fans out to is instead controlled by the task's `bucket_duration`.
 #[durable_object]
 pub struct AggregateStore {
     #[allow(dead_code)]
@@ -39,6 +94,34 @@ pub struct AggregateStore {
     env: Env,
     config: DaphneWorkerConfig,
     touched: bool,
+    alarmed: bool,
+}
+
+impl AggregateStore {
+    /// Return the subset of the given report checksums that have already been merged.
+    async fn included_report_checksums(&self, report_checksums: &[String]) -> Result<Vec<String>> {
+        let mut requests = Vec::with_capacity(report_checksums.len());
+        for checksum_hex in report_checksums {
+            requests.push(async move {
+                let included: Option<bool> =
+                    state_get(&self.state, &format!("report/{checksum_hex}")).await?;
+                Ok::<_, worker::Error>(included.unwrap_or(false).then(|| checksum_hex.clone()))
+            });
+        }
+        let responses: Vec<Option<String>> = try_join_all(requests).await?;
+        Ok(responses.into_iter().flatten().collect())
+    }
+
+    /// Return whether the bucket has been collected by a job other than `collect_id`. If no job is
+    /// given, then any collection counts.
+    async fn collected_by_other(&self, collect_id: Option<&CollectionJobId>) -> Result<bool> {
+        let collected: bool = state_get_or_default(&self.state, "collected").await?;
+        if !collected {
+            return Ok(false);
+        }
+        let collected_by: Option<CollectionJobId> = state_get(&self.state, "collected_by").await?;
+        Ok(collect_id.is_none() || collected_by.as_ref() != collect_id)
+    }
 }
 
 #[durable_object]
@@ -52,6 +135,7 @@ impl DurableObject for AggregateStore {
             env,
             config,
             touched: false,
+            alarmed: false,
         }
     }
 
@@ -60,22 +144,57 @@ impl DurableObject for AggregateStore {
         ensure_garbage_collected!(req, self, id_hex, BINDING_DAP_AGGREGATE_STORE);
 
         match (req.path().as_ref(), req.method()) {
-            // Merge an aggregate share into the stored aggregate.
+            // Merge an aggregate share into the stored aggregate, unless doing so would count a
+            // report more than once.
             //
-            // Input: `agg_share_dellta: DapAggregateShare`
+            // Input: `AggregateStoreMergeReq`
+            // Output: `AggregateStoreMergeResult`
             (DURABLE_AGGREGATE_STORE_MERGE, Method::Post) => {
-                let agg_share_delta = req.json().await?;
+                let merge_req: AggregateStoreMergeReq = req.json().await?;
+                let included = self
+                    .included_report_checksums(&merge_req.report_checksums)
+                    .await?;
 
-                // To keep this pair of get and put operations atomic, there should be no await
-                // points between them. See the note below `transaction()` on
+                // The durable object's input gate ensures that no other request is processed
+                // while we wait on storage, so the reads above and the writes below happen as a
+                // unit. See the note below `transaction()` on
                 // https://developers.cloudflare.com/workers/runtime-apis/durable-objects/#transactional-storage-api.
                 // See issue #109.
+                //
+                // This only holds as long as nothing other than storage is awaited until the
+                // writes are done. In particular, do not make requests to other durable objects
+                // or to KV here, as this would allow a concurrent merge to read the same value of
+                // the aggregate share and overwrite this one.
                 let mut agg_share: DapAggregateShare =
                     state_get_or_default(&self.state, "agg_share").await?;
-                agg_share.merge(agg_share_delta).map_err(int_err)?;
-                self.state.storage().put("agg_share", agg_share).await?;
+                let report_checksums = merge_req.report_checksums.clone();
+                let res = merge_agg_share_checked(&mut agg_share, merge_req, included)
+                    .map_err(int_err)?;
+                if res == AggregateStoreMergeResult::Ok {
+                    // Issue all of the writes at once rather than awaiting each in turn. Writes
+                    // made without awaiting anything in between are coalesced by the runtime into
+                    // a single atomic batch. (`Storage::put_multiple()` can't be used here: it
+                    // serializes maps as JS `Map`s, which the runtime doesn't accept.)
+                    let state = &self.state;
+                    let agg_share_put = async { state.storage().put("agg_share", agg_share).await };
+                    let report_checksum_puts =
+                        try_join_all(report_checksums.iter().map(|checksum_hex| async move {
+                            state
+                                .storage()
+                                .put(&format!("report/{checksum_hex}"), true)
+                                .await
+                        }));
+                    try_join(agg_share_put, report_checksum_puts).await?;
 
-                Response::from_json(&())
+                    // Ensure the report checksums are eventually deleted.
+                    ensure_alarmed!(
+                        self,
+                        Duration::from_secs(self.config.global.report_storage_epoch_duration)
+                            .saturating_add(self.config.processed_alarm_safety_interval)
+                    );
+                }
+
+                Response::from_json(&res)
             }
 
             // Get the current aggregate share.
@@ -87,18 +206,46 @@ impl DurableObject for AggregateStore {
                 Response::from_json(&agg_share)
             }
 
-            // Mark this bucket as collected.
+            // Get the number of reports in the current aggregate share. This is cheaper than
+            // transferring the entire aggregate share when only the count is needed.
+            //
+            // Output: `u64`
+            (DURABLE_AGGREGATE_STORE_COUNT, Method::Get) => {
+                let agg_share: DapAggregateShare =
+                    state_get_or_default(&self.state, "agg_share").await?;
+                Response::from_json(&agg_share.report_count)
+            }
+
+            // Mark this bucket as collected by a collect job. The input gate ensures that no
+            // other request is processed between the read and the write.
+            //
+            // Input: `collect_id: CollectionJobId`
+            // Output: `bool` (indicates whether the bucket was already collected by another job)
             (DURABLE_AGGREGATE_STORE_MARK_COLLECTED, Method::Post) => {
-                self.state.storage().put("collected", true).await?;
-                Response::from_json(&())
+                let collect_id: CollectionJobId = req.json().await?;
+                let collected_by_other = self.collected_by_other(Some(&collect_id)).await?;
+                if !collected_by_other {
+                    self.state.storage().put("collected", true).await?;
+                    self.state
+                        .storage()
+                        .put("collected_by", &collect_id)
+                        .await?;
+                }
+                Response::from_json(&collected_by_other)
             }
 
-            // Get the value of the flag indicating whether this bucket has been collected
+            // Get the value of the flag indicating whether this bucket has been collected. If a
+            // collect job ID is provided, then a collection by that job is ignored.
             //
+            // Input (POST only): `collect_id: Option<CollectionJobId>`
             // Output: `bool`
-            (DURABLE_AGGREGATE_STORE_CHECK_COLLECTED, Method::Get) => {
-                let collected: bool = state_get_or_default(&self.state, "collected").await?;
-                Response::from_json(&collected)
+            (DURABLE_AGGREGATE_STORE_CHECK_COLLECTED, Method::Get | Method::Post) => {
+                let collect_id: Option<CollectionJobId> = if req.method() == Method::Post {
+                    req.json().await?
+                } else {
+                    None
+                };
+                Response::from_json(&self.collected_by_other(collect_id.as_ref()).await?)
             }
 
             _ => Err(int_err(format!(
@@ -108,4 +255,54 @@ impl DurableObject for AggregateStore {
             ))),
         }
     }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        // Delete the report checksums, a page at a time.
+        loop {
+            let opt = ListOptions::new().prefix("report/").limit(MAX_KEYS);
+            let keys: Vec<String> = self
+                .state
+                .storage()
+                .list_with_options(opt)
+                .await?
+                .keys()
+                .into_iter()
+                .map(|key| key.map(|key| key.as_string().unwrap_or_default()))
+                .collect::<std::result::Result<_, _>>()?;
+            if keys.is_empty() {
+                break;
+            }
+            self.state.storage().delete_multiple(keys).await?;
+        }
+        self.alarmed = false;
+        Response::from_json(&())
+    }
+}
+
+/// Merge `merge_req` into `agg_share`, given the subset `included` of the request's report
+/// checksums that have already been merged. The aggregate share is only updated if the result is
+/// `AggregateStoreMergeResult::Ok`.
+pub(crate) fn merge_agg_share_checked(
+    agg_share: &mut DapAggregateShare,
+    merge_req: AggregateStoreMergeReq,
+    included: Vec<String>,
+) -> std::result::Result<AggregateStoreMergeResult, DapError> {
+    let AggregateStoreMergeReq {
+        agg_share_delta,
+        report_checksums,
+    } = merge_req;
+    if agg_share_delta.report_count != report_checksums.len() as u64 {
+        return Err(DapError::fatal(
+            "aggregate share report count does not match the number of report checksums",
+        ));
+    }
+
+    if included.is_empty() {
+        agg_share.merge(agg_share_delta)?;
+        Ok(AggregateStoreMergeResult::Ok)
+    } else if included.len() == report_checksums.len() {
+        Ok(AggregateStoreMergeResult::AlreadyMerged)
+    } else {
+        Ok(AggregateStoreMergeResult::ErrReportOverlap(included))
+    }
 }
@@ -0,0 +1,97 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    config::DaphneWorkerConfig,
+    durable::{state_get_or_default, BINDING_DAP_COUNTERS},
+    initialize_tracing, int_err,
+};
+use std::collections::HashMap;
+use worker::*;
+
+pub(crate) const DURABLE_COUNTERS_MERGE: &str = "/internal/do/counters/merge";
+pub(crate) const DURABLE_COUNTERS_GET: &str = "/internal/do/counters/get";
+
+/// Durable Object (DO) for persisting a set of named counters across isolate restarts. There is
+/// one instance per set of counters; see `CountersName`.
+///
+/// This object defines the following API endpoints:
+///
+/// - `DURABLE_COUNTERS_MERGE`: Add a set of counts to the stored counts.
+/// - `DURABLE_COUNTERS_GET`: Return the stored counts.
+///
+/// The schema for the data stored by this DO is as follows:
+///
+/// ```text
+/// [Counts] counts -> HashMap<String, u64>
+/// ```
+#[durable_object]
+pub struct Counters {
+    #[allow(dead_code)]
+    state: State,
+    env: Env,
+    config: DaphneWorkerConfig,
+    touched: bool,
+}
+
+#[durable_object]
+impl DurableObject for Counters {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        let config =
+            DaphneWorkerConfig::from_worker_env(&env).expect("failed to load configuration");
+        Self {
+            state,
+            env,
+            config,
+            touched: false,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let id_hex = self.state.id().to_string();
+        ensure_garbage_collected!(req, self, id_hex, BINDING_DAP_COUNTERS);
+
+        match (req.path().as_ref(), req.method()) {
+            // Add counts to the stored counts.
+            //
+            // Input: `delta: HashMap<String, u64>` (counter -> count)
+            (DURABLE_COUNTERS_MERGE, Method::Post) => {
+                let delta: HashMap<String, u64> = req.json().await?;
+
+                // To keep this pair of get and put operations atomic, there should be no await
+                // points between them. See the note below `transaction()` on
+                // https://developers.cloudflare.com/workers/runtime-apis/durable-objects/#transactional-storage-api.
+                let mut counts: HashMap<String, u64> =
+                    state_get_or_default(&self.state, "counts").await?;
+                merge_counts(&mut counts, delta);
+                self.state.storage().put("counts", counts).await?;
+
+                Response::from_json(&())
+            }
+
+            // Get the stored counts.
+            //
+            // Output: `HashMap<String, u64>` (counter -> count)
+            (DURABLE_COUNTERS_GET, Method::Get) => {
+                let counts: HashMap<String, u64> =
+                    state_get_or_default(&self.state, "counts").await?;
+                Response::from_json(&counts)
+            }
+
+            _ => Err(int_err(format!(
+                "Counters: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+}
+
+/// Add `delta` to the counts in `counts`.
+pub(crate) fn merge_counts(counts: &mut HashMap<String, u64>, delta: HashMap<String, u64>) {
+    for (counter, count) in delta {
+        let total = counts.entry(counter).or_default();
+        *total = total.saturating_add(count);
+    }
+}
@@ -11,7 +11,8 @@ use worker::*;
 
 pub(crate) const DURABLE_GARBAGE_COLLECTOR_PUT: &str = "/internal/do/garbage_collector/put";
 
-/// Durable Object (DO) for keeping track of all persistent DO storage.
+/// Durable Object (DO) for keeping track of all persistent DO storage. It also answers pings from
+/// the health check.
 #[durable_object]
 pub struct GarbageCollector {
     #[allow(dead_code)]
@@ -32,20 +33,14 @@ impl DurableObject for GarbageCollector {
             // Schedule a durable object (DO) instance for deletion.
             (DURABLE_GARBAGE_COLLECTOR_PUT, Method::Post) => {
                 let durable_ref: DurableReference = req.json().await?;
-                match durable_ref.binding.as_ref() {
-                    durable::BINDING_DAP_REPORTS_PENDING
-                    | durable::BINDING_DAP_REPORTS_PROCESSED
-                    | durable::BINDING_DAP_AGGREGATE_STORE
-                    | durable::BINDING_DAP_LEADER_AGG_JOB_QUEUE
-                    | durable::BINDING_DAP_LEADER_BATCH_QUEUE
-                    | durable::BINDING_DAP_LEADER_COL_JOB_QUEUE
-                    | durable::BINDING_DAP_HELPER_STATE_STORE => (),
-                    s => {
-                        let message = format!("GarbageCollector: unrecognized binding: {s}");
-                        error!("{}", message);
-                        return Err(int_err(message));
-                    }
-                };
+                if !durable::STATE_BINDINGS.contains(&durable_ref.binding.as_str()) {
+                    let message = format!(
+                        "GarbageCollector: unrecognized binding: {}",
+                        durable_ref.binding
+                    );
+                    error!("{}", message);
+                    return Err(int_err(message));
+                }
 
                 let queued = DurableOrdered::new_roughly_ordered(durable_ref, "object");
                 queued.put(&self.state).await?;
@@ -57,6 +52,9 @@ impl DurableObject for GarbageCollector {
                 Response::from_json(&())
             }
 
+            // Respond without touching storage. This is used to check that DOs are reachable.
+            (durable::DURABLE_PING, Method::Get) => Response::from_json(&()),
+
             // Delete all DO instances.
             //
             // NOTE This method is likely to hit memory and/or time limits when run in a production
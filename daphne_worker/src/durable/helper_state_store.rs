@@ -2,23 +2,9 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{config::DaphneWorkerConfig, durable::state_get, initialize_tracing, int_err};
-use daphne::{messages::TaskId, DapVersion, MetaAggregationJobId};
 use tracing::{trace, warn};
 use worker::*;
 
-pub(crate) fn durable_helper_state_name(
-    version: &DapVersion,
-    task_id: &TaskId,
-    agg_job_id: &MetaAggregationJobId,
-) -> String {
-    format!(
-        "{}/task/{}/agg_job/{}",
-        version.as_ref(),
-        task_id.to_hex(),
-        agg_job_id.to_hex()
-    )
-}
-
 pub(crate) const DURABLE_HELPER_STATE_PUT: &str = "/internal/do/helper_state/put";
 pub(crate) const DURABLE_HELPER_STATE_GET: &str = "/internal/do/helper_state/get";
 
@@ -26,10 +12,11 @@ pub(crate) const DURABLE_HELPER_STATE_GET: &str = "/internal/do/helper_state/get
 ///
 /// This object implements the following API endpoints:
 ///
-/// - `DURABLE_HELPER_STATE_PUT`: Stores Helper's hex-encoded state.
-/// - `DURABLE_HELPER_STATE_GET`: Drains the Helper's hex-encoded state.
+/// - `DURABLE_HELPER_STATE_PUT`: Stores Helper's encoded state.
+/// - `DURABLE_HELPER_STATE_GET`: Drains the Helper's encoded state.
 ///
-/// The state blob is stored in `helper_state`.
+/// The state blob is stored in `helper_state`. The blob is encoded, and encrypted if encryption at
+/// rest is configured, before it is sent to this DO (see `helper_state_encryption`).
 #[durable_object]
 pub struct HelperStateStore {
     state: State,
@@ -62,27 +49,27 @@ impl DurableObject for HelperStateStore {
         match (req.path().as_ref(), req.method()) {
             // Store the Helper's state.
             //
-            // Input: `helper_state_hex: String` (hex-encoded state)
+            // Input: `helper_state_blob: String` (encoded state)
             (DURABLE_HELPER_STATE_PUT, Method::Post) => {
-                // The state is handled as an opaque hex string.
-                let mut helper_state_hex: Option<String> =
+                // The state is handled as an opaque string.
+                let mut helper_state_blob: Option<String> =
                     state_get(&self.state, "helper_state").await?;
-                if helper_state_hex.is_some() {
+                if helper_state_blob.is_some() {
                     // TODO spec: Handle this as an abort rather than an internal error.
                     return Err(int_err("tried to overwrite helper state"));
                 }
 
-                helper_state_hex = req.json().await?;
+                helper_state_blob = req.json().await?;
                 self.state
                     .storage()
-                    .put("helper_state", helper_state_hex)
+                    .put("helper_state", helper_state_blob)
                     .await?;
                 Response::from_json(&())
             }
 
             // Drain the Helper's state.
             //
-            // Output: `String` (hex-encoded state)
+            // Output: `String` (encoded state)
             (DURABLE_HELPER_STATE_GET, Method::Post) => {
                 let helper_state: Option<String> = state_get(&self.state, "helper_state").await?;
                 if helper_state.is_some() {
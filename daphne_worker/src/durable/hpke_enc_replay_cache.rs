@@ -0,0 +1,242 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    config::DaphneWorkerConfig,
+    durable::{
+        state_get, state_set_if_not_exists, DurableOrdered, BINDING_DAP_HPKE_ENC_REPLAY_CACHE,
+    },
+    initialize_tracing, int_err, now,
+};
+use daphne::messages::Time;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{trace, warn};
+use worker::*;
+
+pub(crate) const DURABLE_HPKE_ENC_REPLAY_CACHE_CHECK: &str =
+    "/internal/do/hpke_enc_replay_cache/check";
+pub(crate) const DURABLE_HPKE_ENC_REPLAY_CACHE_PUT: &str = "/internal/do/hpke_enc_replay_cache/put";
+
+/// Prefix of the queue of recorded encapsulated keys, oldest first.
+const RECORDED_PREFIX: &str = "recorded";
+
+/// Maximum number of expired encapsulated keys deleted by a single alarm. If more have expired,
+/// then the alarm is rescheduled right away.
+const MAX_EXPIRED_PER_ALARM: usize = 128;
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct HpkeEncReplayCacheRequest {
+    pub(crate) enc_hex: String,
+    pub(crate) task_expiration: Time,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HpkeEncReplayCacheResult {
+    Ok,
+    ErrReplayed,
+}
+
+/// Return the time at which the next alarm should fire, given the time at which the oldest
+/// encapsulated key still stored was recorded, if any. Keys are forgotten `ttl` seconds after they
+/// are recorded, and all state is deleted once the task expires.
+pub(crate) fn next_alarm(
+    oldest_recorded_at: Option<Time>,
+    ttl: u64,
+    task_expiration: Time,
+) -> Time {
+    oldest_recorded_at
+        .map(|recorded_at| recorded_at.saturating_add(ttl).min(task_expiration))
+        .unwrap_or(task_expiration)
+}
+
+/// Durable Object (DO) for detecting reports that reuse an HPKE encapsulated key (`enc`). The
+/// naming scheme for instances is the same as for `LeaderBatchQueue`, i.e., there is one instance
+/// per task.
+///
+/// This object defines the following API endpoints:
+///
+/// - `DURABLE_HPKE_ENC_REPLAY_CACHE_CHECK`: Check whether an encapsulated key was recorded
+///   recently, without recording it.
+/// - `DURABLE_HPKE_ENC_REPLAY_CACHE_PUT`: Record an encapsulated key, failing if it was recorded
+///   recently.
+///
+/// The schema for the data stored by this DO is as follows:
+///
+/// ```text
+/// [Encapsulated key]  enc/<enc_hex> -> Time
+/// [Recorded queue]    recorded/item/time/<time>/nonce/<nonce> -> (String, Time)
+/// [Task expiration]   task_expiration -> Time
+/// ```
+///
+/// where `<enc_hex>` is the hex-encoded encapsulated key and the value is the time at which it
+/// was recorded. The recorded queue lists the same keys in the order in which they were recorded,
+/// so that the alarm can delete them once `hpke_enc_replay_cache_ttl` has elapsed. Since keys are
+/// forgotten, this check is not definitive. The state is deleted once the task expires.
+#[durable_object]
+pub struct HpkeEncReplayCache {
+    #[allow(dead_code)]
+    state: State,
+    env: Env,
+    config: DaphneWorkerConfig,
+    touched: bool,
+    alarmed: bool,
+}
+
+impl HpkeEncReplayCache {
+    fn ttl(&self) -> u64 {
+        self.config
+            .hpke_enc_replay_cache_ttl
+            .map(|ttl| ttl.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// Check whether `enc_hex` was recorded within the last `ttl` seconds. Expired keys may not
+    /// have been deleted yet, so the time at which the key was recorded is checked as well.
+    async fn is_replayed(&self, enc_hex: &str) -> Result<bool> {
+        let recorded_at: Option<Time> = state_get(&self.state, &format!("enc/{enc_hex}")).await?;
+        Ok(match recorded_at {
+            Some(recorded_at) => now() < recorded_at.saturating_add(self.ttl()),
+            None => false,
+        })
+    }
+}
+
+#[durable_object]
+impl DurableObject for HpkeEncReplayCache {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        let config =
+            DaphneWorkerConfig::from_worker_env(&env).expect("failed to load configuration");
+        Self {
+            state,
+            env,
+            config,
+            touched: false,
+            alarmed: false,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let id_hex = self.state.id().to_string();
+        ensure_garbage_collected!(req, self, id_hex, BINDING_DAP_HPKE_ENC_REPLAY_CACHE);
+
+        match (req.path().as_ref(), req.method()) {
+            // Check whether an encapsulated key was recorded recently.
+            //
+            // Input: `HpkeEncReplayCacheRequest`
+            // Output: `HpkeEncReplayCacheResult`
+            (DURABLE_HPKE_ENC_REPLAY_CACHE_CHECK, Method::Post) => {
+                let HpkeEncReplayCacheRequest { enc_hex, .. } = req.json().await?;
+                Response::from_json(&if self.is_replayed(&enc_hex).await? {
+                    HpkeEncReplayCacheResult::ErrReplayed
+                } else {
+                    HpkeEncReplayCacheResult::Ok
+                })
+            }
+
+            // Record an encapsulated key.
+            //
+            // Input: `HpkeEncReplayCacheRequest`
+            // Output: `HpkeEncReplayCacheResult`
+            (DURABLE_HPKE_ENC_REPLAY_CACHE_PUT, Method::Post) => {
+                let HpkeEncReplayCacheRequest {
+                    enc_hex,
+                    task_expiration,
+                } = req.json().await?;
+                let now = now();
+
+                // The alarm forgets the key once it has expired and deletes the state once the
+                // task expires.
+                state_set_if_not_exists(&self.state, "task_expiration", &task_expiration).await?;
+                ensure_alarmed!(
+                    self,
+                    Duration::from_secs(
+                        next_alarm(Some(now), self.ttl(), task_expiration)
+                            .saturating_sub(now)
+                            .max(1)
+                    )
+                );
+
+                // To keep this pair of get and put operations atomic, there should be no await
+                // points between them other than storage operations. See the note below
+                // `transaction()` on
+                // https://developers.cloudflare.com/workers/runtime-apis/durable-objects/#transactional-storage-api.
+                if self.is_replayed(&enc_hex).await? {
+                    return Response::from_json(&HpkeEncReplayCacheResult::ErrReplayed);
+                }
+                self.state
+                    .storage()
+                    .put(&format!("enc/{enc_hex}"), now)
+                    .await?;
+                DurableOrdered::new_roughly_ordered((enc_hex, now), RECORDED_PREFIX)
+                    .put(&self.state)
+                    .await?;
+
+                Response::from_json(&HpkeEncReplayCacheResult::Ok)
+            }
+
+            _ => Err(int_err(format!(
+                "HpkeEncReplayCache: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        self.alarmed = false;
+        let now = now();
+        let task_expiration: Time = state_get(&self.state, "task_expiration")
+            .await?
+            .unwrap_or_default();
+        if now >= task_expiration {
+            self.state.storage().delete_all().await?;
+            self.touched = false;
+            trace!(
+                "HpkeEncReplayCache: deleted instance {}",
+                self.state.id().to_string()
+            );
+            return Response::from_json(&());
+        }
+
+        // The recorded queue is ordered by the time at which each key was recorded, so we can stop
+        // at the first key that has not yet expired.
+        let ttl = self.ttl();
+        let recorded: Vec<DurableOrdered<(String, Time)>> =
+            DurableOrdered::get_front(&self.state, RECORDED_PREFIX, MAX_EXPIRED_PER_ALARM).await?;
+        let mut oldest_recorded_at = None;
+        let mut deleted = 0;
+        for queued in recorded.iter() {
+            let (enc_hex, recorded_at) = queued.as_ref();
+            if now < recorded_at.saturating_add(ttl) {
+                oldest_recorded_at = Some(*recorded_at);
+                break;
+            }
+
+            // The key may have been recorded again since, in which case it is kept.
+            let enc_key = format!("enc/{enc_hex}");
+            let latest: Option<Time> = state_get(&self.state, &enc_key).await?;
+            if latest == Some(*recorded_at) {
+                self.state.storage().delete(&enc_key).await?;
+            }
+            queued.delete(&self.state).await?;
+            deleted += 1;
+        }
+
+        let next_alarm = if deleted == MAX_EXPIRED_PER_ALARM {
+            now
+        } else {
+            next_alarm(oldest_recorded_at, ttl, task_expiration)
+        };
+        self.state
+            .storage()
+            .set_alarm(Duration::from_secs(next_alarm.saturating_sub(now).max(1)))
+            .await?;
+        self.alarmed = true;
+        trace!("HpkeEncReplayCache: forgot {deleted} expired encapsulated keys");
+        Response::from_json(&())
+    }
+}
@@ -12,6 +12,7 @@ use worker::*;
 pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_PUT: &str = "/internal/do/agg_job_queue/put";
 pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_GET: &str = "/internal/do/agg_job_queue/get";
 pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_FINISH: &str = "/internal/do/agg_job_queue/finish";
+pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_FIND: &str = "/internal/do/agg_job_queue/find";
 
 /// Durable Object (DO) representing an aggregation job queue.
 ///
@@ -22,6 +23,8 @@ pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_FINISH: &str = "/internal/do/agg_j
 /// - `DURABLE_LEADER_AGG_JOB_QUEUE_PUT`: Fetches the desired number of jobs from the front of the
 ///    queue.
 /// - `DURABLE_LEADER_AGG_JOB_QUEUE_FINISH`: Removes the indicated job from the queue.
+/// - `DURABLE_LEADER_AGG_JOB_QUEUE_FIND`: Returns every job in the queue whose `ReportsPending`
+///   instance name has the given prefix, without removing any jobs.
 ///
 /// The schemea for data stored in instances of this DO is as follows:
 ///
@@ -102,6 +105,22 @@ impl DurableObject for LeaderAggregationJobQueue {
                 Response::from_json(&())
             }
 
+            // Find the aggregation jobs for the `ReportsPending` instances whose names begin with
+            // the given prefix. The queue is left unmodified.
+            //
+            // Input: `name_prefix: String`
+            // Output: `Vec<DurableOrdered<String>>`
+            (DURABLE_LEADER_AGG_JOB_QUEUE_FIND, Method::Post) => {
+                let name_prefix: String = req.json().await?;
+                let res: Vec<DurableOrdered<String>> =
+                    DurableOrdered::<String>::get_all(&self.state, "agg_job")
+                        .await?
+                        .into_iter()
+                        .filter(|agg_job| agg_job.as_ref().starts_with(&name_prefix))
+                        .collect();
+                Response::from_json(&res)
+            }
+
             _ => Err(int_err(format!(
                 "LeaderAggregationJobQueue: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -16,6 +16,7 @@ pub(crate) const DURABLE_LEADER_BATCH_QUEUE_ASSIGN: &str = "/internal/do/leader_
 pub(crate) const DURABLE_LEADER_BATCH_QUEUE_CURRENT: &str =
     "/internal/do/leader_batch_queue/current";
 pub(crate) const DURABLE_LEADER_BATCH_QUEUE_REMOVE: &str = "/internal/do/leader_batch_queue/remove";
+pub(crate) const DURABLE_LEADER_BATCH_QUEUE_PEEK: &str = "/internal/do/leader_batch_queue/peek";
 
 const CURRENT: &str = "current";
 const PENDING_PREFIX: &str = "pending";
@@ -26,6 +27,29 @@ pub(crate) struct BatchCount {
     pub(crate) report_count: usize,
 }
 
+/// The minimum and maximum number of reports in a batch.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub(crate) struct BatchSizeBounds {
+    pub(crate) min: usize,
+    pub(crate) max: usize,
+}
+
+/// Decide how many of `num_unassigned` reports to assign to the batch currently being filled,
+/// which already contains `report_count` reports, and whether to close the batch afterwards. The
+/// batch is filled up to the maximum batch size, but it is only closed once it reaches the maximum
+/// or once no reports are left to assign and it contains at least the minimum number of reports.
+pub(crate) fn fill_batch(
+    bounds: BatchSizeBounds,
+    report_count: usize,
+    num_unassigned: usize,
+) -> (usize, bool) {
+    let num_assigned = std::cmp::min(bounds.max.saturating_sub(report_count), num_unassigned);
+    let report_count = report_count + num_assigned;
+    let close = report_count >= bounds.max
+        || (num_assigned == num_unassigned && report_count >= bounds.min);
+    (num_assigned, close)
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum LeaderBatchQueueResult {
@@ -37,9 +61,14 @@ pub(crate) enum LeaderBatchQueueResult {
 ///
 /// This object implements the following API endpoints:
 ///
-/// - `DURABLE_LEADER_BATCH_QUEUE_ASSIGN`: Assign the requested number of reports to batches.
+/// - `DURABLE_LEADER_BATCH_QUEUE_ASSIGN`: Assign the requested number of reports to batches. Each
+///   batch is filled with as many reports as are available, up to the maximum batch size. A batch
+///   is closed once it is full or, if it contains at least the minimum number of reports, once
+///   the reports in the request have been assigned.
 /// - `DURABLE_LEADER_BATCH_QUEUE_CURRENT`: Return the ID of the oldest, non-yet-collected batch.
 /// - `DURABLE_LEADER_BATCH_QUEUE_REMOVE`: Remove the given batch from the queue.
+/// - `DURABLE_LEADER_BATCH_QUEUE_PEEK`: Return the number of reports assigned to each batch in the
+///   queue, without modifying the queue.
 ///
 /// The schema for data stored in instances of this DO is as follows:
 ///
@@ -50,6 +79,9 @@ pub(crate) enum LeaderBatchQueueResult {
 /// [Current batch]     current -> BatchCount (the batch currently being filled)
 /// ```
 ///
+/// The report count of a batch in the pending queue is updated once the batch is closed. Until
+/// then, the count is only tracked by the current batch.
+///
 /// Note that the queue ordinal format is inherited from [`DurableOrdered::new_strictly_ordered`].
 #[durable_object]
 pub struct LeaderBatchQueue {
@@ -125,12 +157,12 @@ impl DurableObject for LeaderBatchQueue {
             // Assign the requested number of reports to a sequence of batch IDs. For each batch
             // ID, return the number of reports assigned to the batch.
             //
-            // Input: `(batch_size, num_unassigned): (usize, usize)`
+            // Input: `(bounds, num_unassigned): (BatchSizeBounds, usize)`
             // Output: `Vec<BatchCount>`
             (DURABLE_LEADER_BATCH_QUEUE_ASSIGN, Method::Post) => {
-                let (batch_size, mut num_unassigned): (usize, usize) = req.json().await?;
-                if batch_size == 0 {
-                    return Err(int_err("LeaderBatchQueue: called with batch_size is 0"));
+                let (bounds, mut num_unassigned): (BatchSizeBounds, usize) = req.json().await?;
+                if bounds.max == 0 {
+                    return Err(int_err("LeaderBatchQueue: called with max batch size 0"));
                 }
 
                 // Read the batch that is currently being filled from storage, or, if this is the
@@ -147,15 +179,21 @@ impl DurableObject for LeaderBatchQueue {
                 }];
 
                 while num_unassigned > 0 {
-                    let num_assigned =
-                        std::cmp::min(batch_size, curr.report_count + num_unassigned)
-                            - curr.report_count;
+                    let (num_assigned, close) =
+                        fill_batch(bounds, curr.report_count, num_unassigned);
                     curr.report_count += num_assigned;
                     batch_assignments.last_mut().unwrap().report_count += num_assigned;
                     num_unassigned -= num_assigned;
 
-                    // If the current batch is saturated, then create a new one.
-                    if curr.report_count >= batch_size {
+                    // If the current batch is closed, then record its final count in the queue and
+                    // create a new one.
+                    if close {
+                        let lookup_key = lookup_key(&curr.batch_id.to_hex());
+                        if let Some(lookup_val) =
+                            state_get::<String>(&self.state, &lookup_key).await?
+                        {
+                            self.state.storage().put(&lookup_val, &curr).await?;
+                        }
                         curr = self.create_batch().await?;
                         batch_assignments.push(curr.clone());
                     }
@@ -182,6 +220,23 @@ impl DurableObject for LeaderBatchQueue {
                 Response::from_json(&())
             }
 
+            // Return the number of reports assigned to each batch in the queue, oldest first.
+            //
+            // Output: `Vec<BatchCount>`
+            (DURABLE_LEADER_BATCH_QUEUE_PEEK, Method::Get) => {
+                let curr: Option<BatchCount> = state_get(&self.state, CURRENT).await?;
+                let queued: Vec<DurableOrdered<BatchCount>> =
+                    DurableOrdered::get_all(&self.state, PENDING_PREFIX).await?;
+                let batch_counts: Vec<BatchCount> = queued
+                    .into_iter()
+                    .map(|queued| match curr {
+                        Some(ref curr) if curr.batch_id == queued.as_ref().batch_id => curr.clone(),
+                        _ => queued.into_item(),
+                    })
+                    .collect();
+                Response::from_json(&batch_counts)
+            }
+
             _ => Err(int_err(format!(
                 "LeaderBatchQueue: unexpected request: method={:?}; path={:?}",
                 req.method(),
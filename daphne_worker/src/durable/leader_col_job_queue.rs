@@ -4,10 +4,10 @@
 use crate::{
     config::DaphneWorkerConfig,
     durable::{state_get, state_get_or_default, DurableOrdered, BINDING_DAP_LEADER_COL_JOB_QUEUE},
-    initialize_tracing, int_err,
+    initialize_tracing, int_err, now,
 };
 use daphne::{
-    messages::{Collection, CollectionJobId, CollectionReq, TaskId},
+    messages::{Collection, CollectionJobId, CollectionReq, TaskId, Time},
     DapCollectJob, DapVersion,
 };
 use prio::{
@@ -15,10 +15,15 @@ use prio::{
     vdaf::prg::{Prg, PrgSha3, SeedStream},
 };
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{trace, warn};
 use worker::*;
 
 const PENDING_PREFIX: &str = "pending";
 const PROCESSED_PREFIX: &str = "processed";
+const FINISHED_PREFIX: &str = "finished";
+const EXPIRED_PREFIX: &str = "expired";
+const ABANDONED_PREFIX: &str = "abandoned";
 
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_PUT: &str = "/internal/do/leader_col_job_queue/put";
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET: &str = "/internal/do/leader_col_job_queue/get";
@@ -26,6 +31,8 @@ pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_FINISH: &str =
     "/internal/do/leader_col_job_queue/finish";
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT: &str =
     "/internal/do/leader_col_job_queue/get_result";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_ABANDON: &str =
+    "/internal/do/leader_col_job_queue/abandon";
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -35,26 +42,57 @@ pub(crate) struct CollectQueueRequest {
     pub collect_job_id: Option<CollectionJobId>,
 }
 
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CollectQueuePutResult {
+    /// The collection job was created, or it already existed.
+    Ok(CollectionJobId),
+
+    /// The task has the maximum number of pending collection jobs. The Collector may retry after
+    /// the indicated number of seconds.
+    AtCapacity { retry_after: u64 },
+}
+
 /// Durable Object (DO) for storing the Leader's state for a given task.
 ///
 /// This object implements the following API endpoints:
 ///
-/// - `DURABLE_LEADER_COL_JOB_QUEUE_PUT:` Create a collection job for a CollectReq.
-/// - `DURABLE_LEADER_COL_JOB_QUEUE_GET`: Get the entire list of pending collection jobs.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_PUT:` Create a collection job for a CollectReq, unless the task
+///   already has the maximum number of pending collection jobs.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_GET`: Get the list of pending collection jobs, optionally
+///   only those for a given task.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_FINISH`: Complete a collection job and store the CollectResp.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT`: Poll the queue to see if a collect job is
 ///   complete.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_ABANDON`: Remove a collection job from the pending queue
+///   without a CollectResp, e.g., because its batch overlaps a batch collected by another job.
+///
+/// If a TTL is configured for collection job results, then an alarm deletes each CollectResp once
+/// the TTL has elapsed since the job finished. A marker is kept in its place so that polling the
+/// job indicates that it has expired rather than that it is unknown. The marker is itself deleted
+/// once the TTL has elapsed again, after which the job is unknown.
 ///
 /// The schema for data stored in instances of this DO is as follows:
 ///
 /// ```text
-/// [Pending Lookup ID] pending/id/<collection_job_id> -> String (reference to queue element)
+/// [Pending Lookup ID] pending/tasks/<task_id>/collection_jobs/<collection_job_id> -> String (reference to queue element)
 /// [Pending queue]     pending/next_ordinal -> u64
-/// [Pending queue]     pending/item/order/<order> -> (CollectionJobId, CollectReq)
-/// [Processed]         processed/<collection_job_id> -> CollectResp
+/// [Pending queue]     pending/item/order/<order> -> (TaskId, CollectionJobId, CollectReq)
+/// [Pending count]     pending_count/tasks/<task_id> -> u64
+/// [Processed]         processed/tasks/<task_id>/collection_jobs/<collection_job_id> -> CollectResp
+/// [Finished queue]    finished/item/time/<time>/nonce/<nonce> -> (TaskId, CollectionJobId, Time)
+/// [Expired]           expired/tasks/<task_id>/collection_jobs/<collection_job_id> -> bool
+/// [Expired queue]     expired/item/time/<time>/nonce/<nonce> -> (TaskId, CollectionJobId, Time)
+/// [Abandoned]         abandoned/tasks/<task_id>/collection_jobs/<collection_job_id> -> bool
 /// ```
 ///
-/// Note that the queue ordinal format is inherited from [`DurableOrdered::new_strictly_ordered`].
+/// Collection job IDs are chosen by the Collector, so the same ID may be used for jobs of
+/// different tasks. Each key is therefore namespaced by the task ID.
+///
+/// Note that the pending queue ordinal format is inherited from
+/// [`DurableOrdered::new_strictly_ordered`] and the finished and expired queue ordinal formats are
+/// inherited from [`DurableOrdered::new_roughly_ordered`]. The finished and expired queues are only
+/// populated if a TTL is configured.
 //
 // TODO Implement collection job deletion per the DAP-02.
 #[durable_object]
@@ -64,6 +102,74 @@ pub struct LeaderCollectionJobQueue {
     env: Env,
     config: DaphneWorkerConfig,
     touched: bool,
+    alarmed: bool,
+}
+
+impl LeaderCollectionJobQueue {
+    /// Return the number of pending collection jobs for the given task. Queues written before the
+    /// count was tracked don't store it, in which case it is computed from the pending queue.
+    async fn pending_count(&self, task_id: &TaskId) -> Result<u64> {
+        if let Some(pending_count) = state_get(&self.state, &pending_count_key(task_id)).await? {
+            return Ok(pending_count);
+        }
+
+        let queue: Vec<DurableOrdered<(TaskId, CollectionJobId, CollectionReq)>> =
+            DurableOrdered::get_all(&self.state, PENDING_PREFIX).await?;
+        Ok(queue
+            .iter()
+            .filter(|queued| &queued.as_ref().0 == task_id)
+            .count() as u64)
+    }
+
+    /// Remove a collection job from the pending queue, if it is there, and update the task's
+    /// pending count accordingly.
+    async fn remove_pending(
+        &self,
+        task_id: &TaskId,
+        collection_job_id: &CollectionJobId,
+    ) -> Result<()> {
+        let pending_key = pending_key(task_id, collection_job_id);
+        let Some(lookup_val) = state_get::<String>(&self.state, &pending_key).await? else {
+            return Ok(());
+        };
+
+        // The count is read before the job is removed from the queue, since it may be computed
+        // from the queue.
+        let pending_count = self.pending_count(task_id).await?;
+        self.state.storage().delete(&lookup_val).await?;
+        self.state.storage().delete(&pending_key).await?;
+        self.state
+            .storage()
+            .put(&pending_count_key(task_id), pending_count.saturating_sub(1))
+            .await?;
+        Ok(())
+    }
+
+    /// Remove each collection job from the given queue (either the finished or the expired queue)
+    /// that was added at least `ttl` ago. Return the removed jobs and the time at which the next
+    /// job in the queue expires, if any.
+    async fn take_expired(
+        &self,
+        prefix: &str,
+        now: Time,
+        ttl: Duration,
+    ) -> Result<(Vec<(TaskId, CollectionJobId)>, Option<Time>)> {
+        // The queue is ordered by the time at which each job was added, so we can stop at the
+        // first job that has not yet expired.
+        let queue: Vec<DurableOrdered<(TaskId, CollectionJobId, Time)>> =
+            DurableOrdered::get_all(&self.state, prefix).await?;
+        let mut expired = Vec::new();
+        for queued in queue {
+            let expiration = queued.as_ref().2.saturating_add(ttl.as_secs());
+            if expiration > now {
+                return Ok((expired, Some(expiration)));
+            }
+            queued.delete(&self.state).await?;
+            let (task_id, collection_job_id, _) = queued.into_item();
+            expired.push((task_id, collection_job_id));
+        }
+        Ok((expired, None))
+    }
 }
 
 #[durable_object]
@@ -77,6 +183,7 @@ impl DurableObject for LeaderCollectionJobQueue {
             env,
             config,
             touched: false,
+            alarmed: false,
         }
     }
 
@@ -88,7 +195,7 @@ impl DurableObject for LeaderCollectionJobQueue {
             // Create a collect job for a collect request issued by the Collector.
             //
             // Input: `collect_req: CollectReq`
-            // Output: `Id` (collect job ID)
+            // Output: `CollectQueuePutResult`
             (DURABLE_LEADER_COL_JOB_QUEUE_PUT, Method::Post) => {
                 let collect_queue_req: CollectQueueRequest = req.json().await?;
                 let collection_job_id: CollectionJobId =
@@ -120,7 +227,37 @@ impl DurableObject for LeaderCollectionJobQueue {
                 let processed_key = processed_key(&collect_queue_req.task_id, &collection_job_id);
                 let pending: bool = state_get_or_default(&self.state, &pending_key).await?;
                 let processed: Option<Collection> = state_get(&self.state, &processed_key).await?;
-                if processed.is_none() && !pending {
+                let expired: bool = state_get_or_default(
+                    &self.state,
+                    &expired_key(&collect_queue_req.task_id, &collection_job_id),
+                )
+                .await?;
+                let abandoned: bool = state_get_or_default(
+                    &self.state,
+                    &abandoned_key(&collect_queue_req.task_id, &collection_job_id),
+                )
+                .await?;
+                if processed.is_none() && !pending && !expired && !abandoned {
+                    // Push back on the Collector if the task has too many pending jobs.
+                    let pending_count = self.pending_count(&collect_queue_req.task_id).await?;
+                    if let Some(max_pending) = self.config.max_pending_collection_jobs_per_task {
+                        if pending_count >= max_pending {
+                            return Response::from_json(&CollectQueuePutResult::AtCapacity {
+                                retry_after: self
+                                    .config
+                                    .pending_collection_jobs_retry_after
+                                    .as_secs(),
+                            });
+                        }
+                    }
+                    self.state
+                        .storage()
+                        .put(
+                            &pending_count_key(&collect_queue_req.task_id),
+                            pending_count + 1,
+                        )
+                        .await?;
+
                     let queued = DurableOrdered::new_strictly_ordered(
                         &self.state,
                         (
@@ -137,18 +274,28 @@ impl DurableObject for LeaderCollectionJobQueue {
                         .put(&pending_key, &queued.key())
                         .await?;
                 }
-                Response::from_json(&collection_job_id.to_hex())
+                Response::from_json(&CollectQueuePutResult::Ok(collection_job_id))
             }
 
-            // Get the list of pending collection jobs (oldest jobs first).
+            // Get the list of pending collection jobs (oldest jobs first). If a task ID is
+            // provided, then only the jobs for that task are returned.
             //
-            // Output: `Vec<(Id, CollectReq)>`
-            (DURABLE_LEADER_COL_JOB_QUEUE_GET, Method::Get) => {
+            // Input (POST only): `task_id: Option<TaskId>`
+            // Output: `Vec<(TaskId, Id, CollectReq)>`
+            (DURABLE_LEADER_COL_JOB_QUEUE_GET, Method::Get | Method::Post) => {
+                let task_id: Option<TaskId> = if req.method() == Method::Post {
+                    req.json().await?
+                } else {
+                    None
+                };
                 let queue: Vec<(TaskId, CollectionJobId, CollectionReq)> =
                     DurableOrdered::get_all(&self.state, PENDING_PREFIX)
                         .await?
                         .into_iter()
                         .map(|queued| queued.into_item())
+                        .filter(|(queued_task_id, _, _)| {
+                            task_id.as_ref().map_or(true, |id| id == queued_task_id)
+                        })
                         .collect();
                 Response::from_json(&queue)
             }
@@ -170,23 +317,23 @@ impl DurableObject for LeaderCollectionJobQueue {
                     ));
                 }
 
-                // Remove the collection job from the pending queue.
-                let pending_key = pending_key(&task_id, &collection_job_id);
-                if let Some(lookup_val) = state_get::<String>(&self.state, &pending_key).await? {
-                    self.state.storage().delete(&lookup_val).await?;
-                }
-
-                let mut storage = self.state.storage();
-                let f = storage.delete(&pending_key);
-
-                // Store the CollectResp.
+                // Remove the collection job from the pending queue and store the CollectResp.
+                self.remove_pending(&task_id, &collection_job_id).await?;
                 self.state
                     .storage()
                     .put(&processed_key, collect_resp)
                     .await?;
 
-                // Remove the lookup key.
-                f.await?;
+                // Schedule the CollectResp for deletion.
+                if let Some(ttl) = self.config.collection_job_result_ttl {
+                    DurableOrdered::new_roughly_ordered(
+                        (task_id, collection_job_id, now()),
+                        FINISHED_PREFIX,
+                    )
+                    .put(&self.state)
+                    .await?;
+                    ensure_alarmed!(self, ttl);
+                }
                 Response::from_json(&())
             }
 
@@ -204,16 +351,44 @@ impl DurableObject for LeaderCollectionJobQueue {
                 let processed: Option<Collection> = state_get(&self.state, &processed_key).await?;
                 if let Some(collect_resp) = processed {
                     if pending {
-                        self.state.storage().delete(&pending_key).await?;
+                        self.remove_pending(&task_id, &collection_job_id).await?;
                     }
                     Response::from_json(&DapCollectJob::Done(collect_resp))
                 } else if pending {
                     Response::from_json(&DapCollectJob::Pending)
+                } else if state_get_or_default(
+                    &self.state,
+                    &expired_key(&task_id, &collection_job_id),
+                )
+                .await?
+                {
+                    Response::from_json(&DapCollectJob::Expired)
+                } else if state_get_or_default(
+                    &self.state,
+                    &abandoned_key(&task_id, &collection_job_id),
+                )
+                .await?
+                {
+                    Response::from_json(&DapCollectJob::Abandoned)
                 } else {
                     Response::from_json(&DapCollectJob::Unknown)
                 }
             }
 
+            // Remove a collection job from the pending queue without storing a CollectResp and
+            // remember that it was abandoned.
+            //
+            // Input: `(task_id, collection_job_id): (TaskId, Id)`
+            (DURABLE_LEADER_COL_JOB_QUEUE_ABANDON, Method::Post) => {
+                let (task_id, collection_job_id): (TaskId, CollectionJobId) = req.json().await?;
+                self.remove_pending(&task_id, &collection_job_id).await?;
+                self.state
+                    .storage()
+                    .put(&abandoned_key(&task_id, &collection_job_id), true)
+                    .await?;
+                Response::from_json(&())
+            }
+
             _ => Err(int_err(format!(
                 "LeaderCollectionJobQueue: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -221,6 +396,58 @@ impl DurableObject for LeaderCollectionJobQueue {
             ))),
         }
     }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        self.alarmed = false;
+        let Some(ttl) = self.config.collection_job_result_ttl else {
+            return Response::from_json(&());
+        };
+
+        // Delete the result of each collection job that finished at least `ttl` ago and keep a
+        // marker in its place.
+        let now = now();
+        let (expired, next_result_expiration) =
+            self.take_expired(FINISHED_PREFIX, now, ttl).await?;
+        for (task_id, collection_job_id) in expired {
+            self.state
+                .storage()
+                .delete(&processed_key(&task_id, &collection_job_id))
+                .await?;
+            self.state
+                .storage()
+                .put(&expired_key(&task_id, &collection_job_id), true)
+                .await?;
+            trace!(
+                "LeaderCollectionJobQueue: deleted result of collection job {}",
+                collection_job_id.to_base64url()
+            );
+            DurableOrdered::new_roughly_ordered((task_id, collection_job_id, now), EXPIRED_PREFIX)
+                .put(&self.state)
+                .await?;
+        }
+
+        // Delete each marker that has been kept for at least `ttl`.
+        let (expired, next_marker_expiration) = self.take_expired(EXPIRED_PREFIX, now, ttl).await?;
+        for (task_id, collection_job_id) in expired {
+            self.state
+                .storage()
+                .delete(&expired_key(&task_id, &collection_job_id))
+                .await?;
+        }
+
+        let next_expiration = next_result_expiration
+            .into_iter()
+            .chain(next_marker_expiration)
+            .min();
+        if let Some(expiration) = next_expiration {
+            self.state
+                .storage()
+                .set_alarm(Duration::from_secs(expiration - now))
+                .await?;
+            self.alarmed = true;
+        }
+        Response::from_json(&())
+    }
 }
 
 fn pending_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String {
@@ -231,6 +458,10 @@ fn pending_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String
     )
 }
 
+fn pending_count_key(task_id: &TaskId) -> String {
+    format!("pending_count/tasks/{}", task_id.to_base64url())
+}
+
 fn processed_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String {
     format!(
         "{PROCESSED_PREFIX}/tasks/{}/collection_jobs/{}",
@@ -238,3 +469,19 @@ fn processed_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> Strin
         collection_job_id.to_base64url()
     )
 }
+
+fn expired_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String {
+    format!(
+        "{EXPIRED_PREFIX}/tasks/{}/collection_jobs/{}",
+        task_id.to_base64url(),
+        collection_job_id.to_base64url()
+    )
+}
+
+fn abandoned_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String {
+    format!(
+        "{ABANDONED_PREFIX}/tasks/{}/collection_jobs/{}",
+        task_id.to_base64url(),
+        collection_job_id.to_base64url()
+    )
+}
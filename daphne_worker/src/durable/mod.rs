@@ -2,22 +2,92 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{int_err, now};
-use daphne::{messages::TaskId, DapBatchBucket, DapVersion};
+use daphne::{messages::TaskId, DapBatchBucket, DapVersion, MetaAggregationJobId};
+use prometheus::IntCounterVec;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::cmp::min;
-use worker::*;
+use std::{cmp::min, collections::HashMap, fmt, marker::PhantomData};
+use worker::{
+    wasm_bindgen::{JsCast, JsValue},
+    worker_sys::{web_sys, DurableObject as EdgeDurableObject, DurableObjectNamespace},
+    *,
+};
 
 pub(crate) const DURABLE_DELETE_ALL: &str = "/internal/do/delete_all";
+pub(crate) const DURABLE_PING: &str = "/internal/do/ping";
+
+pub(crate) const BINDING_DAP_REPORTS_PENDING: DurableBinding<ReportStoreName> =
+    DurableBinding::new("DAP_REPORTS_PENDING");
+pub(crate) const BINDING_DAP_REPORTS_PROCESSED: DurableBinding<ReportStoreName> =
+    DurableBinding::new("DAP_REPORTS_PROCESSED");
+pub(crate) const BINDING_DAP_AGGREGATE_STORE: DurableBinding<AggStoreName> =
+    DurableBinding::new("DAP_AGGREGATE_STORE");
+pub(crate) const BINDING_DAP_LEADER_AGG_JOB_QUEUE: DurableBinding<QueueName> =
+    DurableBinding::new("DAP_LEADER_AGG_JOB_QUEUE");
+pub(crate) const BINDING_DAP_LEADER_BATCH_QUEUE: DurableBinding<TaskName> =
+    DurableBinding::new("DAP_LEADER_BATCH_QUEUE");
+pub(crate) const BINDING_DAP_LEADER_COL_JOB_QUEUE: DurableBinding<QueueName> =
+    DurableBinding::new("DAP_LEADER_COL_JOB_QUEUE");
+pub(crate) const BINDING_DAP_HELPER_STATE_STORE: DurableBinding<HelperStateName> =
+    DurableBinding::new("DAP_HELPER_STATE_STORE");
+pub(crate) const BINDING_DAP_GARBAGE_COLLECTOR: DurableBinding<GarbageCollectorName> =
+    DurableBinding::new("DAP_GARBAGE_COLLECTOR");
+pub(crate) const BINDING_DAP_COUNTERS: DurableBinding<CountersName> =
+    DurableBinding::new("DAP_COUNTERS");
+pub(crate) const BINDING_DAP_UPLOAD_RATE_LIMITER: DurableBinding<TaskName> =
+    DurableBinding::new("DAP_UPLOAD_RATE_LIMITER");
+pub(crate) const BINDING_DAP_HPKE_ENC_REPLAY_CACHE: DurableBinding<TaskName> =
+    DurableBinding::new("DAP_HPKE_ENC_REPLAY_CACHE");
+
+/// Bindings of the DO classes that hold DAP state. Instances of these classes are scheduled for
+/// deletion by the `GarbageCollector`.
+pub(crate) const STATE_BINDINGS: [&str; 10] = [
+    BINDING_DAP_REPORTS_PENDING.as_str(),
+    BINDING_DAP_REPORTS_PROCESSED.as_str(),
+    BINDING_DAP_AGGREGATE_STORE.as_str(),
+    BINDING_DAP_LEADER_AGG_JOB_QUEUE.as_str(),
+    BINDING_DAP_LEADER_BATCH_QUEUE.as_str(),
+    BINDING_DAP_LEADER_COL_JOB_QUEUE.as_str(),
+    BINDING_DAP_HELPER_STATE_STORE.as_str(),
+    BINDING_DAP_COUNTERS.as_str(),
+    BINDING_DAP_UPLOAD_RATE_LIMITER.as_str(),
+    BINDING_DAP_HPKE_ENC_REPLAY_CACHE.as_str(),
+];
+
+/// The binding of a DO class. The type parameter is the type of the names of the class's
+/// instances, so that a name derived for one binding can't be used to address another.
+pub(crate) struct DurableBinding<N> {
+    binding: &'static str,
+    name: PhantomData<fn(N)>,
+}
 
-pub(crate) const BINDING_DAP_REPORTS_PENDING: &str = "DAP_REPORTS_PENDING";
-pub(crate) const BINDING_DAP_REPORTS_PROCESSED: &str = "DAP_REPORTS_PROCESSED";
-pub(crate) const BINDING_DAP_AGGREGATE_STORE: &str = "DAP_AGGREGATE_STORE";
-pub(crate) const BINDING_DAP_LEADER_AGG_JOB_QUEUE: &str = "DAP_LEADER_AGG_JOB_QUEUE";
-pub(crate) const BINDING_DAP_LEADER_BATCH_QUEUE: &str = "DAP_LEADER_BATCH_QUEUE";
-pub(crate) const BINDING_DAP_LEADER_COL_JOB_QUEUE: &str = "DAP_LEADER_COL_JOB_QUEUE";
-pub(crate) const BINDING_DAP_HELPER_STATE_STORE: &str = "DAP_HELPER_STATE_STORE";
-pub(crate) const BINDING_DAP_GARBAGE_COLLECTOR: &str = "DAP_GARBAGE_COLLECTOR";
+impl<N> DurableBinding<N> {
+    const fn new(binding: &'static str) -> Self {
+        Self {
+            binding,
+            name: PhantomData,
+        }
+    }
+
+    pub(crate) const fn as_str(&self) -> &'static str {
+        self.binding
+    }
+}
+
+// Implemented by hand, since deriving would require `N: Clone`.
+impl<N> Clone for DurableBinding<N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<N> Copy for DurableBinding<N> {}
+
+impl<N> fmt::Display for DurableBinding<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.binding)
+    }
+}
 
 const ERR_NO_VALUE: &str = "No such value in storage.";
 
@@ -38,37 +108,84 @@ const MAX_KEYS: usize = 128;
 /// Used to send HTTP requests to a durable object (DO) instance.
 pub(crate) struct DurableConnector<'a> {
     env: &'a Env,
+
+    /// If set, then each request is counted, broken down by binding and command. The second
+    /// element is the value of the "host" label.
+    request_counter: Option<(&'a IntCounterVec, &'a str)>,
+
+    /// If set, then instances are created near the location hinted for their binding, if any.
+    location_hints: Option<&'a HashMap<String, String>>,
 }
 
 impl<'a> DurableConnector<'a> {
     pub(crate) fn new(env: &'a Env) -> Self {
-        DurableConnector { env }
+        DurableConnector {
+            env,
+            request_counter: None,
+            location_hints: None,
+        }
+    }
+
+    /// Pass the location hint configured for the binding, if any, when getting a stub for an
+    /// instance. The hint only has an effect when the instance is created, and even then it is
+    /// best-effort: it may reduce latency, but it does not restrict where the instance's data is
+    /// stored.
+    pub(crate) fn with_location_hints(
+        mut self,
+        location_hints: Option<&'a HashMap<String, String>>,
+    ) -> Self {
+        self.location_hints = location_hints;
+        self
+    }
+
+    /// Count each request sent by this connector.
+    pub(crate) fn with_request_counter(
+        mut self,
+        counter: &'a IntCounterVec,
+        host: &'a str,
+    ) -> Self {
+        self.request_counter = Some((counter, host));
+        self
+    }
+
+    fn request_inc(&self, durable_binding: &str, durable_path: &str) {
+        if let Some((counter, host)) = self.request_counter {
+            counter
+                .with_label_values(&[host, durable_binding, durable_path])
+                .inc();
+        }
     }
 
     /// Send a GET request with the given path to the DO instance with the given binding and name.
     /// The response is expected to be a JSON object.
-    pub(crate) async fn get<O: for<'b> Deserialize<'b>>(
+    pub(crate) async fn get<N: DurableName, O: for<'b> Deserialize<'b>>(
         &self,
-        durable_binding: &str,
+        durable_binding: DurableBinding<N>,
         durable_path: &'static str,
-        durable_name: String,
+        durable_name: N,
     ) -> Result<O> {
-        let namespace = self.env.durable_object(durable_binding)?;
-        let stub = namespace.id_from_name(&durable_name)?.get_stub()?;
+        self.request_inc(durable_binding.as_str(), durable_path);
+        let stub = self.stub(
+            durable_binding.as_str(),
+            DurableId::Name(durable_name.as_str()),
+        )?;
         durable_request(stub, durable_path, Method::Get, None::<()>).await
     }
 
     /// Send a POST request with the given path to the DO instance with the given binding and name.
     /// The body of the request is a JSON object. The response is expected to be a JSON object.
-    pub(crate) async fn post<I: Serialize, O: for<'b> Deserialize<'b>>(
+    pub(crate) async fn post<N: DurableName, I: Serialize, O: for<'b> Deserialize<'b>>(
         &self,
-        durable_binding: &str,
+        durable_binding: DurableBinding<N>,
         durable_path: &'static str,
-        durable_name: String,
+        durable_name: N,
         data: I,
     ) -> Result<O> {
-        let namespace = self.env.durable_object(durable_binding)?;
-        let stub = namespace.id_from_name(&durable_name)?.get_stub()?;
+        self.request_inc(durable_binding.as_str(), durable_path);
+        let stub = self.stub(
+            durable_binding.as_str(),
+            DurableId::Name(durable_name.as_str()),
+        )?;
         durable_request(stub, durable_path, Method::Post, Some(data)).await
     }
 
@@ -82,14 +199,76 @@ impl<'a> DurableConnector<'a> {
         durable_id_hex: String,
         data: I,
     ) -> Result<O> {
-        let namespace = self.env.durable_object(durable_binding)?;
-        let stub = namespace.id_from_string(&durable_id_hex)?.get_stub()?;
+        self.request_inc(durable_binding, durable_path);
+        let stub = self.stub(durable_binding, DurableId::Hex(&durable_id_hex))?;
         durable_request(stub, durable_path, Method::Post, Some(data)).await
     }
+
+    fn stub(&self, durable_binding: &str, durable_id: DurableId<'_>) -> Result<DurableStub> {
+        let Some(location_hint) = self
+            .location_hints
+            .and_then(|location_hints| location_hints.get(durable_binding))
+        else {
+            let namespace = self.env.durable_object(durable_binding)?;
+            let id = match durable_id {
+                DurableId::Name(name) => namespace.id_from_name(name)?,
+                DurableId::Hex(id_hex) => namespace.id_from_string(id_hex)?,
+            };
+            return Ok(DurableStub::Plain(id.get_stub()?));
+        };
+
+        // The `worker` crate doesn't yet support location hints, so get the stub via the
+        // namespace's JavaScript API instead. See
+        // https://developers.cloudflare.com/durable-objects/reference/data-location/#provide-a-location-hint.
+        let namespace: DurableObjectNamespace =
+            js_sys::Reflect::get(self.env, &JsValue::from(durable_binding))?.unchecked_into();
+        let id = match durable_id {
+            DurableId::Name(name) => namespace.id_from_name(name)?,
+            DurableId::Hex(id_hex) => namespace.id_from_string(id_hex)?,
+        };
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &options,
+            &JsValue::from("locationHint"),
+            &JsValue::from(location_hint.as_str()),
+        )?;
+        let get: js_sys::Function =
+            js_sys::Reflect::get(&namespace, &JsValue::from("get"))?.unchecked_into();
+        Ok(DurableStub::Hinted(
+            get.call2(&namespace, &id, &options)?.unchecked_into(),
+        ))
+    }
+}
+
+/// Identifies a DO instance by name or by its hex-encoded ID.
+enum DurableId<'a> {
+    Name(&'a str),
+    Hex(&'a str),
+}
+
+/// A stub for sending requests to a DO instance.
+enum DurableStub {
+    Plain(Stub),
+
+    /// Stub obtained with a location hint.
+    Hinted(EdgeDurableObject),
+}
+
+impl DurableStub {
+    async fn fetch_with_request(&self, req: Request) -> Result<Response> {
+        match self {
+            Self::Plain(stub) => stub.fetch_with_request(req).await,
+            Self::Hinted(stub) => {
+                let promise = stub.fetch_with_request(req.inner());
+                let resp = wasm_bindgen_futures::JsFuture::from(promise).await?;
+                Ok(resp.dyn_into::<web_sys::Response>()?.into())
+            }
+        }
+    }
 }
 
 async fn durable_request<I: Serialize, O: for<'a> Deserialize<'a>>(
-    durable_stub: Stub,
+    durable_stub: DurableStub,
     durable_path: &'static str,
     method: Method,
     data: Option<I>,
@@ -137,7 +316,7 @@ macro_rules! ensure_garbage_collected {
                         .post(
                             crate::durable::BINDING_DAP_GARBAGE_COLLECTOR,
                             crate::durable::garbage_collector::DURABLE_GARBAGE_COLLECTOR_PUT,
-                            "garbage_collector".to_string(),
+                            crate::durable::GarbageCollectorName::Collector,
                             &crate::durable::DurableReference {
                                 binding: $binding.to_string(),
                                 id_hex: $id,
@@ -226,11 +405,155 @@ pub(crate) async fn state_set_if_not_exists<T: for<'a> Deserialize<'a> + Seriali
     Ok(None)
 }
 
-pub(crate) fn durable_name_queue(shard: u64) -> String {
+/// The name of a DO instance. Each naming scheme has its own type, and each binding accepts only
+/// names of one type (see [`DurableBinding`]). The string representation of a name is what
+/// identifies the instance, so it must not change.
+pub(crate) trait DurableName {
+    fn as_str(&self) -> &str;
+}
+
+macro_rules! durable_name_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+        pub(crate) struct $name(String);
+
+        impl DurableName for $name {
+            fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+durable_name_type! {
+    /// Name of an `AggregateStore` instance for a bucket of reports.
+    AggStoreName
+}
+
+impl AggStoreName {
+    pub(crate) fn new(
+        version: &DapVersion,
+        task_id_hex: &str,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Self {
+        Self(durable_name_agg_store(version, task_id_hex, bucket))
+    }
+}
+
+durable_name_type! {
+    /// Name of a `ReportsPending` or `ReportsProcessed` instance for a shard of an epoch.
+    ReportStoreName
+}
+
+impl ReportStoreName {
+    pub(crate) fn new(version: &DapVersion, task_id_hex: &str, epoch: u64, shard: u64) -> Self {
+        Self(durable_name_report_store(
+            version,
+            task_id_hex,
+            epoch,
+            shard,
+        ))
+    }
+
+    /// Wrap the name of a `ReportsPending` instance that was recorded by the
+    /// `LeaderAggregationJobQueue`.
+    pub(crate) fn from_agg_job_queue(name: String) -> Self {
+        Self(name)
+    }
+}
+
+durable_name_type! {
+    /// Name of a `LeaderAggregationJobQueue` or `LeaderCollectionJobQueue` instance.
+    QueueName
+}
+
+impl QueueName {
+    pub(crate) fn new(shard: u64) -> Self {
+        Self(durable_name_queue(shard))
+    }
+}
+
+durable_name_type! {
+    /// Name of a per-task instance, e.g., of `LeaderBatchQueue` or `UploadRateLimiter`.
+    TaskName
+}
+
+impl TaskName {
+    pub(crate) fn new(version: &DapVersion, task_id_hex: &str) -> Self {
+        Self(durable_name_task(version, task_id_hex))
+    }
+}
+
+durable_name_type! {
+    /// Name of a `HelperStateStore` instance for an aggregation job.
+    HelperStateName
+}
+
+impl HelperStateName {
+    pub(crate) fn new(
+        version: &DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId<'_>,
+    ) -> Self {
+        Self(durable_name_helper_state(version, task_id, agg_job_id))
+    }
+}
+
+/// Name of a `GarbageCollector` instance. There is just one instance that collects garbage. The
+/// health check pings a separate instance in each colo instead, so that health checks from every
+/// colo don't all land on the same instance.
+#[derive(Clone, Debug)]
+pub(crate) enum GarbageCollectorName {
+    Collector,
+    HealthCheck(String),
+}
+
+impl GarbageCollectorName {
+    pub(crate) fn health_check(colo: &str) -> Self {
+        Self::HealthCheck(format!("health_check/colo/{colo}"))
+    }
+}
+
+impl DurableName for GarbageCollectorName {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Collector => "garbage_collector",
+            Self::HealthCheck(name) => name,
+        }
+    }
+}
+
+/// Name of a `Counters` instance. There is one instance per set of counters.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CountersName {
+    /// Number of rejected reports, broken down by failure reason.
+    RejectionCounts,
+
+    /// Number of reports ingested across all tasks.
+    ReportsIngested,
+}
+
+impl DurableName for CountersName {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::RejectionCounts => "rejection_counts",
+            Self::ReportsIngested => "reports_ingested",
+        }
+    }
+}
+
+fn durable_name_queue(shard: u64) -> String {
     format!("queue/{shard}")
 }
 
-pub(crate) fn durable_name_report_store(
+fn durable_name_report_store(
     version: &DapVersion,
     task_id_hex: &str,
     epoch: u64,
@@ -244,7 +567,7 @@ pub(crate) fn durable_name_report_store(
     )
 }
 
-pub(crate) fn durable_name_agg_store(
+fn durable_name_agg_store(
     version: &DapVersion,
     task_id_hex: &str,
     bucket: &DapBatchBucket<'_>,
@@ -256,10 +579,23 @@ pub(crate) fn durable_name_agg_store(
     )
 }
 
-pub(crate) fn durable_name_task(version: &DapVersion, task_id_hex: &str) -> String {
+fn durable_name_task(version: &DapVersion, task_id_hex: &str) -> String {
     format!("{}/task/{}", version.as_ref(), task_id_hex)
 }
 
+fn durable_name_helper_state(
+    version: &DapVersion,
+    task_id: &TaskId,
+    agg_job_id: &MetaAggregationJobId,
+) -> String {
+    format!(
+        "{}/task/{}/agg_job/{}",
+        version.as_ref(),
+        task_id.to_hex(),
+        agg_job_id.to_hex()
+    )
+}
+
 fn durable_name_bucket(bucket: &DapBatchBucket<'_>) -> String {
     match bucket {
         DapBatchBucket::TimeInterval { batch_window } => {
@@ -439,8 +775,10 @@ async fn get_front<T: for<'a> Deserialize<'a> + Serialize>(
 }
 
 pub(crate) mod aggregate_store;
+pub(crate) mod counters;
 pub(crate) mod garbage_collector;
 pub(crate) mod helper_state_store;
+pub(crate) mod hpke_enc_replay_cache;
 pub(crate) mod leader_agg_job_queue;
 pub(crate) mod leader_batch_queue;
 pub(crate) mod leader_col_job_queue;
@@ -448,3 +786,4 @@ pub(crate) mod leader_col_job_queue;
 pub(crate) mod mod_test;
 pub(crate) mod reports_pending;
 pub(crate) mod reports_processed;
+pub(crate) mod upload_rate_limiter;
@@ -2,16 +2,33 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::durable::{
-    durable_name_agg_store, durable_name_queue, durable_name_report_store,
-    reports_pending::PendingReport,
+    aggregate_store::{merge_agg_share_checked, AggregateStoreMergeReq, AggregateStoreMergeResult},
+    durable_name_agg_store, durable_name_helper_state, durable_name_queue,
+    durable_name_report_store, durable_name_task,
+    hpke_enc_replay_cache::next_alarm,
+    leader_batch_queue::{fill_batch, BatchSizeBounds},
+    reports_pending::{audit_pending_report, check_capacity, PendingReport, ReportsPendingResult},
+    upload_rate_limiter::TokenBucket,
+    AggStoreName, CountersName, DurableName, GarbageCollectorName, HelperStateName, QueueName,
+    ReportStoreName, TaskName,
 };
 use daphne::{
-    messages::{BatchId, Report, ReportId, ReportMetadata, TaskId},
-    test_version, test_versions, DapBatchBucket, DapVersion,
+    messages::{
+        AggregationJobId, BatchId, Draft02AggregationJobId, Report, ReportId, ReportMetadata,
+        TaskId,
+    },
+    test_version, test_versions, DapAggregateShare, DapBatchBucket, DapRateLimit, DapVersion,
+    MetaAggregationJobId,
+};
+use futures::{
+    executor::block_on,
+    future::{join_all, poll_fn},
+    lock::Mutex as AsyncMutex,
 };
 use paste::paste;
 use prio::codec::{ParameterizedDecode, ParameterizedEncode};
 use rand::prelude::*;
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, task::Poll, time::Duration};
 
 #[test]
 fn durable_name() {
@@ -38,6 +55,61 @@ fn durable_name() {
     );
 }
 
+#[test]
+fn typed_durable_name_matches_helpers() {
+    let time = 1664850074;
+    let id1 = TaskId([17; 32]);
+    let id1_hex = id1.to_hex();
+    let id2 = BatchId([34; 32]);
+    let shard = 1234;
+
+    for version in [DapVersion::Draft02, DapVersion::Draft04] {
+        for bucket in [
+            DapBatchBucket::FixedSize { batch_id: &id2 },
+            DapBatchBucket::TimeInterval { batch_window: time },
+        ] {
+            assert_eq!(
+                AggStoreName::new(&version, &id1_hex, &bucket).as_str(),
+                durable_name_agg_store(&version, &id1_hex, &bucket),
+            );
+        }
+
+        assert_eq!(
+            ReportStoreName::new(&version, &id1_hex, time, shard).as_str(),
+            durable_name_report_store(&version, &id1_hex, time, shard),
+        );
+
+        assert_eq!(
+            TaskName::new(&version, &id1_hex).as_str(),
+            durable_name_task(&version, &id1_hex),
+        );
+
+        for agg_job_id in [
+            MetaAggregationJobId::Draft02(Cow::Owned(Draft02AggregationJobId([51; 32]))),
+            MetaAggregationJobId::Draft04(Cow::Owned(AggregationJobId([68; 16]))),
+        ] {
+            assert_eq!(
+                HelperStateName::new(&version, &id1, &agg_job_id).as_str(),
+                durable_name_helper_state(&version, &id1, &agg_job_id),
+            );
+        }
+    }
+
+    assert_eq!(QueueName::new(shard).as_str(), durable_name_queue(shard));
+
+    // The names of singleton instances must not change, since they identify stored state.
+    assert_eq!(
+        GarbageCollectorName::Collector.as_str(),
+        "garbage_collector"
+    );
+    assert_eq!(
+        GarbageCollectorName::health_check("SJC").as_str(),
+        "health_check/colo/SJC"
+    );
+    assert_eq!(CountersName::RejectionCounts.as_str(), "rejection_counts");
+    assert_eq!(CountersName::ReportsIngested.as_str(), "reports_ingested");
+}
+
 // Test that the `PendingReport.report_id_hex()` method properly extracts the report ID from the
 // hex-encoded report. This helps ensure that changes to the `Report` wire format don't cause any
 // regressions to `ReportStore`.
@@ -75,3 +147,309 @@ fn parse_report_id_hex_from_report(version: DapVersion) {
 }
 
 test_versions! {parse_report_id_hex_from_report}
+
+// Test that `audit_pending_report()` accepts a well-formed pending report and flags corrupted
+// entries.
+fn audit_pending_report_flags_corrupted_entry(version: DapVersion) {
+    let mut rng = thread_rng();
+    let task_id = TaskId([17; 32]);
+    let report = Report {
+        draft02_task_id: task_id.for_request_payload(&version),
+        report_metadata: ReportMetadata {
+            id: ReportId(rng.gen()),
+            time: rng.gen(),
+            extensions: Vec::default(),
+        },
+        public_share: Vec::default(),
+        encrypted_input_shares: Vec::default(),
+    };
+
+    let pending_report = PendingReport {
+        task_id,
+        version,
+        report_hex: hex::encode(report.get_encoded_with_param(&version)),
+    };
+    audit_pending_report(serde_json::to_value(&pending_report).unwrap())
+        .expect("audit failed for well-formed report");
+
+    // Truncated report.
+    let mut corrupted = serde_json::to_value(&pending_report).unwrap();
+    corrupted["report_hex"] = pending_report.report_hex[..40].into();
+    assert!(audit_pending_report(corrupted).is_err());
+
+    // Malformed hex.
+    let mut corrupted = serde_json::to_value(&pending_report).unwrap();
+    corrupted["report_hex"] = "not hex".into();
+    assert!(audit_pending_report(corrupted).is_err());
+
+    // Not a pending report.
+    assert!(audit_pending_report(serde_json::json!({ "garbage": 1337 })).is_err());
+}
+
+test_versions! {audit_pending_report_flags_corrupted_entry}
+
+#[test]
+fn reports_pending_at_capacity() {
+    let retry_after = Duration::from_secs(60);
+
+    // Reports are accepted until the instance holds the maximum number of reports.
+    assert_eq!(check_capacity(0, Some(2), retry_after), None);
+    assert_eq!(check_capacity(1, Some(2), retry_after), None);
+    assert_eq!(
+        check_capacity(2, Some(2), retry_after),
+        Some(ReportsPendingResult::AtCapacity { retry_after: 60 })
+    );
+
+    // An instance that filled up before the limit was lowered is also at capacity.
+    assert_eq!(
+        check_capacity(5, Some(2), retry_after),
+        Some(ReportsPendingResult::AtCapacity { retry_after: 60 })
+    );
+
+    // The number of reports is not limited unless configured.
+    assert_eq!(check_capacity(u64::MAX, None, retry_after), None);
+}
+
+#[test]
+fn token_bucket_limits_burst_and_refills() {
+    let limit = DapRateLimit { rate: 2, burst: 3 };
+    let now = 1664850074;
+    let mut bucket = TokenBucket::full(&limit, now);
+
+    // A full bucket permits a burst of requests, then asks the caller to retry.
+    for _ in 0..3 {
+        assert_eq!(bucket.try_take(&limit, now), None);
+    }
+    assert_eq!(bucket.try_take(&limit, now), Some(1));
+
+    // After a second, `rate` tokens are available.
+    assert_eq!(bucket.try_take(&limit, now + 1), None);
+    assert_eq!(bucket.try_take(&limit, now + 1), None);
+    assert_eq!(bucket.try_take(&limit, now + 1), Some(1));
+
+    // The bucket never holds more than `burst` tokens.
+    for _ in 0..3 {
+        assert_eq!(bucket.try_take(&limit, now + 100), None);
+    }
+    assert_eq!(bucket.try_take(&limit, now + 100), Some(1));
+}
+
+#[test]
+fn token_bucket_ignores_clock_going_backwards() {
+    let limit = DapRateLimit { rate: 1, burst: 1 };
+    let now = 1664850074;
+    let mut bucket = TokenBucket::full(&limit, now);
+    assert_eq!(bucket.try_take(&limit, now), None);
+    assert_eq!(bucket.try_take(&limit, now - 10), Some(1));
+    assert_eq!(bucket.try_take(&limit, now + 1), None);
+}
+
+#[test]
+fn hpke_enc_replay_cache_next_alarm() {
+    let task_expiration = 1664850074;
+
+    // The alarm fires when the oldest encapsulated key expires.
+    assert_eq!(
+        next_alarm(Some(task_expiration - 1000), 100, task_expiration),
+        task_expiration - 900
+    );
+
+    // It never fires later than the task expiration, at which point the state is deleted.
+    assert_eq!(
+        next_alarm(Some(task_expiration - 10), 100, task_expiration),
+        task_expiration
+    );
+    assert_eq!(next_alarm(None, 100, task_expiration), task_expiration);
+}
+
+#[test]
+fn aggregate_store_merge_is_idempotent() {
+    // Model of the aggregate store: the aggregate share and the set of included reports.
+    let mut agg_share = DapAggregateShare::default();
+    let mut included_reports = HashSet::new();
+    let mut merge = |report_checksums: &[[u8; 32]]| {
+        let mut agg_share_delta = DapAggregateShare::default();
+        agg_share_delta.report_count = report_checksums.len() as u64;
+        for checksum in report_checksums {
+            for (x, y) in agg_share_delta.checksum.iter_mut().zip(checksum) {
+                *x ^= y;
+            }
+        }
+        let report_checksums: Vec<String> = report_checksums.iter().map(hex::encode).collect();
+        let included = report_checksums
+            .iter()
+            .filter(|checksum_hex| included_reports.contains(*checksum_hex))
+            .cloned()
+            .collect();
+        let res = merge_agg_share_checked(
+            &mut agg_share,
+            AggregateStoreMergeReq {
+                agg_share_delta,
+                report_checksums: report_checksums.clone(),
+            },
+            included,
+        )
+        .unwrap();
+        if res == AggregateStoreMergeResult::Ok {
+            included_reports.extend(report_checksums);
+        }
+        (res, agg_share.report_count, agg_share.checksum)
+    };
+
+    let (res, count, first_checksum) = merge(&[[1; 32], [2; 32]]);
+    assert_eq!(res, AggregateStoreMergeResult::Ok);
+    assert_eq!(count, 2);
+
+    // Merging the same reports again (e.g., because the aggregation job was retried) is a no-op.
+    let (res, count, checksum) = merge(&[[2; 32], [1; 32]]);
+    assert_eq!(res, AggregateStoreMergeResult::AlreadyMerged);
+    assert_eq!(count, 2);
+    assert_eq!(checksum, first_checksum);
+
+    // Merging a set that partially overlaps is rejected.
+    let (res, count, checksum) = merge(&[[2; 32], [3; 32]]);
+    assert_eq!(
+        res,
+        AggregateStoreMergeResult::ErrReportOverlap(vec![hex::encode([2; 32])])
+    );
+    assert_eq!(count, 2);
+    assert_eq!(checksum, first_checksum);
+
+    // New reports are still merged.
+    let (res, count, _checksum) = merge(&[[3; 32]]);
+    assert_eq!(res, AggregateStoreMergeResult::Ok);
+    assert_eq!(count, 3);
+}
+
+/// Let other futures run before continuing.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[test]
+fn aggregate_store_concurrent_merges() {
+    // Model a burst of aggregation jobs merging into the same bucket at once. Each merge is a
+    // read-modify-write of the stored aggregate share with storage operations awaited in between.
+    // The durable object's input gate keeps the merges from interleaving; this is modeled by a
+    // lock held for the duration of each merge.
+    let deltas: Vec<(DapAggregateShare, Vec<String>)> = (0..100u8)
+        .map(|i| {
+            let mut agg_share_delta = DapAggregateShare::default();
+            let mut report_checksums = Vec::new();
+            for j in 0..=i % 7 {
+                let mut checksum = [0; 32];
+                checksum[0] = i;
+                checksum[1] = j;
+                for (x, y) in agg_share_delta.checksum.iter_mut().zip(checksum) {
+                    *x ^= y;
+                }
+                agg_share_delta.report_count += 1;
+                report_checksums.push(hex::encode(checksum));
+            }
+            (agg_share_delta, report_checksums)
+        })
+        .collect();
+
+    let mut want = DapAggregateShare::default();
+    for (agg_share_delta, _) in &deltas {
+        want.merge(agg_share_delta.clone()).unwrap();
+    }
+
+    let mut rng = thread_rng();
+    for _ in 0..10 {
+        let stored = RefCell::new(DapAggregateShare::default());
+        let input_gate = AsyncMutex::new(());
+        let merge = |(agg_share_delta, report_checksums): (DapAggregateShare, Vec<String>)| {
+            let stored = &stored;
+            let input_gate = &input_gate;
+            async move {
+                let _gate = input_gate.lock().await;
+                let mut agg_share = stored.borrow().clone();
+                // Let the other merges run while storage is read from and written to.
+                yield_now().await;
+                let res = merge_agg_share_checked(
+                    &mut agg_share,
+                    AggregateStoreMergeReq {
+                        agg_share_delta,
+                        report_checksums,
+                    },
+                    Vec::new(),
+                )
+                .unwrap();
+                yield_now().await;
+                *stored.borrow_mut() = agg_share;
+                res
+            }
+        };
+
+        let mut order = deltas.clone();
+        order.shuffle(&mut rng);
+        let results = block_on(join_all(order.into_iter().map(merge)));
+        assert!(results
+            .iter()
+            .all(|res| *res == AggregateStoreMergeResult::Ok));
+
+        // No merge was lost.
+        let agg_share = stored.into_inner();
+        assert_eq!(agg_share.report_count, want.report_count);
+        assert_eq!(agg_share.checksum, want.checksum);
+    }
+}
+
+// Simulate the LeaderBatchQueue assigning reports to batches over a sequence of requests. Return
+// the report count of each closed batch and the report count of the batch being filled.
+fn assign_reports(bounds: BatchSizeBounds, requests: &[usize]) -> (Vec<usize>, usize) {
+    let mut closed = Vec::new();
+    let mut curr = 0;
+    for &num_reports in requests {
+        let mut num_unassigned = num_reports;
+        while num_unassigned > 0 {
+            let (num_assigned, close) = fill_batch(bounds, curr, num_unassigned);
+            curr += num_assigned;
+            num_unassigned -= num_assigned;
+            if close {
+                closed.push(curr);
+                curr = 0;
+            }
+        }
+    }
+    (closed, curr)
+}
+
+#[test]
+fn leader_batch_queue_fills_batches_up_to_max() {
+    let bounds = BatchSizeBounds { min: 10, max: 15 };
+
+    // Batches are filled up to the maximum when enough reports are available.
+    assert_eq!(assign_reports(bounds, &[40]), (vec![15, 15, 10], 0));
+    assert_eq!(assign_reports(bounds, &[35]), (vec![15, 15], 5));
+
+    // A batch that reaches the minimum is closed once the request's reports are assigned.
+    assert_eq!(assign_reports(bounds, &[12, 3]), (vec![12], 3));
+
+    // A batch below the minimum stays open until it reaches the minimum.
+    assert_eq!(assign_reports(bounds, &[4, 4, 4]), (vec![12], 0));
+
+    // Batches never exceed the maximum and are never closed below the minimum.
+    let mut rng = thread_rng();
+    let requests: Vec<usize> = (0..100).map(|_| rng.gen_range(0..40)).collect();
+    let (closed, curr) = assign_reports(bounds, &requests);
+    assert!(closed
+        .iter()
+        .all(|&count| bounds.min <= count && count <= bounds.max));
+    assert!(curr <= bounds.max);
+    assert_eq!(
+        closed.iter().sum::<usize>() + curr,
+        requests.iter().sum::<usize>()
+    );
+}
@@ -4,29 +4,41 @@
 use crate::{
     config::DaphneWorkerConfig,
     durable::{
-        durable_name_queue,
         leader_agg_job_queue::{
             DURABLE_LEADER_AGG_JOB_QUEUE_FINISH, DURABLE_LEADER_AGG_JOB_QUEUE_PUT,
         },
-        state_get, state_set_if_not_exists, DurableConnector, DurableOrdered,
+        state_get, state_set_if_not_exists, DurableConnector, DurableOrdered, QueueName,
         BINDING_DAP_LEADER_AGG_JOB_QUEUE, BINDING_DAP_REPORTS_PENDING, MAX_KEYS,
     },
     initialize_tracing, int_err,
 };
-use daphne::{messages::TaskId, DapVersion};
+use daphne::{
+    messages::{Report, TaskId},
+    DapVersion,
+};
+use prio::codec::ParameterizedDecode;
 use serde::{Deserialize, Serialize};
-use std::cmp::min;
+use std::{cmp::min, time::Duration};
 use tracing::debug;
 use worker::*;
 
 pub(crate) const DURABLE_REPORTS_PENDING_GET: &str = "/internal/do/reports_pending/get";
 pub(crate) const DURABLE_REPORTS_PENDING_PUT: &str = "/internal/do/reports_pending/put";
+pub(crate) const DURABLE_REPORTS_PENDING_AUDIT: &str = "/internal/do/reports_pending/audit";
+pub(crate) const DURABLE_REPORTS_PENDING_PURGE: &str = "/internal/do/reports_pending/purge";
+pub(crate) const DURABLE_REPORTS_PENDING_CONTAINS: &str = "/internal/do/reports_pending/contains";
 
-#[derive(Deserialize, Serialize)]
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum ReportsPendingResult {
     Ok,
     ErrReportExists,
+
+    /// The instance holds the maximum number of pending reports. The Client may retry after the
+    /// indicated number of seconds.
+    AtCapacity {
+        retry_after: u64,
+    },
 }
 
 #[derive(Deserialize, Serialize)]
@@ -47,12 +59,47 @@ impl PendingReport {
         match self.version {
             DapVersion::Draft02 if self.report_hex.len() >= 96 => Some(&self.report_hex[64..96]),
             DapVersion::Draft04 if self.report_hex.len() >= 32 => Some(&self.report_hex[..32]),
-            DapVersion::Unknown => unreachable!("unhandled version {:?}", self.version),
+            DapVersion::Draft09 | DapVersion::Unknown => {
+                unreachable!("unhandled version {:?}", self.version)
+            }
             _ => None,
         }
     }
 }
 
+/// Check that a value read from `ReportsPending` storage decodes to a `PendingReport` whose report
+/// can be decoded. If not, then return a description of the problem.
+pub(crate) fn audit_pending_report(value: serde_json::Value) -> std::result::Result<(), String> {
+    let pending_report: PendingReport = serde_json::from_value(value)
+        .map_err(|e| format!("failed to decode pending report: {e}"))?;
+    if pending_report.version == DapVersion::Unknown {
+        return Err("pending report has unknown version".into());
+    }
+    let report_bytes = hex::decode(&pending_report.report_hex)
+        .map_err(|e| format!("failed to decode report hex: {e}"))?;
+    Report::get_decoded_with_param(&pending_report.version, &report_bytes)
+        .map_err(|e| format!("failed to decode report: {e}"))?;
+    Ok(())
+}
+
+/// Check whether an instance holding `report_count` pending reports can accept another one. If
+/// not, then return the result asking the Client to retry after `retry_after`. The number of
+/// pending reports is not limited if `max_reports` is not set.
+pub(crate) fn check_capacity(
+    report_count: u64,
+    max_reports: Option<u64>,
+    retry_after: Duration,
+) -> Option<ReportsPendingResult> {
+    match max_reports {
+        Some(max_reports) if report_count >= max_reports => {
+            Some(ReportsPendingResult::AtCapacity {
+                retry_after: retry_after.as_secs(),
+            })
+        }
+        _ => None,
+    }
+}
+
 /// Durable Object (DO) for storing reports waiting to be processed.
 ///
 /// The following API endpoints are defined:
@@ -60,22 +107,35 @@ impl PendingReport {
 /// - `DURABLE_REPORTS_PENDING_PUT`: Used to store a report uploaded by a Client. Whenever this
 ///   instance becomes non-empty, an aggregate job is created and dispatched to
 ///   `LeaderAggregationJobQueue`. If report is found in this instance with the same ID, then an
-///   error is returned.
+///   error is returned. If the number of pending reports is limited by the configuration and the
+///   instance is at capacity, then the report is rejected and the Client is asked to retry later.
 ///
 /// - `DURABLE_REPORTS_PENDING_GET`: Used to drain reports from storage so that they can be
 ///   aggregated. Whenever the instance becomes empty, the aggregation job is removed from
 ///   `LeadeerAggregationJobQueue`.
 ///
+/// - `DURABLE_REPORTS_PENDING_AUDIT`: Used for testing. Check that each pending report in storage
+///   can be decoded, without removing any reports from storage.
+///
+/// - `DURABLE_REPORTS_PENDING_PURGE`: Used to free storage once the task has expired. All reports
+///   are deleted and the aggregation job, if any, is removed from `LeaderAggregationJobQueue`.
+///
+/// - `DURABLE_REPORTS_PENDING_CONTAINS`: Used for debugging. Check if a report is pending, without
+///   removing it from storage.
+///
 /// The schema for stored reports is as follows:
 ///
 /// ```text
 /// [Pending report]  pending/<report_id> -> PendingReport
 /// [Aggregation job] agg_job -> DurableOrdered<PendingReport>
+/// [Report count]    report_count -> u64
 /// ```
 ///
 /// where `<report_id>` is the ID of the report. The value is the hex-encoded report. The
 /// aggregation job consists of a reference to the name of this DO instance stored in a queue in
-/// `LeaderAggregationJobQueue`.
+/// `LeaderAggregationJobQueue`. The number of pending reports is tracked whether or not it is
+/// limited, so that it is accurate if a limit is configured later. If the count was never stored
+/// (e.g., the instance predates it), then it is derived from the pending reports.
 #[durable_object]
 pub struct ReportsPending {
     #[allow(dead_code)]
@@ -85,6 +145,21 @@ pub struct ReportsPending {
     touched: bool,
 }
 
+impl ReportsPending {
+    /// Return the number of pending reports.
+    async fn report_count(&self) -> Result<u64> {
+        if let Some(report_count) = state_get(&self.state, "report_count").await? {
+            return Ok(report_count);
+        }
+        let pending = self
+            .state
+            .storage()
+            .list_with_options(ListOptions::new().prefix("pending/"))
+            .await?;
+        Ok(pending.size().into())
+    }
+}
+
 #[durable_object]
 impl DurableObject for ReportsPending {
     fn new(state: State, env: Env) -> Self {
@@ -130,7 +205,13 @@ impl DurableObject for ReportsPending {
                 // NOTE In order to support DAP tasks that require longer batch lifetimes, it will
                 // necessary to check if the lifetime has been reached before removing reports from
                 // storage. We might consider putting reports in KV instead.
+                let drained = keys.len() as u64;
+                let report_count = self.report_count().await?;
                 self.state.storage().delete_multiple(keys).await?;
+                self.state
+                    .storage()
+                    .put("report_count", report_count.saturating_sub(drained))
+                    .await?;
 
                 // Check if this bucket is now empty, and if so, remove it from the agg job queue.
                 let empty = self
@@ -159,7 +240,7 @@ impl DurableObject for ReportsPending {
                             .post(
                                 BINDING_DAP_LEADER_AGG_JOB_QUEUE,
                                 DURABLE_LEADER_AGG_JOB_QUEUE_FINISH,
-                                durable_name_queue(0),
+                                QueueName::new(0),
                                 &agg_job,
                             )
                             .await?;
@@ -183,6 +264,16 @@ impl DurableObject for ReportsPending {
                 let report_id_hex = pending_report
                     .report_id_hex()
                     .ok_or_else(|| int_err("failed to parse report ID from report"))?;
+                // Push back on the Client if this instance is at capacity.
+                let report_count = self.report_count().await?;
+                if let Some(res) = check_capacity(
+                    report_count,
+                    self.config.reports_pending_max_reports,
+                    self.config.reports_pending_retry_after,
+                ) {
+                    return Response::from_json(&res);
+                }
+
                 let key = format!("pending/{report_id_hex}");
                 let exists = state_set_if_not_exists(&self.state, &key, &pending_report)
                     .await?
@@ -190,6 +281,10 @@ impl DurableObject for ReportsPending {
                 if exists {
                     return Response::from_json(&ReportsPendingResult::ErrReportExists);
                 }
+                self.state
+                    .storage()
+                    .put("report_count", report_count + 1)
+                    .await?;
 
                 // Check if processing for this bucket of reports has been scheduled. If not, add
                 // this bucket to the aggregation job queue.
@@ -204,7 +299,7 @@ impl DurableObject for ReportsPending {
                         .post(
                             BINDING_DAP_LEADER_AGG_JOB_QUEUE,
                             DURABLE_LEADER_AGG_JOB_QUEUE_PUT,
-                            durable_name_queue(0),
+                            QueueName::new(0),
                             &agg_job,
                         )
                         .await?;
@@ -214,6 +309,67 @@ impl DurableObject for ReportsPending {
                 Response::from_json(&ReportsPendingResult::Ok)
             }
 
+            // Check that each pending report can be decoded. Storage is left unmodified.
+            //
+            // Output: `Vec<(String, String)>` (key and description of each corrupted entry)
+            (DURABLE_REPORTS_PENDING_AUDIT, Method::Get) => {
+                let opt = ListOptions::new().prefix("pending/");
+                let iter = self.state.storage().list_with_options(opt).await?.entries();
+                let mut item = iter.next()?;
+                let mut corrupted = Vec::new();
+                while !item.done() {
+                    let (key, value): (String, serde_json::Value) =
+                        serde_wasm_bindgen::from_value(item.value()).map_err(int_err)?;
+                    if let Err(reason) = audit_pending_report(value) {
+                        corrupted.push((key, reason));
+                    }
+                    item = iter.next()?;
+                }
+
+                Response::from_json(&corrupted)
+            }
+
+            // Delete all reports, along with the aggregation job that references this instance.
+            //
+            // Output: `bool` (indicates whether the instance held any state)
+            (DURABLE_REPORTS_PENDING_PURGE, Method::Post) => {
+                let agg_job: Option<DurableOrdered<String>> =
+                    state_get(&self.state, "agg_job").await?;
+                let non_empty = agg_job.is_some()
+                    || self
+                        .state
+                        .storage()
+                        .list_with_options(ListOptions::new().prefix("pending/").limit(1))
+                        .await?
+                        .size()
+                        > 0;
+                self.state.storage().delete_all().await?;
+                if let Some(agg_job) = agg_job {
+                    durable
+                        .post(
+                            BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+                            DURABLE_LEADER_AGG_JOB_QUEUE_FINISH,
+                            QueueName::new(0),
+                            &agg_job,
+                        )
+                        .await?;
+                }
+
+                debug!("purged bucket {id_hex}");
+                Response::from_json(&non_empty)
+            }
+
+            // Check if a report is pending.
+            //
+            // Input: `report_id_hex: String` (hex-encoded report ID)
+            // Output: `bool`
+            (DURABLE_REPORTS_PENDING_CONTAINS, Method::Post) => {
+                let report_id_hex: String = req.json().await?;
+                let pending_report: Option<PendingReport> =
+                    state_get(&self.state, &format!("pending/{report_id_hex}")).await?;
+                Response::from_json(&pending_report.is_some())
+            }
+
             _ => Err(int_err(format!(
                 "ReportsPending: unexpected request: method={:?}; path={:?}",
                 req.method(),
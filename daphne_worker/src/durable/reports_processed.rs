@@ -3,30 +3,59 @@
 
 use crate::{
     config::DaphneWorkerConfig,
-    durable::{state_set_if_not_exists, BINDING_DAP_REPORTS_PROCESSED},
+    durable::{state_get, state_set_if_not_exists, BINDING_DAP_REPORTS_PROCESSED},
     initialize_tracing, int_err,
 };
 use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tracing::warn;
 use worker::*;
 
 pub(crate) const DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED: &str =
     "/internal/do/report_store/mark_aggregated";
+pub(crate) const DURABLE_REPORTS_PROCESSED_CONTAINS: &str = "/internal/do/report_store/contains";
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct ReportsProcessedMarkAggregatedRequest {
+    pub(crate) agg_job_id_base64url: String,
+    pub(crate) report_id_hex_set: Vec<String>,
+}
+
+/// The value stored for a processed report.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum ProcessedBy {
+    /// The aggregation job that processed the report.
+    AggJob(String),
+
+    /// The report was processed before the aggregation job was recorded.
+    Unknown(bool),
+}
 
 /// Durable Object (DO) for tracking which reports have been processed.
 ///
-/// This object defines a single API endpoint, `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`, which
-/// is used to mark a set of reports as aggregated. It returns the set of reports in that have
-/// already been aggregated (and thus need to be rejected by the caller).
+/// This object defines the following API endpoints:
+///
+/// - `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`: Used to mark a set of reports as aggregated by an
+///   aggregation job. It returns the set of reports in that have already been aggregated by a
+///   different aggregation job (and thus need to be rejected by the caller). Reports marked by the
+///   same aggregation job are not returned so that the job can be re-initialized after the Helper
+///   loses its state.
+///
+/// - `DURABLE_REPORTS_PROCESSED_CONTAINS`: Used for debugging. Check if a report has been
+///   processed without marking it as aggregated.
 ///
 /// The schema for stored report IDs is as follows:
 ///
 /// ```text
-///     processed/<report_id> -> bool
+///     processed/<report_id> -> String
 /// ```
 ///
-/// where `<report_id>` is the hex-encoded report ID.
+/// where `<report_id>` is the hex-encoded report ID and the value is the base64url-encoded ID of
+/// the aggregation job that processed the report. (Reports processed by older versions of this
+/// object are mapped to `true` instead.)
 #[durable_object]
 pub struct ReportsProcessed {
     #[allow(dead_code)]
@@ -38,16 +67,19 @@ pub struct ReportsProcessed {
 }
 
 impl ReportsProcessed {
-    /// Check if the report has been processed. If not, return None; otherwise, return the ID.
-    async fn to_checked(&self, report_id_hex: String) -> Result<Option<String>> {
+    /// Check if the report has been processed by an aggregation job other than the given one. If
+    /// not, return None; otherwise, return the ID.
+    async fn to_checked(
+        &self,
+        agg_job_id_base64url: &str,
+        report_id_hex: String,
+    ) -> Result<Option<String>> {
         let key = format!("processed/{report_id_hex}");
-        let processed: bool = state_set_if_not_exists(&self.state, &key, &true)
-            .await?
-            .unwrap_or(false);
-        if processed {
-            Ok(Some(report_id_hex))
-        } else {
-            Ok(None)
+        let processed_by = ProcessedBy::AggJob(agg_job_id_base64url.to_string());
+        match state_set_if_not_exists(&self.state, &key, &processed_by).await? {
+            None => Ok(None),
+            Some(ProcessedBy::AggJob(id)) if id == agg_job_id_base64url => Ok(None),
+            Some(..) => Ok(Some(report_id_hex)),
         }
     }
 }
@@ -77,16 +109,19 @@ impl DurableObject for ReportsProcessed {
         );
 
         match (req.path().as_ref(), req.method()) {
-            // Mark a set of reports as aggregated. Return the set of report IDs that already
-            // exist.
+            // Mark a set of reports as aggregated. Return the set of report IDs that were already
+            // marked by a different aggregation job.
             //
-            // Input: `report_id_hex_set: Vec<String>` (hex-encoded report IDs)
+            // Input: `ReportsProcessedMarkAggregatedRequest`
             // Output: `Vec<String>` (subset of the inputs that already exist).
             (DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED, Method::Post) => {
-                let report_id_hex_set: Vec<String> = req.json().await?;
+                let ReportsProcessedMarkAggregatedRequest {
+                    agg_job_id_base64url,
+                    report_id_hex_set,
+                } = req.json().await?;
                 let mut requests = Vec::new();
                 for report_id_hex in report_id_hex_set.into_iter() {
-                    requests.push(self.to_checked(report_id_hex));
+                    requests.push(self.to_checked(&agg_job_id_base64url, report_id_hex));
                 }
 
                 let responses: Vec<Option<String>> = try_join_all(requests).await?;
@@ -94,6 +129,17 @@ impl DurableObject for ReportsProcessed {
                 Response::from_json(&res)
             }
 
+            // Check if a report has been processed.
+            //
+            // Input: `report_id_hex: String` (hex-encoded report ID)
+            // Output: `bool`
+            (DURABLE_REPORTS_PROCESSED_CONTAINS, Method::Post) => {
+                let report_id_hex: String = req.json().await?;
+                let processed: Option<ProcessedBy> =
+                    state_get(&self.state, &format!("processed/{report_id_hex}")).await?;
+                Response::from_json(&processed.is_some())
+            }
+
             _ => Err(int_err(format!(
                 "ReportsProcessed: unexpected request: method={:?}; path={:?}",
                 req.method(),
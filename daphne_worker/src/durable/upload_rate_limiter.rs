@@ -0,0 +1,165 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    config::DaphneWorkerConfig,
+    durable::{state_get, BINDING_DAP_UPLOAD_RATE_LIMITER},
+    initialize_tracing, int_err, now,
+};
+use daphne::{messages::Time, DapRateLimit};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{trace, warn};
+use worker::*;
+
+pub(crate) const DURABLE_UPLOAD_RATE_LIMITER_TAKE: &str = "/internal/do/upload_rate_limiter/take";
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct UploadRateLimiterRequest {
+    pub(crate) limit: DapRateLimit,
+    pub(crate) task_expiration: Time,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UploadRateLimiterResult {
+    Ok,
+    ErrRetryAfter(u64),
+}
+
+/// State of a token bucket. Tokens are replenished at a fixed rate, up to the burst size, and
+/// each request consumes one token.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct TokenBucket {
+    /// Number of tokens currently available.
+    tokens: u64,
+
+    /// Time (in seconds since the beginning of UNIX time) at which tokens were last replenished.
+    last_refill: Time,
+}
+
+impl TokenBucket {
+    /// Create a bucket that is full at time `now`.
+    pub(crate) fn full(limit: &DapRateLimit, now: Time) -> Self {
+        Self {
+            tokens: limit.burst,
+            last_refill: now,
+        }
+    }
+
+    /// Try to consume a token at time `now`. If no token is available, then return the number of
+    /// seconds after which the request may be retried.
+    pub(crate) fn try_take(&mut self, limit: &DapRateLimit, now: Time) -> Option<u64> {
+        if now > self.last_refill {
+            let refill = (now - self.last_refill).saturating_mul(limit.rate);
+            self.tokens = self.tokens.saturating_add(refill).min(limit.burst);
+            self.last_refill = now;
+        }
+
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            None
+        } else {
+            // Tokens are replenished once per second.
+            Some(1)
+        }
+    }
+}
+
+/// Durable Object (DO) for limiting the rate at which reports are uploaded for a task. The naming
+/// scheme for instances is the same as for `LeaderBatchQueue`, i.e., there is one instance per
+/// task.
+///
+/// This object defines the following API endpoints:
+///
+/// - `DURABLE_UPLOAD_RATE_LIMITER_TAKE`: Consume a token, if one is available.
+///
+/// The schema for the data stored by this DO is as follows:
+///
+/// ```text
+/// [Token bucket] bucket -> TokenBucket
+/// ```
+///
+/// The state is deleted once the task expires.
+#[durable_object]
+pub struct UploadRateLimiter {
+    #[allow(dead_code)]
+    state: State,
+    env: Env,
+    config: DaphneWorkerConfig,
+    touched: bool,
+    alarmed: bool,
+}
+
+#[durable_object]
+impl DurableObject for UploadRateLimiter {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        let config =
+            DaphneWorkerConfig::from_worker_env(&env).expect("failed to load configuration");
+        Self {
+            state,
+            env,
+            config,
+            touched: false,
+            alarmed: false,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let id_hex = self.state.id().to_string();
+        ensure_garbage_collected!(req, self, id_hex, BINDING_DAP_UPLOAD_RATE_LIMITER);
+
+        match (req.path().as_ref(), req.method()) {
+            // Consume a token from the bucket.
+            //
+            // Input: `UploadRateLimiterRequest`
+            // Output: `UploadRateLimiterResult`
+            (DURABLE_UPLOAD_RATE_LIMITER_TAKE, Method::Post) => {
+                let UploadRateLimiterRequest {
+                    limit,
+                    task_expiration,
+                } = req.json().await?;
+
+                // Delete the state once the task expires.
+                let now = now();
+                ensure_alarmed!(
+                    self,
+                    Duration::from_secs(task_expiration.saturating_sub(now))
+                );
+
+                // To keep this pair of get and put operations atomic, there should be no await
+                // points between them. See the note below `transaction()` on
+                // https://developers.cloudflare.com/workers/runtime-apis/durable-objects/#transactional-storage-api.
+                let mut bucket: TokenBucket = state_get(&self.state, "bucket")
+                    .await?
+                    .unwrap_or_else(|| TokenBucket::full(&limit, now));
+                let res = match bucket.try_take(&limit, now) {
+                    None => UploadRateLimiterResult::Ok,
+                    Some(retry_after) => UploadRateLimiterResult::ErrRetryAfter(retry_after),
+                };
+                self.state.storage().put("bucket", bucket).await?;
+
+                Response::from_json(&res)
+            }
+
+            _ => Err(int_err(format!(
+                "UploadRateLimiter: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        self.state.storage().delete_all().await?;
+        self.alarmed = false;
+        self.touched = false;
+        trace!(
+            "UploadRateLimiter: deleted instance {}",
+            self.state.id().to_string()
+        );
+        Response::from_json(&())
+    }
+}
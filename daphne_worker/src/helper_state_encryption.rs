@@ -0,0 +1,130 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Encryption at rest of the Helper's aggregation job state.
+//!
+//! If encryption is enabled, then the state is encrypted with AES-256-GCM before it is stored.
+//! The stored value has the form
+//!
+//! ```text
+//! enc:<key_id>:<blob(nonce || ciphertext)>
+//! ```
+//!
+//! where `<key_id>` identifies the key used for encryption. Otherwise the state is stored as a
+//! plain blob. A blob is either hex, or, if the compact encoding is enabled, `b64:` followed by
+//! the base64 encoding, which is two thirds the size. Since hex never contains a ':', the forms
+//! can be told apart by their prefixes, so state written under an earlier configuration can still
+//! be read.
+
+use base64::engine::{general_purpose::STANDARD, Engine};
+use daphne::DapError;
+use rand::{thread_rng, Rng};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use serde::{Deserialize, Serialize};
+
+const ENCRYPTED_HELPER_STATE_PREFIX: &str = "enc:";
+const BASE64_HELPER_STATE_PREFIX: &str = "b64:";
+
+/// Encode a blob of the Helper's state for storage, using base64 if `compact` is set and hex
+/// otherwise.
+pub(crate) fn encode_helper_state_blob(data: &[u8], compact: bool) -> String {
+    if compact {
+        format!("{BASE64_HELPER_STATE_PREFIX}{}", STANDARD.encode(data))
+    } else {
+        hex::encode(data)
+    }
+}
+
+fn decode_helper_state_blob(blob: &str) -> Result<Vec<u8>, DapError> {
+    match blob.strip_prefix(BASE64_HELPER_STATE_PREFIX) {
+        Some(encoded) => STANDARD
+            .decode(encoded)
+            .map_err(|e| DapError::Fatal(e.to_string())),
+        None => hex::decode(blob).map_err(|e| DapError::Fatal(e.to_string())),
+    }
+}
+
+/// Symmetric key used to encrypt the Helper's state.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct HelperStateEncryptionKey {
+    /// Identifies the key. This is stored alongside each ciphertext so that the key can be
+    /// rotated.
+    pub(crate) id: u8,
+
+    /// The AES-256-GCM key.
+    #[serde(with = "hex")]
+    pub(crate) key: [u8; 32],
+}
+
+impl HelperStateEncryptionKey {
+    fn aead_key(&self) -> Result<LessSafeKey, DapError> {
+        let key = UnboundKey::new(&AES_256_GCM, &self.key)
+            .map_err(|_| DapError::fatal("failed to construct helper state encryption key"))?;
+        Ok(LessSafeKey::new(key))
+    }
+}
+
+/// Encrypt the encoded Helper state under `key`. The `aad` binds the ciphertext to the context in
+/// which it is stored.
+pub(crate) fn seal_helper_state(
+    key: &HelperStateEncryptionKey,
+    aad: &[u8],
+    helper_state: &[u8],
+    compact: bool,
+) -> Result<String, DapError> {
+    let nonce_bytes: [u8; NONCE_LEN] = thread_rng().gen();
+    let mut in_out = helper_state.to_vec();
+    key.aead_key()?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce_bytes),
+            Aad::from(aad),
+            &mut in_out,
+        )
+        .map_err(|_| DapError::fatal("failed to encrypt helper state"))?;
+
+    let mut sealed = nonce_bytes.to_vec();
+    sealed.extend_from_slice(&in_out);
+    Ok(format!(
+        "{ENCRYPTED_HELPER_STATE_PREFIX}{}:{}",
+        key.id,
+        encode_helper_state_blob(&sealed, compact)
+    ))
+}
+
+/// Recover the encoded Helper state from its stored form. If the state is encrypted, then the key
+/// with the stored key ID is used to decrypt it.
+pub(crate) fn open_helper_state(
+    keys: &[HelperStateEncryptionKey],
+    aad: &[u8],
+    stored: &str,
+) -> Result<Vec<u8>, DapError> {
+    let Some(encrypted) = stored.strip_prefix(ENCRYPTED_HELPER_STATE_PREFIX) else {
+        return decode_helper_state_blob(stored);
+    };
+
+    let (key_id, sealed_blob) = encrypted
+        .split_once(':')
+        .ok_or_else(|| DapError::fatal("malformed encrypted helper state"))?;
+    let key_id: u8 = key_id
+        .parse()
+        .map_err(|_| DapError::fatal("malformed encrypted helper state"))?;
+    let key = keys.iter().find(|key| key.id == key_id).ok_or_else(|| {
+        DapError::Fatal(format!(
+            "helper state is encrypted under unknown key {key_id}"
+        ))
+    })?;
+
+    let sealed = decode_helper_state_blob(sealed_blob)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(DapError::fatal("malformed encrypted helper state"));
+    }
+    let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce_bytes)
+        .map_err(|_| DapError::fatal("malformed encrypted helper state"))?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = key
+        .aead_key()?
+        .open_in_place(nonce, Aad::from(aad), &mut in_out)
+        .map_err(|_| DapError::fatal("failed to decrypt helper state"))?;
+    Ok(plaintext.to_vec())
+}
@@ -0,0 +1,81 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::helper_state_encryption::{
+    encode_helper_state_blob, open_helper_state, seal_helper_state, HelperStateEncryptionKey,
+};
+
+fn test_keys() -> Vec<HelperStateEncryptionKey> {
+    vec![
+        HelperStateEncryptionKey {
+            id: 1,
+            key: [1; 32],
+        },
+        HelperStateEncryptionKey {
+            id: 0,
+            key: [0; 32],
+        },
+    ]
+}
+
+#[test]
+fn helper_state_encryption_roundtrip() {
+    let keys = test_keys();
+    let aad = b"v04/task/00/agg_job/00";
+    let helper_state = b"some helper state";
+
+    // Encrypted state carries the key ID and can be decrypted with any configured key, including
+    // one that is no longer used for encryption.
+    for key in keys.iter() {
+        for compact in [false, true] {
+            let stored = seal_helper_state(key, aad, helper_state, compact).unwrap();
+            assert!(stored.starts_with(&format!("enc:{}:", key.id)));
+            assert_eq!(
+                open_helper_state(&keys, aad, &stored).unwrap(),
+                helper_state
+            );
+        }
+    }
+
+    // Plaintext state written before encryption was enabled can still be read.
+    assert_eq!(
+        open_helper_state(&keys, aad, &hex::encode(helper_state)).unwrap(),
+        helper_state
+    );
+}
+
+#[test]
+fn helper_state_encryption_failure() {
+    let keys = test_keys();
+    let aad = b"v04/task/00/agg_job/00";
+    let stored = seal_helper_state(&keys[0], aad, b"some helper state", false).unwrap();
+
+    // The state is bound to the context in which it was stored.
+    assert!(open_helper_state(&keys, b"v04/task/00/agg_job/01", &stored).is_err());
+
+    // The key used for encryption is no longer configured.
+    assert!(open_helper_state(&keys[1..], aad, &stored).is_err());
+
+    // The ciphertext was tampered with.
+    let last = if stored.ends_with('0') { "1" } else { "0" };
+    let mut tampered = stored;
+    tampered.replace_range(tampered.len() - 1.., last);
+    assert!(open_helper_state(&keys, aad, &tampered).is_err());
+}
+
+#[test]
+fn helper_state_compact_encoding() {
+    let aad = b"v04/task/00/agg_job/00";
+    let helper_state = vec![0xff; 300];
+
+    // The compact encoding is tagged and smaller than hex.
+    let compact = encode_helper_state_blob(&helper_state, true);
+    let hex = encode_helper_state_blob(&helper_state, false);
+    assert!(compact.starts_with("b64:"));
+    assert!(compact.len() < hex.len());
+
+    // State stored with either encoding can be read.
+    for stored in [compact, hex] {
+        assert_eq!(open_helper_state(&[], aad, &stored).unwrap(), helper_state);
+    }
+}
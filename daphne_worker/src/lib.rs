@@ -66,8 +66,13 @@
 //!
 //! where <version> is the DAP version, `<task_id>` is the task ID, `<window>` is a batch window,
 //! and `<batch_id>` is a batch ID. A batch window is a UNIX timestamp (in seconds) truncated by
-//! the time precision for the task. (See the `time_precision` paramaeter of
-//! [`DapTaskConfig`](daphne::DapTaskConfig).)
+//! the bucket duration for the task. (See
+//! [`DapTaskConfig::bucket_duration()`](daphne::DapTaskConfig::bucket_duration).)
+//!
+//! Each instance also records the checksum of every report merged into its aggregate share. A
+//! merge whose reports were all merged previously (e.g., because an aggregation job was retried)
+//! is ignored, and a merge that overlaps only partially with previous merges is rejected. This
+//! ensures that no report is counted twice.
 //!
 //! ## Aggregation Jobs (Leader-only)
 //!
@@ -81,7 +86,12 @@
 //! Aggregation jobs are driven by the Leader's main processing loop (see
 //! [`DapLeader::process()`](daphne::roles::DapLeader::process)). The report selector for
 //! Daphne-Worker, [`DaphneWorkerReportSelector`], indicates the number of jobs to fetch at once
-//! (`max_agg_jobs`) and the number of reports to drain per job (`max_reports`).
+//! (`max_agg_jobs`) and the number of reports to drain per job (`max_reports`). Tasks may be
+//! prioritized by assigning them a weight (`task_weights`), which multiplies the number of reports
+//! drained per job for that task. The total number of reports drained at once is bounded by
+//! `max_agg_jobs * max_reports` regardless of the weights.
+//! If `DAP_ADAPTIVE_REPORT_SELECTOR` is configured, then these limits are treated as upper bounds
+//! and are scaled down when aggregation is slow.
 //!
 //! Jobs are handled roughly in order of creation (oldest jobs are handled first). The time at
 //! which an aggregation job was created is used determine the order in which it was processed.
@@ -119,8 +129,26 @@
 //!
 //! where `<version>` is the DAP version and `<task_id>` is the task ID. Each instance maintains a
 //! queue of batch. When a set of reports is drained from a `ReportsPending` instance, the the
-//! batch in the front of the queue is filled first; if the batch is saturated (i.e., the target
-//! batch size is met) then the batch is removed from the queue and the process is repeated.
+//! batch in the front of the queue is filled first, up to the task's maximum batch size. If the
+//! batch is full, or if it has reached the minimum batch size once all of the reports are
+//! assigned, then the batch is removed from the queue and the process is repeated.
+//!
+//! ## Upload Rate Limiting (Leader-only)
+//!
+//! If a task's configuration sets `upload_rate_limit`, then each upload consumes a token from the
+//! `UploadRateLimiter` DO instance for the task before the report is stored. Instances are named
+//! the same way as `LeaderBatchQueue` instances. If no token is available, the upload is rejected
+//! with status 429 and a `Retry-After` header. The instance's state is deleted when the task
+//! expires.
+//!
+//! ## HPKE Replay Cache (Leader-only)
+//!
+//! If `DAP_HPKE_ENC_REPLAY_CACHE_TTL_SECS` is set, then each upload checks the HPKE encapsulated
+//! key of the Leader's input share against the `HpkeEncReplayCache` DO instance for the task, and
+//! records the key once the report has been stored. Instances are named the same way as
+//! `LeaderBatchQueue` instances. An upload that reuses a key recorded within the TTL is rejected.
+//! Each key is stored under its own key in the instance and is deleted by the instance's alarm once
+//! it expires. The instance's state is deleted when the task expires.
 //!
 //! ## Storage of the Helper's State (Helper-only)
 //!
@@ -135,6 +163,21 @@
 //! where `<version>` is the DAP version, `<task_id>` is the task ID, and `<agg_job_id>` is the
 //! aggregation job ID.
 //!
+//! ## Rejection Counts (Leader and Helper)
+//!
+//! If `rejection_counts_flush_interval` is set in the DAP global config, then the number of
+//! rejected reports, broken down by failure reason, is accumulated by each isolate and
+//! periodically merged into the `Counters` DO instance named `rejection_counts`. The counts are
+//! flushed in the background so that they do not add latency to requests.
+//!
+//! ## Ingested Report Count (Leader-only)
+//!
+//! If `DAP_REPORTS_INGESTED_FLUSH_INTERVAL_SECS` is set, then the number of reports ingested, across
+//! all tasks, is accumulated by each isolate and periodically added to the `Counters` DO instance
+//! named `reports_ingested`. As with the rejection counts, the count is flushed in the background
+//! and survives isolate restarts. It can be read from `GET /reports_ingested` with the admin bearer
+//! token.
+//!
 //! # Environment Variables
 //!
 //! The runtime behavior of Daphne-Worker is controlled by the environment variables defined in the
@@ -151,25 +194,54 @@
 //! | `DAP_DEPLOYMENT` | `String` | no | Deployment type, only "prod" for now. |
 //! | `DAP_REPORT_SHARD_COUNT` | `u64` | no | Number of report shards per storage epoch. |
 //! | `DAP_REPORT_SHARD_KEY` | `String` | yes | Hex-encoded key used to hash a report into one of the report shards. |
+//! | `DAP_HELPER_REQUEST_TIMEOUT_MILLIS` | `u64` | no | Leader: Optional timeout for requests sent to the Helper. |
+//! | `DAP_HELPER_REQUEST_MAX_RETRIES` | `u32` | no | Leader: Number of times to retry a request to the Helper after a transient failure (default 0). |
+//! | `DAP_ADAPTIVE_REPORT_SELECTOR` | `AdaptiveReportSelectorConfig` | no | Leader: Optional bounds for scaling the report selector based on aggregation latency. |
+//! | `DAP_TASK_CONFIG_CACHE_TTL_SECS` | `u64` | no | Number of seconds for which a task config or Leader bearer token read from KV is cached by the isolate (default 300). |
+//! | `DAP_TASK_CONFIG_CACHE_CAPACITY` | `usize` | no | Maximum number of task configs, and of Leader bearer tokens, cached by the isolate. Must be positive (default 1000). |
+//! | `DAP_TASKPROV_IN_MEMORY` | `bool` | no | If "true", then taskprov task configs and the Leader's bearer token are kept in the memory of the isolate that provisioned them until the task expires, instead of being written to KV (default "false"). |
+//! | `DAP_TASKPROV_POLICY` | [`TaskprovPolicy`](daphne::taskprov::TaskprovPolicy) | no | Optional criteria for opting in to tasks provisioned via taskprov. |
+//! | `DAP_REPORTS_PENDING_MAX_REPORTS` | `u64` | no | Leader: Optional maximum number of reports pending in each ReportsPending instance. Uploads to an instance at capacity are rejected with status 429. |
+//! | `DAP_REPORTS_PENDING_RETRY_AFTER_SECS` | `u64` | no | Leader: Value of the Retry-After header sent when a ReportsPending instance is at capacity (default 60). |
+//! | `DAP_HELPER_STATE_ENCRYPTION_KEYS` | `Vec<HelperStateEncryptionKey>` | yes | Helper: Optional list of keys used to encrypt aggregation job state at rest. The first key is used for encryption; all keys may be used for decryption. |
+//! | `DAP_HELPER_STATE_COMPACT_ENCODING` | `bool` | no | Helper: If "true", then aggregation job state is stored as base64 instead of hex (default "false"). State stored with either encoding can be read. |
+//! | `DAP_COLLECTION_JOB_RESULT_TTL_SECS` | `u64` | no | Leader: Optional number of seconds for which the result of a finished collection job is kept. Once deleted, polling the job indicates that it has expired for the same number of seconds, after which the job is unknown. |
+//! | `DAP_MAX_PENDING_COLLECTION_JOBS_PER_TASK` | `u64` | no | Leader: Optional maximum number of collection jobs pending for each task. New collection jobs for a task at capacity are rejected with status 429. |
+//! | `DAP_PENDING_COLLECTION_JOBS_RETRY_AFTER_SECS` | `u64` | no | Leader: Number of seconds a Collector is asked to wait before retrying a collection job rejected because its task was at capacity (default 60). |
+//! | `DAP_DURABLE_LOCATION_HINTS` | `HashMap<String, String>` | no | Optional location hint for each durable object binding, e.g., `{"DAP_AGGREGATE_STORE": "weur"}`. The hint is applied when an instance is created. Hints are best-effort and only affect latency; they do not keep data within a region. Unrecognized bindings are rejected. |
+//! | `DAP_HPKE_ENC_REPLAY_CACHE_TTL_SECS` | `u64` | no | Leader: Optional number of seconds for which the HPKE encapsulated keys of uploaded reports are remembered. Uploads that reuse a remembered key are rejected. |
+//! | `DAP_REQUEST_BODY_LIMITS` | `RequestBodyLimits` | no | Optional maximum size in bytes of request bodies, by kind of request as determined by the route. Requests that exceed the limit are rejected with status 413 before they are decoded. |
+//! | `DAP_MAX_CONCURRENT_DURABLE_REQUESTS` | `usize` | no | Maximum number of requests to durable objects in flight at once when a request fans out to many instances (default 32). |
+//! | `DAP_HPKE_CONFIG_GC_GRACE_PERIOD` | `u64` | no | Number of seconds after an HPKE receiver config expires before it is deleted when the HPKE config is next rotated (default one week). |
+//! | `DAP_COLLECT_POLL_RETRY_AFTER_SECS` | `u64` | no | Leader: Optional number of seconds a Collector polling a pending collection job is asked to wait before polling again. A task may override this with its `collect_poll_retry_after` parameter. If neither is set, then no Retry-After header is sent. |
+//! | `DAP_COLLECT_POLL_RETRY_AFTER_JITTER_SECS` | `u64` | no | Leader: Maximum number of seconds chosen at random and added to the Retry-After header of pending collection job responses (default 0). |
+//! | `DAP_COLLECTION_COMPRESSION_MIN_BYTES` | `usize` | no | Leader: Optional minimum size in bytes of a collection response body for it to be compressed. If set, then larger responses are compressed with brotli or gzip, as accepted by the Collector's `Accept-Encoding` header. |
+//! | `DAP_REPORTS_INGESTED_FLUSH_INTERVAL_SECS` | `u64` | no | Leader: Optional minimum number of seconds between flushes of the isolate's ingested report count to the `Counters` DO. If not set, then ingested reports are not counted. |
+//! | `DAP_HPKE_CONFIG_SIGNING_KEY` | `String` | yes | Optional hex-encoded Ed25519 seed. If set, then `GET /:version/hpke_config/signed` returns the HPKE config list along with its signature under this key, for Clients that obtain the config out-of-band. |
 pub use crate::tracing_utils::initialize_tracing;
 use crate::{
-    config::{DaphneWorkerIsolateState, DaphneWorkerRequestState},
-    dap::dap_response_to_worker,
+    config::{DaphneWorker, DaphneWorkerIsolateState, DaphneWorkerRequestState},
+    dap::IntoWorkerResponse,
+    request_body_limits::RequestBody,
 };
 use daphne::{
     aborts::DapAbort,
     auth::BearerToken,
     constants::DapMediaType,
-    messages::{CollectionJobId, Duration, TaskId, Time},
+    hpke::HpkeReceiverConfig,
+    messages::{
+        BatchId, BatchSelector, CollectionJobId, Duration, Interval, ReportId, TaskId, Time,
+    },
     roles::{DapAggregator, DapHelper, DapLeader},
-    DapCollectJob, DapError, DapResponse, DapVersion,
+    DapAggregateShare, DapCollectJob, DapError, DapQueryConfig, DapRateLimit, DapResponse,
+    DapTaskConfig, DapVersion,
 };
 pub use error_reporting::ErrorReporter;
 use once_cell::sync::OnceCell;
 use prio::codec::ParameterizedEncode;
 use serde::{Deserialize, Serialize};
-use std::str;
-use tracing::{debug, error, info_span, Instrument};
+use std::{collections::HashMap, str};
+use tracing::{debug, error, field, info_span, Instrument};
 use worker::*;
 
 /// Parameters used by the Leader to select a set of reports for aggregation.
@@ -180,6 +252,32 @@ pub struct DaphneWorkerReportSelector {
 
     /// Maximum number of reports to drain for each aggregation job.
     pub max_reports: u64,
+
+    /// Relative priority of tasks, keyed by the URL-safe base64 encoding of the task ID. Up to
+    /// `weight * max_reports` reports may be drained for each aggregation job of a task with the
+    /// given weight. Tasks that are not listed, or whose weight is 0, have weight 1. In any case,
+    /// at most `max_agg_jobs * max_reports` reports are drained at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_weights: Option<HashMap<String, u64>>,
+}
+
+impl DaphneWorkerReportSelector {
+    /// Maximum number of reports to drain for each aggregation job of the given task.
+    pub(crate) fn max_reports_for_task(&self, task_id: &TaskId) -> u64 {
+        let weight = self
+            .task_weights
+            .as_ref()
+            .and_then(|task_weights| task_weights.get(&task_id.to_base64url()))
+            .copied()
+            .unwrap_or(1)
+            .max(1);
+        self.max_reports.saturating_mul(weight)
+    }
+
+    /// Maximum number of reports to drain at once, across all aggregation jobs.
+    pub(crate) fn report_budget(&self) -> u64 {
+        self.max_agg_jobs.saturating_mul(self.max_reports)
+    }
 }
 
 /// HTTP request handler for Daphne-Worker.
@@ -235,6 +333,22 @@ impl DaphneWorkerRouter<'_> {
         // it's definitely ready for use even if the caller hasn't done anything.
         initialize_tracing(&env);
 
+        // The span covers the entire request, including failures to set up the request state.
+        // When the span is closed, the time elapsed since it was created is logged along with the
+        // recorded fields.
+        let span = request_span(&req);
+        let result = self
+            .handle_request_in_span(req, env)
+            .instrument(span.clone())
+            .await;
+        span.record(
+            "status",
+            result.as_ref().map_or(500, |resp| resp.status_code()),
+        );
+        result
+    }
+
+    async fn handle_request_in_span(&self, req: Request, env: Env) -> Result<Response> {
         #[allow(unused_assignments)]
         let mut uncached_isolate_state: Option<DaphneWorkerIsolateState> = None;
         let shared_state = if env.var("DAP_NO_CACHE").is_ok() {
@@ -249,29 +363,60 @@ impl DaphneWorkerRouter<'_> {
         let router = Router::with_data(&state)
             .get_async("/:version/hpke_config", |req, ctx| async move {
                 let daph = ctx.data.handler(&ctx.env);
-                let req = daph.worker_request_to_dap(req, &ctx).await?;
+                let req = match daph
+                    .worker_request_to_dap(req, &ctx, RequestBody::Other)
+                    .await
+                {
+                    Ok(req) => req,
+                    Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                };
                 match daph
                     .http_get_hpke_config(&req)
-                    .instrument(info_span!("hpke_config"))
+                    .instrument(info_span!("hpke_config", version = ?req.version))
                     .await
                 {
-                    Ok(req) => dap_response_to_worker(req),
+                    Ok(resp) => resp.into_worker_response(),
                     Err(e) => daph.state.dap_abort_to_worker_response(e),
                 }
             })
-            .post_async("/task", |mut req, ctx| async move {
+            .get_async("/:version/hpke_config/signed", |req, ctx| async move {
                 let daph = ctx.data.handler(&ctx.env);
-                let admin_token = req
-                    .headers()
-                    .get("X-Daphne-Worker-Admin-Bearer-Token")?
-                    .map(BearerToken::from);
-
-                if daph.config().admin_token.is_none() {
-                    return Response::error("admin not configured", 400);
+                let version = daph.extract_version_parameter(&req)?;
+                if version == DapVersion::Unknown {
+                    return daph
+                        .state
+                        .dap_abort_to_worker_response(DapAbort::version_unknown());
                 }
-
-                if admin_token.is_none() || admin_token != daph.config().admin_token {
-                    return Response::error("missing or invalid bearer token for admin", 401);
+                match daph
+                    .signed_hpke_config_list(version, None)
+                    .instrument(info_span!("signed_hpke_config", version = ?version))
+                    .await
+                {
+                    Ok(Some(signed)) => Response::from_json(&signed),
+                    Ok(None) => Response::error("HPKE config signing not configured", 404),
+                    Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                }
+            })
+            // Respond with 200 only if KV and DOs are reachable. Intended for load balancers and
+            // monitors.
+            .get_async("/health", |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                match daph
+                    .health_check(&req.cf().colo())
+                    .instrument(info_span!("health"))
+                    .await
+                {
+                    Ok(()) => Response::empty(),
+                    Err(e) => {
+                        error!("health check failed: {e}");
+                        Response::error("storage unavailable", 503)
+                    }
+                }
+            })
+            .post_async("/task", |mut req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                    return Ok(resp);
                 }
 
                 let cmd: InternalTestAddTask = req.json().await?;
@@ -279,6 +424,80 @@ impl DaphneWorkerRouter<'_> {
                     .instrument(info_span!("task"))
                     .await?;
                 Response::empty()
+            })
+            .post_async(
+                "/task/:task_id/rotate_leader_bearer_token",
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    let task_id =
+                        match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                            Some(id) => id,
+                            None => {
+                                return daph.state.dap_abort_to_worker_response(
+                                    DapAbort::BadRequest("missing or malformed task ID".into()),
+                                )
+                            }
+                        };
+
+                    let cmd: RotateLeaderBearerToken = req.json().await?;
+                    daph.rotate_leader_bearer_token(
+                        &task_id,
+                        BearerToken::from(cmd.leader_authentication_token),
+                        std::time::Duration::from_secs(cmd.grace_period),
+                    )
+                    .instrument(info_span!("rotate_leader_bearer_token"))
+                    .await?;
+                    Response::empty()
+                },
+            )
+            // Stop accepting uploads for the task, while still aggregating and collecting the
+            // reports already uploaded.
+            .post_async("/task/:task_id/quiesce", |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                    return Ok(resp);
+                }
+
+                let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                    Some(id) => id,
+                    None => {
+                        return daph
+                            .state
+                            .dap_abort_to_worker_response(DapAbort::BadRequest(
+                                "missing or malformed task ID".into(),
+                            ))
+                    }
+                };
+                match daph
+                    .quiesce_task(&task_id)
+                    .instrument(info_span!("quiesce_task"))
+                    .await
+                {
+                    Ok(()) => Response::empty(),
+                    Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                }
+            })
+            // Leader: Number of reports ingested across all tasks that has been persisted so far.
+            .get_async("/reports_ingested", |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                    return Ok(resp);
+                }
+
+                match daph
+                    .reports_ingested()
+                    .instrument(info_span!("reports_ingested"))
+                    .await
+                {
+                    Ok(count) => Response::from_json(&serde_json::json!({
+                        "reports_ingested": count,
+                    })),
+                    Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                }
             });
 
         let router = match env.var("DAP_AGGREGATOR_ROLE")?.to_string().as_ref() {
@@ -288,11 +507,17 @@ impl DaphneWorkerRouter<'_> {
                     .put_async("/:version/tasks/:task_id/reports", put_report_into_task)
                     .post_async("/v02/collect", |req, ctx| async move {
                         let daph = ctx.data.handler(&ctx.env);
-                        let req = daph.worker_request_to_dap(req, &ctx).await?;
+                        let req = match daph
+                            .worker_request_to_dap(req, &ctx, RequestBody::Other)
+                            .await
+                        {
+                            Ok(req) => req,
+                            Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                        };
 
                         match daph
                             .http_post_collect(&req)
-                            .instrument(info_span!("collect"))
+                            .instrument(info_span!("collect", version = ?req.version))
                             .await
                         {
                             Ok(collect_uri) => {
@@ -334,22 +559,45 @@ impl DaphneWorkerRouter<'_> {
                                 }
                             };
                             let daph = ctx.data.handler(&ctx.env);
-                            let version = daph.extract_version_parameter(&req)?;
+                            let accept_encoding = req.headers().get("Accept-Encoding")?;
+
+                            // This is the URI returned by `init_collect_job()`, which is only
+                            // polled by draft02 Collectors. The response is always encoded for
+                            // draft02.
+                            let version = DapVersion::Draft02;
                             match daph
                                 .poll_collect_job(&task_id, &collect_id)
-                                .instrument(info_span!("poll_collect_job (draft02)"))
+                                .instrument(
+                                    info_span!("poll_collect_job (draft02)", version = ?version),
+                                )
                                 .await
                             {
-                                Ok(DapCollectJob::Done(collect_resp)) => {
-                                    dap_response_to_worker(DapResponse {
-                                        version: DapVersion::Draft02,
-                                        media_type: DapMediaType::Collection,
-                                        payload: collect_resp.get_encoded_with_param(&version),
-                                    })
+                                Ok(DapCollectJob::Done(collect_resp)) => DapResponse {
+                                    version,
+                                    media_type: DapMediaType::Collection,
+                                    payload: collect_resp.get_encoded_with_param(&version),
+                                    cache_max_age: None,
                                 }
+                                .into_compressible_worker_response(
+                                    accept_encoding.as_deref(),
+                                    daph.config().collection_compression_min_bytes,
+                                ),
                                 Ok(DapCollectJob::Pending) => {
-                                    Ok(Response::empty().unwrap().with_status(202))
+                                    daph.pending_collect_job_response(&task_id).await
                                 }
+                                Ok(DapCollectJob::Expired) => daph
+                                    .state
+                                    .dap_abort_to_worker_response(DapAbort::BadRequest(
+                                        "collect job has expired".into(),
+                                    )),
+                                Ok(DapCollectJob::Abandoned) => daph
+                                    .state
+                                    .dap_abort_to_worker_response(DapAbort::BatchOverlap {
+                                        detail: "collect job was abandoned because its batch \
+                                            overlaps a collected batch"
+                                            .into(),
+                                        task_id: task_id.clone(),
+                                    }),
                                 // TODO spec: Decide whether to define this behavior.
                                 Ok(DapCollectJob::Unknown) => daph
                                     .state
@@ -364,11 +612,17 @@ impl DaphneWorkerRouter<'_> {
                         "/:version/tasks/:task_id/collection_jobs/:collect_job_id",
                         |req, ctx| async move {
                             let daph = ctx.data.handler(&ctx.env);
-                            let req = daph.worker_request_to_dap(req, &ctx).await?;
+                            let req = match daph
+                                .worker_request_to_dap(req, &ctx, RequestBody::Other)
+                                .await
+                            {
+                                Ok(req) => req,
+                                Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                            };
 
                             match daph
                                 .http_post_collect(&req)
-                                .instrument(info_span!("collect (PUT)"))
+                                .instrument(info_span!("collect (PUT)", version = ?req.version))
                                 .await
                             {
                                 Ok(_) => Ok(Response::empty().unwrap().with_status(201)),
@@ -380,7 +634,21 @@ impl DaphneWorkerRouter<'_> {
                         "/:version/tasks/:task_id/collection_jobs/:collect_job_id",
                         |req, ctx| async move {
                             let daph = ctx.data.handler(&ctx.env);
-                            let req = daph.worker_request_to_dap(req, &ctx).await?;
+                            let accept_encoding = req.headers().get("Accept-Encoding")?;
+                            let req = match daph
+                                .worker_request_to_dap(req, &ctx, RequestBody::Other)
+                                .await
+                            {
+                                Ok(req) => req,
+                                Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                            };
+                            if req.version == DapVersion::Unknown {
+                                return daph.state.dap_abort_to_worker_response(
+                                    DapAbort::UnsupportedVersion(
+                                        "DAP version of request is not recognized".into(),
+                                    ),
+                                );
+                            }
                             let task_id = match req.task_id() {
                                 Ok(id) => id,
                                 Err(e) => return daph.state.dap_abort_to_worker_response(e),
@@ -403,19 +671,35 @@ impl DaphneWorkerRouter<'_> {
 
                             match daph
                                 .poll_collect_job(task_id, &collect_job_id)
-                                .instrument(info_span!("poll_collect_job"))
+                                .instrument(info_span!("poll_collect_job", version = ?req.version))
                                 .await
                             {
-                                Ok(DapCollectJob::Done(collect_resp)) => {
-                                    dap_response_to_worker(DapResponse {
-                                        version: req.version,
-                                        media_type: DapMediaType::Collection,
-                                        payload: collect_resp.get_encoded_with_param(&req.version),
-                                    })
+                                Ok(DapCollectJob::Done(collect_resp)) => DapResponse {
+                                    version: req.version,
+                                    media_type: DapMediaType::Collection,
+                                    payload: collect_resp.get_encoded_with_param(&req.version),
+                                    cache_max_age: None,
                                 }
+                                .into_compressible_worker_response(
+                                    accept_encoding.as_deref(),
+                                    daph.config().collection_compression_min_bytes,
+                                ),
                                 Ok(DapCollectJob::Pending) => {
-                                    Ok(Response::empty().unwrap().with_status(202))
+                                    daph.pending_collect_job_response(task_id).await
                                 }
+                                Ok(DapCollectJob::Expired) => daph
+                                    .state
+                                    .dap_abort_to_worker_response(DapAbort::BadRequest(
+                                        "collect job has expired".into(),
+                                    )),
+                                Ok(DapCollectJob::Abandoned) => daph
+                                    .state
+                                    .dap_abort_to_worker_response(DapAbort::BatchOverlap {
+                                        detail: "collect job was abandoned because its batch \
+                                            overlaps a collected batch"
+                                            .into(),
+                                        task_id: task_id.clone(),
+                                    }),
                                 // TODO spec: Decide whether to define this behavior.
                                 Ok(DapCollectJob::Unknown) => daph
                                     .state
@@ -430,11 +714,23 @@ impl DaphneWorkerRouter<'_> {
                         // TODO(cjpatton) Only enable this if `self.enable_internal_test` is set.
                         let daph = ctx.data.handler(&ctx.env);
                         let report_sel: DaphneWorkerReportSelector = req.json().await?;
-                        match daph
+                        let adaptive_report_selector =
+                            daph.isolate_state().adaptive_report_selector.as_ref();
+                        let report_sel = match adaptive_report_selector {
+                            Some(adaptive) => adaptive.apply(&report_sel),
+                            None => report_sel,
+                        };
+                        let start = Date::now().as_millis();
+                        let result = daph
                             .process(&report_sel, &daph.state.host)
                             .instrument(info_span!("process"))
-                            .await
-                        {
+                            .await;
+                        if let Some(adaptive) = adaptive_report_selector {
+                            adaptive.observe_latency(std::time::Duration::from_millis(
+                                Date::now().as_millis() - start,
+                            ));
+                        }
+                        match result {
                             Ok(telem) => {
                                 debug!("{:?}", telem);
                                 Response::from_json(&telem)
@@ -532,6 +828,353 @@ impl DaphneWorkerRouter<'_> {
                             .await
                     },
                 )
+                .get_async(
+                    "/internal/test/audit_reports_pending/task/:task_id",
+                    |_req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+                        match daph
+                            .internal_audit_reports_pending(&task_id)
+                            .instrument(info_span!("audit_reports_pending"))
+                            .await
+                        {
+                            Ok(corrupted) => Response::from_json(&corrupted),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async(
+                    "/internal/test/batch_queue/task/:task_id",
+                    |_req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+                        match daph
+                            .internal_peek_batch_queue(&task_id)
+                            .instrument(info_span!("peek_batch_queue"))
+                            .await
+                        {
+                            Ok(batches) => Response::from_json(&batches),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async(
+                    "/internal/test/pending_collection_jobs/task/:task_id",
+                    |_req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+                        match daph
+                            .get_pending_collect_jobs_for_task(&task_id)
+                            .instrument(info_span!("pending_collection_jobs"))
+                            .await
+                        {
+                            Ok(collect_jobs) => Response::from_json(
+                                &collect_jobs
+                                    .iter()
+                                    .map(|(collect_id, _collect_req)| collect_id.to_base64url())
+                                    .collect::<Vec<_>>(),
+                            ),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async(
+                    "/internal/test/aggregated_report_count/task/:task_id",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+
+                        // The count is scoped either to a time window ("start" and "end") or to a
+                        // batch ("batch_id"), depending on the task's query type.
+                        let batch_sel = match batch_sel_from_query(&req.url()?) {
+                            Ok(batch_sel) => batch_sel,
+                            Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                        };
+
+                        match daph
+                            .internal_aggregated_report_count(&task_id, batch_sel)
+                            .instrument(info_span!("aggregated_report_count"))
+                            .await
+                        {
+                            Ok(report_count) => Response::from_json(&serde_json::json!({
+                                "report_count": report_count,
+                            })),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async(
+                    "/internal/test/collection_estimate/task/:task_id",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+
+                        let batch_sel = match batch_sel_from_query(&req.url()?) {
+                            Ok(batch_sel) => batch_sel,
+                            Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                        };
+
+                        match daph
+                            .internal_collection_estimate(&task_id, batch_sel)
+                            .instrument(info_span!("collection_estimate"))
+                            .await
+                        {
+                            Ok(estimate) => Response::from_json(&estimate),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async(
+                    "/internal/test/agg_shares/task/:task_id",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+
+                        let batch_sel = match batch_sel_from_query(&req.url()?) {
+                            Ok(batch_sel) => batch_sel,
+                            Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                        };
+
+                        match daph
+                            .internal_agg_shares_by_bucket(&task_id, batch_sel)
+                            .instrument(info_span!("agg_shares_by_bucket"))
+                            .await
+                        {
+                            Ok(agg_shares) => Response::from_json(&agg_shares),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .post_async(
+                    "/internal/test/mark_collected/task/:task_id",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+
+                        let batch_sel = match batch_sel_from_query(&req.url()?) {
+                            Ok(batch_sel) => batch_sel,
+                            Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                        };
+
+                        match daph
+                            .internal_mark_collected(&task_id, batch_sel)
+                            .instrument(info_span!("mark_collected"))
+                            .await
+                        {
+                            Ok(()) => Response::from_json(&serde_json::json!({
+                                "status": "success",
+                            })),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .post_async(
+                    "/internal/test/sweep_expired_task/task/:task_id",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+                        match daph
+                            .internal_sweep_expired_task(&task_id)
+                            .instrument(info_span!("sweep_expired_task"))
+                            .await
+                        {
+                            Ok(instances_deleted) => Response::from_json(&serde_json::json!({
+                                "status": "success",
+                                "instances_deleted": instances_deleted,
+                            })),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async(
+                    "/internal/test/report_status/task/:task_id/report/:report_id",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let task_id =
+                            match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                                Some(id) => id,
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("missing or malformed task ID".into()),
+                                    )
+                                }
+                            };
+                        let report_id = match ctx
+                            .param("report_id")
+                            .and_then(ReportId::try_from_base64url)
+                        {
+                            Some(id) => id,
+                            None => {
+                                return daph.state.dap_abort_to_worker_response(
+                                    DapAbort::BadRequest("missing or malformed report ID".into()),
+                                )
+                            }
+                        };
+                        match daph
+                            .report_status(&task_id, &report_id)
+                            .instrument(info_span!("report_status"))
+                            .await
+                        {
+                            Ok(status) => Response::from_json(&serde_json::json!({
+                                "status": status,
+                            })),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
+                .get_async("/internal/test/tasks", |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    match daph
+                        .internal_list_tasks()
+                        .instrument(info_span!("list_tasks"))
+                        .await
+                    {
+                        Ok(tasks) => Response::from_json(&tasks),
+                        Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                    }
+                })
+                .get_async("/internal/test/tasks/export", |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    match daph
+                        .internal_export_tasks()
+                        .instrument(info_span!("export_tasks"))
+                        .await
+                    {
+                        Ok(bundle) => Response::from_json(&bundle),
+                        Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                    }
+                })
+                .post_async("/internal/test/tasks/import", |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    let bundle: InternalTestTaskBundle = req.json().await?;
+                    match daph
+                        .internal_import_tasks(bundle)
+                        .instrument(info_span!("import_tasks"))
+                        .await
+                    {
+                        Ok(()) => Response::empty(),
+                        Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                    }
+                })
+                .post_async("/internal/test/set_time", |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    let cmd: InternalTestSetTime = req.json().await?;
+                    daph.set_time_override(cmd.time);
+                    Response::from_json(&serde_json::json!({
+                        "status": "success",
+                    }))
+                })
+                .get_async("/internal/test/rejection_counts", |_req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    match daph
+                        .internal_rejection_counts()
+                        .instrument(info_span!("rejection_counts"))
+                        .await
+                    {
+                        Ok(counts) => Response::from_json(&counts),
+                        Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                    }
+                })
                 .post_async("/internal/test/add_task", |mut req, ctx| async move {
                     let daph = ctx.data.handler(&ctx.env);
                     let cmd: InternalTestAddTask = req.json().await?;
@@ -556,6 +1199,84 @@ impl DaphneWorkerRouter<'_> {
                         }))
                     },
                 )
+                .post_async(
+                    "/internal/test/add_hpke_config",
+                    |mut req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        let cmd: InternalTestAddHpkeConfig = req.json().await?;
+                        daph.internal_add_hpke_config(daph.config().default_version, cmd)
+                            .instrument(info_span!("add_hpke_config"))
+                            .await?;
+                        Response::from_json(&serde_json::json!({
+                            "status": "success",
+                        }))
+                    },
+                )
+                .post_async(
+                    "/:version/internal/test/add_hpke_config",
+                    |mut req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        let cmd: InternalTestAddHpkeConfig = req.json().await?;
+                        let version = daph.extract_version_parameter(&req)?;
+                        daph.internal_add_hpke_config(version, cmd)
+                            .instrument(info_span!("add_hpke_config"))
+                            .await?;
+                        Response::from_json(&serde_json::json!({
+                            "status": "success",
+                        }))
+                    },
+                )
+                .post_async("/internal/test/rotate_hpke_config", |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    let task_id = match task_id_from_query(&req.url()?) {
+                        Ok(task_id) => task_id,
+                        Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                    };
+                    match daph
+                        .internal_rotate_hpke_config(
+                            daph.config().default_version,
+                            task_id.as_ref(),
+                        )
+                        .instrument(info_span!("rotate_hpke_config"))
+                        .await
+                    {
+                        Ok(hpke_config_id) => Response::from_json(&serde_json::json!({
+                            "status": "success",
+                            "hpke_config_id": hpke_config_id,
+                        })),
+                        Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                    }
+                })
+                .post_async(
+                    "/:version/internal/test/rotate_hpke_config",
+                    |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = admin_auth_failure(&req, &daph)? {
+                            return Ok(resp);
+                        }
+
+                        let version = daph.extract_version_parameter(&req)?;
+                        let task_id = match task_id_from_query(&req.url()?) {
+                            Ok(task_id) => task_id,
+                            Err(e) => return daph.state.dap_abort_to_worker_response(e),
+                        };
+                        match daph
+                            .internal_rotate_hpke_config(version, task_id.as_ref())
+                            .instrument(info_span!("rotate_hpke_config"))
+                            .await
+                        {
+                            Ok(hpke_config_id) => Response::from_json(&serde_json::json!({
+                                "status": "success",
+                                "hpke_config_id": hpke_config_id,
+                            })),
+                            Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
+                        }
+                    },
+                )
         } else {
             router
         };
@@ -571,11 +1292,10 @@ impl DaphneWorkerRouter<'_> {
             router
         };
 
-        // NOTE that we do not have a tracing span for the whole request because it typically
-        // reports the same times as the span covering the specific API entry point that the
-        // router creates. If curious, you can add .instrument(info_span!("http")) just before
-        // the await and see.
-        let result = router.run(req, env).await;
+        // `Env` is a handle to a JavaScript object, so this clone refers to the same bindings.
+        let result = router
+            .run(req, Env::from(wasm_bindgen::JsValue::clone(&env)))
+            .await;
 
         state
             .metrics
@@ -586,6 +1306,8 @@ impl DaphneWorkerRouter<'_> {
             ])
             .inc();
 
+        state.maybe_flush_counters(&env);
+
         // Push metrics to Prometheus metrics server, if configured.
         //
         // TODO(cjpatton) Figure out how to do this step only after we have responded to the client
@@ -602,11 +1324,17 @@ async fn put_report_into_task(
     ctx: RouteContext<&DaphneWorkerRequestState<'_>>,
 ) -> Result<Response> {
     let daph = ctx.data.handler(&ctx.env);
-    let req = daph.worker_request_to_dap(req, &ctx).await?;
+    let req = match daph
+        .worker_request_to_dap(req, &ctx, RequestBody::Report)
+        .await
+    {
+        Ok(req) => req,
+        Err(e) => return daph.state.dap_abort_to_worker_response(e),
+    };
 
     match daph
         .http_post_upload(&req)
-        .instrument(info_span!("upload"))
+        .instrument(info_span!("upload", version = ?req.version))
         .await
     {
         Ok(()) => Response::empty(),
@@ -619,14 +1347,24 @@ async fn handle_agg_job(
     ctx: RouteContext<&DaphneWorkerRequestState<'_>>,
 ) -> Result<Response> {
     let daph = ctx.data.handler(&ctx.env);
-    let req = daph.worker_request_to_dap(req, &ctx).await?;
+    // Aggregation jobs are initialized with PUT and continued with POST, except in draft02, where
+    // both are sent with POST to the same route.
+    let body = match (req.method(), ctx.param("agg_job_id")) {
+        (Method::Put, _) => RequestBody::AggJobInit,
+        (Method::Post, Some(..)) => RequestBody::AggJobContinue,
+        _ => RequestBody::AggJob,
+    };
+    let req = match daph.worker_request_to_dap(req, &ctx, body).await {
+        Ok(req) => req,
+        Err(e) => return daph.state.dap_abort_to_worker_response(e),
+    };
 
     match daph
         .http_post_aggregate(&req)
-        .instrument(info_span!("aggregate"))
+        .instrument(info_span!("aggregate", version = ?req.version))
         .await
     {
-        Ok(resp) => dap_response_to_worker(resp),
+        Ok(resp) => resp.into_worker_response(),
         Err(e) => daph.state.dap_abort_to_worker_response(e),
     }
 }
@@ -636,18 +1374,110 @@ async fn handle_agg_share_req(
     ctx: RouteContext<&DaphneWorkerRequestState<'_>>,
 ) -> Result<Response> {
     let daph = ctx.data.handler(&ctx.env);
-    let req = daph.worker_request_to_dap(req, &ctx).await?;
+    let req = match daph
+        .worker_request_to_dap(req, &ctx, RequestBody::Other)
+        .await
+    {
+        Ok(req) => req,
+        Err(e) => return daph.state.dap_abort_to_worker_response(e),
+    };
 
     match daph
         .http_post_aggregate_share(&req)
-        .instrument(info_span!("aggregate_share"))
+        .instrument(info_span!("aggregate_share", version = ?req.version))
         .await
     {
-        Ok(resp) => dap_response_to_worker(resp),
+        Ok(resp) => resp.into_worker_response(),
         Err(e) => daph.state.dap_abort_to_worker_response(e),
     }
 }
 
+/// Create the span for a request. The DAP version and task ID are recorded if they can be parsed
+/// from the path. The response status is recorded once the request is handled.
+fn request_span(req: &Request) -> tracing::Span {
+    let path = req.path();
+    let span = info_span!(
+        "http",
+        method = ?req.method(),
+        path = %path,
+        version = field::Empty,
+        task_id = field::Empty,
+        status = field::Empty,
+    );
+
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let version = segments.next().map(DapVersion::from);
+    if let Some(version) = version.filter(|version| *version != DapVersion::Unknown) {
+        span.record("version", version.as_ref());
+    }
+
+    // The task ID follows "tasks" (draft04 and later) or "task" (draft02 and internal APIs).
+    let task_id = segments
+        .skip_while(|segment| *segment != "tasks" && *segment != "task")
+        .nth(1)
+        .and_then(TaskId::try_from_base64url);
+    if let Some(task_id) = task_id {
+        span.record("task_id", task_id.to_base64url().as_str());
+    }
+
+    span
+}
+
+/// Check that the request carries the admin bearer token in the
+/// "X-Daphne-Worker-Admin-Bearer-Token" header. If it doesn't, or if no admin token is
+/// configured, then return the response to send instead.
+fn admin_auth_failure(req: &Request, daph: &DaphneWorker) -> Result<Option<Response>> {
+    if daph.config().admin_token.is_none() {
+        return Response::error("admin not configured", 400).map(Some);
+    }
+
+    let admin_token = req
+        .headers()
+        .get("X-Daphne-Worker-Admin-Bearer-Token")?
+        .map(BearerToken::from);
+    if admin_token.is_none() || admin_token != daph.config().admin_token {
+        return Response::error("missing or invalid bearer token for admin", 401).map(Some);
+    }
+
+    Ok(None)
+}
+
+/// Parse a batch selector from the query parameters of an internal test API request. The batch is
+/// determined either by a time window ("start" and "end") or by a batch ID ("batch_id").
+fn batch_sel_from_query(url: &Url) -> std::result::Result<BatchSelector, DapAbort> {
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    match (
+        params.get("start").map(|start| start.parse::<Time>()),
+        params.get("end").map(|end| end.parse::<Time>()),
+        params.get("batch_id"),
+    ) {
+        (Some(Ok(start)), Some(Ok(end)), None) if start <= end => Ok(BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start,
+                duration: end - start,
+            },
+        }),
+        (None, None, Some(batch_id)) => BatchId::try_from_base64url(batch_id)
+            .map(|batch_id| BatchSelector::FixedSizeByBatchId { batch_id })
+            .ok_or_else(|| DapAbort::BadRequest("malformed batch ID".into())),
+        _ => Err(DapAbort::BadRequest(
+            "expected either a time window or a batch ID".into(),
+        )),
+    }
+}
+
+/// Parse the optional task ID ("task_id") from the query parameters of an internal test API
+/// request.
+fn task_id_from_query(url: &Url) -> std::result::Result<Option<TaskId>, DapAbort> {
+    url.query_pairs()
+        .find(|(key, _)| key == "task_id")
+        .map(|(_, task_id)| {
+            TaskId::try_from_base64url(task_id)
+                .ok_or_else(|| DapAbort::BadRequest("malformed task ID".into()))
+        })
+        .transpose()
+}
+
 pub(crate) fn now() -> u64 {
     Date::now().as_millis() / 1000
 }
@@ -668,6 +1498,27 @@ pub(crate) fn dap_err(e: Error) -> DapError {
     DapError::Fatal(format!("worker: {e}"))
 }
 
+/// Like [`dap_err`], but the resulting error names the operation during which it occurred. This
+/// way a failure can be traced back to its call site from the logs.
+pub(crate) fn dap_err_in(op: &'static str) -> impl Fn(Error) -> DapError {
+    move |e| DapError::Fatal(format!("worker: {op}: {e}"))
+}
+
+/// Like [`dap_err`], but for an error returned by KV or by a durable object. The resulting error
+/// is a [`DapError::Storage`] rather than a [`DapError::Fatal`].
+pub(crate) fn storage_err(e: Error) -> DapError {
+    DapError::Storage(format!("worker: {e}"))
+}
+
+/// Like [`dap_err_in`], but for an error returned by a durable object. The resulting error is a
+/// [`DapError::Storage`] that also names the binding of the durable object that was being called.
+pub(crate) fn durable_err_in(
+    op: &'static str,
+    binding: impl std::fmt::Display,
+) -> impl Fn(Error) -> DapError {
+    move |e| DapError::Storage(format!("worker: {op}: {binding}: {e}"))
+}
+
 #[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum InternalTestRole {
@@ -698,6 +1549,10 @@ pub(crate) struct InternalTestAddTask {
     leader_authentication_token: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     collector_authentication_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional_collector_authentication_tokens: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_authentication_token: Option<String>,
     role: InternalTestRole,
     vdaf_verify_key: String, // base64url
     query_type: u8,
@@ -707,14 +1562,157 @@ pub(crate) struct InternalTestAddTask {
     time_precision: Duration,
     collector_hpke_config: String, // base64url
     task_expiration: Time,
+    #[serde(default)]
+    upload_rate_limit: Option<DapRateLimit>,
+    #[serde(default)]
+    max_reports_per_agg_job: Option<u64>,
+    #[serde(default)]
+    task_scoped_hpke_config: bool,
+    #[serde(default)]
+    quiescing: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bucket_duration: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending_report_ttl: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collect_poll_retry_after: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permitted_extensions: Vec<u16>,
+    #[serde(default)]
+    reject_future_buckets: bool,
+}
+
+/// Replace the Leader's bearer token for a task. The old token remains valid for `grace_period`
+/// seconds.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct RotateLeaderBearerToken {
+    leader_authentication_token: String,
+    grace_period: Duration,
+}
+
+/// Store an HPKE receiver config, e.g., one with a known key pair. If a task ID is specified,
+/// then the config is scoped to the task. Any config with the same ID is replaced.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestAddHpkeConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    task_id: Option<String>, // base64url
+    hpke_receiver_config: HpkeReceiverConfig,
+}
+
+/// Fix the current time used by the isolate that handles the request. If `time` is `None`, then
+/// the wall clock is used.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestSetTime {
+    time: Option<Time>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestCorruptedPendingReport {
+    durable_name: String,
+    key: String,
+    reason: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestBatchFill {
+    batch_id: String, // base64url
+    report_count: usize,
+    min_batch_size: u64,
+}
+
+/// The aggregate share of a single bucket. Exactly one of `batch_window` (time-interval tasks) and
+/// `batch_id` (fixed-size tasks) is set.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestBucketAggShare {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_window: Option<Time>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_id: Option<String>, // base64url
+    agg_share: DapAggregateShare,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InternalTestReportStatus {
+    /// Leader: The report was uploaded and is waiting to be aggregated.
+    Pending,
+
+    /// The report was aggregated, or was rejected because its ID was replayed.
+    Processed,
+
+    /// The report is neither pending nor processed.
+    Unknown,
+}
+
+/// Task configs and their bearer tokens, as exported from or imported into KV.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestTaskBundle {
+    tasks: Vec<InternalTestTaskBundleEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestTaskBundleEntry {
+    task_id: String, // base64url
+    task_config: DapTaskConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    leader_bearer_token: Option<BearerToken>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collector_bearer_token: Option<BearerToken>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional_collector_bearer_tokens: Vec<BearerToken>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_bearer_token: Option<BearerToken>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestTaskInfo {
+    task_id: String, // base64url
+    version: DapVersion,
+    query: DapQueryConfig,
+    expiration: Time,
+    taskprov: bool,
 }
 
 mod auth;
 #[cfg(test)]
 mod auth_test;
+mod cache;
+#[cfg(test)]
+mod cache_test;
 mod config;
+#[cfg(test)]
+mod config_test;
+mod counter_buffer;
+#[cfg(test)]
+mod counter_buffer_test;
 mod dap;
+#[cfg(test)]
+mod dap_test;
 mod durable;
 mod error_reporting;
+mod helper_state_encryption;
+#[cfg(test)]
+mod helper_state_encryption_test;
 mod metrics;
+mod rejection_counts;
+#[cfg(test)]
+mod rejection_counts_test;
+mod report_selector;
+#[cfg(test)]
+mod report_selector_test;
+mod request_body_limits;
+#[cfg(test)]
+mod request_body_limits_test;
+mod single_flight;
+#[cfg(test)]
+mod single_flight_test;
 mod tracing_utils;
@@ -16,6 +16,21 @@ pub(crate) struct DaphneWorkerMetrics {
 
     /// DAP aborts.
     pub(crate) dap_abort_counter: IntCounterVec,
+
+    /// Outbound requests that timed out, broken down by peer.
+    pub(crate) outbound_request_timeout_counter: IntCounterVec,
+
+    /// Outbound requests that were retried, broken down by peer.
+    pub(crate) outbound_request_retry_counter: IntCounterVec,
+
+    /// Outbound requests, broken down by peer and final outcome.
+    pub(crate) outbound_request_counter: IntCounterVec,
+
+    /// Task config lookups, broken down by whether the config was cached.
+    pub(crate) task_config_cache_counter: IntCounterVec,
+
+    /// Requests sent to durable objects, broken down by binding and command.
+    pub(crate) durable_request_counter: IntCounterVec,
 }
 
 impl DaphneWorkerMetrics {
@@ -40,12 +55,52 @@ impl DaphneWorkerMetrics {
             registry
         )?;
 
+        let outbound_request_timeout_counter = register_int_counter_vec_with_registry!(
+            format!("{front}outbound_request_timeout"),
+            "Outbound requests that timed out.",
+            &["host", "peer"],
+            registry
+        )?;
+
+        let outbound_request_retry_counter = register_int_counter_vec_with_registry!(
+            format!("{front}outbound_request_retry"),
+            "Outbound requests that were retried.",
+            &["host", "peer"],
+            registry
+        )?;
+
+        let outbound_request_counter = register_int_counter_vec_with_registry!(
+            format!("{front}outbound_request"),
+            "Outbound requests, broken down by final outcome.",
+            &["host", "peer", "outcome"],
+            registry
+        )?;
+
+        let task_config_cache_counter = register_int_counter_vec_with_registry!(
+            format!("{front}task_config_cache"),
+            "Task config lookups, broken down by whether the config was cached.",
+            &["host", "outcome"],
+            registry
+        )?;
+
+        let durable_request_counter = register_int_counter_vec_with_registry!(
+            format!("{front}durable_request"),
+            "Requests sent to durable objects, broken down by binding and command.",
+            &["host", "binding", "command"],
+            registry
+        )?;
+
         let daphne = DaphneMetrics::register(registry, prefix)?;
 
         Ok(Self {
             daphne,
             http_status_code_counter,
             dap_abort_counter,
+            outbound_request_timeout_counter,
+            outbound_request_retry_counter,
+            outbound_request_counter,
+            task_config_cache_counter,
+            durable_request_counter,
         })
     }
 }
@@ -0,0 +1,33 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Extraction of rejected report counts from the metrics registry.
+
+use prometheus::Registry;
+
+/// Status label prefix used by Daphne's report counter for rejected reports.
+const REJECTED_STATUS_PREFIX: &str = "rejected_";
+
+/// Extract from the metrics registry the number of rejected reports for each failure reason.
+pub(crate) fn rejection_counts_from_registry(registry: &Registry) -> Vec<(String, u64)> {
+    let mut counts = Vec::new();
+    for family in registry.gather() {
+        if !family.get_name().ends_with("report_counter") {
+            continue;
+        }
+
+        for metric in family.get_metric() {
+            let reason = metric.get_label().iter().find_map(|label| {
+                if label.get_name() == "status" {
+                    label.get_value().strip_prefix(REJECTED_STATUS_PREFIX)
+                } else {
+                    None
+                }
+            });
+            if let Some(reason) = reason {
+                counts.push((reason.to_string(), metric.get_counter().get_value() as u64));
+            }
+        }
+    }
+    counts
+}
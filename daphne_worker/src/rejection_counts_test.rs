@@ -0,0 +1,29 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{metrics::DaphneWorkerMetrics, rejection_counts::rejection_counts_from_registry};
+use daphne::metrics::DaphneRole;
+use prometheus::Registry;
+
+#[test]
+fn rejection_counts_from_registry_only_counts_rejections() {
+    let registry = Registry::new();
+    let metrics = DaphneWorkerMetrics::register(&registry, None).unwrap();
+    let daphne_metrics = metrics
+        .daphne
+        .with_host("leader.com")
+        .with_role(DaphneRole::Leader);
+    daphne_metrics.report_inc_by("rejected_report_replayed", 2);
+    daphne_metrics.report_inc_by("rejected_batch_collected", 1);
+    daphne_metrics.report_inc_by("aggregated", 5);
+
+    let mut counts = rejection_counts_from_registry(&registry);
+    counts.sort();
+    assert_eq!(
+        counts,
+        vec![
+            ("batch_collected".to_string(), 1),
+            ("report_replayed".to_string(), 2),
+        ]
+    );
+}
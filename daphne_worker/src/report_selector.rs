@@ -0,0 +1,78 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Adaptive scaling of the report selector used by the Leader's processing loop.
+
+use crate::DaphneWorkerReportSelector;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Parameters for scaling the report selector based on recent aggregation latency. The limits
+/// requested by the caller are never exceeded.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct AdaptiveReportSelectorConfig {
+    /// If processing takes longer than this, then the limits are scaled down.
+    pub(crate) high_latency_millis: u64,
+
+    /// If processing takes less time than this, then the limits are scaled up.
+    pub(crate) low_latency_millis: u64,
+
+    /// Smallest fraction of the requested limits to use, in percent.
+    pub(crate) min_scale_percent: u64,
+}
+
+/// Report selector that adapts its limits to the observed aggregation latency. The limits are
+/// halved each time the latency is high and doubled each time the latency is low, bounded by the
+/// configured minimum scale and the limits requested by the caller.
+pub(crate) struct AdaptiveReportSelector {
+    config: AdaptiveReportSelectorConfig,
+
+    /// Current fraction of the requested limits to use, in percent.
+    scale_percent: AtomicU64,
+}
+
+impl AdaptiveReportSelector {
+    pub(crate) fn new(config: AdaptiveReportSelectorConfig) -> Self {
+        Self {
+            config,
+            scale_percent: AtomicU64::new(100),
+        }
+    }
+
+    /// Compute the effective report selector for the requested one.
+    pub(crate) fn apply(
+        &self,
+        report_sel: &DaphneWorkerReportSelector,
+    ) -> DaphneWorkerReportSelector {
+        let scale_percent = self.scale_percent.load(Ordering::Relaxed);
+        let scale = |limit: u64| (limit.saturating_mul(scale_percent) / 100).max(1);
+        DaphneWorkerReportSelector {
+            max_agg_jobs: scale(report_sel.max_agg_jobs),
+            max_reports: scale(report_sel.max_reports),
+            task_weights: report_sel.task_weights.clone(),
+        }
+    }
+
+    /// Adjust the limits based on the time it took to process the last set of reports.
+    pub(crate) fn observe_latency(&self, latency: Duration) {
+        let latency_millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        let min_scale_percent = self.config.min_scale_percent.clamp(1, 100);
+        let _ = self.scale_percent.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |scale_percent| {
+                if latency_millis > self.config.high_latency_millis {
+                    Some((scale_percent / 2).max(min_scale_percent))
+                } else if latency_millis < self.config.low_latency_millis {
+                    Some(scale_percent.saturating_mul(2).min(100))
+                } else {
+                    None
+                }
+            },
+        );
+    }
+}
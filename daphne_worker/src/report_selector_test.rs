@@ -0,0 +1,88 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    report_selector::{AdaptiveReportSelector, AdaptiveReportSelectorConfig},
+    DaphneWorkerReportSelector,
+};
+use daphne::messages::TaskId;
+use std::{collections::HashMap, time::Duration};
+
+fn test_selector() -> AdaptiveReportSelector {
+    AdaptiveReportSelector::new(AdaptiveReportSelectorConfig {
+        high_latency_millis: 1000,
+        low_latency_millis: 100,
+        min_scale_percent: 10,
+    })
+}
+
+const REQUESTED: DaphneWorkerReportSelector = DaphneWorkerReportSelector {
+    max_agg_jobs: 100,
+    max_reports: 1000,
+    task_weights: None,
+};
+
+#[test]
+fn adaptive_report_selector_shrinks_under_high_latency() {
+    let selector = test_selector();
+
+    // Limits start at the requested values.
+    let report_sel = selector.apply(&REQUESTED);
+    assert_eq!(report_sel.max_agg_jobs, 100);
+    assert_eq!(report_sel.max_reports, 1000);
+
+    selector.observe_latency(Duration::from_secs(5));
+    let report_sel = selector.apply(&REQUESTED);
+    assert_eq!(report_sel.max_agg_jobs, 50);
+    assert_eq!(report_sel.max_reports, 500);
+
+    // The limits never go below the configured minimum.
+    for _ in 0..10 {
+        selector.observe_latency(Duration::from_secs(5));
+    }
+    let report_sel = selector.apply(&REQUESTED);
+    assert_eq!(report_sel.max_agg_jobs, 10);
+    assert_eq!(report_sel.max_reports, 100);
+}
+
+#[test]
+fn adaptive_report_selector_recovers_under_low_latency() {
+    let selector = test_selector();
+    for _ in 0..10 {
+        selector.observe_latency(Duration::from_secs(5));
+    }
+
+    // Latency within the target range does not change the limits.
+    selector.observe_latency(Duration::from_millis(500));
+    assert_eq!(selector.apply(&REQUESTED).max_agg_jobs, 10);
+
+    // The limits never exceed the requested values.
+    for _ in 0..10 {
+        selector.observe_latency(Duration::from_millis(10));
+    }
+    let report_sel = selector.apply(&REQUESTED);
+    assert_eq!(report_sel.max_agg_jobs, 100);
+    assert_eq!(report_sel.max_reports, 1000);
+}
+
+#[test]
+fn report_selector_task_weights() {
+    let high_priority = TaskId([1; 32]);
+    let other = TaskId([2; 32]);
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 10,
+        max_reports: 100,
+        task_weights: Some(HashMap::from([
+            (high_priority.to_base64url(), 4),
+            // A weight of 0 is treated as 1 so that the task isn't starved.
+            (other.to_base64url(), 0),
+        ])),
+    };
+
+    assert_eq!(report_sel.max_reports_for_task(&high_priority), 400);
+    assert_eq!(report_sel.max_reports_for_task(&other), 100);
+    assert_eq!(report_sel.max_reports_for_task(&TaskId([3; 32])), 100);
+
+    // The weights don't change the total number of reports drained at once.
+    assert_eq!(report_sel.report_budget(), 1000);
+}
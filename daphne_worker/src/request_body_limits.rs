@@ -0,0 +1,69 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Limits on the size of request bodies, enforced before the body is decoded.
+
+use serde::{Deserialize, Serialize};
+
+/// The kind of body a route accepts. Limits are chosen by the route that received the request
+/// rather than by its declared media type, so that a sender cannot raise the limit by mislabelling
+/// its request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RequestBody {
+    /// A report uploaded by a Client.
+    Report,
+
+    /// A request to initialize an aggregation job.
+    AggJobInit,
+
+    /// A request to continue an aggregation job.
+    AggJobContinue,
+
+    /// A request to initialize or continue an aggregation job, for routes that accept both.
+    AggJob,
+
+    /// Any other request.
+    Other,
+}
+
+/// Maximum size (in bytes) of the body of a request, by kind of request. Fields that are omitted
+/// from the configuration take their default values.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, rename_all = "snake_case")]
+pub(crate) struct RequestBodyLimits {
+    /// Limit for reports uploaded by Clients (default 64 KiB).
+    pub(crate) report: u64,
+
+    /// Limit for requests to initialize an aggregation job (default 32 MiB).
+    pub(crate) agg_job_init: u64,
+
+    /// Limit for requests to continue an aggregation job (default 32 MiB).
+    pub(crate) agg_job_continue: u64,
+
+    /// Limit for requests of any other media type (default 1 MiB).
+    pub(crate) default: u64,
+}
+
+impl Default for RequestBodyLimits {
+    fn default() -> Self {
+        Self {
+            report: 64 << 10,
+            agg_job_init: 32 << 20,
+            agg_job_continue: 32 << 20,
+            default: 1 << 20,
+        }
+    }
+}
+
+impl RequestBodyLimits {
+    /// Return the maximum size of a request body of the given kind.
+    pub(crate) fn max_bytes(&self, body: RequestBody) -> u64 {
+        match body {
+            RequestBody::Report => self.report,
+            RequestBody::AggJobInit => self.agg_job_init,
+            RequestBody::AggJobContinue => self.agg_job_continue,
+            RequestBody::AggJob => self.agg_job_init.max(self.agg_job_continue),
+            RequestBody::Other => self.default,
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::request_body_limits::{RequestBody, RequestBodyLimits};
+
+#[test]
+fn request_body_limits_by_route() {
+    let limits: RequestBodyLimits =
+        serde_json::from_str(r#"{"report": 1000, "agg_job_continue": 3000, "default": 2000}"#)
+            .unwrap();
+
+    assert_eq!(limits.max_bytes(RequestBody::Report), 1000);
+    assert_eq!(limits.max_bytes(RequestBody::AggJobContinue), 3000);
+    assert_eq!(limits.max_bytes(RequestBody::Other), 2000);
+
+    // Omitted fields take their default values.
+    assert_eq!(
+        limits.max_bytes(RequestBody::AggJobInit),
+        RequestBodyLimits::default().agg_job_init
+    );
+
+    // Routes that accept both kinds of aggregation job requests get the larger limit.
+    assert_eq!(
+        limits.max_bytes(RequestBody::AggJob),
+        RequestBodyLimits::default().agg_job_init
+    );
+}
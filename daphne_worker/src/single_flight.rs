@@ -0,0 +1,85 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Deduplication of work done concurrently by requests handled by the same isolate.
+
+use futures::lock::{Mutex as AsyncMutex, OwnedMutexGuard};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+type InFlight<K> = HashMap<K, Arc<AsyncMutex<()>>>;
+
+/// Serializes work on the same key so that work that only needs to be done once, such as writing
+/// a newly provisioned task to KV, is not repeated by concurrent requests.
+pub(crate) struct SingleFlight<K> {
+    /// Lock for each key for which work is in flight.
+    in_flight: Mutex<InFlight<K>>,
+}
+
+impl<K: Clone + Eq + Hash> SingleFlight<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, InFlight<K>> {
+        // The map is always left in a consistent state, so it's safe to keep using it even if a
+        // thread panicked while holding the lock.
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Wait until no other caller holds the guard for `key`, then return the guard. If the caller
+    /// had to wait, then the work may already have been done, so the caller should check before
+    /// doing it again.
+    pub(crate) async fn acquire(&self, key: K) -> SingleFlightGuard<'_, K> {
+        let lock = self.lock().entry(key.clone()).or_default().clone();
+        let (guard, waited) = match lock.try_lock_owned() {
+            Some(guard) => (guard, false),
+            None => (lock.clone().lock_owned().await, true),
+        };
+        SingleFlightGuard {
+            single_flight: self,
+            key,
+            lock,
+            _guard: guard,
+            waited,
+        }
+    }
+}
+
+/// Held by the caller doing the work for a key. Other callers for the same key wait until it is
+/// dropped.
+pub(crate) struct SingleFlightGuard<'a, K: Clone + Eq + Hash> {
+    single_flight: &'a SingleFlight<K>,
+    key: K,
+    lock: Arc<AsyncMutex<()>>,
+    _guard: OwnedMutexGuard<()>,
+    waited: bool,
+}
+
+impl<K: Clone + Eq + Hash> SingleFlightGuard<'_, K> {
+    /// Indicates whether another caller held the guard for the key before this one.
+    pub(crate) fn waited(&self) -> bool {
+        self.waited
+    }
+}
+
+impl<K: Clone + Eq + Hash> Drop for SingleFlightGuard<'_, K> {
+    fn drop(&mut self) {
+        // Forget the lock once no other caller is waiting on it, i.e., it is only referred to by
+        // the map and by this guard (which holds it twice: once directly and once via the lock
+        // guard). The lock guard itself is released after this.
+        let mut in_flight = self.single_flight.lock();
+        if matches!(in_flight.get(&self.key), Some(lock) if Arc::ptr_eq(lock, &self.lock))
+            && Arc::strong_count(&self.lock) <= 3
+        {
+            in_flight.remove(&self.key);
+        }
+    }
+}
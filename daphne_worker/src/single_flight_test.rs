@@ -0,0 +1,73 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::single_flight::SingleFlight;
+use futures::{executor::block_on, future::poll_fn, join};
+use std::{cell::Cell, task::Poll};
+
+/// Let other futures run before continuing.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[test]
+fn single_flight_does_work_once() {
+    let single_flight = SingleFlight::new();
+    let writes = Cell::new(0);
+    let done = Cell::new(false);
+
+    // Write once, unless another caller already did.
+    let provision = || async {
+        let guard = single_flight.acquire("task").await;
+        if guard.waited() && done.get() {
+            return false;
+        }
+        // Give the other caller a chance to run while we hold the guard.
+        yield_now().await;
+        writes.set(writes.get() + 1);
+        done.set(true);
+        true
+    };
+
+    let (first, second) = block_on(async { join!(provision(), provision()) });
+    assert!(first);
+    assert!(!second);
+    assert_eq!(writes.get(), 1);
+
+    // Once the work is done, later callers don't wait.
+    assert!(!block_on(single_flight.acquire("task")).waited());
+}
+
+#[test]
+fn single_flight_retried_after_failure() {
+    let single_flight = SingleFlight::new();
+    let attempts = Cell::new(0);
+    let done = Cell::new(false);
+
+    // The first attempt fails, so the waiting caller does the work instead.
+    let provision = || async {
+        let guard = single_flight.acquire("task").await;
+        if guard.waited() && done.get() {
+            return;
+        }
+        yield_now().await;
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > 1 {
+            done.set(true);
+        }
+    };
+
+    block_on(async { join!(provision(), provision()) });
+    assert_eq!(attempts.get(), 2);
+    assert!(done.get());
+}
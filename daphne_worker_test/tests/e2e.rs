@@ -8,24 +8,31 @@ mod test_runner;
 use daphne::{
     async_test_versions,
     constants::DapMediaType,
+    hpke::HpkeReceiverConfig,
     messages::{
+        decode_base64url_vec,
         taskprov::{
             DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafTypeVar,
         },
-        BatchSelector, Collection, CollectionReq, Extension, HpkeCiphertext, Interval, Query,
-        Report, ReportId, ReportMetadata, TaskId,
+        BatchSelector, Collection, CollectionJobId, CollectionReq, Extension, HpkeCiphertext,
+        HpkeConfig, HpkeConfigList, HpkeKemId, Interval, Query, Report, ReportId, ReportMetadata,
+        TaskId,
     },
     taskprov::{compute_task_id, TaskprovVersion},
     DapAggregateResult, DapMeasurement, DapTaskConfig, DapVersion,
 };
 use daphne_worker::DaphneWorkerReportSelector;
 use paste::paste;
-use prio::codec::{ParameterizedDecode, ParameterizedEncode};
+use prio::codec::{Decode, ParameterizedDecode, ParameterizedEncode};
 use rand::prelude::*;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
 use serde::Deserialize;
 use serde_json::json;
-use std::cmp::{max, min};
-use test_runner::{TestRunner, MIN_BATCH_SIZE, TIME_PRECISION};
+use std::{
+    cmp::{max, min},
+    time::SystemTime,
+};
+use test_runner::{TestRunner, MAX_BATCH_SIZE, MIN_BATCH_SIZE, TIME_PRECISION};
 use url::Url;
 
 // Redefine async_test_version locally because we want a
@@ -165,6 +172,40 @@ async fn e2e_helper_hpke_config(version: DapVersion) {
 
 async_test_versions! { e2e_helper_hpke_config }
 
+async fn e2e_leader_signed_hpke_config(version: DapVersion) {
+    #[derive(Deserialize)]
+    struct SignedHpkeConfigList {
+        hpke_config_list: String,
+        signature: String,
+        signature_algorithm: String,
+    }
+
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let url = t.leader_url.join("hpke_config/signed").unwrap();
+    let resp = client.get(url).send().await.expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let signed: SignedHpkeConfigList = resp.json().await.unwrap();
+    assert_eq!(signed.signature_algorithm, "ed25519");
+
+    // The signature verifies under the key configured for the Leader. This must match
+    // DAP_HPKE_CONFIG_SIGNING_KEY in daphne_worker_test/wrangler.toml.
+    let seed =
+        hex::decode("5770eab537ccfba20c0c4dcee446194bb8553b3e1cbb2a04c7f7c4a91a6a4459").unwrap();
+    let signing_key = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
+    let hpke_config_list = decode_base64url_vec(signed.hpke_config_list).unwrap();
+    UnparsedPublicKey::new(&ED25519, signing_key.public_key())
+        .verify(
+            &hpke_config_list,
+            &decode_base64url_vec(signed.signature).unwrap(),
+        )
+        .unwrap();
+    let hpke_config_list = HpkeConfigList::get_decoded(&hpke_config_list).unwrap();
+    assert_eq!(hpke_config_list.hpke_configs.len(), 1);
+}
+
+async_test_versions! { e2e_leader_signed_hpke_config }
+
 async fn e2e_hpke_configs_are_cached(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let client = t.http_client();
@@ -181,6 +222,146 @@ async fn e2e_hpke_configs_are_cached(version: DapVersion) {
 
 async_test_versions! { e2e_hpke_configs_are_cached }
 
+async fn e2e_leader_hpke_config_by_id(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let [leader_hpke_config, _] = t.get_hpke_configs(version, &client).await;
+
+    // Requesting the advertised config by its ID yields the same config.
+    let mut url = t.leader_url.join("hpke_config").unwrap();
+    url.query_pairs_mut()
+        .append_pair("task_id", &t.task_id.to_base64url())
+        .append_pair("id", &leader_hpke_config.id.to_string());
+    let resp = client.get(url).send().await.expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let raw_hpke_config = resp.bytes().await.unwrap();
+    let hpke_config = match version {
+        DapVersion::Draft02 => HpkeConfig::get_decoded(&raw_hpke_config).unwrap(),
+        _ => {
+            let mut hpke_config_list = HpkeConfigList::get_decoded(&raw_hpke_config).unwrap();
+            assert_eq!(hpke_config_list.hpke_configs.len(), 1);
+            hpke_config_list.hpke_configs.pop().unwrap()
+        }
+    };
+    assert_eq!(hpke_config, leader_hpke_config);
+
+    // A malformed ID is rejected.
+    let mut url = t.leader_url.join("hpke_config").unwrap();
+    url.query_pairs_mut()
+        .append_pair("task_id", &t.task_id.to_base64url())
+        .append_pair("id", "not a config ID");
+    let resp = client.get(url).send().await.expect("request failed");
+    assert_eq!(resp.status(), 400);
+}
+
+async_test_versions! { e2e_leader_hpke_config_by_id }
+
+#[tokio::test]
+#[cfg_attr(not(feature = "test_e2e"), ignore)]
+async fn e2e_health() {
+    let t = TestRunner::default_with_version(DapVersion::Draft04).await;
+    let client = t.http_client();
+    for base_url in [&t.leader_url, &t.helper_url] {
+        let resp = client
+            .get(base_url.join("/health").unwrap())
+            .send()
+            .await
+            .expect("request failed");
+        assert_eq!(resp.status(), 200, "unexpected status for {base_url}");
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "test_e2e"), ignore)]
+async fn e2e_leader_reports_ingested() {
+    let t = TestRunner::default_with_version(DapVersion::Draft04).await;
+    let client = t.http_client();
+    let url = t.leader_url.join("/reports_ingested").unwrap();
+
+    // Reading the count requires the admin bearer token.
+    let resp = client
+        .get(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .get(url)
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let res: serde_json::Value = resp.json().await.unwrap();
+    assert!(res["reports_ingested"].is_u64());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "test_e2e"), ignore)]
+async fn e2e_leader_upload_unknown_version() {
+    let t = TestRunner::default_with_version(DapVersion::Draft04).await;
+    let client = t.http_client();
+    let url = t
+        .leader_url
+        .join(&format!("/v99/tasks/{}/reports", t.task_id.to_base64url()))
+        .unwrap();
+
+    let resp = client
+        .put(url.as_str())
+        .header(reqwest::header::CONTENT_TYPE, "application/dap-report")
+        .body(b"report".to_vec())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let problem_details: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        problem_details.as_object().unwrap().get("title").unwrap(),
+        "Unsupported DAP version"
+    );
+}
+
+async fn e2e_leader_upload_unsupported_media_type(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let url = t.leader_url.join(&t.upload_path()).unwrap();
+
+    let builder = match t.version {
+        DapVersion::Draft02 => client.post(url.as_str()),
+        DapVersion::Draft04 => client.put(url.as_str()),
+        _ => unreachable!("unhandled version {}", t.version),
+    };
+    let resp = builder
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/x-not-a-dap-report",
+        )
+        .body(b"report".to_vec())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 415);
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let problem_details: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        problem_details.as_object().unwrap().get("title").unwrap(),
+        "Unsupported media type"
+    );
+}
+
+async_test_versions! { e2e_leader_upload_unsupported_media_type }
+
 async fn e2e_leader_upload(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let mut rng = thread_rng();
@@ -220,6 +401,21 @@ async fn e2e_leader_upload(version: DapVersion) {
     )
     .await;
 
+    // Try uploading a report with a fresh ID that reuses the HPKE encapsulated key of the previous
+    // report (expect failure due to repeated encapsulated key).
+    let mut replayed_report = report.clone();
+    replayed_report.report_metadata.id = ReportId(rng.gen());
+    t.leader_put_expect_abort(
+        &client,
+        None, // dap_auth_token
+        &path,
+        DapMediaType::Report,
+        replayed_report.get_encoded_with_param(&version),
+        400,
+        "reportRejected",
+    )
+    .await;
+
     // Try uploading a report with the incorrect task ID.
     let bad_id = TaskId(rng.gen());
     let bad_path = t.upload_path_for_task(&bad_id);
@@ -541,6 +737,7 @@ async fn e2e_internal_leader_process(version: DapVersion) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
         max_reports: t.task_config.min_batch_size,
+        task_weights: None,
     };
 
     let batch_interval = t.batch_interval();
@@ -590,6 +787,79 @@ async fn e2e_internal_leader_process(version: DapVersion) {
 
 async_test_versions! { e2e_internal_leader_process }
 
+// Test that the Helper keeps accepting the Leader's old bearer token during the grace period that
+// follows a rotation.
+async fn e2e_helper_rotate_leader_bearer_token(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let path = t.upload_path();
+
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+
+    let mut url = t.helper_url.clone();
+    url.set_path(&format!(
+        "task/{}/rotate_leader_bearer_token",
+        t.task_id.to_base64url()
+    ));
+    let cmd = json!({
+        "leader_authentication_token": hex::encode(thread_rng().gen::<[u8; 16]>()),
+        "grace_period": 300,
+    });
+
+    // Rotating the token requires the admin bearer token.
+    let resp = client
+        .post(url.clone())
+        .json(&cmd)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .post(url)
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
+        )
+        .json(&cmd)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+
+    // The Leader still uses the old token.
+    let batch_interval = t.batch_interval();
+    let now = thread_rng().gen_range(t.report_interval(&batch_interval));
+    t.leader_put_expect_ok(
+        &client,
+        &path,
+        DapMediaType::Report,
+        t.task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap()
+            .get_encoded_with_param(&version),
+    )
+    .await;
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100,
+        max_reports: 100,
+        task_weights: None,
+    };
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 1, "reports processed");
+    assert_eq!(agg_telem.reports_aggregated, 1, "reports aggregated");
+}
+
+async_test_versions! { e2e_helper_rotate_leader_bearer_token }
+
 // Test that all reports eventually get drained at minimum aggregation rate.
 async fn e2e_leader_process_min_agg_rate(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
@@ -625,6 +895,7 @@ async fn e2e_leader_process_min_agg_rate(version: DapVersion) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 1,
         max_reports: 1,
+        task_weights: None,
     };
 
     for i in 0..7 {
@@ -639,22 +910,16 @@ async fn e2e_leader_process_min_agg_rate(version: DapVersion) {
 
 async_test_versions! { e2e_leader_process_min_agg_rate }
 
-async fn e2e_leader_collect_ok(version: DapVersion) {
+async fn e2e_leader_aggregated_report_count(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
-    let batch_interval = t.batch_interval();
-
     let client = t.http_client();
+    let batch_interval = t.batch_interval();
     let hpke_config_list = t.get_hpke_configs(version, &client).await;
     let path = t.upload_path();
 
-    // The reports are uploaded in the background.
     let mut rng = thread_rng();
-    let mut time_min = u64::MAX;
-    let mut time_max = 0u64;
-    for _ in 0..t.task_config.min_batch_size {
+    for _ in 0..5 {
         let now = rng.gen_range(t.report_interval(&batch_interval));
-        time_min = min(time_min, now);
-        time_max = max(time_max, now);
         t.leader_put_expect_ok(
             &client,
             &path,
@@ -673,118 +938,429 @@ async fn e2e_leader_collect_ok(version: DapVersion) {
         )
         .await;
     }
-
-    // Get the collect URI.
-    let collect_req = CollectionReq {
-        draft02_task_id: t.collect_task_id_field(),
-        query: Query::TimeInterval {
-            batch_interval: batch_interval.clone(),
+    t.internal_process(
+        &client,
+        &DaphneWorkerReportSelector {
+            max_agg_jobs: 100,
+            max_reports: 100,
+            task_weights: None,
         },
-        agg_param: Vec::new(),
-    };
-    let collect_uri = t
-        .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
-        .await;
-    println!("collect_uri: {}", collect_uri);
+    )
+    .await;
 
-    // Poll the collect URI before the CollectResp is ready.
-    let resp = t.poll_collection_url(&client, &collect_uri).await;
-    assert_eq!(resp.status(), 202, "response: {:?}", resp);
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!(
+        "internal/test/aggregated_report_count/task/{}",
+        t.task_id.to_base64url()
+    ));
+    url.query_pairs_mut()
+        .append_pair("start", &batch_interval.start.to_string())
+        .append_pair("end", &batch_interval.end().to_string());
+
+    // Counting the aggregated reports requires the admin bearer token.
+    let resp = client
+        .get(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
 
-    // The reports are aggregated in the background.
-    let agg_telem = t
-        .internal_process(
-            &client,
-            &DaphneWorkerReportSelector {
-                max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
-                max_reports: 100,
-            },
+    let resp = client
+        .get(url)
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
         )
-        .await;
-    assert_eq!(
-        agg_telem.reports_processed, t.task_config.min_batch_size,
-        "reports processed"
-    );
-    assert_eq!(
-        agg_telem.reports_aggregated, t.task_config.min_batch_size,
-        "reports aggregated"
-    );
-    assert_eq!(
-        agg_telem.reports_collected, t.task_config.min_batch_size,
-        "reports collected"
-    );
-
-    // Poll the collect URI.
-    let resp = t.poll_collection_url(&client, &collect_uri).await;
+        .send()
+        .await
+        .expect("request failed");
     assert_eq!(resp.status(), 200);
+    let res: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(res["report_count"], 5);
+}
 
-    let collection =
-        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
-    let agg_res = t
-        .task_config
-        .vdaf
-        .consume_encrypted_agg_shares(
-            &t.collector_hpke_receiver,
-            &t.task_id,
-            &BatchSelector::TimeInterval {
-                batch_interval: batch_interval.clone(),
-            },
-            collection.report_count,
-            collection.encrypted_agg_shares.clone(),
-            version,
-        )
-        .await
-        .unwrap();
-    assert_eq!(
-        agg_res,
-        DapAggregateResult::U128(t.task_config.min_batch_size as u128)
-    );
+async_test_versions! { e2e_leader_aggregated_report_count }
 
-    if version != DapVersion::Draft02 {
-        // Check that the time interval for the reports is correct.
-        let interval = collection.interval.as_ref().unwrap();
-        let low = t.task_config.quantized_time_lower_bound(time_min);
-        let high = t.task_config.quantized_time_upper_bound(time_max);
-        assert!(low < high);
-        assert_eq!(interval.start, low);
-        assert_eq!(interval.duration, high - low);
-    }
+async fn e2e_leader_collection_estimate(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let batch_interval = t.batch_interval();
 
-    // Poll the collect URI once more. Expect the response to be the same as the first, per HTTP
-    // GET semantics.
-    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!(
+        "internal/test/collection_estimate/task/{}",
+        t.task_id.to_base64url()
+    ));
+    url.query_pairs_mut()
+        .append_pair("start", &batch_interval.start.to_string())
+        .append_pair("end", &batch_interval.end().to_string());
+
+    // Estimating the cost of a collection requires the admin bearer token.
+    let resp = client
+        .get(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .get(url)
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
     assert_eq!(resp.status(), 200);
+    let res: serde_json::Value = resp.json().await.unwrap();
     assert_eq!(
-        resp.bytes().await.unwrap(),
-        collection.get_encoded_with_param(&version)
+        res["bucket_count"],
+        batch_interval.duration / t.task_config.time_precision
     );
-
-    // NOTE Our Leader doesn't check if a report is stale until it is ready to process it. As such,
-    // It won't tell the Client at this point that its report is stale. Delaying this check allows
-    // to avoid sharding ReportsProcessed by batch bucket, which is not feasilbe for fixed-size
-    // tasks.
-    //
-    //  let now = rng.gen_range(t.report_interval(&batch_interval));
-    //  t.leader_post_expect_abort(
-    //      &client,
-    //      None, // dap_auth_token
-    //      "upload",
-    //      DapMediaType::Report,
-    //      t.task_config.vdaf
-    //          .produce_report(&hpke_config_list, now, &t.task_id, DapMeasurement::U64(1))
-    //          .unwrap()
-    //          .get_encoded(),
-    //      400,
-    //      "staleReport",
-    //  )
-    //  .await;
+    assert_eq!(res["permitted"], true);
 }
 
-async_test_versions! { e2e_leader_collect_ok }
+async_test_versions! { e2e_leader_collection_estimate }
 
-// Test that collect jobs complete even if the request is issued after all reports for the task
-// have been processed.
-async fn e2e_leader_collect_ok_interleaved(version: DapVersion) {
+async fn e2e_leader_admin_mark_collected(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let batch_interval = t.batch_interval();
+
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!(
+        "internal/test/mark_collected/task/{}",
+        t.task_id.to_base64url()
+    ));
+    url.query_pairs_mut()
+        .append_pair("start", &batch_interval.start.to_string())
+        .append_pair("end", &batch_interval.end().to_string());
+
+    // Marking a batch collected requires the admin bearer token.
+    let resp = client
+        .post(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .post(url.clone())
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+
+    // The batch was already collected, so marking it again overlaps.
+    let resp = client
+        .post(url)
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+    let problem_details: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        problem_details["type"],
+        "urn:ietf:params:ppm:dap:error:batchOverlap"
+    );
+}
+
+async_test_versions! { e2e_leader_admin_mark_collected }
+
+async fn e2e_helper_admin_agg_shares_by_bucket(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let batch_interval = t.batch_interval();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    let mut rng = thread_rng();
+    for _ in 0..5 {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+        max_reports: 5,
+        task_weights: None,
+    };
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 5);
+
+    let mut url = t.helper_url.clone();
+    url.set_path(&format!(
+        "internal/test/agg_shares/task/{}",
+        t.task_id.to_base64url()
+    ));
+    url.query_pairs_mut()
+        .append_pair("start", &batch_interval.start.to_string())
+        .append_pair("end", &batch_interval.end().to_string());
+
+    // The per-bucket aggregate shares are only available with the admin bearer token.
+    let resp = client
+        .get(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .get(url)
+        .header(
+            "X-Daphne-Worker-Admin-Bearer-Token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let agg_shares: Vec<serde_json::Value> = resp.json().await.unwrap();
+
+    // There is one share per bucket in the batch interval, and together they account for every
+    // report.
+    let bucket_count = batch_interval.duration / t.task_config.bucket_duration();
+    assert_eq!(agg_shares.len() as u64, bucket_count);
+    let report_count: u64 = agg_shares
+        .iter()
+        .map(|agg_share| {
+            assert!(agg_share["batch_window"].is_u64());
+            agg_share["agg_share"]["report_count"].as_u64().unwrap()
+        })
+        .sum();
+    assert_eq!(report_count, 5);
+}
+
+async_test_versions! { e2e_helper_admin_agg_shares_by_bucket }
+
+async fn e2e_leader_process_fixed_time(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    // Upload a report at the earliest time the Leader will accept once the clock is fixed below,
+    // and another report just before it.
+    let report_time = t.task_config.quantized_time_lower_bound(t.now);
+    for time in [report_time, report_time - 1] {
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    time,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    // Fix the Leader's clock so that the first report is exactly at the lower time bound.
+    let res: serde_json::Value = t
+        .leader_post_internal(
+            "/internal/test/set_time",
+            &json!({ "time": report_time + t.global_config.report_storage_epoch_duration }),
+        )
+        .await;
+    assert_eq!(res["status"], "success");
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100,
+        max_reports: 100,
+        task_weights: None,
+    };
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 2);
+    assert_eq!(agg_telem.reports_aggregated, 1);
+
+    // Restore the wall clock.
+    let res: serde_json::Value = t
+        .leader_post_internal("/internal/test/set_time", &json!({ "time": null }))
+        .await;
+    assert_eq!(res["status"], "success");
+}
+
+async_test_versions! { e2e_leader_process_fixed_time }
+
+async fn e2e_leader_collect_ok(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let batch_interval = t.batch_interval();
+
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    // The reports are uploaded in the background.
+    let mut rng = thread_rng();
+    let mut time_min = u64::MAX;
+    let mut time_max = 0u64;
+    for _ in 0..t.task_config.min_batch_size {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        time_min = min(time_min, now);
+        time_max = max(time_max, now);
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    // Get the collect URI.
+    let collect_req = CollectionReq {
+        draft02_task_id: t.collect_task_id_field(),
+        query: Query::TimeInterval {
+            batch_interval: batch_interval.clone(),
+        },
+        agg_param: Vec::new(),
+    };
+    let collect_uri = t
+        .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
+        .await;
+    println!("collect_uri: {}", collect_uri);
+
+    // Poll the collect URI before the CollectResp is ready.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 202, "response: {:?}", resp);
+
+    // The reports are aggregated in the background.
+    let agg_telem = t
+        .internal_process(
+            &client,
+            &DaphneWorkerReportSelector {
+                max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+                max_reports: 100,
+                task_weights: None,
+            },
+        )
+        .await;
+    assert_eq!(
+        agg_telem.reports_processed, t.task_config.min_batch_size,
+        "reports processed"
+    );
+    assert_eq!(
+        agg_telem.reports_aggregated, t.task_config.min_batch_size,
+        "reports aggregated"
+    );
+    assert_eq!(
+        agg_telem.reports_collected, t.task_config.min_batch_size,
+        "reports collected"
+    );
+
+    // Poll the collect URI.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+
+    let collection =
+        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
+    let agg_res = t
+        .task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver,
+            &t.task_id,
+            &BatchSelector::TimeInterval {
+                batch_interval: batch_interval.clone(),
+            },
+            collection.report_count,
+            collection.encrypted_agg_shares.clone(),
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        agg_res,
+        DapAggregateResult::U128(t.task_config.min_batch_size as u128)
+    );
+
+    if version != DapVersion::Draft02 {
+        // Check that the time interval for the reports is correct.
+        let interval = collection.interval.as_ref().unwrap();
+        let low = t.task_config.quantized_time_lower_bound(time_min);
+        let high = t.task_config.quantized_time_upper_bound(time_max);
+        assert!(low < high);
+        assert_eq!(interval.start, low);
+        assert_eq!(interval.duration, high - low);
+    }
+
+    // Poll the collect URI once more. Expect the response to be the same as the first, per HTTP
+    // GET semantics.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.bytes().await.unwrap(),
+        collection.get_encoded_with_param(&version)
+    );
+
+    // NOTE Our Leader doesn't check if a report is stale until it is ready to process it. As such,
+    // It won't tell the Client at this point that its report is stale. Delaying this check allows
+    // to avoid sharding ReportsProcessed by batch bucket, which is not feasilbe for fixed-size
+    // tasks.
+    //
+    //  let now = rng.gen_range(t.report_interval(&batch_interval));
+    //  t.leader_post_expect_abort(
+    //      &client,
+    //      None, // dap_auth_token
+    //      "upload",
+    //      DapMediaType::Report,
+    //      t.task_config.vdaf
+    //          .produce_report(&hpke_config_list, now, &t.task_id, DapMeasurement::U64(1))
+    //          .unwrap()
+    //          .get_encoded(),
+    //      400,
+    //      "staleReport",
+    //  )
+    //  .await;
+}
+
+async_test_versions! { e2e_leader_collect_ok }
+
+// Test that collect jobs complete even if the request is issued after all reports for the task
+// have been processed.
+async fn e2e_leader_collect_ok_interleaved(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let client = t.http_client();
     let batch_interval = t.batch_interval();
@@ -817,6 +1393,7 @@ async fn e2e_leader_collect_ok_interleaved(version: DapVersion) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
         max_reports: 100,
+        task_weights: None,
     };
 
     // All reports for the task get processed ...
@@ -848,6 +1425,122 @@ async fn e2e_leader_collect_ok_interleaved(version: DapVersion) {
 
 async_test_versions! { e2e_leader_collect_ok_interleaved }
 
+// Test that aggregation jobs that merge into the same bucket concurrently don't clobber each
+// other's updates: the aggregate share of the bucket must be the sum of every job's share.
+async fn e2e_leader_concurrent_merges(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let batch_interval = t.batch_interval();
+
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    // Upload every report into the same bucket.
+    let report_count = 4 * t.task_config.min_batch_size;
+    let now = t.report_interval(&batch_interval).start;
+    for _ in 0..report_count {
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    // Aggregate the reports in many small jobs at once.
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 1,
+        max_reports: 2,
+        task_weights: None,
+    };
+    let agg_telems = futures::future::join_all(
+        (0..report_count / 2).map(|_| t.internal_process(&client, &report_sel)),
+    )
+    .await;
+    let mut reports_aggregated: u64 = agg_telems
+        .iter()
+        .map(|agg_telem| agg_telem.reports_aggregated)
+        .sum();
+
+    // Aggregate any reports that are left over.
+    loop {
+        let agg_telem = t
+            .internal_process(
+                &client,
+                &DaphneWorkerReportSelector {
+                    max_agg_jobs: 100,
+                    max_reports: 100,
+                    task_weights: None,
+                },
+            )
+            .await;
+        if agg_telem.reports_processed == 0 {
+            break;
+        }
+        reports_aggregated += agg_telem.reports_aggregated;
+    }
+    assert_eq!(reports_aggregated, report_count, "reports aggregated");
+
+    // Collect the batch.
+    let collect_req = CollectionReq {
+        draft02_task_id: t.collect_task_id_field(),
+        query: Query::TimeInterval {
+            batch_interval: batch_interval.clone(),
+        },
+        agg_param: Vec::new(),
+    };
+    let collect_uri = t
+        .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
+        .await;
+    let agg_telem = t
+        .internal_process(
+            &client,
+            &DaphneWorkerReportSelector {
+                max_agg_jobs: 100,
+                max_reports: 100,
+                task_weights: None,
+            },
+        )
+        .await;
+    assert_eq!(
+        agg_telem.reports_collected, report_count,
+        "reports collected"
+    );
+
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+    let collection =
+        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(collection.report_count, report_count);
+    let agg_res = t
+        .task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver,
+            &t.task_id,
+            &BatchSelector::TimeInterval { batch_interval },
+            collection.report_count,
+            collection.encrypted_agg_shares.clone(),
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(agg_res, DapAggregateResult::U128(report_count as u128));
+}
+
+async_test_versions! { e2e_leader_concurrent_merges }
+
 async fn e2e_leader_collect_not_ready_min_batch_size(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let batch_interval = t.batch_interval();
@@ -898,6 +1591,7 @@ async fn e2e_leader_collect_not_ready_min_batch_size(version: DapVersion) {
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                task_weights: None,
             },
         )
         .await;
@@ -914,10 +1608,49 @@ async fn e2e_leader_collect_not_ready_min_batch_size(version: DapVersion) {
     // Poll the collect URI before the CollectResp is ready.
     let resp = t.poll_collection_url(&client, &collect_uri).await;
     assert_eq!(resp.status(), 202);
+
+    // The Collector is told when to poll again. This must match
+    // DAP_COLLECT_POLL_RETRY_AFTER_SECS and DAP_COLLECT_POLL_RETRY_AFTER_JITTER_SECS in
+    // daphne_worker_test/wrangler.toml.
+    let retry_after: u64 = resp
+        .headers()
+        .get("Retry-After")
+        .expect("missing Retry-After header")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((5..=10).contains(&retry_after), "{retry_after}");
 }
 
 async_test_versions! { e2e_leader_collect_not_ready_min_batch_size }
 
+async fn e2e_leader_collect_backup_collector(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+
+    // A Collector other than the primary one is authorized for the task.
+    let collect_req = CollectionReq {
+        draft02_task_id: t.collect_task_id_field(),
+        query: Query::TimeInterval {
+            batch_interval: t.batch_interval(),
+        },
+        agg_param: Vec::new(),
+    };
+    let collect_uri = t
+        .leader_post_collect_using_token(
+            &client,
+            collect_req.get_encoded_with_param(&t.version),
+            &t.backup_collector_bearer_token,
+        )
+        .await;
+
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 202);
+}
+
+async_test_versions! { e2e_leader_collect_backup_collector }
+
 async fn e2e_leader_collect_abort_unknown_request(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let client = t.http_client();
@@ -940,7 +1673,298 @@ async fn e2e_leader_collect_abort_unknown_request(version: DapVersion) {
     assert_eq!(resp.status(), expected_status);
 }
 
-async_test_versions! { e2e_leader_collect_abort_unknown_request }
+async_test_versions! { e2e_leader_collect_abort_unknown_request }
+
+// draft02 Collectors poll the collect URI returned by the Leader. Check that the URI has the
+// draft02 format and that polling it yields the draft02 collect response.
+async fn e2e_leader_collect_draft02_poll(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let batch_interval = t.batch_interval();
+
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    // The reports are uploaded in the background.
+    let mut rng = thread_rng();
+    for _ in 0..t.task_config.min_batch_size {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    let collect_req = CollectionReq {
+        draft02_task_id: t.collect_task_id_field(),
+        query: Query::TimeInterval {
+            batch_interval: batch_interval.clone(),
+        },
+        agg_param: Vec::new(),
+    };
+    let collect_uri = t
+        .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
+        .await;
+    let prefix = format!("/v02/collect/task/{}/req/", t.task_id.to_base64url());
+    let collect_id = collect_uri
+        .path()
+        .strip_prefix(&prefix)
+        .unwrap_or_else(|| panic!("unexpected collect URI: {collect_uri}"));
+    assert!(CollectionJobId::try_from_base64url(collect_id).is_some());
+
+    // The collection job is pending until the reports are aggregated.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 202);
+
+    t.internal_process(
+        &client,
+        &DaphneWorkerReportSelector {
+            max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+            max_reports: 100,
+            task_weights: None,
+        },
+    )
+    .await;
+
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        DapMediaType::Collection
+            .as_str_for_version(DapVersion::Draft02)
+            .unwrap()
+    );
+    let collection =
+        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(collection.report_count, t.task_config.min_batch_size);
+
+    // A malformed collect ID is rejected.
+    let mut bad_uri = collect_uri.clone();
+    bad_uri.set_path(&format!("{prefix}not-a-collect-id"));
+    let resp = t.poll_collection_url(&client, &bad_uri).await;
+    assert_eq!(resp.status(), 400);
+}
+
+async_test_version! { e2e_leader_collect_draft02_poll, Draft02 }
+
+// Collection job IDs are chosen by the Collector, so the same ID may be used for jobs of two
+// different tasks. The jobs must be tracked independently.
+async fn e2e_leader_collect_same_collection_job_id_for_two_tasks(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let batch_interval = t.batch_interval();
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    let mut rng = thread_rng();
+    let other_task_id = TaskId(rng.gen());
+    t.leader_add_task(&other_task_id).await;
+
+    let collect_job_id = CollectionJobId(rng.gen());
+    let collect_req = CollectionReq {
+        draft02_task_id: None,
+        query: Query::TimeInterval {
+            batch_interval: batch_interval.clone(),
+        },
+        agg_param: Vec::new(),
+    };
+
+    // Create the collection job for the first task. The collection job for the other task
+    // doesn't exist yet.
+    let collect_uri = t
+        .leader_post_collect_to(
+            &client,
+            &t.collect_url_suffix_for(&t.task_id, &collect_job_id),
+            collect_req.get_encoded_with_param(&t.version),
+            &t.collector_bearer_token,
+        )
+        .await;
+    let other_collect_uri = t
+        .leader_url
+        .join(&t.collect_url_suffix_for(&other_task_id, &collect_job_id))
+        .unwrap();
+    let resp = t.poll_collection_url(&client, &other_collect_uri).await;
+    assert_eq!(resp.status(), 400);
+
+    // Create the collection job for the other task.
+    t.leader_post_collect_to(
+        &client,
+        &t.collect_url_suffix_for(&other_task_id, &collect_job_id),
+        collect_req.get_encoded_with_param(&t.version),
+        &t.collector_bearer_token,
+    )
+    .await;
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 202);
+    let resp = t.poll_collection_url(&client, &other_collect_uri).await;
+    assert_eq!(resp.status(), 202);
+
+    // Upload reports for the first task only and process them.
+    for _ in 0..t.task_config.min_batch_size {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+    let agg_telem = t
+        .internal_process(
+            &client,
+            &DaphneWorkerReportSelector {
+                max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+                max_reports: 100,
+                task_weights: None,
+            },
+        )
+        .await;
+    assert_eq!(agg_telem.reports_collected, t.task_config.min_batch_size);
+
+    // Only the collection job for the first task is complete.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+    let resp = t.poll_collection_url(&client, &other_collect_uri).await;
+    assert_eq!(resp.status(), 202);
+}
+
+async_test_version! { e2e_leader_collect_same_collection_job_id_for_two_tasks, Draft04 }
+
+async fn e2e_leader_pending_collection_jobs_for_task(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    t.leader_add_task(&task_id).await;
+
+    let pending_collection_jobs = |task_id: TaskId| {
+        let client = client.clone();
+        let mut url = t.leader_url.clone();
+        url.set_path(&format!(
+            "internal/test/pending_collection_jobs/task/{}",
+            task_id.to_base64url()
+        ));
+        async move {
+            let resp = client.get(url).send().await.expect("request failed");
+            assert_eq!(resp.status(), 200);
+            resp.json::<Vec<String>>().await.unwrap()
+        }
+    };
+    assert!(pending_collection_jobs(task_id.clone()).await.is_empty());
+
+    // Only the collection job for the task is listed, even though the queue is shared by all tasks.
+    let collect_job_id = CollectionJobId(rng.gen());
+    t.leader_post_collect_to(
+        &client,
+        &t.collect_url_suffix_for(&task_id, &collect_job_id),
+        CollectionReq {
+            draft02_task_id: None,
+            query: Query::TimeInterval {
+                batch_interval: t.batch_interval(),
+            },
+            agg_param: Vec::new(),
+        }
+        .get_encoded_with_param(&t.version),
+        &t.collector_bearer_token,
+    )
+    .await;
+    assert_eq!(
+        pending_collection_jobs(task_id.clone()).await,
+        vec![collect_job_id.to_base64url()]
+    );
+    assert!(pending_collection_jobs(TaskId(rng.gen())).await.is_empty());
+}
+
+async_test_version! { e2e_leader_pending_collection_jobs_for_task, Draft04 }
+
+async fn e2e_leader_collect_abort_too_many_pending_jobs(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let mut rng = thread_rng();
+    let collect_req = CollectionReq {
+        draft02_task_id: None,
+        query: Query::TimeInterval {
+            batch_interval: t.batch_interval(),
+        },
+        agg_param: Vec::new(),
+    }
+    .get_encoded_with_param(&t.version);
+
+    // The Leader allows at most this many pending collection jobs per task. See wrangler.toml.
+    let max_pending_collection_jobs = 10;
+    let mut collect_job_ids = Vec::new();
+    for _ in 0..max_pending_collection_jobs {
+        let collect_job_id = CollectionJobId(rng.gen());
+        t.leader_post_collect_to(
+            &client,
+            &t.collect_url_suffix_for(&t.task_id, &collect_job_id),
+            collect_req.clone(),
+            &t.collector_bearer_token,
+        )
+        .await;
+        collect_job_ids.push(collect_job_id);
+    }
+
+    // Re-submitting a pending job doesn't count against the limit.
+    t.leader_post_collect_to(
+        &client,
+        &t.collect_url_suffix_for(&t.task_id, &collect_job_ids[0]),
+        collect_req.clone(),
+        &t.collector_bearer_token,
+    )
+    .await;
+
+    // A new job is rejected with a retriable error.
+    let url = t
+        .leader_url
+        .join(&t.collect_url_suffix_for(&t.task_id, &CollectionJobId(rng.gen())))
+        .unwrap();
+    let resp = client
+        .put(url)
+        .body(collect_req)
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            DapMediaType::CollectReq
+                .as_str_for_version(version)
+                .unwrap(),
+        )
+        .header("dap-auth-token", &t.collector_bearer_token)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 429);
+    assert!(resp.headers().get("Retry-After").is_some());
+}
+
+async_test_version! { e2e_leader_collect_abort_too_many_pending_jobs, Draft04 }
 
 async fn e2e_leader_collect_accept_global_config_max_batch_duration(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
@@ -1092,6 +2116,7 @@ async fn e2e_leader_collect_abort_overlapping_batch_interval(version: DapVersion
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                task_weights: None,
             },
         )
         .await;
@@ -1164,6 +2189,7 @@ async fn e2e_fixed_size(version: DapVersion, use_current: bool) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
         max_reports: 100,
+        task_weights: None,
     };
 
     let client = t.http_client();
@@ -1225,138 +2251,404 @@ async fn e2e_fixed_size(version: DapVersion, use_current: bool) {
         .await;
     println!("collect_uri: {}", collect_uri);
 
-    // Collector: Poll the collect URI before the CollectResp is ready.
-    let resp = t.poll_collection_url(&client, &collect_uri).await;
-    assert_eq!(resp.status(), 202, "response: {:?}", resp);
+    // Collector: Poll the collect URI before the CollectResp is ready.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 202, "response: {:?}", resp);
+
+    // ... Aggregators run processing loop.
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 0, "reports processed");
+    assert_eq!(agg_telem.reports_aggregated, 0, "reports aggregated");
+    assert_eq!(
+        agg_telem.reports_collected, t.task_config.min_batch_size,
+        "reports collected"
+    );
+
+    // Collector: Poll the collect URI.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+
+    let collection =
+        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
+    let agg_res = t
+        .task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver,
+            &t.task_id,
+            &BatchSelector::FixedSizeByBatchId {
+                batch_id: batch_id.clone(),
+            },
+            collection.report_count,
+            collection.encrypted_agg_shares.clone(),
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        agg_res,
+        DapAggregateResult::U128(t.task_config.min_batch_size as u128)
+    );
+
+    // Collector: Poll the collect URI once more. Expect the response to be the same as the first,
+    // per HTTP GET semantics.
+    let resp = t.poll_collection_url(&client, &collect_uri).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.bytes().await.unwrap(),
+        collection.get_encoded_with_param(&t.version)
+    );
+
+    // Clients: Upload reports.
+    for _ in 0..2 {
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    t.now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    // ... Aggregators run processing loop.
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 2, "reports processed");
+    assert_eq!(agg_telem.reports_aggregated, 2, "reports aggregated");
+    assert_eq!(agg_telem.reports_collected, 0, "reports collected");
+
+    // Get the oldest, not-yet-collected batch ID. This should be different than the one we got
+    // before, since that batch was collected.
+    let prev_batch_id = batch_id;
+    let batch_id = t.internal_current_batch(&t.task_id).await;
+    assert_ne!(batch_id, prev_batch_id);
+
+    // Collector: Try CollectReq with out-dated batch ID.
+    if t.version == DapVersion::Draft02 {
+        t.leader_post_expect_abort(
+            &client,
+            Some(&t.collector_bearer_token),
+            &t.collect_url_suffix(),
+            DapMediaType::CollectReq,
+            CollectionReq {
+                draft02_task_id: t.collect_task_id_field(),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: prev_batch_id.clone(),
+                },
+                agg_param: Vec::new(),
+            }
+            .get_encoded_with_param(&t.version),
+            400,
+            "batchOverlap",
+        )
+        .await;
+    } else {
+        t.leader_put_expect_abort(
+            &client,
+            Some(&t.collector_bearer_token),
+            &t.collect_url_suffix(),
+            DapMediaType::CollectReq,
+            CollectionReq {
+                draft02_task_id: t.collect_task_id_field(),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: prev_batch_id.clone(),
+                },
+                agg_param: Vec::new(),
+            }
+            .get_encoded_with_param(&t.version),
+            400,
+            "batchOverlap",
+        )
+        .await;
+    }
+}
+
+async fn e2e_fixed_size_no_current(version: DapVersion) {
+    e2e_fixed_size(version, true).await;
+}
+
+async_test_versions! { e2e_fixed_size_no_current }
+
+async fn e2e_fixed_size_current(version: DapVersion) {
+    e2e_fixed_size(version, true).await;
+}
+
+async_test_versions! { e2e_fixed_size_current }
+
+async fn e2e_fixed_size_peek_batch_queue(version: DapVersion) {
+    let t = TestRunner::fixed_size(version).await;
+    let path = t.upload_path();
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+        max_reports: 100,
+        task_weights: None,
+    };
+
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+
+    // Clients: Upload enough reports to fill one batch and start filling the next.
+    for _ in 0..MAX_BATCH_SIZE + 1 {
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    t.now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    // ... Aggregators run processing loop, which assigns the reports to batches.
+    t.internal_process(&client, &report_sel).await;
+
+    // Peeking at the batch queue doesn't change it, so both requests get the same response.
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!(
+        "internal/test/batch_queue/task/{}",
+        t.task_id.to_base64url()
+    ));
+    let mut batches = Vec::new();
+    for _ in 0..2 {
+        let resp = client
+            .get(url.clone())
+            .send()
+            .await
+            .expect("request failed");
+        assert_eq!(resp.status(), 200);
+        batches.push(resp.json::<Vec<serde_json::Value>>().await.unwrap());
+    }
+    assert_eq!(batches[0], batches[1]);
+
+    let batches = &batches[0];
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0]["report_count"], MAX_BATCH_SIZE);
+    assert_eq!(batches[1]["report_count"], 1);
+    for batch in batches {
+        assert_eq!(batch["min_batch_size"], t.task_config.min_batch_size);
+    }
+}
+
+async_test_versions! { e2e_fixed_size_peek_batch_queue }
+
+async fn e2e_leader_admin_sweep_expired_task(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!(
+        "internal/test/sweep_expired_task/task/{}",
+        t.task_id.to_base64url()
+    ));
+
+    // Sweeping a task requires the admin bearer token.
+    let resp = client
+        .post(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    // The task has not expired, so its reports are not swept.
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::HeaderName::from_lowercase(b"x-daphne-worker-admin-bearer-token").unwrap(),
+        "administrator bearer token".parse().unwrap(),
+    );
+    let resp = client
+        .post(url.clone())
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+
+    // Upload a report for a task that is about to expire.
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let task_id = TaskId(thread_rng().gen());
+    t.leader_add_task_expiring_at(&task_id, now + 3).await;
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    t.leader_put_expect_ok(
+        &client,
+        &t.upload_path_for_task(&task_id),
+        DapMediaType::Report,
+        t.task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                t.now,
+                &task_id,
+                DapMeasurement::U64(23),
+                version,
+            )
+            .unwrap()
+            .get_encoded_with_param(&version),
+    )
+    .await;
+
+    // Once the task has expired, the instance holding the report is swept, along with its
+    // aggregation job. Sweeping again finds nothing left to delete.
+    tokio::time::sleep(std::time::Duration::from_secs(4)).await;
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!(
+        "internal/test/sweep_expired_task/task/{}",
+        task_id.to_base64url()
+    ));
+    for expected_instances_deleted in [1, 0] {
+        let resp = client
+            .post(url.clone())
+            .headers(headers.clone())
+            .send()
+            .await
+            .expect("request failed");
+        assert_eq!(resp.status(), 200);
+        let res: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(res["instances_deleted"], expected_instances_deleted);
+    }
+}
+
+async_test_versions! { e2e_leader_admin_sweep_expired_task }
+
+async fn e2e_leader_admin_quiesce_task(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!("task/{}/quiesce", t.task_id.to_base64url()));
+
+    // Quiescing a task requires the admin bearer token.
+    let resp = client
+        .post(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::HeaderName::from_lowercase(b"x-daphne-worker-admin-bearer-token").unwrap(),
+        "administrator bearer token".parse().unwrap(),
+    );
+    let resp = client
+        .post(url)
+        .headers(headers)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+
+    // Uploads for the task are rejected once it is quiescing.
+    t.leader_put_expect_abort(
+        &client,
+        None, // dap_auth_token
+        &path,
+        DapMediaType::Report,
+        t.task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                t.now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap()
+            .get_encoded_with_param(&version),
+        400,
+        "reportRejected",
+    )
+    .await;
+}
+
+async_test_versions! { e2e_leader_admin_quiesce_task }
 
-    // ... Aggregators run processing loop.
-    let agg_telem = t.internal_process(&client, &report_sel).await;
-    assert_eq!(agg_telem.reports_processed, 0, "reports processed");
-    assert_eq!(agg_telem.reports_aggregated, 0, "reports aggregated");
-    assert_eq!(
-        agg_telem.reports_collected, t.task_config.min_batch_size,
-        "reports collected"
-    );
+async fn e2e_leader_admin_report_status(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let mut rng = thread_rng();
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
 
-    // Collector: Poll the collect URI.
-    let resp = t.poll_collection_url(&client, &collect_uri).await;
-    assert_eq!(resp.status(), 200);
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::HeaderName::from_lowercase(b"x-daphne-worker-admin-bearer-token").unwrap(),
+        "administrator bearer token".parse().unwrap(),
+    );
+    let report_status = |report_id: ReportId| {
+        let mut url = t.leader_url.clone();
+        url.set_path(&format!(
+            "internal/test/report_status/task/{}/report/{}",
+            t.task_id.to_base64url(),
+            report_id.to_base64url()
+        ));
+        let req = client.get(url).headers(headers.clone());
+        async move {
+            let resp = req.send().await.expect("request failed");
+            assert_eq!(resp.status(), 200);
+            let body: serde_json::Value = resp.json().await.unwrap();
+            body["status"].as_str().unwrap().to_string()
+        }
+    };
 
-    let collection =
-        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
-    let agg_res = t
+    let report = t
         .task_config
         .vdaf
-        .consume_encrypted_agg_shares(
-            &t.collector_hpke_receiver,
+        .produce_report(
+            &hpke_config_list,
+            t.now,
             &t.task_id,
-            &BatchSelector::FixedSizeByBatchId {
-                batch_id: batch_id.clone(),
-            },
-            collection.report_count,
-            collection.encrypted_agg_shares.clone(),
+            DapMeasurement::U64(1),
             version,
         )
-        .await
         .unwrap();
-    assert_eq!(
-        agg_res,
-        DapAggregateResult::U128(t.task_config.min_batch_size as u128)
-    );
-
-    // Collector: Poll the collect URI once more. Expect the response to be the same as the first,
-    // per HTTP GET semantics.
-    let resp = t.poll_collection_url(&client, &collect_uri).await;
-    assert_eq!(resp.status(), 200);
-    assert_eq!(
-        resp.bytes().await.unwrap(),
-        collection.get_encoded_with_param(&t.version)
-    );
-
-    // Clients: Upload reports.
-    for _ in 0..2 {
-        t.leader_put_expect_ok(
-            &client,
-            &path,
-            DapMediaType::Report,
-            t.task_config
-                .vdaf
-                .produce_report(
-                    &hpke_config_list,
-                    t.now,
-                    &t.task_id,
-                    DapMeasurement::U64(1),
-                    version,
-                )
-                .unwrap()
-                .get_encoded_with_param(&version),
-        )
-        .await;
-    }
-
-    // ... Aggregators run processing loop.
-    let agg_telem = t.internal_process(&client, &report_sel).await;
-    assert_eq!(agg_telem.reports_processed, 2, "reports processed");
-    assert_eq!(agg_telem.reports_aggregated, 2, "reports aggregated");
-    assert_eq!(agg_telem.reports_collected, 0, "reports collected");
-
-    // Get the oldest, not-yet-collected batch ID. This should be different than the one we got
-    // before, since that batch was collected.
-    let prev_batch_id = batch_id;
-    let batch_id = t.internal_current_batch(&t.task_id).await;
-    assert_ne!(batch_id, prev_batch_id);
-
-    // Collector: Try CollectReq with out-dated batch ID.
-    if t.version == DapVersion::Draft02 {
-        t.leader_post_expect_abort(
-            &client,
-            Some(&t.collector_bearer_token),
-            &t.collect_url_suffix(),
-            DapMediaType::CollectReq,
-            CollectionReq {
-                draft02_task_id: t.collect_task_id_field(),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: prev_batch_id.clone(),
-                },
-                agg_param: Vec::new(),
-            }
-            .get_encoded_with_param(&t.version),
-            400,
-            "batchOverlap",
-        )
-        .await;
-    } else {
-        t.leader_put_expect_abort(
-            &client,
-            Some(&t.collector_bearer_token),
-            &t.collect_url_suffix(),
-            DapMediaType::CollectReq,
-            CollectionReq {
-                draft02_task_id: t.collect_task_id_field(),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: prev_batch_id.clone(),
-                },
-                agg_param: Vec::new(),
-            }
-            .get_encoded_with_param(&t.version),
-            400,
-            "batchOverlap",
-        )
-        .await;
-    }
-}
+    let report_id = report.report_metadata.id.clone();
+    assert_eq!(report_status(report_id.clone()).await, "unknown");
 
-async fn e2e_fixed_size_no_current(version: DapVersion) {
-    e2e_fixed_size(version, true).await;
-}
+    // The report is pending once it has been uploaded.
+    t.leader_put_expect_ok(
+        &client,
+        &path,
+        DapMediaType::Report,
+        report.get_encoded_with_param(&version),
+    )
+    .await;
+    assert_eq!(report_status(report_id.clone()).await, "pending");
 
-async_test_versions! { e2e_fixed_size_no_current }
+    // The report is processed once it has been aggregated.
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100,
+        max_reports: 100,
+        task_weights: None,
+    };
+    t.internal_process(&client, &report_sel).await;
+    assert_eq!(report_status(report_id).await, "processed");
 
-async fn e2e_fixed_size_current(version: DapVersion) {
-    e2e_fixed_size(version, true).await;
+    // A report that was never uploaded is unknown.
+    assert_eq!(report_status(ReportId(rng.gen())).await, "unknown");
 }
 
-async_test_versions! { e2e_fixed_size_current }
+async_test_versions! { e2e_leader_admin_report_status }
 
 async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
@@ -1455,6 +2747,7 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                task_weights: None,
             },
         )
         .await;
@@ -1554,3 +2847,255 @@ async fn e2e_helper_admin_add_task(version: DapVersion) {
 }
 
 async_test_versions! { e2e_helper_admin_add_task }
+
+async fn e2e_helper_admin_list_tasks(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let url = t.helper_url.join("/internal/test/tasks").unwrap();
+
+    // Listing tasks requires the admin bearer token.
+    let resp = client
+        .get(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::HeaderName::from_lowercase(b"x-daphne-worker-admin-bearer-token").unwrap(),
+        "administrator bearer token".parse().unwrap(),
+    );
+    let resp = client
+        .get(url.clone())
+        .headers(headers)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+
+    let tasks: Vec<serde_json::Value> = resp.json().await.unwrap();
+    let task = tasks
+        .iter()
+        .find(|task| task["task_id"] == t.task_id.to_base64url())
+        .expect("task not listed");
+    assert_eq!(task["version"], version.as_ref());
+    assert_eq!(task["expiration"], t.task_config.expiration);
+    assert_eq!(task["taskprov"], false);
+}
+
+async_test_versions! { e2e_helper_admin_list_tasks }
+
+async fn e2e_helper_admin_export_import_tasks(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let export_url = t.helper_url.join("/internal/test/tasks/export").unwrap();
+    let import_url = t.helper_url.join("/internal/test/tasks/import").unwrap();
+
+    // Exporting tasks requires the admin bearer token.
+    let resp = client
+        .get(export_url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::HeaderName::from_lowercase(b"x-daphne-worker-admin-bearer-token").unwrap(),
+        "administrator bearer token".parse().unwrap(),
+    );
+    let export = |client: reqwest::Client, headers: reqwest::header::HeaderMap| {
+        let export_url = export_url.clone();
+        let task_id = t.task_id.to_base64url();
+        async move {
+            let resp = client
+                .get(export_url)
+                .headers(headers)
+                .send()
+                .await
+                .expect("request failed");
+            assert_eq!(resp.status(), 200);
+            let bundle: serde_json::Value = resp.json().await.unwrap();
+            let task = bundle["tasks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|task| task["task_id"] == task_id)
+                .expect("task not exported")
+                .clone();
+            (bundle, task)
+        }
+    };
+
+    let (bundle, task) = export(client.clone(), headers.clone()).await;
+    assert_eq!(task["task_config"]["version"], version.as_ref());
+    assert_eq!(task["leader_bearer_token"]["raw"], t.leader_bearer_token);
+
+    // Importing the bundle is idempotent.
+    for _ in 0..2 {
+        let resp = client
+            .post(import_url.clone())
+            .json(&bundle)
+            .headers(headers.clone())
+            .send()
+            .await
+            .expect("request failed");
+        assert_eq!(resp.status(), 200);
+    }
+    let (_bundle, task_after_import) = export(client.clone(), headers.clone()).await;
+    assert_eq!(task_after_import, task);
+
+    // A malformed task ID is rejected.
+    let resp = client
+        .post(import_url.clone())
+        .json(&serde_json::json!({
+            "tasks": [{
+                "task_id": "not a task ID",
+                "task_config": task["task_config"],
+            }],
+        }))
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+
+    // So is a config that `add_task` would reject.
+    let mut task_config = task["task_config"].clone();
+    task_config["query"] = serde_json::json!({
+        "time_interval": {
+            "bucket_duration": task_config["time_precision"].as_u64().unwrap() + 1,
+        },
+    });
+    let resp = client
+        .post(import_url)
+        .json(&serde_json::json!({
+            "tasks": [{
+                "task_id": task["task_id"],
+                "task_config": task_config,
+            }],
+        }))
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+    let (_bundle, task_after_import) = export(client, headers).await;
+    assert_eq!(task_after_import, task);
+}
+
+async_test_versions! { e2e_helper_admin_export_import_tasks }
+
+async fn e2e_helper_admin_rotate_hpke_config(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let hpke_config_list_0 = t.get_hpke_configs(version, &client).await;
+    let url = t
+        .helper_url
+        .join(&format!(
+            "/{}/internal/test/rotate_hpke_config",
+            version.as_ref()
+        ))
+        .unwrap();
+
+    // Rotating the HPKE config requires the admin bearer token.
+    let resp = client
+        .post(url.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::HeaderName::from_lowercase(b"x-daphne-worker-admin-bearer-token").unwrap(),
+        "administrator bearer token".parse().unwrap(),
+    );
+    let resp = client
+        .post(url.clone())
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let res: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(res["status"], "success");
+
+    // The new config is advertised from now on.
+    let hpke_config_list_1 = t.get_hpke_configs(version, &client).await;
+    assert_eq!(res["hpke_config_id"], hpke_config_list_1[1].id);
+    assert_ne!(hpke_config_list_0[1].id, hpke_config_list_1[1].id);
+
+    // The task-scoped configs of a task are rotated separately from the global configs.
+    let task_id = TaskId(thread_rng().gen());
+    t.helper_add_task(&task_id, true).await;
+    let task_hpke_config_0 = t.helper_get_hpke_config_for(&client, &task_id).await;
+    let mut task_url = url.clone();
+    task_url
+        .query_pairs_mut()
+        .append_pair("task_id", &task_id.to_base64url());
+    let resp = client
+        .post(task_url)
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let res: serde_json::Value = resp.json().await.unwrap();
+    let task_hpke_config_1 = t.helper_get_hpke_config_for(&client, &task_id).await;
+    assert_eq!(res["hpke_config_id"], task_hpke_config_1.id);
+    assert_ne!(task_hpke_config_0, task_hpke_config_1);
+    assert_eq!(
+        t.get_hpke_configs(version, &client).await[1],
+        hpke_config_list_1[1]
+    );
+
+    // Only tasks that opted in to task-scoped configs can have them rotated.
+    let mut task_url = url.clone();
+    task_url
+        .query_pairs_mut()
+        .append_pair("task_id", &t.task_id.to_base64url());
+    let resp = client
+        .post(task_url)
+        .headers(headers)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+}
+
+async_test_versions! { e2e_helper_admin_rotate_hpke_config }
+
+async fn e2e_helper_internal_add_hpke_config(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let task_id = TaskId(thread_rng().gen());
+    t.helper_add_task(&task_id, true).await;
+
+    // Pin the HPKE receiver config for the task to one whose private key is known.
+    let hpke_receiver_config =
+        HpkeReceiverConfig::gen(thread_rng().gen(), HpkeKemId::X25519HkdfSha256).unwrap();
+    let res: serde_json::Value = t
+        .helper_post_internal(
+            &format!("{}/internal/test/add_hpke_config", version.as_ref()),
+            &json!({
+                "task_id": task_id.to_base64url(),
+                "hpke_receiver_config": hpke_receiver_config,
+            }),
+        )
+        .await;
+    assert_eq!(res["status"], "success");
+
+    // The pinned config is advertised for the task, but not for other tasks.
+    assert_eq!(
+        t.helper_get_hpke_config_for(&client, &task_id).await,
+        hpke_receiver_config.config
+    );
+    assert_ne!(
+        t.helper_get_hpke_config_for(&client, &t.task_id).await,
+        hpke_receiver_config.config
+    );
+}
+
+async_test_versions! { e2e_helper_internal_add_hpke_config }
@@ -9,7 +9,7 @@ use daphne::{
     hpke::HpkeReceiverConfig,
     messages::{
         encode_base64url, BatchId, CollectionJobId, Duration, HpkeAeadId, HpkeConfig,
-        HpkeConfigList, HpkeKdfId, HpkeKemId, Interval, TaskId,
+        HpkeConfigList, HpkeKdfId, HpkeKemId, Interval, TaskId, Time,
     },
     taskprov::TaskprovVersion,
     DapGlobalConfig, DapLeaderProcessTelemetry, DapQueryConfig, DapTaskConfig, DapVersion,
@@ -47,6 +47,7 @@ pub struct TestRunner {
     pub helper_url: Url,
     pub leader_bearer_token: String,
     pub collector_bearer_token: String,
+    pub backup_collector_bearer_token: String,
     pub collector_hpke_receiver: HpkeReceiverConfig,
     pub taskprov_vdaf_verify_key_init: [u8; 32],
     pub taskprov_collector_hpke_receiver: HpkeReceiverConfig,
@@ -56,7 +57,13 @@ pub struct TestRunner {
 #[allow(dead_code)]
 impl TestRunner {
     pub async fn default_with_version(version: DapVersion) -> Self {
-        Self::with(version, &DapQueryConfig::TimeInterval).await
+        Self::with(
+            version,
+            &DapQueryConfig::TimeInterval {
+                bucket_duration: None,
+            },
+        )
+        .await
     }
 
     pub async fn default() -> Self {
@@ -115,6 +122,15 @@ impl TestRunner {
             vdaf: VDAF_CONFIG.clone(),
             vdaf_verify_key: VDAF_CONFIG.gen_verify_key(),
             collector_hpke_config: collector_hpke_receiver.config.clone(),
+            upload_rate_limit: None,
+            max_reports_per_agg_job: None,
+            client_auth: false,
+            task_scoped_hpke_config: false,
+            quiescing: false,
+            pending_report_ttl: None,
+            collect_poll_retry_after: None,
+            permitted_extensions: Vec::new(),
+            reject_future_buckets: false,
         };
 
         // This block needs to be kept in-sync with daphne_worker_test/wrangler.toml.
@@ -125,8 +141,14 @@ impl TestRunner {
             min_batch_interval_start: 259200,
             max_batch_interval_end: 259200,
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+            supported_hpke_kdfs: vec![HpkeKdfId::HkdfSha256],
+            supported_hpke_aeads: vec![HpkeAeadId::Aes128Gcm],
             allow_taskprov: true,
             taskprov_version: TaskprovVersion::Draft02,
+            rejection_counts_flush_interval: None,
+            max_batch_span: None,
+            max_reports_per_agg_job: None,
+            hpke_config_max_age: 3600,
         };
         let taskprov_vdaf_verify_key_init =
             hex::decode("b029a72fa327931a5cb643dcadcaafa098fcbfac07d990cb9e7c9a8675fafb18")
@@ -146,6 +168,7 @@ impl TestRunner {
 
         let leader_bearer_token = hex::encode(rng.gen::<[u8; 16]>());
         let collector_bearer_token = hex::encode(rng.gen::<[u8; 16]>());
+        let backup_collector_bearer_token = hex::encode(rng.gen::<[u8; 16]>());
         let t = Self {
             global_config,
             task_id: task_id.clone(),
@@ -155,88 +178,106 @@ impl TestRunner {
             helper_url,
             leader_bearer_token,
             collector_bearer_token,
+            backup_collector_bearer_token,
             collector_hpke_receiver,
             taskprov_vdaf_verify_key_init,
             taskprov_collector_hpke_receiver,
             version,
         };
 
-        let vdaf_verify_key_base64url = encode_base64url(t.task_config.vdaf_verify_key.as_ref());
+        // Configure the endpoints.
+        //
+        // First, delete the data from the previous test.
+        t.internal_delete_all(&t.batch_interval()).await;
 
-        let collector_hpke_config_base64url =
-            encode_base64url(t.collector_hpke_receiver.config.get_encoded());
+        // Configure the Leader with the task.
+        t.leader_add_task(&t.task_id).await;
 
+        // Configure the Helper with the task.
+        let add_task_path = format!("{}/internal/test/add_task", version.as_ref());
+        let res: InternalTestAddTaskResult = t
+            .helper_post_internal(&add_task_path, &t.add_task_cmd(&t.task_id, "helper"))
+            .await;
+        assert_eq!(
+            res.status, "success",
+            "response status: {}, error: {:?}",
+            res.status, res.error
+        );
+
+        t
+    }
+
+    /// Command for adding a task with this runner's parameters to the Aggregator with the given
+    /// role.
+    fn add_task_cmd(&self, task_id: &TaskId, role: &str) -> serde_json::Value {
         let vdaf = json!({
             "type": "Prio3Sum",
             "bits": assert_matches!(
-                t.task_config.vdaf,
+                self.task_config.vdaf,
                 VdafConfig::Prio3(Prio3Config::Sum{ bits }) => format!("{bits}")
             ),
         });
 
-        let (query_type, max_batch_size) = match t.task_config.query {
-            DapQueryConfig::TimeInterval => (1, None),
+        let (query_type, max_batch_size) = match self.task_config.query {
+            DapQueryConfig::TimeInterval { .. } => (1, None),
             DapQueryConfig::FixedSize { max_batch_size } => (2, Some(max_batch_size)),
         };
 
-        // Configure the endpoints.
-        //
-        // First, delete the data from the previous test.
-        t.internal_delete_all(&t.batch_interval()).await;
-
-        // Configure the Leader with the task.
-        let leader_add_task_cmd = json!({
-            "task_id": t.task_id.to_base64url(),
-            "leader": t.leader_url,
-            "helper": t.helper_url,
-            "vdaf": vdaf.clone(),
-            "leader_authentication_token": t.leader_bearer_token.clone(),
-            "collector_authentication_token": t.collector_bearer_token.clone(),
-            "role": "leader",
-            "vdaf_verify_key": vdaf_verify_key_base64url,
+        let mut cmd = json!({
+            "task_id": task_id.to_base64url(),
+            "leader": self.leader_url,
+            "helper": self.helper_url,
+            "vdaf": vdaf,
+            "leader_authentication_token": self.leader_bearer_token.clone(),
+            "role": role,
+            "vdaf_verify_key": encode_base64url(self.task_config.vdaf_verify_key.as_ref()),
             "query_type": query_type,
-            "min_batch_size": t.task_config.min_batch_size,
+            "min_batch_size": self.task_config.min_batch_size,
             "max_batch_size": max_batch_size,
-            "time_precision": t.task_config.time_precision,
-            "collector_hpke_config": collector_hpke_config_base64url.clone(),
-            "task_expiration": t.task_config.expiration,
+            "time_precision": self.task_config.time_precision,
+            "collector_hpke_config": encode_base64url(self.collector_hpke_receiver.config.get_encoded()),
+            "task_expiration": self.task_config.expiration,
         });
-        let add_task_path = format!("{}/internal/test/add_task", version.as_ref());
-        let res: InternalTestAddTaskResult = t
-            .leader_post_internal(&add_task_path, &leader_add_task_cmd)
+        if role == "leader" {
+            cmd["collector_authentication_token"] = self.collector_bearer_token.clone().into();
+            cmd["additional_collector_authentication_tokens"] =
+                json!([self.backup_collector_bearer_token.clone()]);
+        }
+        cmd
+    }
+
+    /// Configure the Leader with a task with the given ID. The task's parameters are the same as
+    /// this runner's task.
+    pub async fn leader_add_task(&self, task_id: &TaskId) {
+        self.leader_add_task_expiring_at(task_id, self.task_config.expiration)
             .await;
+    }
+
+    /// Like `leader_add_task()`, except that the task expires at the given time.
+    pub async fn leader_add_task_expiring_at(&self, task_id: &TaskId, expiration: Time) {
+        let add_task_path = format!("{}/internal/test/add_task", self.version.as_ref());
+        let mut cmd = self.add_task_cmd(task_id, "leader");
+        cmd["task_expiration"] = expiration.into();
+        let res: InternalTestAddTaskResult = self.leader_post_internal(&add_task_path, &cmd).await;
         assert_eq!(
             res.status, "success",
             "response status: {}, error: {:?}",
             res.status, res.error
         );
+    }
 
-        // Configure the Helper with the task.
-        let helper_add_task_cmd = json!({
-            "task_id": t.task_id.to_base64url(),
-            "leader": t.leader_url,
-            "helper": t.helper_url,
-            "vdaf": vdaf.clone(),
-            "leader_authentication_token": t.leader_bearer_token.clone(),
-            "role": "helper",
-            "vdaf_verify_key": vdaf_verify_key_base64url,
-            "query_type": query_type,
-            "min_batch_size": t.task_config.min_batch_size,
-            "max_batch_size": max_batch_size,
-            "time_precision": t.task_config.time_precision,
-            "collector_hpke_config": collector_hpke_config_base64url.clone(),
-            "task_expiration": t.task_config.expiration,
-        });
-        let res: InternalTestAddTaskResult = t
-            .helper_post_internal(&add_task_path, &helper_add_task_cmd)
-            .await;
+    /// Configure the Helper with a task with the given ID. The task's parameters are the same as
+    /// this runner's task, except that it may opt in to task-scoped HPKE receiver configs.
+    pub async fn helper_add_task(&self, task_id: &TaskId, task_scoped_hpke_config: bool) {
+        let add_task_path = format!("{}/internal/test/add_task", self.version.as_ref());
+        let mut cmd = self.add_task_cmd(task_id, "helper");
+        cmd["task_scoped_hpke_config"] = task_scoped_hpke_config.into();
+        let res: InternalTestAddTaskResult = self.helper_post_internal(&add_task_path, &cmd).await;
         assert_eq!(
             res.status, "success",
             "response status: {}, error: {:?}",
             res.status, res.error
         );
-
-        t
     }
 
     pub fn http_client(&self) -> reqwest::Client {
@@ -290,6 +331,24 @@ impl TestRunner {
         }
     }
 
+    /// Get the HPKE config the Helper advertises for the given task.
+    pub async fn helper_get_hpke_config_for(
+        &self,
+        client: &reqwest::Client,
+        task_id: &TaskId,
+    ) -> HpkeConfig {
+        let raw_hpke_config =
+            get_raw_hpke_config(client, task_id.as_ref(), &self.helper_url, "helper").await;
+        match self.version {
+            DapVersion::Draft02 => HpkeConfig::get_decoded(&raw_hpke_config).unwrap(),
+            _ => {
+                let mut hpke_config_list = HpkeConfigList::get_decoded(&raw_hpke_config).unwrap();
+                assert_eq!(hpke_config_list.hpke_configs.len(), 1);
+                hpke_config_list.hpke_configs.pop().unwrap()
+            }
+        }
+    }
+
     pub async fn leader_get_raw_hpke_config(&self, client: &reqwest::Client) -> Vec<u8> {
         get_raw_hpke_config(client, self.task_id.as_ref(), &self.leader_url, "leader").await
     }
@@ -508,8 +567,20 @@ impl TestRunner {
         data: Vec<u8>,
         token: &str,
     ) -> Url {
-        let url_suffix = self.collect_url_suffix();
-        let url = self.leader_url.join(&url_suffix).unwrap();
+        self.leader_post_collect_to(client, &self.collect_url_suffix(), data, token)
+            .await
+    }
+
+    /// Send a collect request to the given URL, relative to the Leader's URL. Return the collect
+    /// URI.
+    pub async fn leader_post_collect_to(
+        &self,
+        client: &reqwest::Client,
+        url_suffix: &str,
+        data: Vec<u8>,
+        token: &str,
+    ) -> Url {
+        let url = self.leader_url.join(url_suffix).unwrap();
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             reqwest::header::CONTENT_TYPE,
@@ -675,15 +746,24 @@ impl TestRunner {
             "collect".to_string()
         } else {
             let mut rng = thread_rng();
-            let collect_job_id = CollectionJobId(rng.gen());
-            format!(
-                "tasks/{}/collection_jobs/{}",
-                self.task_id.to_base64url(),
-                collect_job_id.to_base64url()
-            )
+            self.collect_url_suffix_for(&self.task_id, &CollectionJobId(rng.gen()))
         }
     }
 
+    /// The path of the collection job with the given ID for the given task, relative to the
+    /// Leader's URL. This is only applicable to draft04 and later.
+    pub fn collect_url_suffix_for(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> String {
+        format!(
+            "tasks/{}/collection_jobs/{}",
+            task_id.to_base64url(),
+            collect_job_id.to_base64url()
+        )
+    }
+
     pub async fn poll_collection_url(
         &self,
         client: &reqwest::Client,